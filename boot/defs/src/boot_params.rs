@@ -144,6 +144,13 @@ pub struct BootParamBlock {
     /// range.
     pub vmsa_in_kernel_range: u8,
 
+    /// Indicates whether SVSM should continue to boot without provisioned
+    /// keys if attestation fails, instead of halting.
+    pub continue_unprovisioned: u8,
+
+    #[doc(hidden)]
+    pub _reserved: [u8; 7],
+
     /// Metadata containing information about the firmware image embedded in the
     /// IGVM file.
     pub firmware: GuestFwInfoBlock,
@@ -8,7 +8,7 @@
 extern crate alloc;
 
 use crate::{
-    crypto::SecretSlice,
+    crypto::{SecretSlice, keys::ProvisionedSecret},
     error::SvsmError,
     greq::{pld_report::*, services::get_regular_report},
     io::{DEFAULT_IO_DRIVER, Read, Write},
@@ -129,19 +129,39 @@ impl TryFrom<Tee> for AttestationDriver<'_> {
 
 impl AttestationDriver<'_> {
     /// Attest SVSM's launch state by communicating with the attestation proxy.
-    pub fn attest(&mut self) -> Result<SecretSlice, SvsmError> {
-        let negotiation = self.negotiation()?;
-
-        Ok(self.attestation(negotiation)?)
+    ///
+    /// # Arguments
+    ///
+    /// * `state_version` - Version of the persistent state, if there is any,
+    ///   which is offered to the attestation server for rollback protection.
+    pub fn attest(&mut self, state_version: Option<u64>) -> Result<ProvisionedSecret, SvsmError> {
+        let negotiation = self.negotiation(state_version)?;
+
+        // The server only verified the state version if it had it included
+        // in the evidence.
+        let verified = negotiation
+            .params
+            .iter()
+            .any(|p| matches!(p, NegotiationParam::StateVersion));
+        let secret = self.attestation(negotiation, state_version)?;
+
+        Ok(ProvisionedSecret {
+            secret,
+            state_version: state_version.filter(|_| verified),
+        })
     }
 
     /// Send a negotiation request to the proxy. Proxy should reply with Negotiation parameters
     /// that should be included in attestation evidence (e.g. through SEV-SNP's REPORT_DATA
     /// mechanism).
-    fn negotiation(&mut self) -> Result<NegotiationResponse, AttestationError> {
+    fn negotiation(
+        &mut self,
+        state_version: Option<u64>,
+    ) -> Result<NegotiationResponse, AttestationError> {
         let request = NegotiationRequest {
             version: (0, 1, 0), // Only version supported at present.
             tee: self.tee,
+            state_version,
         };
 
         self.write(request)?;
@@ -153,7 +173,11 @@ impl AttestationDriver<'_> {
     /// Send an attestation request to the proxy. Proxy should reply with attestation response
     /// containing the status (success/fail) and an optional secret returned from the server upon
     /// successful attestation.
-    fn attestation(&mut self, n: NegotiationResponse) -> Result<SecretSlice, AttestationError> {
+    fn attestation(
+        &mut self,
+        n: NegotiationResponse,
+        state_version: Option<u64>,
+    ) -> Result<SecretSlice, AttestationError> {
         let curve =
             Curve::new(self.ecc.pub_key().get_curve_id()).map_err(AttestationError::Crypto)?;
 
@@ -163,7 +187,7 @@ impl AttestationDriver<'_> {
             .to_tpms_ecc_point(&curve.curve_ops().map_err(AttestationError::Crypto)?)
             .map_err(AttestationError::Crypto)?;
 
-        let evidence = evidence(&self.tee, hash(&n, &pub_key)?)?;
+        let evidence = evidence(&self.tee, hash(&n, &pub_key, state_version)?)?;
 
         let req = AttestationRequest {
             tee: self.tee,
@@ -311,6 +335,8 @@ pub enum AttestationError {
     SecretDecrypt(aes_gcm::Error),
     /// Attestation successful, but no secret found.
     SecretMissing,
+    /// Server requested the state version, but there is no persistent state.
+    StateVersionMissing,
     /// Unable to fetch SEV-SNP attestation report.
     SnpGetReport,
     /// Unsupported TEE architecture.
@@ -402,6 +428,7 @@ fn evidence(tee: &Tee, hash: Vec<u8>) -> Result<AttestationEvidence, Attestation
 fn hash(
     n: &NegotiationResponse,
     pub_key: &TpmsEccPoint<'static>,
+    state_version: Option<u64>,
 ) -> Result<Vec<u8>, AttestationError> {
     let mut sha = Sha512::new();
 
@@ -415,6 +442,10 @@ fn hash(
                 sha.update(&*pub_key.x.buffer);
                 sha.update(&*pub_key.y.buffer);
             }
+            NegotiationParam::StateVersion => {
+                let version = state_version.ok_or(AttestationError::StateVersionMissing)?;
+                sha.update(version.to_le_bytes());
+            }
         }
     }
 
@@ -0,0 +1,921 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Encrypted and integrity-protected block device.
+//!
+//! [`EncryptedBlockDevice`] wraps another [`BlockDriver`], which is assumed
+//! to be fully controlled by the host, and exposes a block device with
+//! 4 KiB blocks on top of it. The backing device is organized as follows:
+//!
+//! ```text
+//! +-------------+-------------+-------------------------+-----------+
+//! | superblock0 | superblock1 | metadata (2 copies each)| data pool |
+//! +-------------+-------------+-------------------------+-----------+
+//! ```
+//!
+//! * Every data block is encrypted with AES-256-GCM. The IV, the
+//!   authentication tag and the location in the data pool of each data block
+//!   are kept in a metadata entry, and the block number is used as
+//!   additional authenticated data, so that blocks can not be moved around.
+//! * The metadata blocks form the leaves of a Merkle tree. Its root is kept
+//!   in the superblock, which is authenticated with HMAC-SHA256. Replaying an
+//!   old data block fails authentication against its current metadata entry,
+//!   and replaying old metadata is detected through the Merkle root.
+//! * Each metadata block has two copies. Updates are written to the inactive
+//!   copy and only become visible when the superblock, which records the
+//!   active copy of every metadata block, is written to the inactive
+//!   superblock slot.
+//! * Data blocks are never overwritten in place. The data pool holds some
+//!   spare blocks beyond the capacity of the device, and every data block
+//!   which is written for the first time since the last commit is moved to
+//!   a free block of the pool. Its previous location is only reused after
+//!   the commit. An update interrupted before the commit therefore leaves
+//!   the previous state intact.
+//!
+//! Writes are committed on [`BlockDriver::flush`], or earlier if the data
+//! pool runs out of free blocks. Every data block is encrypted with a fresh
+//! random IV, so that IVs are not reused even if the host makes the device
+//! go through the same sequence of states again.
+//!
+//! The authentication above can not detect the host replaying a complete
+//! older image of the device. The superblock therefore contains an epoch,
+//! which is incremented and committed whenever the device is opened, and
+//! serves as the version of the stored state: it can be read before the key
+//! is known with [`state_version`], checked by a party the host can not
+//! rewind, such as the attestation server, and is then verified by
+//! [`EncryptedBlockDevice::open`].
+//!
+//! The epoch only changes when the device is opened, not on every commit,
+//! because the external party is only consulted at boot. All states committed
+//! while the device is open therefore share an epoch, and the host can replace
+//! the device with any of them without being detected when it is opened
+//! again. This includes discarding the newest superblock, in which case the
+//! previous one is used as after an interrupted commit. The writes of the most
+//! recent boot are thus only protected against rollback once the device has
+//! been opened with a newer epoch.
+//!
+//! All metadata is cached in memory, which takes about 0.8% of the size of
+//! the device.
+
+extern crate alloc;
+
+use super::api::BlockDriver;
+use crate::block::BlockDeviceError;
+use crate::crypto::aead::{AUTHTAG_SIZE, Aes256Gcm, Aes256GcmTrait, IV_SIZE, KEY_SIZE};
+use crate::crypto::kdf::{HkdfSha256, HkdfSha256Trait};
+use crate::crypto::mac::{HMAC_SHA256_SIZE, HmacSha256, HmacSha256Trait};
+use crate::crypto::rng::fill_random;
+use crate::error::SvsmError;
+use crate::locking::SpinLock;
+use crate::types::{PAGE_SHIFT, PAGE_SIZE};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use sha2::{Digest, Sha256};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+use zeroize::Zeroizing;
+
+const CRYPT_BLOCK_SIZE: usize = PAGE_SIZE;
+
+const SB_MAGIC: [u8; 8] = *b"SVSMCRYP";
+const SB_VERSION: u32 = 2;
+const SB_SLOTS: usize = 2;
+/// Bytes of the superblock covered by the HMAC.
+const SB_MAC_OFFSET: usize = CRYPT_BLOCK_SIZE - HMAC_SHA256_SIZE;
+const SB_BITMAP_SIZE: usize = SB_MAC_OFFSET - size_of::<SuperblockHeader>();
+
+const META_ENTRY_SIZE: usize = size_of::<MetaEntry>();
+const META_ENTRIES_PER_BLOCK: usize = CRYPT_BLOCK_SIZE / META_ENTRY_SIZE;
+const META_COPIES: usize = 2;
+/// Limited by the active-copy bitmap in the superblock.
+const MAX_META_BLOCKS: usize = SB_BITMAP_SIZE * 8;
+/// One spare block in the data pool for every `SPARE_RATIO` data blocks.
+const SPARE_RATIO: usize = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct SuperblockHeader {
+    magic: [u8; 8],
+    version: u32,
+    /// Incremented every time the device is opened.
+    epoch: u32,
+    /// Incremented on every commit; the valid superblock with the highest
+    /// sequence number is the current one.
+    seq: u64,
+    data_blocks: u64,
+    meta_blocks: u64,
+    /// Root of the Merkle tree over the active metadata blocks.
+    root: [u8; 32],
+}
+
+/// Per data block metadata. An all-zero IV marks a block which has never
+/// been written.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct MetaEntry {
+    iv: [u8; IV_SIZE],
+    tag: [u8; AUTHTAG_SIZE],
+    /// Block of the data pool holding the data.
+    location: u32,
+}
+
+impl MetaEntry {
+    fn is_written(&self) -> bool {
+        self.iv != [0; IV_SIZE]
+    }
+}
+
+#[derive(Debug)]
+struct CryptState {
+    epoch: u32,
+    seq: u64,
+    /// Superblock slot holding the current superblock.
+    sb_slot: usize,
+    /// Bitmap of the active copy of each metadata block.
+    bitmap: Vec<u8>,
+    /// Contents of all metadata blocks.
+    meta: Vec<u8>,
+    /// Merkle tree leaves, one per metadata block.
+    leaves: Vec<[u8; 32]>,
+    /// Metadata blocks modified since the last commit.
+    dirty: Vec<bool>,
+    /// Blocks of the data pool referenced by the committed metadata or by
+    /// data blocks written since the last commit.
+    in_use: Vec<bool>,
+    /// Blocks of the data pool which become free with the next commit.
+    released: Vec<u32>,
+    /// Data blocks which have been moved since the last commit.
+    moved: Vec<bool>,
+    /// Where to start looking for a free block of the data pool.
+    next_free: usize,
+}
+
+impl CryptState {
+    fn active_copy(&self, meta_block: usize) -> usize {
+        usize::from(self.bitmap[meta_block / 8] & (1 << (meta_block % 8)) != 0)
+    }
+
+    fn entry(&self, block: usize) -> MetaEntry {
+        let offset = block * META_ENTRY_SIZE + block / META_ENTRIES_PER_BLOCK * padding();
+        MetaEntry::read_from_bytes(&self.meta[offset..offset + META_ENTRY_SIZE]).unwrap()
+    }
+
+    fn set_entry(&mut self, block: usize, entry: &MetaEntry) {
+        let offset = block * META_ENTRY_SIZE + block / META_ENTRIES_PER_BLOCK * padding();
+        self.meta[offset..offset + META_ENTRY_SIZE].copy_from_slice(entry.as_bytes());
+        self.dirty[block / META_ENTRIES_PER_BLOCK] = true;
+    }
+
+    fn meta_block(&self, meta_block: usize) -> &[u8] {
+        let offset = meta_block * CRYPT_BLOCK_SIZE;
+        &self.meta[offset..offset + CRYPT_BLOCK_SIZE]
+    }
+
+    /// Takes a free block of the data pool.
+    fn allocate(&mut self) -> Option<u32> {
+        let pool_blocks = self.in_use.len();
+        let location = (self.next_free..pool_blocks)
+            .chain(0..self.next_free)
+            .find(|l| !self.in_use[*l])?;
+        self.in_use[location] = true;
+        self.next_free = (location + 1) % pool_blocks;
+        Some(location as u32)
+    }
+}
+
+/// Unused bytes at the end of each metadata block.
+const fn padding() -> usize {
+    CRYPT_BLOCK_SIZE - META_ENTRIES_PER_BLOCK * META_ENTRY_SIZE
+}
+
+fn leaf_hash(meta_block: usize, data: &[u8]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update((meta_block as u64).to_le_bytes())
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new()
+                    .chain_update(left)
+                    .chain_update(right)
+                    .finalize()
+                    .into(),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level.first().copied().unwrap_or_default()
+}
+
+/// Block counts of the regions of the backing device.
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    data_blocks: usize,
+    meta_blocks: usize,
+    pool_blocks: usize,
+}
+
+/// Computes the layout of a backing device of `size` bytes.
+fn layout(size: usize) -> Option<Layout> {
+    let avail = (size / CRYPT_BLOCK_SIZE).checked_sub(SB_SLOTS)?;
+    let per_meta_block = META_ENTRIES_PER_BLOCK + META_ENTRIES_PER_BLOCK / SPARE_RATIO;
+    let mut data_blocks = min(
+        avail * META_ENTRIES_PER_BLOCK / (per_meta_block + META_COPIES),
+        MAX_META_BLOCKS * META_ENTRIES_PER_BLOCK,
+    );
+    loop {
+        let meta_blocks = data_blocks.div_ceil(META_ENTRIES_PER_BLOCK);
+        let pool_blocks = data_blocks + data_blocks.div_ceil(SPARE_RATIO);
+        if pool_blocks + meta_blocks * META_COPIES <= avail {
+            return (data_blocks > 0).then_some(Layout {
+                data_blocks,
+                meta_blocks,
+                pool_blocks,
+            });
+        }
+        data_blocks -= 1;
+    }
+}
+
+/// Returns a random IV, which is never all-zero.
+fn random_iv() -> Result<[u8; IV_SIZE], SvsmError> {
+    let mut iv = [0u8; IV_SIZE];
+    while iv == [0; IV_SIZE] {
+        fill_random(&mut iv)?;
+    }
+    Ok(iv)
+}
+
+/// Returns the version of the state stored on `inner`, without authenticating
+/// it.
+///
+/// # Returns
+///
+/// The epoch of the newest superblock, or 0 if the device has not been
+/// formatted yet.
+pub fn state_version(inner: &dyn BlockDriver) -> Result<u64, SvsmError> {
+    if usize::from(inner.block_size_log2()) > PAGE_SHIFT {
+        return Err(SvsmError::NotSupported);
+    }
+
+    let mut newest: Option<SuperblockHeader> = None;
+    let mut buf = vec![0u8; CRYPT_BLOCK_SIZE];
+    for slot in 0..SB_SLOTS {
+        inner.read_blocks(
+            (slot * CRYPT_BLOCK_SIZE) >> inner.block_size_log2(),
+            &mut buf,
+        )?;
+        let (header, _) = SuperblockHeader::read_from_prefix(&buf).unwrap();
+        if header.magic == SB_MAGIC && newest.is_none_or(|cur| header.seq > cur.seq) {
+            newest = Some(header);
+        }
+    }
+
+    Ok(newest.map_or(0, |header| u64::from(header.epoch)))
+}
+
+/// An authenticated-encryption layer on top of another [`BlockDriver`].
+pub struct EncryptedBlockDevice {
+    inner: &'static dyn BlockDriver,
+    enc_key: Zeroizing<[u8; KEY_SIZE]>,
+    mac_key: Zeroizing<[u8; HMAC_SHA256_SIZE]>,
+    data_blocks: usize,
+    meta_blocks: usize,
+    pool_blocks: usize,
+    state: SpinLock<CryptState>,
+}
+
+impl core::fmt::Debug for EncryptedBlockDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EncryptedBlockDevice")
+            .field("data_blocks", &self.data_blocks)
+            .field("meta_blocks", &self.meta_blocks)
+            .field("pool_blocks", &self.pool_blocks)
+            .finish()
+    }
+}
+
+impl EncryptedBlockDevice {
+    /// Opens the encrypted device stored on `inner`, formatting it if it
+    /// does not contain one yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The untrusted backing device.
+    /// * `key` - Secret from which the encryption and authentication keys
+    ///   are derived.
+    /// * `state_version` - The version the stored state is known to have, as
+    ///   returned by [`state_version`] and verified externally, or `None` to
+    ///   skip the check.
+    ///
+    /// # Returns
+    ///
+    /// The opened device, `BlockDeviceError::Integrity` if the device holds
+    /// state which fails authentication with `key`,
+    /// `BlockDeviceError::Rollback` if the state does not have the expected
+    /// version, or another error if the backing device can not be used.
+    pub fn open(
+        inner: &'static dyn BlockDriver,
+        key: &[u8],
+        state_version: Option<u64>,
+    ) -> Result<Self, SvsmError> {
+        if usize::from(inner.block_size_log2()) > PAGE_SHIFT {
+            return Err(SvsmError::NotSupported);
+        }
+        let Layout {
+            data_blocks,
+            meta_blocks,
+            pool_blocks,
+        } = layout(inner.size()).ok_or(SvsmError::InvalidParameter)?;
+
+        let mut enc_key = Zeroizing::new([0u8; KEY_SIZE]);
+        HkdfSha256::derive(None, key, b"svsm block encryption", &mut *enc_key)?;
+        let mut mac_key = Zeroizing::new([0u8; HMAC_SHA256_SIZE]);
+        HkdfSha256::derive(None, key, b"svsm block authentication", &mut *mac_key)?;
+
+        let dev = Self {
+            inner,
+            enc_key,
+            mac_key,
+            data_blocks,
+            meta_blocks,
+            pool_blocks,
+            state: SpinLock::new(CryptState {
+                epoch: 0,
+                seq: 0,
+                sb_slot: SB_SLOTS - 1,
+                bitmap: vec![0; SB_BITMAP_SIZE],
+                meta: vec![0; meta_blocks * CRYPT_BLOCK_SIZE],
+                leaves: vec![[0; 32]; meta_blocks],
+                dirty: vec![false; meta_blocks],
+                in_use: vec![false; pool_blocks],
+                released: Vec::new(),
+                moved: vec![false; data_blocks],
+                next_free: 0,
+            }),
+        };
+
+        {
+            let mut state = dev.state.lock();
+            match dev.read_superblock()? {
+                Some((slot, header, bitmap)) => dev.load(&mut state, slot, &header, bitmap)?,
+                None => dev.format(&mut state)?,
+            }
+
+            if let Some(version) = state_version {
+                if version != u64::from(state.epoch) {
+                    log::error!(
+                        "Encrypted block device: stale state (version {}, expected {version})",
+                        state.epoch
+                    );
+                    return Err(SvsmError::Block(BlockDeviceError::Rollback));
+                }
+            }
+
+            state.epoch = state
+                .epoch
+                .checked_add(1)
+                .ok_or(SvsmError::Block(BlockDeviceError::Failed))?;
+            dev.commit(&mut state)?;
+        }
+
+        Ok(dev)
+    }
+
+    fn inner_block(&self, block: usize) -> usize {
+        (block * CRYPT_BLOCK_SIZE) >> self.inner.block_size_log2()
+    }
+
+    fn meta_location(&self, meta_block: usize, copy: usize) -> usize {
+        self.inner_block(SB_SLOTS + meta_block * META_COPIES + copy)
+    }
+
+    fn data_location(&self, location: u32) -> usize {
+        self.inner_block(SB_SLOTS + self.meta_blocks * META_COPIES + location as usize)
+    }
+
+    /// Reads both superblock slots and returns the newest authentic one.
+    ///
+    /// Falling back to the older slot is needed to recover from a commit
+    /// interrupted while writing the superblock, so it also lets the host roll
+    /// back the last commit. Both slots have the same epoch unless the last
+    /// commit was the one made by [`EncryptedBlockDevice::open`], see the
+    /// module documentation.
+    fn read_superblock(&self) -> Result<Option<(usize, SuperblockHeader, Vec<u8>)>, SvsmError> {
+        let mut found_magic = false;
+        let mut current: Option<(usize, SuperblockHeader, Vec<u8>)> = None;
+        let mut buf = vec![0u8; CRYPT_BLOCK_SIZE];
+
+        for slot in 0..SB_SLOTS {
+            self.inner.read_blocks(self.inner_block(slot), &mut buf)?;
+            let (header, _) = SuperblockHeader::read_from_prefix(&buf).unwrap();
+            if header.magic != SB_MAGIC {
+                continue;
+            }
+            found_magic = true;
+
+            if !HmacSha256::verify(&*self.mac_key, &buf[..SB_MAC_OFFSET], &buf[SB_MAC_OFFSET..])
+                || header.version != SB_VERSION
+            {
+                continue;
+            }
+            if current
+                .as_ref()
+                .is_none_or(|(_, cur, _)| header.seq > cur.seq)
+            {
+                let bitmap = buf[size_of::<SuperblockHeader>()..SB_MAC_OFFSET].to_vec();
+                current = Some((slot, header, bitmap));
+            }
+        }
+
+        if current.is_none() && found_magic {
+            log::error!("Encrypted block device: no authentic superblock found");
+            return Err(SvsmError::Block(BlockDeviceError::Integrity));
+        }
+
+        Ok(current)
+    }
+
+    fn load(
+        &self,
+        state: &mut CryptState,
+        slot: usize,
+        header: &SuperblockHeader,
+        bitmap: Vec<u8>,
+    ) -> Result<(), SvsmError> {
+        if header.data_blocks != self.data_blocks as u64
+            || header.meta_blocks != self.meta_blocks as u64
+        {
+            log::error!("Encrypted block device: geometry does not match the backing device");
+            return Err(SvsmError::InvalidFormat);
+        }
+
+        state.epoch = header.epoch;
+        state.seq = header.seq;
+        state.sb_slot = slot;
+        state.bitmap = bitmap;
+
+        for meta_block in 0..self.meta_blocks {
+            let copy = state.active_copy(meta_block);
+            let offset = meta_block * CRYPT_BLOCK_SIZE;
+            self.inner.read_blocks(
+                self.meta_location(meta_block, copy),
+                &mut state.meta[offset..offset + CRYPT_BLOCK_SIZE],
+            )?;
+            state.leaves[meta_block] = leaf_hash(meta_block, state.meta_block(meta_block));
+        }
+
+        if merkle_root(&state.leaves) != header.root {
+            log::error!("Encrypted block device: metadata does not match the Merkle root");
+            return Err(SvsmError::Block(BlockDeviceError::Integrity));
+        }
+
+        for block in 0..self.data_blocks {
+            let entry = state.entry(block);
+            if !entry.is_written() {
+                continue;
+            }
+            let location = entry.location as usize;
+            if location >= self.pool_blocks || state.in_use[location] {
+                log::error!("Encrypted block device: invalid location of block {block}");
+                return Err(SvsmError::Block(BlockDeviceError::Integrity));
+            }
+            state.in_use[location] = true;
+        }
+
+        Ok(())
+    }
+
+    fn format(&self, state: &mut CryptState) -> Result<(), SvsmError> {
+        log::info!(
+            "Encrypted block device: formatting {} data blocks",
+            self.data_blocks
+        );
+        // Mark every metadata block dirty so that all of them get written
+        // with the initial commit.
+        state.dirty.fill(true);
+        Ok(())
+    }
+
+    /// Writes all modified metadata blocks and a new superblock, which makes
+    /// all preceding writes visible atomically.
+    fn commit(&self, state: &mut CryptState) -> Result<(), SvsmError> {
+        let mut bitmap = state.bitmap.clone();
+        let mut leaves = state.leaves.clone();
+
+        for meta_block in (0..self.meta_blocks).filter(|m| state.dirty[*m]) {
+            let copy = state.active_copy(meta_block) ^ 1;
+            self.inner.write_blocks(
+                self.meta_location(meta_block, copy),
+                state.meta_block(meta_block),
+            )?;
+            bitmap[meta_block / 8] ^= 1 << (meta_block % 8);
+            leaves[meta_block] = leaf_hash(meta_block, state.meta_block(meta_block));
+        }
+        self.inner.flush()?;
+
+        let header = SuperblockHeader {
+            magic: SB_MAGIC,
+            version: SB_VERSION,
+            epoch: state.epoch,
+            seq: state.seq + 1,
+            data_blocks: self.data_blocks as u64,
+            meta_blocks: self.meta_blocks as u64,
+            root: merkle_root(&leaves),
+        };
+        let mut buf = vec![0u8; CRYPT_BLOCK_SIZE];
+        buf[..size_of::<SuperblockHeader>()].copy_from_slice(header.as_bytes());
+        buf[size_of::<SuperblockHeader>()..SB_MAC_OFFSET].copy_from_slice(&bitmap);
+        let mac = HmacSha256::mac(&*self.mac_key, &buf[..SB_MAC_OFFSET]);
+        buf[SB_MAC_OFFSET..].copy_from_slice(&mac);
+
+        let slot = state.sb_slot ^ 1;
+        self.inner.write_blocks(self.inner_block(slot), &buf)?;
+        self.inner.flush()?;
+
+        state.seq = header.seq;
+        state.sb_slot = slot;
+        state.bitmap = bitmap;
+        state.leaves = leaves;
+        state.dirty.fill(false);
+        state.moved.fill(false);
+        for location in core::mem::take(&mut state.released) {
+            state.in_use[location as usize] = false;
+        }
+
+        Ok(())
+    }
+
+    /// Returns a free block of the data pool, committing to release the
+    /// blocks replaced since the last commit if there is none.
+    fn allocate(&self, state: &mut CryptState) -> Result<u32, SvsmError> {
+        if let Some(location) = state.allocate() {
+            return Ok(location);
+        }
+        self.commit(state)?;
+        state
+            .allocate()
+            .ok_or(SvsmError::Block(BlockDeviceError::Failed))
+    }
+
+    fn check_range(&self, block_id: usize, len: usize) -> Result<(), SvsmError> {
+        let blocks = len / CRYPT_BLOCK_SIZE;
+        if len % CRYPT_BLOCK_SIZE != 0
+            || block_id
+                .checked_add(blocks)
+                .is_none_or(|end| end > self.data_blocks)
+        {
+            return Err(SvsmError::Block(BlockDeviceError::Failed));
+        }
+        Ok(())
+    }
+}
+
+impl BlockDriver for EncryptedBlockDevice {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), SvsmError> {
+        self.check_range(block_id, buf.len())?;
+        let state = self.state.lock();
+        let mut ciphertext = vec![0u8; CRYPT_BLOCK_SIZE + AUTHTAG_SIZE];
+
+        for (chunk, block) in buf.chunks_mut(CRYPT_BLOCK_SIZE).zip(block_id..) {
+            let entry = state.entry(block);
+            if !entry.is_written() {
+                chunk.fill(0);
+                continue;
+            }
+
+            self.inner.read_blocks(
+                self.data_location(entry.location),
+                &mut ciphertext[..CRYPT_BLOCK_SIZE],
+            )?;
+            ciphertext[CRYPT_BLOCK_SIZE..].copy_from_slice(&entry.tag);
+            Aes256Gcm::decrypt(
+                &entry.iv,
+                &self.enc_key,
+                &(block as u64).to_le_bytes(),
+                &ciphertext,
+                chunk,
+            )
+            .map_err(|_| SvsmError::Block(BlockDeviceError::Integrity))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), SvsmError> {
+        self.check_range(block_id, buf.len())?;
+        let mut state = self.state.lock();
+        let mut ciphertext = vec![0u8; CRYPT_BLOCK_SIZE + AUTHTAG_SIZE];
+
+        for (chunk, block) in buf.chunks(CRYPT_BLOCK_SIZE).zip(block_id..) {
+            let iv = random_iv()?;
+            Aes256Gcm::encrypt(
+                &iv,
+                &self.enc_key,
+                &(block as u64).to_le_bytes(),
+                chunk,
+                &mut ciphertext,
+            )?;
+
+            // Data which is part of the committed state is not overwritten,
+            // but moved to a free block.
+            let old = state.entry(block);
+            let moved = state.moved[block];
+            let location = if moved {
+                old.location
+            } else {
+                self.allocate(&mut state)?
+            };
+            if let Err(e) = self.inner.write_blocks(
+                self.data_location(location),
+                &ciphertext[..CRYPT_BLOCK_SIZE],
+            ) {
+                if !moved {
+                    state.in_use[location as usize] = false;
+                }
+                return Err(e);
+            }
+
+            if !moved {
+                if old.is_written() {
+                    state.released.push(old.location);
+                }
+                state.moved[block] = true;
+            }
+            let mut entry = MetaEntry {
+                iv,
+                tag: [0; AUTHTAG_SIZE],
+                location,
+            };
+            entry.tag.copy_from_slice(&ciphertext[CRYPT_BLOCK_SIZE..]);
+            state.set_entry(block, &entry);
+        }
+
+        Ok(())
+    }
+
+    fn block_size_log2(&self) -> u8 {
+        PAGE_SHIFT as u8
+    }
+
+    fn size(&self) -> usize {
+        self.data_blocks * CRYPT_BLOCK_SIZE
+    }
+
+    fn flush(&self) -> Result<(), SvsmError> {
+        let mut state = self.state.lock();
+        if state.dirty.iter().any(|d| *d) {
+            self.commit(&mut state)
+        } else {
+            self.inner.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::ramdisk::{pattern, ramdisk};
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+    const DISK_SIZE: usize = 1024 * 1024;
+
+    /// Returns the offset on the backing device of the data of `block`.
+    fn data_offset(dev: &EncryptedBlockDevice, block: usize) -> usize {
+        let location = dev.state.lock().entry(block).location;
+        dev.data_location(location) << dev.inner.block_size_log2()
+    }
+
+    #[test]
+    fn layout_fits() {
+        let layout = layout(DISK_SIZE).unwrap();
+        assert!(
+            (SB_SLOTS + layout.pool_blocks + META_COPIES * layout.meta_blocks) * CRYPT_BLOCK_SIZE
+                <= DISK_SIZE
+        );
+        assert!(layout.meta_blocks * META_ENTRIES_PER_BLOCK >= layout.data_blocks);
+        assert!(layout.pool_blocks > layout.data_blocks);
+        assert!(super::layout(CRYPT_BLOCK_SIZE * 2).is_none());
+    }
+
+    #[test]
+    fn roundtrip_and_reopen() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+
+        let mut buf = vec![0xffu8; CRYPT_BLOCK_SIZE];
+        dev.read_blocks(3, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        let data = pattern(2 * CRYPT_BLOCK_SIZE, 7);
+        dev.write_blocks(2, &data).unwrap();
+        let mut buf = vec![0u8; data.len()];
+        dev.read_blocks(2, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        dev.flush().unwrap();
+
+        // The plaintext must not show up on the backing device.
+        disk.with_data(|raw| {
+            assert!(!raw.windows(64).any(|w| w == &data[64..128]));
+        });
+
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        let mut buf = vec![0u8; data.len()];
+        dev.read_blocks(2, &mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn wrong_key() {
+        let disk = ramdisk(DISK_SIZE);
+        EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        assert!(matches!(
+            EncryptedBlockDevice::open(disk, b"another key", None),
+            Err(SvsmError::Block(BlockDeviceError::Integrity))
+        ));
+    }
+
+    #[test]
+    fn tampered_data() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        dev.write_blocks(0, &pattern(2 * CRYPT_BLOCK_SIZE, 3))
+            .unwrap();
+        dev.flush().unwrap();
+
+        let offset = data_offset(&dev, 1);
+        disk.with_data(|raw| raw[offset + 10] ^= 1);
+
+        let mut buf = vec![0u8; CRYPT_BLOCK_SIZE];
+        dev.read_blocks(0, &mut buf).unwrap();
+        assert!(matches!(
+            dev.read_blocks(1, &mut buf),
+            Err(SvsmError::Block(BlockDeviceError::Integrity))
+        ));
+    }
+
+    #[test]
+    fn replayed_data_block() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+
+        dev.write_blocks(5, &pattern(CRYPT_BLOCK_SIZE, 1)).unwrap();
+        dev.flush().unwrap();
+        let offset = data_offset(&dev, 5);
+        let old = disk.with_data(|raw| raw[offset..offset + CRYPT_BLOCK_SIZE].to_vec());
+
+        dev.write_blocks(5, &pattern(CRYPT_BLOCK_SIZE, 2)).unwrap();
+        dev.flush().unwrap();
+        let offset = data_offset(&dev, 5);
+        disk.with_data(|raw| raw[offset..offset + CRYPT_BLOCK_SIZE].copy_from_slice(&old));
+
+        let mut buf = vec![0u8; CRYPT_BLOCK_SIZE];
+        assert!(dev.read_blocks(5, &mut buf).is_err());
+    }
+
+    #[test]
+    fn replayed_metadata() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        dev.write_blocks(0, &pattern(2 * CRYPT_BLOCK_SIZE, 1))
+            .unwrap();
+        dev.flush().unwrap();
+        let snapshot = disk.with_data(|raw| raw.to_vec());
+
+        dev.write_blocks(0, &pattern(2 * CRYPT_BLOCK_SIZE, 2))
+            .unwrap();
+        dev.flush().unwrap();
+
+        // Roll back everything except the superblocks.
+        let sb_end = SB_SLOTS * CRYPT_BLOCK_SIZE;
+        disk.with_data(|raw| raw[sb_end..].copy_from_slice(&snapshot[sb_end..]));
+        assert!(matches!(
+            EncryptedBlockDevice::open(disk, KEY, None),
+            Err(SvsmError::Block(BlockDeviceError::Integrity))
+        ));
+    }
+
+    #[test]
+    fn uncommitted_writes_are_discarded() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        let committed = pattern(2 * CRYPT_BLOCK_SIZE, 1);
+        dev.write_blocks(0, &committed).unwrap();
+        dev.flush().unwrap();
+
+        // Write without flushing, as if the guest crashed before the commit.
+        dev.write_blocks(0, &pattern(CRYPT_BLOCK_SIZE, 2)).unwrap();
+        drop(dev);
+
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        let mut buf = vec![0u8; committed.len()];
+        dev.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(buf, committed);
+    }
+
+    #[test]
+    fn writes_beyond_spare_blocks() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        let blocks = dev.size() / CRYPT_BLOCK_SIZE;
+
+        for block in 0..blocks {
+            dev.write_blocks(block, &pattern(CRYPT_BLOCK_SIZE, 1 + block as u8))
+                .unwrap();
+        }
+        dev.flush().unwrap();
+
+        // Rewriting every block without a flush needs more free blocks than
+        // the spare ones, which forces intermediate commits.
+        for block in 0..blocks {
+            dev.write_blocks(block, &pattern(CRYPT_BLOCK_SIZE, 2 + block as u8))
+                .unwrap();
+        }
+        drop(dev);
+
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        let mut buf = vec![0u8; CRYPT_BLOCK_SIZE];
+        let mut committed = 0;
+        for block in 0..blocks {
+            dev.read_blocks(block, &mut buf).unwrap();
+            if buf == pattern(CRYPT_BLOCK_SIZE, 2 + block as u8) {
+                committed += 1;
+            } else {
+                assert_eq!(buf, pattern(CRYPT_BLOCK_SIZE, 1 + block as u8));
+            }
+        }
+        assert!(committed > 0);
+    }
+
+    #[test]
+    fn ivs_are_unique() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        dev.write_blocks(0, &pattern(CRYPT_BLOCK_SIZE, 1)).unwrap();
+        dev.flush().unwrap();
+        let snapshot = disk.with_data(|raw| raw.to_vec());
+        let iv = dev.state.lock().entry(0).iv;
+        drop(dev);
+
+        // Writing the same data again after the host restored the previous
+        // image must not reuse the IV.
+        disk.with_data(|raw| raw.copy_from_slice(&snapshot));
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        dev.write_blocks(0, &pattern(CRYPT_BLOCK_SIZE, 1)).unwrap();
+        assert_ne!(dev.state.lock().entry(0).iv, iv);
+    }
+
+    #[test]
+    fn state_version_is_checked() {
+        let disk = ramdisk(DISK_SIZE);
+        assert_eq!(state_version(disk).unwrap(), 0);
+        let dev = EncryptedBlockDevice::open(disk, KEY, Some(0)).unwrap();
+        dev.write_blocks(0, &pattern(2 * CRYPT_BLOCK_SIZE, 1))
+            .unwrap();
+        dev.flush().unwrap();
+        drop(dev);
+        let snapshot = disk.with_data(|raw| raw.to_vec());
+
+        let version = state_version(disk).unwrap();
+        assert_eq!(version, 1);
+        let dev = EncryptedBlockDevice::open(disk, KEY, Some(version)).unwrap();
+        dev.write_blocks(0, &pattern(2 * CRYPT_BLOCK_SIZE, 2))
+            .unwrap();
+        dev.flush().unwrap();
+        drop(dev);
+        assert_eq!(state_version(disk).unwrap(), 2);
+
+        // Replay the complete image from before the last boot.
+        disk.with_data(|raw| raw.copy_from_slice(&snapshot));
+        assert!(matches!(
+            EncryptedBlockDevice::open(disk, KEY, Some(2)),
+            Err(SvsmError::Block(BlockDeviceError::Rollback))
+        ));
+
+        // So is wiping the device.
+        disk.with_data(|raw| raw.fill(0));
+        assert!(matches!(
+            EncryptedBlockDevice::open(disk, KEY, Some(2)),
+            Err(SvsmError::Block(BlockDeviceError::Rollback))
+        ));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let disk = ramdisk(DISK_SIZE);
+        let dev = EncryptedBlockDevice::open(disk, KEY, None).unwrap();
+        let mut buf = vec![0u8; CRYPT_BLOCK_SIZE];
+        let last = dev.size() / CRYPT_BLOCK_SIZE;
+        assert!(dev.read_blocks(last - 1, &mut buf).is_ok());
+        assert!(dev.read_blocks(last, &mut buf).is_err());
+        assert!(dev.write_blocks(0, &buf[..512]).is_err());
+    }
+}
@@ -8,4 +8,8 @@
 pub enum BlockDeviceError {
     /// Generic error for all read and write operations on a block device.
     Failed,
+    /// Data read from the block device failed authentication.
+    Integrity,
+    /// The block device holds an older state than expected.
+    Rollback,
 }
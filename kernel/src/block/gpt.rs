@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! GUID Partition Table support.
+//!
+//! [`read_partition_table`] parses the GPT of a block device as specified in
+//! chapter 5 of the UEFI specification. The primary table is used if it is
+//! valid, the backup table at the end of the device otherwise. Each entry can
+//! then be exposed as a block device of its own through [`Partition`].
+
+extern crate alloc;
+
+use super::api::BlockDriver;
+use crate::block::BlockDeviceError;
+use crate::error::SvsmError;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use uuid::Uuid;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+const GPT_HEADER_LBA: u64 = 1;
+/// Size of the defined fields of the GPT header.
+const GPT_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = size_of::<GptEntry>();
+/// Upper bound for the size of the partition entry array, to limit the
+/// memory a malicious table can make the SVSM allocate.
+const GPT_MAX_ENTRIES_SIZE: usize = 1024 * 1024;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct GptHeader {
+    signature: [u8; 8],
+    revision: u32,
+    header_size: u32,
+    header_crc32: u32,
+    _reserved: u32,
+    my_lba: u64,
+    alternate_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    disk_guid: [u8; 16],
+    partition_entry_lba: u64,
+    num_partition_entries: u32,
+    size_of_partition_entry: u32,
+    partition_entry_array_crc32: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct GptEntry {
+    type_guid: [u8; 16],
+    unique_guid: [u8; 16],
+    starting_lba: u64,
+    ending_lba: u64,
+    attributes: u64,
+    name: [u16; 36],
+}
+
+/// CRC-32 as used by GPT (IEEE 802.3, reflected).
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// A partition described by a GPT entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// Partition type GUID.
+    pub type_guid: Uuid,
+    /// Unique partition GUID.
+    pub guid: Uuid,
+    /// First logical block of the partition.
+    pub first_lba: u64,
+    /// Last logical block of the partition, inclusive.
+    pub last_lba: u64,
+    /// Partition name.
+    pub label: String,
+}
+
+fn read_lbas(dev: &dyn BlockDriver, lba: u64, count: usize) -> Result<Vec<u8>, SvsmError> {
+    let lba = usize::try_from(lba).map_err(|_| SvsmError::InvalidFormat)?;
+    let mut buf = vec![0u8; count << dev.block_size_log2()];
+    dev.read_blocks(lba, &mut buf)?;
+    Ok(buf)
+}
+
+/// Reads and validates the GPT header at `lba`.
+///
+/// # Returns
+///
+/// `Ok(None)` if there is no GPT header at `lba`, the used partition entries
+/// on success, or `SvsmError::InvalidFormat` if the header
+/// or the entries are damaged.
+fn read_table(dev: &dyn BlockDriver, lba: u64) -> Result<Option<Vec<PartitionInfo>>, SvsmError> {
+    let lba_size = 1usize << dev.block_size_log2();
+    let lbas = (dev.size() / lba_size) as u64;
+
+    let mut buf = read_lbas(dev, lba, 1)?;
+    let (header, _) = GptHeader::read_from_prefix(&buf).map_err(|_| SvsmError::InvalidFormat)?;
+    if header.signature != GPT_SIGNATURE {
+        return Ok(None);
+    }
+
+    let header_size = header.header_size as usize;
+    if header_size < GPT_HEADER_SIZE || header_size > lba_size {
+        return Err(SvsmError::InvalidFormat);
+    }
+    buf[16..20].fill(0);
+    if crc32(&buf[..header_size]) != header.header_crc32 || header.my_lba != lba {
+        return Err(SvsmError::InvalidFormat);
+    }
+
+    let entry_size = header.size_of_partition_entry as usize;
+    let entries_size = (header.num_partition_entries as usize)
+        .checked_mul(entry_size)
+        .filter(|size| *size <= GPT_MAX_ENTRIES_SIZE)
+        .ok_or(SvsmError::InvalidFormat)?;
+    let entries_lbas = entries_size.div_ceil(lba_size);
+    if entry_size < GPT_MIN_ENTRY_SIZE
+        || !entry_size.is_power_of_two()
+        || header.first_usable_lba > header.last_usable_lba
+        || header.last_usable_lba >= lbas
+        || header
+            .partition_entry_lba
+            .checked_add(entries_lbas as u64)
+            .is_none_or(|end| end > lbas)
+    {
+        return Err(SvsmError::InvalidFormat);
+    }
+
+    let buf = read_lbas(dev, header.partition_entry_lba, entries_lbas)?;
+    let entries = &buf[..entries_size];
+    if crc32(entries) != header.partition_entry_array_crc32 {
+        return Err(SvsmError::InvalidFormat);
+    }
+
+    let mut partitions = Vec::new();
+    for raw in entries.chunks_exact(entry_size) {
+        let (entry, _) = GptEntry::read_from_prefix(raw).unwrap();
+        if entry.type_guid == [0; 16] {
+            continue;
+        }
+        if entry.starting_lba > entry.ending_lba
+            || entry.starting_lba < header.first_usable_lba
+            || entry.ending_lba > header.last_usable_lba
+        {
+            return Err(SvsmError::InvalidFormat);
+        }
+
+        let name = entry.name;
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        let label = char::decode_utf16(name[..len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        partitions.push(PartitionInfo {
+            type_guid: Uuid::from_bytes_le(entry.type_guid),
+            guid: Uuid::from_bytes_le(entry.unique_guid),
+            first_lba: entry.starting_lba,
+            last_lba: entry.ending_lba,
+            label,
+        });
+    }
+
+    Ok(Some(partitions))
+}
+
+/// Reads the partition table of a block device.
+///
+/// # Returns
+///
+/// `Ok(None)` if the device has no GPT, the used partition entries on
+/// success, or `SvsmError::InvalidFormat` if neither the primary nor the
+/// backup table is valid.
+pub fn read_partition_table(
+    dev: &dyn BlockDriver,
+) -> Result<Option<Vec<PartitionInfo>>, SvsmError> {
+    let lbas = (dev.size() >> dev.block_size_log2()) as u64;
+    if lbas <= GPT_HEADER_LBA {
+        return Ok(None);
+    }
+
+    if let Ok(table) = read_table(dev, GPT_HEADER_LBA) {
+        return Ok(table);
+    }
+
+    log::warn!("Primary GPT is damaged, trying the backup table");
+    read_table(dev, lbas - 1)?
+        .ok_or(SvsmError::InvalidFormat)
+        .map(Some)
+}
+
+/// A partition of a block device.
+pub struct Partition {
+    dev: &'static dyn BlockDriver,
+    info: PartitionInfo,
+}
+
+impl core::fmt::Debug for Partition {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Partition")
+            .field("info", &self.info)
+            .finish()
+    }
+}
+
+impl Partition {
+    /// Creates the block device for a partition of `dev`.
+    ///
+    /// # Returns
+    ///
+    /// The partition, or `SvsmError::InvalidParameter` if it exceeds the
+    /// device.
+    pub fn new(dev: &'static dyn BlockDriver, info: PartitionInfo) -> Result<Self, SvsmError> {
+        let lbas = (dev.size() >> dev.block_size_log2()) as u64;
+        if info.first_lba > info.last_lba || info.last_lba >= lbas {
+            return Err(SvsmError::InvalidParameter);
+        }
+        Ok(Self { dev, info })
+    }
+
+    /// Returns the unique GUID of the partition.
+    pub fn guid(&self) -> Uuid {
+        self.info.guid
+    }
+
+    /// Returns the partition type GUID.
+    pub fn type_guid(&self) -> Uuid {
+        self.info.type_guid
+    }
+
+    /// Returns the name of the partition.
+    pub fn label(&self) -> &str {
+        &self.info.label
+    }
+
+    fn blocks(&self) -> usize {
+        (self.info.last_lba - self.info.first_lba + 1) as usize
+    }
+
+    /// Translates a block range of the partition to the device.
+    fn map(&self, block_id: usize, len: usize) -> Result<usize, SvsmError> {
+        let count = len.div_ceil(1 << self.block_size_log2());
+        match block_id.checked_add(count) {
+            Some(end) if end <= self.blocks() => Ok(self.info.first_lba as usize + block_id),
+            _ => Err(SvsmError::Block(BlockDeviceError::Failed)),
+        }
+    }
+}
+
+impl BlockDriver for Partition {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), SvsmError> {
+        self.dev.read_blocks(self.map(block_id, buf.len())?, buf)
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), SvsmError> {
+        self.dev.write_blocks(self.map(block_id, buf.len())?, buf)
+    }
+
+    fn block_size_log2(&self) -> u8 {
+        self.dev.block_size_log2()
+    }
+
+    fn size(&self) -> usize {
+        self.blocks() << self.block_size_log2()
+    }
+
+    fn flush(&self) -> Result<(), SvsmError> {
+        self.dev.flush()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::block::ramdisk::RamDisk;
+    use alloc::boxed::Box;
+    use uuid::uuid;
+
+    const LBA_SIZE: usize = 512;
+    const ENTRIES: usize = 128;
+    const ENTRIES_LBAS: usize = ENTRIES * GPT_MIN_ENTRY_SIZE / LBA_SIZE;
+
+    pub const LINUX_DATA: Uuid = uuid!("0fc63daf-8483-4772-8e79-3d69d8477de4");
+
+    fn write_header(disk: &mut [u8], lba: u64, alternate: u64, entries: u64, crc: u32) {
+        let lbas = (disk.len() / LBA_SIZE) as u64;
+        let mut header = GptHeader {
+            signature: GPT_SIGNATURE,
+            revision: 0x10000,
+            header_size: GPT_HEADER_SIZE as u32,
+            header_crc32: 0,
+            _reserved: 0,
+            my_lba: lba,
+            alternate_lba: alternate,
+            first_usable_lba: 2 + ENTRIES_LBAS as u64,
+            last_usable_lba: lbas - 2 - ENTRIES_LBAS as u64,
+            disk_guid: [0x11; 16],
+            partition_entry_lba: entries,
+            num_partition_entries: ENTRIES as u32,
+            size_of_partition_entry: GPT_MIN_ENTRY_SIZE as u32,
+            partition_entry_array_crc32: crc,
+            _padding: 0,
+        };
+        header.header_crc32 = crc32(&header.as_bytes()[..GPT_HEADER_SIZE]);
+        let offset = lba as usize * LBA_SIZE;
+        disk[offset..offset + size_of::<GptHeader>()].copy_from_slice(header.as_bytes());
+    }
+
+    /// Formats a RAM disk with a GPT containing the given partitions.
+    pub fn gpt_disk(size: usize, partitions: &[(Uuid, u64, u64, &str)]) -> &'static RamDisk {
+        let disk = Box::leak(Box::new(RamDisk::new(size)));
+        let lbas = (size / LBA_SIZE) as u64;
+
+        let mut entries = vec![0u8; ENTRIES * GPT_MIN_ENTRY_SIZE];
+        for (raw, (guid, first, last, label)) in
+            entries.chunks_exact_mut(GPT_MIN_ENTRY_SIZE).zip(partitions)
+        {
+            let mut name = [0u16; 36];
+            for (c, l) in name.iter_mut().zip(label.encode_utf16()) {
+                *c = l;
+            }
+            let entry = GptEntry {
+                type_guid: LINUX_DATA.to_bytes_le(),
+                unique_guid: guid.to_bytes_le(),
+                starting_lba: *first,
+                ending_lba: *last,
+                attributes: 0,
+                name,
+            };
+            raw.copy_from_slice(entry.as_bytes());
+        }
+        let crc = crc32(&entries);
+        let backup_entries = lbas - 1 - ENTRIES_LBAS as u64;
+
+        disk.with_data(|raw| {
+            raw[2 * LBA_SIZE..][..entries.len()].copy_from_slice(&entries);
+            raw[backup_entries as usize * LBA_SIZE..][..entries.len()].copy_from_slice(&entries);
+            write_header(raw, 1, lbas - 1, 2, crc);
+            write_header(raw, lbas - 1, 1, backup_entries, crc);
+        });
+
+        disk
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn parse_table() {
+        let a = uuid!("b6e5c5a0-43d4-4d3e-9f3c-6a3f0e7f1d01");
+        let b = uuid!("b6e5c5a0-43d4-4d3e-9f3c-6a3f0e7f1d02");
+        let disk = gpt_disk(
+            1024 * 1024,
+            &[(a, 64, 127, "first"), (b, 128, 1023, "second")],
+        );
+
+        let table = read_partition_table(disk).unwrap().unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table[0].guid, a);
+        assert_eq!(table[0].type_guid, LINUX_DATA);
+        assert_eq!(table[0].label, "first");
+        assert_eq!((table[1].first_lba, table[1].last_lba), (128, 1023));
+
+        // Damaging the primary header makes the parser use the backup.
+        disk.with_data(|raw| raw[LBA_SIZE + 24] ^= 1);
+        assert_eq!(read_partition_table(disk).unwrap().unwrap(), table);
+
+        disk.with_data(|raw| {
+            let len = raw.len();
+            raw[len - LBA_SIZE + 24] ^= 1;
+        });
+        assert!(matches!(
+            read_partition_table(disk),
+            Err(SvsmError::InvalidFormat)
+        ));
+
+        let blank = RamDisk::new(64 * 1024);
+        assert!(read_partition_table(&blank).unwrap().is_none());
+    }
+
+    #[test]
+    fn partition_bounds() {
+        let guid = uuid!("b6e5c5a0-43d4-4d3e-9f3c-6a3f0e7f1d01");
+        let disk = gpt_disk(1024 * 1024, &[(guid, 64, 127, "data")]);
+        let info = read_partition_table(disk).unwrap().unwrap().remove(0);
+        let part = Partition::new(disk, info).unwrap();
+        assert_eq!(part.size(), 64 * LBA_SIZE);
+
+        part.write_blocks(63, &[0xaa; LBA_SIZE]).unwrap();
+        assert!(part.write_blocks(63, &[0xaa; 2 * LBA_SIZE]).is_err());
+        assert!(part.read_blocks(64, &mut [0; LBA_SIZE]).is_err());
+        disk.with_data(|raw| assert_eq!(raw[127 * LBA_SIZE], 0xaa));
+    }
+}
@@ -5,15 +5,124 @@
 // Author: Oliver Steffen <osteffen@redhat.com>
 
 pub mod api;
+pub mod encrypted;
 pub mod error;
+pub mod gpt;
+pub mod nvstore;
+#[cfg(test)]
+pub mod ramdisk;
+pub mod registry;
 #[cfg(feature = "virtio-drivers")]
 pub mod virtio_blk;
 
 pub use error::BlockDeviceError;
 
 extern crate alloc;
+use crate::block::encrypted::EncryptedBlockDevice;
+use crate::block::registry::{block_registry, find_partition_by_label, initialize_block_registry};
+use crate::crypto::keys::{KeyId, get_key, verified_state_version};
+use crate::error::SvsmError;
 use crate::{block::api::BlockDriver, utils::immut_after_init::ImmutAfterInitCell};
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
-// Currently only one block device is supported.
-pub static BLOCK_DEVICE: ImmutAfterInitCell<Box<dyn BlockDriver>> = ImmutAfterInitCell::uninit();
+/// Label of the partition holding the persistent state of the SVSM.
+pub const STATE_PARTITION_LABEL: &str = "svsm-state";
+
+/// Block device holding the persistent state of the SVSM.
+pub static BLOCK_DEVICE: ImmutAfterInitCell<&'static dyn BlockDriver> =
+    ImmutAfterInitCell::uninit();
+
+/// Registers the block devices found during boot and selects the one holding
+/// the persistent state: the partition labelled [`STATE_PARTITION_LABEL`] or,
+/// if there is none, the first device without a partition table.
+///
+/// # Returns
+///
+/// `Ok(())` on success, or an error if the devices could not be probed or
+/// the block devices have already been initialized.
+pub fn initialize_block_devices(devices: Vec<Box<dyn BlockDriver>>) -> Result<(), SvsmError> {
+    initialize_block_registry(devices)?;
+
+    let state_device = find_partition_by_label(STATE_PARTITION_LABEL).or_else(|| {
+        block_registry().and_then(|registry| registry.unpartitioned().first().copied())
+    });
+    match state_device {
+        Some(dev) => BLOCK_DEVICE.init(dev)?,
+        None => log::info!("No block device for persistent state found"),
+    }
+
+    Ok(())
+}
+
+/// Encrypted view of [`BLOCK_DEVICE`], used by all services persisting state.
+static SECURE_BLOCK_DEVICE: ImmutAfterInitCell<EncryptedBlockDevice> = ImmutAfterInitCell::uninit();
+
+/// Returns the version of the state on the global block device, which is
+/// offered to the attestation server for rollback protection.
+///
+/// # Returns
+///
+/// The unauthenticated version of the state, or `None` if there is no block
+/// device.
+pub fn secure_storage_version() -> Result<Option<u64>, SvsmError> {
+    let Ok(dev) = BLOCK_DEVICE.try_get_inner() else {
+        return Ok(None);
+    };
+    encrypted::state_version(*dev).map(Some)
+}
+
+/// Sets up the encrypted layer on top of the global block device, using the
+/// [`KeyId::Storage`] key.
+///
+/// If the attestation server verified the version of the stored state, the
+/// state is only accepted if it has this version, so that the host can not
+/// roll it back.
+///
+/// # Returns
+///
+/// `Ok(())` on success or if there is no block device or no provisioned key,
+/// or an error if the encrypted device can not be opened.
+pub fn initialize_secure_storage() -> Result<(), SvsmError> {
+    let Ok(dev) = BLOCK_DEVICE.try_get_inner() else {
+        return Ok(());
+    };
+    let Some(key) = get_key(KeyId::Storage) else {
+        log::warn!("No storage key provisioned, persistent storage is not available");
+        return Ok(());
+    };
+
+    let state_version = verified_state_version();
+    if state_version.is_none() {
+        log::warn!("Persistent storage is not protected against rollback");
+    }
+
+    SECURE_BLOCK_DEVICE.init(EncryptedBlockDevice::open(*dev, &*key, state_version)?)?;
+    Ok(())
+}
+
+/// Returns the encrypted block device for persistent state, or `None` if
+/// there is no block device or no storage key has been provided.
+pub fn secure_storage() -> Option<&'static dyn BlockDriver> {
+    SECURE_BLOCK_DEVICE
+        .try_get_inner()
+        .ok()
+        .map(|dev| dev as &dyn BlockDriver)
+}
+
+/// Byte offset of the [`secure_storage`] region holding the vTPM NV image.
+pub const VTPM_NV_REGION_OFFSET: usize = 0;
+/// Size of the [`secure_storage`] region holding the vTPM NV image.
+pub const VTPM_NV_REGION_SIZE: usize = 1024 * 1024;
+
+/// Byte offset of the [`secure_storage`] region holding the UEFI variable store.
+pub const UEFI_VARS_REGION_OFFSET: usize = VTPM_NV_REGION_OFFSET + VTPM_NV_REGION_SIZE;
+/// Size of the [`secure_storage`] region holding the UEFI variable store.
+pub const UEFI_VARS_REGION_SIZE: usize = 2 * 1024 * 1024;
+
+/// Byte offset of the [`secure_storage`] region holding the persistent
+/// filesystem mounted at `/var`.
+pub const DATA_FS_REGION_OFFSET: usize = UEFI_VARS_REGION_OFFSET + UEFI_VARS_REGION_SIZE;
+/// Size of the [`secure_storage`] region holding the persistent filesystem.
+/// The region ends early if the device is smaller.
+pub const DATA_FS_REGION_SIZE: usize = 16 * 1024 * 1024;
@@ -0,0 +1,390 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Crash-consistent storage of a single binary image on a block device.
+//!
+//! An [`NvStore`] owns a fixed byte range of a [`BlockDriver`], which is
+//! split into two equally sized slots. Each slot starts with one block
+//! holding an [`NvStoreHeader`], followed by the image data. Updates are
+//! always written to the slot which does not hold the current image and carry
+//! an incremented generation counter. A write which is interrupted half-way
+//! leaves a slot with a mismatching digest behind, so the previous image
+//! stays the valid one until the new slot has been written completely.
+
+extern crate alloc;
+
+use super::api::BlockDriver;
+use crate::error::SvsmError;
+use crate::utils::align_up;
+use alloc::vec;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+const NVSTORE_MAGIC: [u8; 8] = *b"SVSMNVS\0";
+const NVSTORE_VERSION: u32 = 1;
+
+/// On-disk header at the start of each slot.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct NvStoreHeader {
+    magic: [u8; 8],
+    version: u32,
+    _reserved: u32,
+    /// Incremented on every update; the valid slot with the highest
+    /// generation holds the current image.
+    generation: u64,
+    /// Length of the image in bytes.
+    length: u64,
+    /// SHA-256 over the header (with this field zeroed) and the image.
+    digest: [u8; 32],
+}
+
+impl NvStoreHeader {
+    fn new(generation: u64, data: &[u8]) -> Self {
+        let mut header = Self {
+            magic: NVSTORE_MAGIC,
+            version: NVSTORE_VERSION,
+            _reserved: 0,
+            generation,
+            length: data.len() as u64,
+            digest: [0; 32],
+        };
+        header.digest = header.compute_digest(data);
+        header
+    }
+
+    fn compute_digest(&self, data: &[u8]) -> [u8; 32] {
+        let mut header = *self;
+        header.digest = [0; 32];
+        let mut hasher = Sha256::new();
+        hasher.update(header.as_bytes());
+        hasher.update(data);
+        hasher.finalize().into()
+    }
+
+    fn is_valid(&self, data: &[u8]) -> bool {
+        self.digest == self.compute_digest(data)
+    }
+}
+
+/// A double-buffered, generation-counted image store in a fixed region of a
+/// block device.
+#[derive(Clone)]
+pub struct NvStore {
+    dev: &'static dyn BlockDriver,
+    /// First block of the region.
+    start_block: usize,
+    /// Size of one slot in blocks, including the header block.
+    slot_blocks: usize,
+    /// Generation of the current image, 0 if there is none.
+    generation: u64,
+    /// Index of the slot holding the current image.
+    active: Option<usize>,
+}
+
+impl core::fmt::Debug for NvStore {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NvStore")
+            .field("start_block", &self.start_block)
+            .field("slot_blocks", &self.slot_blocks)
+            .field("generation", &self.generation)
+            .field("active", &self.active)
+            .finish()
+    }
+}
+
+impl NvStore {
+    /// Opens the store located at `offset` on `dev`, spanning `size` bytes,
+    /// and determines which of the two slots holds the current image.
+    ///
+    /// # Arguments
+    ///
+    /// * `dev` - The block device backing the store.
+    /// * `offset` - Byte offset of the region, must be block aligned.
+    /// * `size` - Size of the region in bytes.
+    ///
+    /// # Returns
+    ///
+    /// The opened store on success, or `SvsmError::InvalidParameter` if the
+    /// region is misaligned, too small or does not fit on the device.
+    pub fn open(
+        dev: &'static dyn BlockDriver,
+        offset: usize,
+        size: usize,
+    ) -> Result<Self, SvsmError> {
+        let block_size = 1usize << dev.block_size_log2();
+        let end = offset
+            .checked_add(size)
+            .ok_or(SvsmError::InvalidParameter)?;
+        if offset % block_size != 0 || end > dev.size() {
+            return Err(SvsmError::InvalidParameter);
+        }
+
+        let slot_blocks = (size / block_size) / 2;
+        if slot_blocks < 2 {
+            return Err(SvsmError::InvalidParameter);
+        }
+
+        let mut store = Self {
+            dev,
+            start_block: offset / block_size,
+            slot_blocks,
+            generation: 0,
+            active: None,
+        };
+
+        for slot in 0..2 {
+            if let Some(header) = store.read_slot(slot)?.map(|(header, _)| header) {
+                if store.active.is_none() || header.generation > store.generation {
+                    store.generation = header.generation;
+                    store.active = Some(slot);
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn block_size(&self) -> usize {
+        1usize << self.dev.block_size_log2()
+    }
+
+    /// Returns the maximum size of an image which fits into the store.
+    pub fn capacity(&self) -> usize {
+        (self.slot_blocks - 1) * self.block_size()
+    }
+
+    /// Returns the generation of the current image, or 0 if the store is
+    /// empty.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Reads and validates one slot. Returns `None` if the slot does not
+    /// contain a consistent image. Blocks failing authentication on an
+    /// integrity-protected device are reported as an error, as such a device
+    /// never exposes the result of an interrupted write.
+    fn read_slot(&self, slot: usize) -> Result<Option<(NvStoreHeader, Vec<u8>)>, SvsmError> {
+        let block_size = self.block_size();
+        let first_block = self.start_block + slot * self.slot_blocks;
+
+        let mut buf = vec![0u8; block_size];
+        self.dev.read_blocks(first_block, &mut buf)?;
+        let Ok((header, _)) = NvStoreHeader::read_from_prefix(&buf) else {
+            return Ok(None);
+        };
+
+        if header.magic != NVSTORE_MAGIC || header.version != NVSTORE_VERSION {
+            return Ok(None);
+        }
+        let Ok(length) = usize::try_from(header.length) else {
+            return Ok(None);
+        };
+        if length > self.capacity() {
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; align_up(length, block_size)];
+        if !data.is_empty() {
+            self.dev.read_blocks(first_block + 1, &mut data)?;
+        }
+        data.truncate(length);
+
+        if !header.is_valid(&data) {
+            return Ok(None);
+        }
+
+        Ok(Some((header, data)))
+    }
+
+    /// Loads the current image.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Some(image))` if the store holds a valid image, `Ok(None)` if it
+    /// is empty, or an error if the device could not be read.
+    pub fn load(&self) -> Result<Option<Vec<u8>>, SvsmError> {
+        let Some(slot) = self.active else {
+            return Ok(None);
+        };
+
+        match self.read_slot(slot)? {
+            Some((header, data)) if header.generation == self.generation => Ok(Some(data)),
+            _ => {
+                log::error!("NvStore: slot {slot} changed underneath the store");
+                Err(SvsmError::InvalidFormat)
+            }
+        }
+    }
+
+    /// Stores `data` as the new current image.
+    ///
+    /// The image is written to the inactive slot, and the device is flushed
+    /// before the new slot is considered current.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, `SvsmError::InvalidParameter` if `data` exceeds
+    /// the capacity of the store, or the error reported by the device.
+    pub fn store(&mut self, data: &[u8]) -> Result<(), SvsmError> {
+        if data.len() > self.capacity() {
+            return Err(SvsmError::InvalidParameter);
+        }
+
+        let block_size = self.block_size();
+        let slot = self.active.map_or(0, |active| active ^ 1);
+        let generation = self.generation + 1;
+        let header = NvStoreHeader::new(generation, data);
+
+        let mut buf = vec![0u8; block_size + align_up(data.len(), block_size)];
+        let header = header.as_bytes();
+        buf[..header.len()].copy_from_slice(header);
+        buf[block_size..block_size + data.len()].copy_from_slice(data);
+
+        self.dev
+            .write_blocks(self.start_block + slot * self.slot_blocks, &buf)?;
+        self.dev.flush()?;
+
+        self.generation = generation;
+        self.active = Some(slot);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::ramdisk::ramdisk;
+    use alloc::boxed::Box;
+
+    const REGION_OFFSET: usize = 4096;
+    const REGION_SIZE: usize = 8192;
+
+    const DISK_SIZE: usize = 16384;
+
+    #[test]
+    fn empty_store() {
+        let disk = ramdisk(DISK_SIZE);
+        let store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        assert_eq!(store.capacity(), 4096 - 512);
+        assert_eq!(store.generation(), 0);
+        assert!(store.load().unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_region() {
+        let disk = ramdisk(DISK_SIZE);
+        assert!(NvStore::open(disk, 100, REGION_SIZE).is_err());
+        assert!(NvStore::open(disk, REGION_OFFSET, 16384).is_err());
+        assert!(NvStore::open(disk, REGION_OFFSET, 1024).is_err());
+    }
+
+    #[test]
+    fn store_and_reload() {
+        let disk = ramdisk(DISK_SIZE);
+        let mut store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        store.store(b"first").unwrap();
+        store.store(b"second image").unwrap();
+        assert_eq!(store.load().unwrap().unwrap(), b"second image");
+
+        let store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        assert_eq!(store.generation(), 2);
+        assert_eq!(store.load().unwrap().unwrap(), b"second image");
+
+        // Nothing outside of the region must be touched.
+        disk.with_data(|data| {
+            assert!(data[..REGION_OFFSET].iter().all(|b| *b == 0));
+            assert!(data[REGION_OFFSET + REGION_SIZE..].iter().all(|b| *b == 0));
+        });
+    }
+
+    #[test]
+    fn too_large() {
+        let disk = ramdisk(DISK_SIZE);
+        let mut store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        let data = vec![0xaau8; store.capacity() + 1];
+        assert!(store.store(&data).is_err());
+        assert!(store.store(&data[1..]).is_ok());
+    }
+
+    #[test]
+    fn torn_write_keeps_previous_image() {
+        let disk = ramdisk(DISK_SIZE);
+        let mut store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        store.store(b"old").unwrap();
+        store.store(b"new").unwrap();
+
+        // Corrupt the data of the slot holding the newest image, as if the
+        // write had not completed.
+        let slot = store.active.unwrap();
+        let data_offset = REGION_OFFSET + slot * REGION_SIZE / 2 + 512;
+        disk.with_data(|data| data[data_offset] ^= 0xff);
+
+        let store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        assert_eq!(store.generation(), 1);
+        assert_eq!(store.load().unwrap().unwrap(), b"old");
+    }
+
+    #[test]
+    fn update_after_torn_write() {
+        let disk = ramdisk(DISK_SIZE);
+        let mut store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        store.store(b"one").unwrap();
+        store.store(b"two").unwrap();
+        disk.with_data(|data| data[REGION_OFFSET + REGION_SIZE / 2] = 0);
+
+        let mut store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        store.store(b"three").unwrap();
+        let store = NvStore::open(disk, REGION_OFFSET, REGION_SIZE).unwrap();
+        assert_eq!(store.generation(), 2);
+        assert_eq!(store.load().unwrap().unwrap(), b"three");
+    }
+
+    #[test]
+    fn uncommitted_write_on_encrypted_device() {
+        use crate::block::encrypted::EncryptedBlockDevice;
+
+        let disk = ramdisk(1024 * 1024);
+        let key = b"nvstore test key";
+        let dev = Box::leak(Box::new(
+            EncryptedBlockDevice::open(disk, key, None).unwrap(),
+        ));
+        let mut store = NvStore::open(dev, 0, 8 * 4096).unwrap();
+        store.store(b"committed").unwrap();
+
+        // Overwrite the inactive slot without committing it, as if the CVM
+        // crashed in the middle of the next update.
+        dev.write_blocks(4, &[0x55u8; 8192]).unwrap();
+
+        let dev = Box::leak(Box::new(
+            EncryptedBlockDevice::open(disk, key, None).unwrap(),
+        ));
+        let store = NvStore::open(dev, 0, 8 * 4096).unwrap();
+        assert_eq!(store.load().unwrap().unwrap(), b"committed");
+    }
+
+    #[test]
+    fn tampered_encrypted_device() {
+        use crate::block::BlockDeviceError;
+        use crate::block::encrypted::EncryptedBlockDevice;
+
+        let disk = ramdisk(1024 * 1024);
+        let key = b"nvstore test key";
+        let dev = Box::leak(Box::new(
+            EncryptedBlockDevice::open(disk, key, None).unwrap(),
+        ));
+        let mut store = NvStore::open(dev, 0, 8 * 4096).unwrap();
+        store.store(b"committed").unwrap();
+
+        // Corrupt everything but the superblocks of the encrypted device,
+        // whose metadata stays cached. The failing authentication of the
+        // data must be reported instead of treating the slots as empty.
+        disk.with_data(|raw| raw[2 * 4096..].iter_mut().for_each(|b| *b ^= 0xff));
+        assert!(matches!(
+            NvStore::open(dev, 0, 8 * 4096),
+            Err(SvsmError::Block(BlockDeviceError::Integrity))
+        ));
+    }
+}
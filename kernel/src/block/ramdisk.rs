@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Memory backed block device used to unit-test users of [`BlockDriver`].
+
+extern crate alloc;
+
+use super::api::BlockDriver;
+use crate::block::BlockDeviceError;
+use crate::error::SvsmError;
+use crate::locking::SpinLock;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const RAMDISK_BLOCK_SIZE_LOG2: u8 = 9;
+
+#[derive(Debug)]
+pub struct RamDisk {
+    data: SpinLock<Vec<u8>>,
+}
+
+impl RamDisk {
+    /// Creates a zero-filled RAM disk of `size` bytes.
+    pub fn new(size: usize) -> Self {
+        Self {
+            data: SpinLock::new(vec![0u8; size]),
+        }
+    }
+
+    /// Runs `f` with mutable access to the raw disk contents, e.g. to
+    /// simulate corruption or a torn write.
+    pub fn with_data<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        f(&mut self.data.lock())
+    }
+
+    fn range(&self, block_id: usize, len: usize) -> Result<core::ops::Range<usize>, SvsmError> {
+        let start = block_id << RAMDISK_BLOCK_SIZE_LOG2;
+        let end = start
+            .checked_add(len)
+            .ok_or(SvsmError::Block(BlockDeviceError::Failed))?;
+        if end > self.size() || len & ((1 << RAMDISK_BLOCK_SIZE_LOG2) - 1) != 0 {
+            return Err(SvsmError::Block(BlockDeviceError::Failed));
+        }
+        Ok(start..end)
+    }
+}
+
+impl BlockDriver for RamDisk {
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> Result<(), SvsmError> {
+        let range = self.range(block_id, buf.len())?;
+        buf.copy_from_slice(&self.data.lock()[range]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> Result<(), SvsmError> {
+        let range = self.range(block_id, buf.len())?;
+        self.data.lock()[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn block_size_log2(&self) -> u8 {
+        RAMDISK_BLOCK_SIZE_LOG2
+    }
+
+    fn size(&self) -> usize {
+        self.data.lock().len()
+    }
+
+    fn flush(&self) -> Result<(), SvsmError> {
+        Ok(())
+    }
+}
+
+/// Creates a zero-filled RAM disk of `size` bytes which lives for the rest
+/// of the test, as the block device users expect a `'static` device.
+pub fn ramdisk(size: usize) -> &'static RamDisk {
+    Box::leak(Box::new(RamDisk::new(size)))
+}
+
+/// Returns `len` bytes of test data, which differs for every `seed`.
+pub fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(seed)).collect()
+}
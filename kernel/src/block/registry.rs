@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Registry of the block devices available to the SVSM.
+//!
+//! All block devices found during boot are registered once, together with
+//! the partitions of those with a GUID Partition Table. Partitions can then
+//! be looked up by their unique GUID or their label.
+
+extern crate alloc;
+
+use super::api::BlockDriver;
+use super::gpt::{Partition, read_partition_table};
+use crate::error::SvsmError;
+use crate::utils::immut_after_init::ImmutAfterInitCell;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use uuid::Uuid;
+
+/// The registered block devices and their partitions.
+#[derive(Default)]
+pub struct BlockRegistry {
+    /// Devices without a partition table.
+    unpartitioned: Vec<&'static dyn BlockDriver>,
+    partitions: Vec<Partition>,
+}
+
+impl core::fmt::Debug for BlockRegistry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BlockRegistry")
+            .field("unpartitioned", &self.unpartitioned.len())
+            .field("partitions", &self.partitions)
+            .finish()
+    }
+}
+
+impl BlockRegistry {
+    /// Registers block devices and probes them for partitions. Devices are
+    /// never unregistered, so they live for the rest of the runtime.
+    ///
+    /// # Returns
+    ///
+    /// The registry, or an error if reading a partition table failed. Devices
+    /// with a damaged partition table are skipped.
+    pub fn new(devices: Vec<&'static dyn BlockDriver>) -> Result<Self, SvsmError> {
+        let mut registry = Self::default();
+
+        for (index, dev) in devices.into_iter().enumerate() {
+            let table = match read_partition_table(dev) {
+                Ok(table) => table,
+                Err(SvsmError::InvalidFormat) => {
+                    log::warn!("Block device {index}: invalid partition table, ignoring device");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let Some(table) = table else {
+                log::info!("Block device {index}: {} bytes", dev.size());
+                registry.unpartitioned.push(dev);
+                continue;
+            };
+
+            for info in table {
+                let part = Partition::new(dev, info)?;
+                log::info!(
+                    "Block device {index}: partition {} \"{}\", {} bytes",
+                    part.guid(),
+                    part.label(),
+                    part.size()
+                );
+                if registry.find_by_guid(&part.guid()).is_some() {
+                    log::warn!("Duplicate partition GUID {}, ignoring", part.guid());
+                    continue;
+                }
+                registry.partitions.push(part);
+            }
+        }
+
+        Ok(registry)
+    }
+
+    /// Returns the devices without a partition table, in registration order.
+    pub fn unpartitioned(&self) -> &[&'static dyn BlockDriver] {
+        &self.unpartitioned
+    }
+
+    /// Returns all partitions of all devices.
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    /// Looks up a partition by its unique GUID.
+    pub fn find_by_guid(&self, guid: &Uuid) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.guid() == *guid)
+    }
+
+    /// Looks up a partition by its label. If several partitions share the
+    /// label, the first one found is returned.
+    pub fn find_by_label(&self, label: &str) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.label() == label)
+    }
+}
+
+static BLOCK_REGISTRY: ImmutAfterInitCell<BlockRegistry> = ImmutAfterInitCell::uninit();
+
+/// Initializes the global block device registry.
+///
+/// # Arguments
+///
+/// * `devices` - The drivers of all block devices found.
+///
+/// # Returns
+///
+/// `Ok(())` on success, or an error if the devices could not be probed or
+/// the registry has already been initialized.
+pub fn initialize_block_registry(devices: Vec<Box<dyn BlockDriver>>) -> Result<(), SvsmError> {
+    let devices = devices.into_iter().map(|dev| &*Box::leak(dev)).collect();
+    BLOCK_REGISTRY.init(BlockRegistry::new(devices)?)?;
+    Ok(())
+}
+
+/// Returns the global block device registry, or `None` if no block devices
+/// have been probed.
+pub fn block_registry() -> Option<&'static BlockRegistry> {
+    BLOCK_REGISTRY.try_get_inner().ok()
+}
+
+/// Looks up a partition of any registered block device by its unique GUID.
+pub fn find_partition_by_guid(guid: &Uuid) -> Option<&'static dyn BlockDriver> {
+    block_registry()?
+        .find_by_guid(guid)
+        .map(|p| p as &dyn BlockDriver)
+}
+
+/// Looks up a partition of any registered block device by its label.
+pub fn find_partition_by_label(label: &str) -> Option<&'static dyn BlockDriver> {
+    block_registry()?
+        .find_by_label(label)
+        .map(|p| p as &dyn BlockDriver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::gpt::tests::gpt_disk;
+    use crate::block::ramdisk::RamDisk;
+    use alloc::vec;
+    use uuid::uuid;
+
+    #[test]
+    fn lookup_partitions() {
+        let a = uuid!("b6e5c5a0-43d4-4d3e-9f3c-6a3f0e7f1d01");
+        let b = uuid!("b6e5c5a0-43d4-4d3e-9f3c-6a3f0e7f1d02");
+        let c = uuid!("b6e5c5a0-43d4-4d3e-9f3c-6a3f0e7f1d03");
+        let first = gpt_disk(1024 * 1024, &[(a, 64, 127, "vtpm"), (b, 128, 1023, "data")]);
+        let second = gpt_disk(1024 * 1024, &[(c, 64, 1023, "uefi")]);
+        let raw: &'static RamDisk = Box::leak(Box::new(RamDisk::new(64 * 1024)));
+
+        let registry = BlockRegistry::new(vec![first, raw, second]).unwrap();
+        assert_eq!(registry.partitions().len(), 3);
+        assert_eq!(registry.unpartitioned().len(), 1);
+        assert_eq!(registry.unpartitioned()[0].size(), 64 * 1024);
+
+        assert_eq!(registry.find_by_label("data").unwrap().guid(), b);
+        assert_eq!(registry.find_by_label("uefi").unwrap().guid(), c);
+        assert_eq!(registry.find_by_guid(&a).unwrap().label(), "vtpm");
+        assert!(registry.find_by_label("missing").is_none());
+
+        // Partitions of different devices are independent.
+        let uefi = registry.find_by_guid(&c).unwrap();
+        uefi.write_blocks(0, &[0x55; 512]).unwrap();
+        second.with_data(|raw| assert_eq!(raw[64 * 512], 0x55));
+        first.with_data(|raw| assert_eq!(raw[64 * 512], 0));
+    }
+
+    #[test]
+    fn damaged_table() {
+        let guid = uuid!("b6e5c5a0-43d4-4d3e-9f3c-6a3f0e7f1d01");
+        let disk = gpt_disk(1024 * 1024, &[(guid, 64, 127, "data")]);
+        disk.with_data(|raw| {
+            let len = raw.len();
+            raw[512 + 24] ^= 1;
+            raw[len - 512 + 24] ^= 1;
+        });
+
+        let registry = BlockRegistry::new(vec![disk]).unwrap();
+        assert!(registry.partitions().is_empty());
+        assert!(registry.unpartitioned().is_empty());
+    }
+}
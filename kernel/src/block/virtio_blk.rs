@@ -6,7 +6,8 @@
 // Author: Oliver Steffen <osteffen@redhat.com>
 
 use super::api::BlockDriver;
-use crate::block::{BLOCK_DEVICE, BlockDeviceError};
+use crate::block::BlockDeviceError;
+use crate::block::initialize_block_devices;
 use crate::error::SvsmError;
 use crate::locking::SpinLock;
 use crate::mm::GlobalRangeGuard;
@@ -20,6 +21,7 @@ use virtio_drivers::transport::mmio::MmioTransport;
 
 extern crate alloc;
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 pub struct VirtIOBlkDriver {
     device: SpinLock<VirtIOBlk<SvsmHal, MmioTransport<'static>>>,
@@ -82,12 +84,11 @@ impl BlockDriver for VirtIOBlkDriver {
     }
 }
 
-/// Initializes the global block device subsystem with a VirtIO block driver.
+/// Initializes the global block device subsystem with VirtIO block drivers.
 ///
-/// This function searches for a virtio-blk device in the MMIO slots list.
-/// If discovered, the first virtio-blk device will be initialized and
-/// registered as the global block device.
-/// **Only one block device is supported**
+/// This function initializes a driver for every virtio-blk device in the
+/// MMIO slots list and registers them, including their partitions, with the
+/// block device registry.
 ///
 /// # Arguments
 ///
@@ -96,21 +97,22 @@ impl BlockDriver for VirtIOBlkDriver {
 /// # Returns
 ///
 /// * Returns Ok() if:
-///     * The driver is correctly initialized
+///     * The drivers are correctly initialized
 ///     * No virtio-block devices are found
 /// * Returns an error if:
-///     * The driver initialization fails
-///     * The global block device has already been initialized
+///     * The initialization of a driver fails
+///     * The block devices have already been initialized
 pub fn initialize_block(slots: &mut MmioSlots) -> Result<(), SvsmError> {
-    let Some(slot) = slots.pop_slot(Block) else {
-        return Ok(());
-    };
-
-    let driver = VirtIOBlkDriver::new(slot)?;
+    let mut drivers: Vec<Box<dyn BlockDriver>> = Vec::new();
+    while let Some(slot) = slots.pop_slot(Block) {
+        drivers.push(VirtIOBlkDriver::new(slot)?);
+    }
 
-    BLOCK_DEVICE.init(driver)?;
+    if drivers.is_empty() {
+        return Ok(());
+    }
 
-    Ok(())
+    initialize_block_devices(drivers)
 }
 
 #[cfg(all(test, test_in_svsm))]
@@ -119,6 +121,7 @@ mod tests {
     use core::cmp::min;
     extern crate alloc;
     use super::*;
+    use crate::block::BLOCK_DEVICE;
 
     /// Get the sha256 sum of the disk image from the host (see `scripts/test-in-svsm.sh`)
     fn get_image_hash_from_host() -> Option<[u8; 32]> {
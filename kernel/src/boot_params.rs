@@ -476,6 +476,10 @@ impl BootParams<'_> {
     pub fn has_test_iorequests(&self) -> bool {
         self.boot_param_block.has_test_iorequests != 0
     }
+
+    pub fn continue_unprovisioned(&self) -> bool {
+        self.boot_param_block.continue_unprovisioned != 0
+    }
 }
 
 /// `IgvmBox` is a `Box`-type object that tracks the allocation lifetime of the
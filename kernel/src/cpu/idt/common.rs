@@ -48,6 +48,7 @@ pub const VC_VECTOR: usize = 29;
 pub const SX_VECTOR: usize = 30;
 
 pub const SCHEDULE_VECTOR: usize = 0x20;
+pub const TIMER_VECTOR: usize = 0x30;
 pub const INT_INJ_VECTOR: usize = 0x50;
 pub const IPI_VECTOR: usize = 0xE0;
 
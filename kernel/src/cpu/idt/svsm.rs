@@ -14,8 +14,8 @@ use super::super::x86::apic_eoi;
 use super::common::{
     AC_VECTOR, BP_VECTOR, BR_VECTOR, CP_VECTOR, DB_VECTOR, DE_VECTOR, DF_VECTOR, GP_VECTOR,
     HV_VECTOR, IDT, INT_INJ_VECTOR, IPI_VECTOR, IdtEntry, IdtEventType, MCE_VECTOR, MF_VECTOR,
-    NMI_VECTOR, OF_VECTOR, PF_VECTOR, PageFaultError, SCHEDULE_VECTOR, SS_VECTOR, UD_VECTOR,
-    VC_VECTOR, VE_VECTOR, XF_VECTOR, user_mode,
+    NMI_VECTOR, OF_VECTOR, PF_VECTOR, PageFaultError, SCHEDULE_VECTOR, SS_VECTOR, TIMER_VECTOR,
+    UD_VECTOR, VC_VECTOR, VE_VECTOR, XF_VECTOR, user_mode,
 };
 use crate::address::VirtAddr;
 use crate::cpu::X86ExceptionContext;
@@ -28,8 +28,9 @@ use crate::mm::GuestPtr;
 use crate::mm::PAGE_SIZE;
 use crate::platform::PageValidateOp;
 use crate::platform::SvsmPlatform;
-use crate::task::{is_task_fault, terminate};
+use crate::task::{is_task_fault, preempt_on_irq_return, terminate};
 use crate::tdx::ve::handle_virtualization_exception;
+use crate::time::handle_timer_interrupt;
 use crate::utils::MemoryRegion;
 use crate::utils::immut_after_init::ImmutAfterInitCell;
 use core::arch::global_asm;
@@ -77,6 +78,7 @@ unsafe extern "C" {
     fn asm_entry_irq_int_inj();
     fn asm_entry_irq_ipi();
     fn asm_entry_irq_schedule();
+    fn asm_entry_irq_timer();
 
     pub static mut HV_DOORBELL_ADDR: usize;
 }
@@ -152,6 +154,7 @@ pub unsafe fn idt_init(idt_vaddr: VirtAddr, platform: &dyn SvsmPlatform) -> Resu
     idt.set_entry(0x80, IdtEntry::user_entry(asm_entry_int80));
     idt.set_entry(IPI_VECTOR, IdtEntry::entry(asm_entry_irq_ipi));
     idt.set_entry(SCHEDULE_VECTOR, IdtEntry::entry(asm_entry_irq_schedule));
+    idt.set_entry(TIMER_VECTOR, IdtEntry::entry(asm_entry_irq_timer));
 
     // Set IST vectors
     init_ist_vectors(&mut idt);
@@ -433,8 +436,30 @@ extern "C" fn ex_handler_system_call(
     ctxt.regs.rax = match input {
         // Class 0 SysCalls.
         SYS_EXIT => sys_exit(ctxt.regs.rdi as u32),
-        SYS_EXEC => sys_exec(ctxt.regs.rdi, ctxt.regs.rsi, ctxt.regs.r8),
+        SYS_EXEC => sys_exec(
+            ctxt.regs.rdi,
+            ctxt.regs.rsi,
+            ctxt.regs.r8,
+            ctxt.regs.r9,
+            ctxt.regs.r10,
+        ),
         SYS_CLOSE => sys_close(ctxt.regs.rdi as u32),
+        SYS_MMAP => sys_mmap(
+            ctxt.regs.rdi,
+            ctxt.regs.rsi,
+            ctxt.regs.r8,
+            ctxt.regs.r9 as u32,
+            ctxt.regs.r10,
+        ),
+        SYS_MUNMAP => sys_munmap(ctxt.regs.rdi),
+        SYS_WAIT => sys_wait(ctxt.regs.rdi as u32),
+        SYS_THREAD_CREATE => {
+            sys_thread_create(ctxt.regs.rdi, ctxt.regs.rsi, ctxt.regs.r8, ctxt.regs.r9)
+        }
+        SYS_FUTEX_WAIT => sys_futex_wait(ctxt.regs.rdi, ctxt.regs.rsi as u32),
+        SYS_FUTEX_WAKE => sys_futex_wake(ctxt.regs.rdi, ctxt.regs.rsi as u32),
+        SYS_CLOCK_GETTIME => sys_clock_gettime(ctxt.regs.rdi as u32),
+        SYS_SLEEP => sys_sleep(ctxt.regs.rdi as u64),
         // Class 1 SysCalls.
         SYS_OPEN => sys_open(ctxt.regs.rdi, ctxt.regs.rsi, ctxt.regs.r8),
         SYS_READ => sys_read(ctxt.regs.rdi as u32, ctxt.regs.rsi, ctxt.regs.r8),
@@ -448,6 +473,25 @@ extern "C" fn ex_handler_system_call(
         SYS_RMDIR => sys_rmdir(ctxt.regs.rdi),
         // Class 3 SysCalls.
         SYS_CAPABILITIES => sys_capabilities(ctxt.regs.rdi as u32),
+        SYS_PROTOCOL_REGISTER => sys_protocol_register(ctxt.regs.rdi as u32),
+        SYS_GUEST_READ => sys_guest_read(
+            ctxt.regs.rdi as u32,
+            ctxt.regs.rsi,
+            ctxt.regs.r8,
+            ctxt.regs.r9,
+        ),
+        SYS_GUEST_WRITE => sys_guest_write(
+            ctxt.regs.rdi as u32,
+            ctxt.regs.rsi,
+            ctxt.regs.r8,
+            ctxt.regs.r9,
+        ),
+        SYS_VTPM_REGISTER => sys_vtpm_register(),
+        // Class 4 SysCalls.
+        SYS_CHANNEL_CREATE => sys_channel_create(ctxt.regs.rdi),
+        SYS_CHANNEL_SEND => sys_channel_send(ctxt.regs.rdi as u32, ctxt.regs.rsi),
+        SYS_CHANNEL_RECV => sys_channel_recv(ctxt.regs.rdi as u32, ctxt.regs.rsi),
+        SYS_CHANNEL_POLL => sys_channel_poll(ctxt.regs.rdi as u32, ctxt.regs.rsi as u32),
         _ => Err(SysCallError::EINVAL),
     }
     .map_or_else(|e| e as usize, |v| v as usize);
@@ -481,6 +525,9 @@ pub extern "C" fn common_isr_handler_entry(vector: usize) {
     // interrupts to be reenabled.  They will be reenabled during the IRET
     // flow.
     cpu.irqs_pop_nesting();
+
+    // Switch to another task if the time slice of the current task has ended.
+    preempt_on_irq_return();
 }
 
 pub fn common_isr_handler(vector: usize) {
@@ -497,6 +544,7 @@ pub fn common_isr_handler(vector: usize) {
     // Process the requested interrupt vector.
     match vector {
         IPI_VECTOR => handle_ipi_interrupt(),
+        TIMER_VECTOR => handle_timer_interrupt(),
         _ => {
             // Ignore all unrecognized interrupt vectors and treat them as
             // spurious interrupts.  The scheduler interrupt is included here
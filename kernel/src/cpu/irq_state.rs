@@ -4,7 +4,7 @@
 //
 // Author: Joerg Roedel <jroedel@suse.de>
 
-use crate::cpu::percpu::this_cpu;
+use crate::cpu::percpu::{preempt_disable, preempt_enable, this_cpu};
 use crate::cpu::{irqs_disable, irqs_enable, lower_tpr, raise_tpr};
 use core::arch::asm;
 use core::marker::PhantomData;
@@ -372,6 +372,44 @@ impl Drop for TprGuard {
     }
 }
 
+/// A guard which disables preemption of the current task upon creation.
+/// Interrupts are still delivered, but the scheduler does not switch to
+/// another task on their return until the guard goes out of scope.
+///
+/// The struct implements the `Default` and `Drop` traits for easy use.
+#[derive(Debug)]
+#[must_use = "if unused preemption will be immediately enabled again"]
+pub struct PreemptGuard {
+    /// Whether the preemption-disable nesting level has been incremented
+    counted: bool,
+
+    /// Make the type !Send + !Sync
+    phantom: PhantomData<*const ()>,
+}
+
+impl PreemptGuard {
+    pub fn new() -> Self {
+        Self {
+            counted: preempt_disable(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl Default for PreemptGuard {
+    fn default() -> Self {
+        PreemptGuard::new()
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        if self.counted {
+            preempt_enable();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -409,6 +447,18 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg_attr(not(test_in_svsm), ignore = "Can only be run inside guest")]
+    fn preempt_guard_test() {
+        let count = this_cpu().preempt_count();
+        let g1 = PreemptGuard::new();
+        let g2 = PreemptGuard::new();
+        assert_eq!(this_cpu().preempt_count(), count + 2);
+        drop(g2);
+        drop(g1);
+        assert_eq!(this_cpu().preempt_count(), count);
+    }
+
     #[test]
     #[cfg_attr(not(test_in_svsm), ignore = "Can only be run inside guest")]
     fn irq_guard_test() {
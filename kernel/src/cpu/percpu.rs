@@ -21,13 +21,13 @@ use crate::cpu::tss::TSS_LIMIT;
 use crate::cpu::vmsa::{init_guest_vmsa, init_svsm_vmsa};
 use crate::cpu::vmsa::{svsm_code_segment, svsm_data_segment, svsm_gdt_segment, svsm_idt_segment};
 use crate::cpu::x86::{ApicAccess, X86Apic};
-use crate::cpu::{IrqGuard, IrqState, LocalApic, ShadowStackInit};
+use crate::cpu::{IrqGuard, IrqState, LocalApic, PreemptGuard, ShadowStackInit};
 use crate::error::{ApicError, SvsmError};
 use crate::hyperv::HypercallPagesGuard;
 use crate::hyperv::{self, HypercallPage};
 use crate::locking::{
     LockGuard, RWLock, RWLockIrqSafe, ReadLockGuard, ReadLockGuardIrqSafe, SpinLock,
-    WriteLockGuard, WriteLockGuardIrqSafe,
+    SpinLockIrqSafe, WriteLockGuard, WriteLockGuardIrqSafe,
 };
 use crate::mm::page_visibility::SharedBox;
 use crate::mm::pagetable::{PTEntryFlags, PageTable};
@@ -53,6 +53,7 @@ use crate::task::TaskPointer;
 use crate::task::schedule;
 use crate::task::scheduler_idle;
 use crate::task::wake_and_schedule_task;
+use crate::time::TimerQueue;
 use crate::types::{
     PAGE_SHIFT, PAGE_SHIFT_2M, PAGE_SIZE, PAGE_SIZE_2M, SVSM_TR_ATTRIBUTES, SVSM_TSS,
 };
@@ -79,6 +80,10 @@ use cpuarch::vmsa::VMSA;
 // PERCPU areas virtual addresses into shared memory
 pub static PERCPU_AREAS: PerCpuAreas = PerCpuAreas::new();
 
+/// Set once the per-CPU area of the BSP is loaded, so that [`this_cpu()`]
+/// can be used on every CPU.
+static PERCPU_LOADED: AtomicBool = AtomicBool::new(false);
+
 // We use an UnsafeCell to allow for a static with interior mutability.
 // Normally, we would need to guarantee synchronization on the backing
 // datatype, but this is not needed because writes to the structure only occur
@@ -277,6 +282,14 @@ pub struct PerCpuShared {
     /// visible across CPUs so that tasks can be queued to remote CPUs for
     /// execution.
     runqueue: RWLockIrqSafe<RunQueue>,
+
+    /// Timers which expire on this CPU. Timers can be cancelled from any
+    /// CPU.
+    timers: SpinLockIrqSafe<TimerQueue>,
+
+    /// Events signalled to the request-processing task of this CPU, one bit
+    /// per event source.
+    pending_events: AtomicU64,
 }
 
 impl PerCpuShared {
@@ -291,6 +304,8 @@ impl PerCpuShared {
             nmi_pending: AtomicBool::new(false),
             ipi_state: Default::default(),
             runqueue: RWLockIrqSafe::new(RunQueue::new()),
+            timers: SpinLockIrqSafe::new(TimerQueue::new()),
+            pending_events: AtomicU64::new(0),
         }
     }
 
@@ -302,6 +317,25 @@ impl PerCpuShared {
         self.cpu_index
     }
 
+    pub fn timers(&self) -> &SpinLockIrqSafe<TimerQueue> {
+        &self.timers
+    }
+
+    /// Marks the events in `mask` as pending and returns the events which
+    /// were pending before.
+    pub fn signal_events(&self, mask: u64) -> u64 {
+        self.pending_events.fetch_or(mask, Ordering::AcqRel)
+    }
+
+    /// Returns the pending events and clears them.
+    pub fn take_pending_events(&self) -> u64 {
+        self.pending_events.swap(0, Ordering::AcqRel)
+    }
+
+    pub fn events_pending(&self) -> bool {
+        self.pending_events.load(Ordering::Acquire) != 0
+    }
+
     pub fn update_guest_vmsa_caa(&self, vmsa: PhysAddr, caa: PhysAddr) {
         let mut locked = self.guest_vmsa.lock();
         locked.update_vmsa_caa(Some(vmsa), Some(caa));
@@ -312,6 +346,12 @@ impl PerCpuShared {
         locked.update_vmsa(Some(vmsa));
     }
 
+    /// Returns the physical address of the calling area of the guest, if
+    /// any.
+    pub fn guest_caa(&self) -> Option<PhysAddr> {
+        self.guest_vmsa.lock().caa_phys()
+    }
+
     pub fn update_guest_caa(&self, caa: PhysAddr) {
         let mut locked = self.guest_vmsa.lock();
         locked.update_caa(Some(caa));
@@ -397,7 +437,9 @@ const _: () = assert!(size_of::<PerCpu>() <= PAGE_SIZE);
 /// Thus, we require that the type is [`Sync`]. This is currently enforced by
 /// protecting mutable fields with a [`RWLock`]. To avoid deadlocks, only the
 /// non-blocking methods in the lock are used, causing an immediate panic when a
-/// reentrant access is attempted.
+/// reentrant access is attempted. Holding one of these locks also disables
+/// preemption of the current task, so another task on the same CPU can not
+/// run into the held lock.
 ///
 /// If the [`Sync`] requirement below breaks your build, it means you introduced
 /// interior mutability, which is not safe.
@@ -416,6 +458,10 @@ where
     /// PerCpu IRQ state tracking
     irq_state: IrqState,
 
+    /// Nesting depth of sections in which the current task must not be
+    /// preempted
+    preempt_count: AtomicU32,
+
     pgtbl: AtomicUsize,
     cr3: AtomicUsize,
     tss: X86Tss,
@@ -456,6 +502,7 @@ impl PerCpu {
             cr3: AtomicUsize::new(0),
             apic: X86Apic::default(),
             irq_state: IrqState::new(),
+            preempt_count: AtomicU32::new(0),
             tss: X86Tss::new(),
             isst: RWLock::new(Isst::default()),
             svsm_vmsa: ImmutAfterInitCell::uninit(),
@@ -580,6 +627,31 @@ impl PerCpu {
         self.irq_state.lower_tpr(tpr_value);
     }
 
+    /// Increments the preemption-disable nesting level of the current CPU.
+    ///
+    /// Caller needs to make sure to match every `preempt_disable()` call with
+    /// a `preempt_enable()` call.
+    #[inline(always)]
+    pub fn preempt_disable(&self) {
+        self.preempt_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decrements the preemption-disable nesting level of the current CPU.
+    #[inline(always)]
+    pub fn preempt_enable(&self) {
+        let count = self.preempt_count.fetch_sub(1, Ordering::Relaxed);
+        debug_assert!(count > 0);
+    }
+
+    /// Get preemption-disable nesting count on the current CPU
+    ///
+    /// # Returns
+    ///
+    /// Current nesting depth of preempt_disable() calls.
+    pub fn preempt_count(&self) -> u32 {
+        self.preempt_count.load(Ordering::Relaxed)
+    }
+
     /// Sets up the CPU-local GHCB page.
     pub fn setup_ghcb(&self) -> Result<(), SvsmError> {
         self.ghcb.try_init_from_fn(GhcbPage::new)?;
@@ -766,7 +838,7 @@ impl PerCpu {
 
     pub fn setup_hv_doorbell(&self) -> Result<(), SvsmError> {
         self.hv_doorbell
-            .try_init_from_fn(|| allocate_hv_doorbell_page(current_ghcb()))?;
+            .try_init_from_fn(|| allocate_hv_doorbell_page(&current_ghcb()))?;
         Ok(())
     }
 
@@ -925,6 +997,7 @@ impl PerCpu {
         if is_cet_ss_enabled() {
             self.load_isst();
         }
+        PERCPU_LOADED.store(true, Ordering::Release);
     }
 
     pub fn set_reset_ip(&self, reset_ip: u64) {
@@ -1321,6 +1394,29 @@ pub fn irq_nesting_count() -> i32 {
     this_cpu().irq_nesting_count()
 }
 
+/// Increments the preemption-disable nesting level on the current CPU. Does
+/// nothing before the per-CPU area of the BSP has been loaded, since no task
+/// can be preempted before that.
+///
+/// # Returns
+///
+/// `true` if the nesting level has been incremented. Only in that case the
+/// call must be matched with a `preempt_enable()` call.
+#[inline(always)]
+pub fn preempt_disable() -> bool {
+    if !PERCPU_LOADED.load(Ordering::Acquire) {
+        return false;
+    }
+    this_cpu().preempt_disable();
+    true
+}
+
+/// Decrements the preemption-disable nesting level on the current CPU.
+#[inline(always)]
+pub fn preempt_enable() {
+    this_cpu().preempt_enable();
+}
+
 /// Raises TPR on the current CPU.  Keeps track of the nesting level.
 ///
 /// The caller must ensure that every `raise_tpr()` call is followed by a
@@ -1343,14 +1439,35 @@ pub fn lower_tpr(tpr_value: usize) {
     this_cpu().lower_tpr(tpr_value);
 }
 
+/// Reference to the GHCB of the current CPU, see [`current_ghcb()`].
+/// Preemption is disabled while it exists, so that no other task on the CPU
+/// can use the GHCB between setting up a request and reading its results.
+#[derive(Debug)]
+pub struct GhcbRef {
+    ghcb: &'static GHCB,
+    _guard: PreemptGuard,
+}
+
+impl Deref for GhcbRef {
+    type Target = GHCB;
+
+    fn deref(&self) -> &GHCB {
+        self.ghcb
+    }
+}
+
 /// Gets the GHCB for this CPU.
 ///
 /// # Panics
 ///
 /// Panics if the GHCB for this CPU has not been set up via
 /// [`PerCpu::setup_ghcb()`].
-pub fn current_ghcb() -> &'static GHCB {
-    this_cpu().ghcb().unwrap()
+pub fn current_ghcb() -> GhcbRef {
+    let guard = PreemptGuard::new();
+    GhcbRef {
+        ghcb: this_cpu().ghcb().unwrap(),
+        _guard: guard,
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -122,7 +122,7 @@ pub fn handle_vc_exception(ctx: &mut X86ExceptionContext, vector: usize) -> Resu
         }
         (SVM_EXIT_CPUID, Some(DecodedInsn::Cpuid)) => handle_cpuid(ctx),
         (SVM_EXIT_IOIO, Some(_)) => handle_ioio(ctx, &insn_ctx.unwrap()),
-        (SVM_EXIT_MSR, Some(ins)) => handle_msr(ctx, ghcb, ins),
+        (SVM_EXIT_MSR, Some(ins)) => handle_msr(ctx, &ghcb, ins),
         (SVM_EXIT_RDTSC, Some(DecodedInsn::Rdtsc)) => ghcb.rdtsc_regs(&mut ctx.regs),
         (SVM_EXIT_RDTSCP, Some(DecodedInsn::Rdtscp)) => ghcb.rdtscp_regs(&mut ctx.regs),
         _ => Err(VcError::new(ctx, VcErrorType::Unsupported).into()),
@@ -267,7 +267,7 @@ mod tests {
     /// with test data.
     fn verify_ghcb_was_altered() -> bool {
         let ghcb = current_ghcb();
-        let ptr: *const GHCB = core::ptr::from_ref(ghcb);
+        let ptr: *const GHCB = core::ptr::from_ref(&*ghcb);
         let ghcb_bytes =
             // SAFETY: The pointer points to a GHCB.
             unsafe { core::slice::from_raw_parts(ptr.cast::<u8>(), core::mem::size_of::<GHCB>()) };
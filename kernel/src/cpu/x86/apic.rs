@@ -71,9 +71,20 @@ pub const APIC_OFFSET_SPIV: usize = 0xF;
 pub const APIC_OFFSET_ISR: usize = 0x10;
 /// Interrupt-Control-Register register MSR offset
 pub const APIC_OFFSET_ICR: usize = 0x30;
+/// LVT Timer register MSR offset
+pub const APIC_OFFSET_LVT_TIMER: usize = 0x32;
+/// Timer Initial-Count register MSR offset
+pub const APIC_OFFSET_TIMER_ICR: usize = 0x38;
+/// Timer Current-Count register MSR offset
+pub const APIC_OFFSET_TIMER_CCR: usize = 0x39;
+/// Timer Divide-Configuration register MSR offset
+pub const APIC_OFFSET_TIMER_DCR: usize = 0x3E;
 /// SELF-IPI register MSR offset (x2APIC only)
 pub const APIC_OFFSET_SELF_IPI: usize = 0x3F;
 
+// Timer DCR value to divide the timer clock by 1
+const APIC_TIMER_DIVIDE_BY_1: u64 = 0b1011;
+
 // SPIV bits
 const APIC_SPIV_VECTOR_MASK: u64 = (1u64 << 8) - 1;
 const APIC_SPIV_SW_ENABLE_MASK: u64 = 1 << 8;
@@ -179,6 +190,31 @@ impl X86Apic {
         (self.regs().apic_read(APIC_OFFSET_ISR + offset) & mask as u64) != 0
     }
 
+    /// Starts the APIC timer in one-shot mode. The timer counts down at the
+    /// frequency of the APIC timer clock and raises an interrupt when it
+    /// reaches zero.
+    ///
+    /// # Arguments
+    ///
+    /// - `vector` - The IRQ vector of the timer interrupt.
+    /// - `count` - The initial count of the timer.
+    pub fn timer_start(&self, vector: u8, count: u32) {
+        let regs = self.regs();
+        regs.apic_write(APIC_OFFSET_TIMER_DCR, APIC_TIMER_DIVIDE_BY_1);
+        regs.apic_write(APIC_OFFSET_LVT_TIMER, vector as u64);
+        regs.apic_write(APIC_OFFSET_TIMER_ICR, count as u64);
+    }
+
+    /// Stops the APIC timer.
+    pub fn timer_stop(&self) {
+        self.regs().apic_write(APIC_OFFSET_TIMER_ICR, 0);
+    }
+
+    /// Returns the current count of the APIC timer.
+    pub fn timer_count(&self) -> u32 {
+        self.regs().apic_read(APIC_OFFSET_TIMER_CCR) as u32
+    }
+
     /// Set Spurious-Interrupt-Vector Register
     ///
     /// # Arguments
@@ -243,3 +279,23 @@ pub fn apic_eoi() {
 pub fn apic_in_service(vector: usize) -> bool {
     this_cpu().get_apic().check_isr(vector)
 }
+
+/// Starts the APIC timer of the current CPU in one-shot mode.
+///
+/// # Arguments
+///
+/// - `vector` - The IRQ vector of the timer interrupt.
+/// - `count` - The initial count of the timer.
+pub fn apic_timer_start(vector: u8, count: u32) {
+    this_cpu().get_apic().timer_start(vector, count);
+}
+
+/// Stops the APIC timer of the current CPU.
+pub fn apic_timer_stop() {
+    this_cpu().get_apic().timer_stop();
+}
+
+/// Returns the current count of the APIC timer of the current CPU.
+pub fn apic_timer_count() -> u32 {
+    this_cpu().get_apic().timer_count()
+}
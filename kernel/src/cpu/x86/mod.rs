@@ -10,6 +10,6 @@ pub mod x2apic;
 
 pub use apic::{
     ApicAccess, MSR_APIC_BASE, X86Apic, apic_enable, apic_eoi, apic_in_service, apic_initialize,
-    apic_post_irq, apic_sw_enable,
+    apic_post_irq, apic_sw_enable, apic_timer_count, apic_timer_start, apic_timer_stop,
 };
 pub use x2apic::{X2APIC_ACCESSOR, X2ApicAccessor};
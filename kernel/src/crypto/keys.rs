@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Management of the keys provisioned through attestation.
+//!
+//! The secret released by the attestation server is the root of all the keys
+//! used by the SVSM. It is moved into a page of its own, which is zeroed when
+//! the key store is dropped, and never handed out directly. Subsystems
+//! instead look up keys which are derived from the secret for a single
+//! purpose, so that compromising one of them does not reveal the others.
+//!
+//! Along with the secret, the key store records the version of the persistent
+//! state which the attestation server verified to be current, so that stale
+//! state replayed by the host can be refused.
+
+use core::mem::size_of;
+
+use zerocopy::FromZeros;
+use zeroize::Zeroizing;
+
+use crate::crypto::SecretSlice;
+use crate::crypto::mac::{HMAC_SHA256_SIZE, HmacSha256, HmacSha256Trait};
+use crate::error::SvsmError;
+use crate::mm::PageBox;
+use crate::types::PAGE_SIZE;
+use crate::utils::immut_after_init::ImmutAfterInitCell;
+
+/// Size of the keys derived from the provisioned secret.
+pub const DERIVED_KEY_SIZE: usize = HMAC_SHA256_SIZE;
+
+/// Largest secret which can be provisioned.
+pub const MAX_SECRET_SIZE: usize = PAGE_SIZE - size_of::<usize>();
+
+/// A key derived from the provisioned secret. It is zeroed when dropped.
+pub type DerivedKey = Zeroizing<[u8; DERIVED_KEY_SIZE]>;
+
+/// Purpose of a derived key.
+///
+/// There is no key per consumer of persistent state. The vTPM NV memory, the
+/// UEFI variable store and the persistent filesystem are regions of a single
+/// encrypted block device, see [`crate::block`], which is the only user of a
+/// derived key. The consumers never see a key, they read and write their
+/// regions through the device in the kernel, so separate keys would not
+/// isolate them any further. A new key is only needed for a subsystem which
+/// uses the key itself, for example to seal data handed out of the SVSM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyId {
+    /// Key of the encrypted block device holding all persistent state.
+    Storage,
+}
+
+impl KeyId {
+    /// Label used to derive the key, which must be unique for every key.
+    const fn label(self) -> &'static [u8] {
+        match self {
+            Self::Storage => b"svsm storage key",
+        }
+    }
+}
+
+/// A secret released by the attestation server.
+#[derive(Debug)]
+pub struct ProvisionedSecret {
+    pub secret: SecretSlice,
+    /// Version of the persistent state which the attestation server verified
+    /// to be current, or `None` if the server does not provide rollback
+    /// protection.
+    pub state_version: Option<u64>,
+}
+
+/// What to do if no secret could be obtained during boot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProvisioningFailurePolicy {
+    /// Stop booting.
+    #[default]
+    Halt,
+    /// Keep booting without keys. Services which depend on a key are not
+    /// available.
+    ContinueUnprovisioned,
+}
+
+#[derive(FromZeros)]
+struct SecretPage {
+    len: usize,
+    data: [u8; MAX_SECRET_SIZE],
+}
+
+impl Drop for SecretPage {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.data);
+    }
+}
+
+/// Holds the provisioned secret and derives keys from it.
+pub struct KeyStore {
+    secret: PageBox<SecretPage>,
+    state_version: Option<u64>,
+}
+
+impl core::fmt::Debug for KeyStore {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("KeyStore")
+            .field("len", &self.secret.len)
+            .field("secret", &"[REDACTED]")
+            .field("state_version", &self.state_version)
+            .finish()
+    }
+}
+
+impl KeyStore {
+    /// Takes ownership of the secret and moves it into dedicated memory. The
+    /// original buffer is zeroed.
+    pub fn new(provisioned: ProvisionedSecret) -> Result<Self, SvsmError> {
+        let ProvisionedSecret {
+            secret,
+            state_version,
+        } = provisioned;
+        if secret.is_empty() || secret.len() > MAX_SECRET_SIZE {
+            return Err(SvsmError::InvalidParameter);
+        }
+
+        let mut page = PageBox::<SecretPage>::try_new_zeroed()?;
+        page.len = secret.len();
+        page.data[..secret.len()].copy_from_slice(&secret);
+
+        Ok(Self {
+            secret: page,
+            state_version,
+        })
+    }
+
+    /// Derives the key for the given purpose.
+    pub fn derive(&self, id: KeyId) -> DerivedKey {
+        let secret = &self.secret.data[..self.secret.len];
+        Zeroizing::new(HmacSha256::mac(secret, id.label()))
+    }
+
+    /// Returns the verified version of the persistent state.
+    pub fn state_version(&self) -> Option<u64> {
+        self.state_version
+    }
+}
+
+static KEY_STORE: ImmutAfterInitCell<KeyStore> = ImmutAfterInitCell::uninit();
+
+/// Provisions the keys from the outcome of attestation.
+///
+/// # Arguments
+///
+/// * `result` - The secret released by the attestation server, or the error
+///   which prevented to obtain it.
+/// * `policy` - How to handle a failure to obtain the secret.
+///
+/// # Returns
+///
+/// `Ok(())` if the keys have been provisioned or if the policy allows to
+/// continue without them, or the error otherwise.
+pub fn provision_keys(
+    result: Result<ProvisionedSecret, SvsmError>,
+    policy: ProvisioningFailurePolicy,
+) -> Result<(), SvsmError> {
+    let err = match result.and_then(KeyStore::new) {
+        Ok(store) => {
+            KEY_STORE.init(store)?;
+            log::info!("Keys provisioned");
+            return Ok(());
+        }
+        Err(e) => e,
+    };
+
+    match policy {
+        ProvisioningFailurePolicy::Halt => Err(err),
+        ProvisioningFailurePolicy::ContinueUnprovisioned => {
+            log::warn!("Failed to provision keys ({err:?}), continuing without them");
+            Ok(())
+        }
+    }
+}
+
+/// Whether the keys have been provisioned.
+pub fn keys_provisioned() -> bool {
+    KEY_STORE.try_get_inner().is_ok()
+}
+
+/// Returns the version of the persistent state which the attestation server
+/// verified to be current.
+///
+/// # Returns
+///
+/// The version, or `None` if no keys have been provisioned or the server does
+/// not provide rollback protection.
+pub fn verified_state_version() -> Option<u64> {
+    KEY_STORE
+        .try_get_inner()
+        .ok()
+        .and_then(KeyStore::state_version)
+}
+
+/// Looks up a derived key.
+///
+/// # Returns
+///
+/// The key, or `None` if no keys have been provisioned.
+pub fn get_key(id: KeyId) -> Option<DerivedKey> {
+    KEY_STORE.try_get_inner().ok().map(|store| store.derive(id))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use crate::mm::alloc::{DEFAULT_TEST_MEMORY_SIZE, TestRootMem};
+    use alloc::boxed::Box;
+
+    fn secret(data: &[u8]) -> ProvisionedSecret {
+        ProvisionedSecret {
+            secret: SecretSlice::from(Box::from(data)),
+            state_version: None,
+        }
+    }
+
+    #[test]
+    fn derived_keys_depend_on_secret() {
+        let _test_mem = TestRootMem::setup(DEFAULT_TEST_MEMORY_SIZE);
+
+        let store = KeyStore::new(secret(b"attestation secret")).unwrap();
+        let storage = store.derive(KeyId::Storage);
+        assert_eq!(*storage, *store.derive(KeyId::Storage));
+
+        let other = KeyStore::new(secret(b"another secret")).unwrap();
+        assert_ne!(*storage, *other.derive(KeyId::Storage));
+    }
+
+    #[test]
+    fn invalid_secret_size() {
+        let _test_mem = TestRootMem::setup(DEFAULT_TEST_MEMORY_SIZE);
+
+        assert!(KeyStore::new(secret(&[])).is_err());
+        assert!(KeyStore::new(secret(&[0xaa; MAX_SECRET_SIZE + 1])).is_err());
+        assert!(KeyStore::new(secret(&[0xaa; MAX_SECRET_SIZE])).is_ok());
+    }
+
+    #[test]
+    fn failure_policy() {
+        let halt = provision_keys(
+            Err(SvsmError::InvalidParameter),
+            ProvisioningFailurePolicy::Halt,
+        );
+        assert!(halt.is_err());
+
+        let cont = provision_keys(
+            Err(SvsmError::InvalidParameter),
+            ProvisioningFailurePolicy::ContinueUnprovisioned,
+        );
+        assert!(cont.is_ok());
+        assert!(!keys_provisioned());
+        assert!(get_key(KeyId::Storage).is_none());
+        assert!(verified_state_version().is_none());
+    }
+}
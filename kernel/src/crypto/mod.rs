@@ -145,11 +145,93 @@ pub mod digest {
         fn digest(input: &[u8]) -> Vec<u8>;
     }
 
+    /// Sha384 type
+    #[derive(Copy, Clone, Debug)]
+    pub struct Sha384;
+
     /// Sha512 type
     #[derive(Copy, Clone, Debug)]
     pub struct Sha512;
 }
 
+pub mod mac {
+    //! API for message authentication codes.
+
+    /// HMAC-SHA256 output size
+    pub const HMAC_SHA256_SIZE: usize = 32;
+
+    /// HMAC-SHA256
+    pub trait HmacSha256Trait {
+        /// Computes the HMAC-SHA256 of `input` using `key`.
+        ///
+        /// # Arguments
+        ///
+        /// * `key`: Authentication key of arbitrary length
+        /// * `input`: Message to authenticate
+        ///
+        /// # Returns
+        ///
+        /// The authentication code.
+        fn mac(key: &[u8], input: &[u8]) -> [u8; HMAC_SHA256_SIZE];
+
+        /// Checks in constant time whether `tag` is the HMAC-SHA256 of
+        /// `input` using `key`.
+        ///
+        /// # Arguments
+        ///
+        /// * `key`: Authentication key of arbitrary length
+        /// * `input`: Authenticated message
+        /// * `tag`: Authentication code to check
+        ///
+        /// # Returns
+        ///
+        /// `true` if `tag` is valid.
+        fn verify(key: &[u8], input: &[u8], tag: &[u8]) -> bool;
+    }
+
+    /// HmacSha256 type
+    #[derive(Copy, Clone, Debug)]
+    pub struct HmacSha256;
+}
+
+pub mod kdf {
+    //! API for key derivation functions.
+
+    use crate::error::SvsmError;
+
+    /// HKDF-SHA256 (RFC 5869)
+    pub trait HkdfSha256Trait {
+        /// Derives key material from input key material.
+        ///
+        /// # Arguments
+        ///
+        /// * `salt`: Optional salt
+        /// * `ikm`: Input key material
+        /// * `info`: Context and application specific information
+        /// * `okm`: Buffer to store the output key material
+        ///
+        /// # Returns
+        ///
+        /// * Success
+        ///     * `()`
+        /// * Error
+        ///     * [SvsmError]: `okm` is too large
+        fn derive(
+            salt: Option<&[u8]>,
+            ikm: &[u8],
+            info: &[u8],
+            okm: &mut [u8],
+        ) -> Result<(), SvsmError>;
+    }
+
+    /// HkdfSha256 type
+    #[derive(Copy, Clone, Debug)]
+    pub struct HkdfSha256;
+}
+
+pub mod keys;
+pub mod rng;
+
 // Crypto implementations supported. Only one of them must be compiled-in.
 
 pub mod rustcrypto;
@@ -160,6 +242,87 @@ mod tests {
     use alloc::vec::Vec;
     use zeroize::Zeroize;
 
+    #[test]
+    fn hmac_sha256_rfc4231() {
+        use crate::crypto::mac::{HmacSha256, HmacSha256Trait};
+
+        // RFC 4231 test case 2
+        let mac = HmacSha256::mac(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            mac,
+            [
+                0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+                0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+                0x64, 0xec, 0x38, 0x43,
+            ]
+        );
+
+        // RFC 4231 test case 6, key larger than the block size
+        let mac = HmacSha256::mac(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First",
+        );
+        assert_eq!(
+            mac,
+            [
+                0x60, 0xe4, 0x31, 0x59, 0x1e, 0xe0, 0xb6, 0x7f, 0x0d, 0x8a, 0x26, 0xaa, 0xcb, 0xf5,
+                0xb7, 0x7f, 0x8e, 0x0b, 0xc6, 0x21, 0x37, 0x28, 0xc5, 0x14, 0x05, 0x46, 0x04, 0x0f,
+                0x0e, 0xe3, 0x7f, 0x54,
+            ]
+        );
+    }
+
+    #[test]
+    fn hmac_sha256_verify() {
+        use crate::crypto::mac::{HmacSha256, HmacSha256Trait};
+
+        let mut mac = HmacSha256::mac(b"key", b"message");
+        assert!(HmacSha256::verify(b"key", b"message", &mac));
+        assert!(!HmacSha256::verify(b"key", b"massage", &mac));
+        assert!(!HmacSha256::verify(b"key", b"message", &mac[..16]));
+        mac[0] ^= 1;
+        assert!(!HmacSha256::verify(b"key", b"message", &mac));
+    }
+
+    #[test]
+    fn hkdf_sha256_rfc5869() {
+        use crate::crypto::kdf::{HkdfSha256, HkdfSha256Trait};
+
+        // RFC 5869 test case 1
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        let mut okm = [0u8; 42];
+        HkdfSha256::derive(Some(&salt), &[0x0b; 22], &info, &mut okm).unwrap();
+        assert_eq!(
+            okm,
+            [
+                0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36,
+                0x2f, 0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56,
+                0xec, 0xc4, 0xc5, 0xbf, 0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65,
+            ]
+        );
+
+        // The output is limited to 255 blocks.
+        let mut okm = alloc::vec![0u8; 255 * 32 + 1];
+        assert!(HkdfSha256::derive(None, b"ikm", b"", &mut okm).is_err());
+    }
+
+    #[test]
+    fn sha384_fips180() {
+        use crate::crypto::digest::{Algorithm, Sha384};
+
+        // FIPS 180-2 appendix D.1, one-block message
+        assert_eq!(
+            Sha384::digest(b"abc"),
+            [
+                0xcb, 0x00, 0x75, 0x3f, 0x45, 0xa3, 0x5e, 0x8b, 0xb5, 0xa0, 0x3d, 0x69, 0x9a, 0xc6,
+                0x50, 0x07, 0x27, 0x2c, 0x32, 0xab, 0x0e, 0xde, 0xd1, 0x63, 0x1a, 0x8b, 0x60, 0x5a,
+                0x43, 0xff, 0x5b, 0xed, 0x80, 0x86, 0x07, 0x2b, 0xa1, 0xe7, 0xcc, 0x23, 0x58, 0xba,
+                0xec, 0xa1, 0x34, 0xc8, 0x25, 0xa7,
+            ]
+        );
+    }
+
     #[test]
     fn secretslice_stores_secret() {
         let b: SecretSlice = Vec::from([1u8, 2, 3, 4, 5]).into_boxed_slice().into();
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Random numbers from the hardware random number generator.
+//!
+//! The random numbers are obtained with the RDRAND instruction, which is
+//! executed by the CPU without involvement of the host.
+
+use core::arch::x86_64::_rdrand64_step;
+
+use crate::cpu::features::{Feature, cpu_has_feat};
+use crate::error::SvsmError;
+
+/// RDRAND can fail transiently if the entropy source is exhausted. Intel
+/// recommends giving up after 10 consecutive failures.
+const RDRAND_RETRIES: usize = 10;
+
+fn rdrand_available() -> bool {
+    // Host tests can not query the CPUID information of the platform, and
+    // every host able to run them supports RDRAND.
+    cfg!(test) || cpu_has_feat(Feature::Rdrand)
+}
+
+#[target_feature(enable = "rdrand")]
+fn rdrand64() -> Option<u64> {
+    for _ in 0..RDRAND_RETRIES {
+        let mut val = 0u64;
+        // SAFETY: the caller made sure the CPU supports RDRAND, and `val` is
+        // a valid location for the result.
+        if unsafe { _rdrand64_step(&mut val) } == 1 {
+            return Some(val);
+        }
+    }
+    None
+}
+
+/// Fills `buf` with random bytes.
+///
+/// # Returns
+///
+/// `Ok(())` on success, or `SvsmError::Rng` if the CPU does not support
+/// RDRAND or its entropy source keeps failing.
+pub fn fill_random(buf: &mut [u8]) -> Result<(), SvsmError> {
+    if !rdrand_available() {
+        return Err(SvsmError::Rng);
+    }
+
+    for chunk in buf.chunks_mut(size_of::<u64>()) {
+        // SAFETY: RDRAND support has been checked above.
+        let val = unsafe { rdrand64() }.ok_or(SvsmError::Rng)?;
+        chunk.copy_from_slice(&val.to_le_bytes()[..chunk.len()]);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_bytes_differ() {
+        let mut a = [0u8; 13];
+        let mut b = [0u8; 13];
+        fill_random(&mut a).unwrap();
+        fill_random(&mut b).unwrap();
+        assert_ne!(a, b);
+    }
+}
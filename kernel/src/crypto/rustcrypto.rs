@@ -13,14 +13,20 @@ use aes_gcm::{
     aead::{Aead, Payload},
 };
 use alloc::vec::Vec;
-use sha2::{Digest, Sha512};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha384, Sha512};
 
 use crate::error::SvsmError;
 use crate::{
     crypto::aead::{
         Aes256Gcm as CryptoAes256Gcm, Aes256GcmTrait as CryptoAes256GcmTrait, IV_SIZE, KEY_SIZE,
     },
-    crypto::digest::{Algorithm as CryptoHashTrait, Sha512 as CryptoSha512},
+    crypto::digest::{
+        Algorithm as CryptoHashTrait, Sha384 as CryptoSha384, Sha512 as CryptoSha512,
+    },
+    crypto::kdf::{HkdfSha256 as CryptoHkdfSha256, HkdfSha256Trait},
+    crypto::mac::{HMAC_SHA256_SIZE, HmacSha256 as CryptoHmacSha256, HmacSha256Trait},
 };
 
 #[repr(u64)]
@@ -81,8 +87,44 @@ impl CryptoAes256GcmTrait for CryptoAes256Gcm {
     }
 }
 
+impl CryptoHashTrait for CryptoSha384 {
+    fn digest(input: &[u8]) -> Vec<u8> {
+        Sha384::digest(input).to_vec()
+    }
+}
+
 impl CryptoHashTrait for CryptoSha512 {
     fn digest(input: &[u8]) -> Vec<u8> {
         Sha512::digest(input).to_vec()
     }
 }
+
+fn hmac_sha256(key: &[u8], input: &[u8]) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length.
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+    mac.update(input);
+    mac
+}
+
+impl HmacSha256Trait for CryptoHmacSha256 {
+    fn mac(key: &[u8], input: &[u8]) -> [u8; HMAC_SHA256_SIZE] {
+        hmac_sha256(key, input).finalize().into_bytes().into()
+    }
+
+    fn verify(key: &[u8], input: &[u8], tag: &[u8]) -> bool {
+        hmac_sha256(key, input).verify_slice(tag).is_ok()
+    }
+}
+
+impl HkdfSha256Trait for CryptoHkdfSha256 {
+    fn derive(
+        salt: Option<&[u8]>,
+        ikm: &[u8],
+        info: &[u8],
+        okm: &mut [u8],
+    ) -> Result<(), SvsmError> {
+        Hkdf::<Sha256>::new(salt, ikm)
+            .expand(info, okm)
+            .map_err(|_| SvsmError::InvalidParameter)
+    }
+}
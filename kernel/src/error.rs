@@ -25,6 +25,7 @@ use crate::cpu::vc::VcError;
 use crate::fs::FsError;
 use crate::fw_cfg::FwCfgError;
 use crate::insn_decode::InsnError;
+use crate::ipc::ChannelError;
 use crate::mm::alloc::AllocError;
 use crate::sev::SevSnpError;
 use crate::sev::ghcb::GhcbError;
@@ -132,6 +133,8 @@ pub enum SvsmError {
     FileSystem(FsError),
     /// Obj related error
     Obj(ObjError),
+    /// Errors related to IPC channels.
+    Channel(ChannelError),
     /// Task management errors,
     Task(TaskError),
     /// Errors from #VC handler
@@ -144,6 +147,10 @@ pub enum SvsmError {
     InvalidFormat,
     /// An encrypted message could not be decrypted.
     MessageDecryptionFailure,
+    /// No random numbers could be obtained.
+    Rng,
+    /// All event sources are registered already.
+    NoEventSources,
     /// Errors raised following an SNP guest request.
     SnpGuestRequest(u32),
     /// Generic errors related to APIC emulation.
@@ -200,6 +207,12 @@ impl From<ObjError> for SvsmError {
     }
 }
 
+impl From<ChannelError> for SvsmError {
+    fn from(err: ChannelError) -> Self {
+        Self::Channel(err)
+    }
+}
+
 #[cfg(feature = "virtio-drivers")]
 impl From<VirtioError> for SvsmError {
     fn from(err: VirtioError) -> Self {
@@ -228,12 +241,19 @@ impl From<SvsmError> for SysCallError {
             SvsmError::FileSystem(FsError::FileExists) => SysCallError::EEXIST,
             SvsmError::FileSystem(FsError::WriteOnly) => SysCallError::EWRONLY,
             SvsmError::FileSystem(FsError::ReadOnly) => SysCallError::ERDONLY,
+            SvsmError::FileSystem(FsError::NoSpace) => SysCallError::ENOSPC,
 
             SvsmError::FileSystem(FsError::FileNotFound) | SvsmError::Obj(ObjError::NotFound) => {
                 SysCallError::ENOTFOUND
             }
 
             SvsmError::NotSupported => SysCallError::ENOTSUPP,
+            SvsmError::Obj(ObjError::Busy) => SysCallError::EBUSY,
+
+            SvsmError::Channel(ChannelError::Empty | ChannelError::Full) => SysCallError::EAGAIN,
+            SvsmError::Channel(ChannelError::Closed) => SysCallError::EPIPE,
+            SvsmError::Channel(ChannelError::BufferTooSmall) => SysCallError::ENOSPC,
+            SvsmError::Channel(ChannelError::TooLarge) => SysCallError::EINVAL,
 
             SvsmError::FileSystem(FsError::Inval)
             | SvsmError::Obj(ObjError::InvalidHandle)
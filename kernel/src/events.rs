@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Event loop of the request-processing tasks.
+//!
+//! Besides servicing guest requests, the request-processing task of every
+//! CPU runs the handlers of the events signalled to that CPU. An event
+//! source is registered with a handler and can be signalled to any CPU from
+//! any context, including interrupt handlers. The task runs the handlers of
+//! the pending events before entering the guest, unless the guest waits for
+//! the response to a request, which is always delivered first.
+//!
+//! Signalling an event that is already pending on a CPU has no further
+//! effect, so a handler must process everything that has accumulated since
+//! it last ran.
+//!
+//! IPC channels can signal an event source when their state changes, see
+//! [`ChannelObj::set_notifier`].
+//!
+//! [`ChannelObj::set_notifier`]: crate::ipc::ChannelObj::set_notifier
+
+extern crate alloc;
+
+use crate::cpu::idt::common::SCHEDULE_VECTOR;
+use crate::cpu::percpu::{PERCPU_AREAS, this_cpu};
+use crate::cpu::x86::apic_post_irq;
+use crate::error::SvsmError;
+use crate::locking::RWLock;
+use alloc::sync::Arc;
+use cpuarch::x86apic::ApicIcr;
+
+/// Maximum number of registered event sources.
+const MAX_EVENT_SOURCES: usize = u64::BITS as usize;
+
+/// Function handling an event. It runs in the request-processing task of the
+/// CPU to which the event was signalled, so it may block, but the guest
+/// vCPU of that CPU can not run until it returns.
+pub type EventHandler = Arc<dyn Fn() + Send + Sync>;
+
+static EVENT_HANDLERS: RWLock<[Option<EventHandler>; MAX_EVENT_SOURCES]> =
+    RWLock::new([const { None }; MAX_EVENT_SOURCES]);
+
+/// A registered event source. Dropping it unregisters its handler.
+#[derive(Debug)]
+pub struct EventSource {
+    index: usize,
+}
+
+impl EventSource {
+    /// Registers a new event source whose events are handled by `handler`.
+    pub fn new(handler: impl Fn() + Send + Sync + 'static) -> Result<Self, SvsmError> {
+        let mut handlers = EVENT_HANDLERS.lock_write();
+        let index = handlers
+            .iter()
+            .position(Option::is_none)
+            .ok_or(SvsmError::NoEventSources)?;
+        handlers[index] = Some(Arc::new(handler));
+        Ok(Self { index })
+    }
+
+    const fn mask(&self) -> u64 {
+        1 << self.index
+    }
+
+    /// Signals the event to the request-processing task of the CPU with the
+    /// index `cpu_index`. If that is another CPU, it is interrupted so that
+    /// the task notices the event while it runs the guest or is idle.
+    pub fn signal(&self, cpu_index: usize) {
+        let cpu = PERCPU_AREAS.get_by_cpu_index(cpu_index);
+        let pending = cpu.signal_events(self.mask());
+        if pending & self.mask() == 0 && cpu_index != this_cpu().get_cpu_index() {
+            let icr = ApicIcr::new()
+                .with_vector(SCHEDULE_VECTOR as u8)
+                .with_destination(cpu.apic_id());
+            apic_post_irq(icr.into());
+        }
+    }
+
+    /// Signals the event to the request-processing task of the current CPU.
+    pub fn signal_local(&self) {
+        this_cpu().shared().signal_events(self.mask());
+    }
+}
+
+impl Drop for EventSource {
+    fn drop(&mut self) {
+        EVENT_HANDLERS.lock_write()[self.index] = None;
+    }
+}
+
+/// Checks whether events are pending on the current CPU.
+pub fn events_pending() -> bool {
+    this_cpu().shared().events_pending()
+}
+
+/// Runs the handlers of the events pending on the current CPU. Events which
+/// are signalled while the handlers run are handled by the next call.
+pub fn handle_events() {
+    let mut pending = this_cpu().shared().take_pending_events();
+    while pending != 0 {
+        let index = pending.trailing_zeros() as usize;
+        pending &= pending - 1;
+
+        // The lock is not held while the handler runs, so that the handler
+        // can register and unregister event sources. The event source may
+        // have been unregistered since the event was signalled.
+        let handler = EVENT_HANDLERS.lock_read()[index].clone();
+        if let Some(handler) = handler {
+            handler();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn register_sources() {
+        let first = EventSource::new(|| {}).unwrap();
+        let second = EventSource::new(|| {}).unwrap();
+        assert_ne!(first.mask(), second.mask());
+
+        // The slot of an unregistered source can be reused.
+        let index = first.index;
+        drop(first);
+        let sources = (0..MAX_EVENT_SOURCES - 1)
+            .map_while(|_| EventSource::new(|| {}).ok())
+            .collect::<Vec<_>>();
+        assert!(sources.iter().any(|source| source.index == index));
+        assert!(matches!(
+            EventSource::new(|| {}),
+            Err(SvsmError::NoEventSources)
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(not(test_in_svsm), ignore = "Can only be run inside guest")]
+    fn handle_signalled_events() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let source = EventSource::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+
+        source.signal_local();
+        source.signal_local();
+        assert!(events_pending());
+        handle_events();
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        handle_events();
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+}
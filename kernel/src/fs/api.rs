@@ -34,6 +34,7 @@ pub enum FsError {
     NotEmpty,
     IsFile,
     IsDir,
+    NoSpace,
     PackIt(PackItError),
 }
 
@@ -76,6 +77,7 @@ impl FsError {
     impl_fs_err!(not_empty, NotEmpty);
     impl_fs_err!(is_dir, IsDir);
     impl_fs_err!(is_file, IsFile);
+    impl_fs_err!(no_space, NoSpace);
 }
 
 /// Represents file operations
@@ -176,8 +178,32 @@ pub trait File: Debug + Send + Sync {
     }
 }
 
+/// Identifies a directory independently of the object representing it, see
+/// [`Directory::id`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirId {
+    owner: usize,
+    index: u64,
+}
+
+impl DirId {
+    /// Creates the identity of directory `index` of the filesystem or
+    /// directory object at `owner`.
+    pub fn new<T: ?Sized>(owner: *const T, index: u64) -> Self {
+        Self {
+            owner: owner.cast::<()>().addr(),
+            index,
+        }
+    }
+}
+
 /// Represents directory operations
 pub trait Directory: Debug + Send + Sync {
+    /// Returns the identity of the directory. All objects representing the
+    /// same directory return the same identity, which no other directory has
+    /// while one of these objects is alive.
+    fn id(&self) -> DirId;
+
     /// Used to get the list of entries in the directory.
     ///
     /// # Returns
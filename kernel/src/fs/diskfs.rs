@@ -0,0 +1,1245 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Persistent copy-on-write filesystem on a block device.
+//!
+//! [`DiskFs`] owns a fixed byte range of a [`BlockDriver`] and implements the
+//! [`Directory`] and [`File`] traits on top of it. The range is divided into
+//! 4 KiB blocks:
+//!
+//! * Blocks 0 and 1 are superblock slots. The valid superblock with the
+//!   highest generation is the current one. It points to the blocks of the
+//!   inode map.
+//! * The inode map translates inode numbers into the block holding the
+//!   inode. An inode records the type and size of a file or directory and the
+//!   blocks holding its data, using indirect blocks for larger files.
+//!   Directories store their entries as file data.
+//!
+//! Blocks reachable from the current superblock are never modified. Every
+//! operation writes new data, inodes and inode map blocks to free blocks and
+//! then commits them by writing a new superblock to the other slot, so an
+//! interrupted operation leaves the previous state intact. All metadata
+//! blocks carry a SHA-256 checksum over their location and contents.
+//!
+//! All metadata is cached in memory, and free space is computed when the
+//! filesystem is opened by walking all reachable blocks.
+
+extern crate alloc;
+
+use super::*;
+
+use crate::block::BlockDeviceError;
+use crate::block::api::BlockDriver;
+use crate::error::SvsmError;
+use crate::locking::RWLock;
+use crate::types::{PAGE_SHIFT, PAGE_SIZE};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use sha2::{Digest, Sha256};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+const FS_BLOCK_SIZE: usize = PAGE_SIZE;
+const FS_MAGIC: [u8; 8] = *b"SVSMFS\0\0";
+const FS_VERSION: u32 = 1;
+const INODE_MAGIC: [u8; 4] = *b"SFSI";
+const SB_SLOTS: u64 = 2;
+
+const CHECKSUM_SIZE: usize = 32;
+/// Bytes of a metadata block in front of its checksum.
+const META_PAYLOAD: usize = FS_BLOCK_SIZE - CHECKSUM_SIZE;
+/// Block pointers in an indirect or inode map block.
+const PTRS_PER_BLOCK: usize = META_PAYLOAD / size_of::<u64>();
+/// Inode map block pointers in the superblock.
+const SB_IMAP_PTRS: usize = (META_PAYLOAD - size_of::<SuperblockHeader>()) / size_of::<u64>();
+const MAX_INODES: usize = SB_IMAP_PTRS * PTRS_PER_BLOCK;
+/// Block pointers in an inode.
+const INODE_PTRS: usize = (META_PAYLOAD - size_of::<InodeHeader>()) / size_of::<u64>();
+/// Inode pointers referring to indirect blocks.
+const INODE_INDIRECT: usize = 16;
+const INODE_DIRECT: usize = INODE_PTRS - INODE_INDIRECT;
+const MAX_FILE_BLOCKS: usize = INODE_DIRECT + INODE_INDIRECT * PTRS_PER_BLOCK;
+
+const MAX_NAME_LEN: usize = 255;
+const ROOT_INO: usize = 0;
+/// Blocks kept free for operations which release space, so that files can
+/// still be removed from a full filesystem.
+const RESERVED_BLOCKS: usize = 16;
+const MIN_BLOCKS: u64 = 2 * RESERVED_BLOCKS as u64;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct SuperblockHeader {
+    magic: [u8; 8],
+    version: u32,
+    _reserved: u32,
+    /// Incremented on every commit.
+    generation: u64,
+    /// Size of the filesystem in blocks.
+    blocks: u64,
+    /// Number of inode map entries.
+    inodes: u64,
+    /// Identifier for the next inode to be created.
+    next_id: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct InodeHeader {
+    magic: [u8; 4],
+    kind: u32,
+    /// Unique identifier, which tells apart inodes reusing the same number.
+    id: u64,
+    size: u64,
+    /// Number of data block pointers.
+    blocks: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InodeKind {
+    File = 1,
+    Directory = 2,
+}
+
+impl TryFrom<u32> for InodeKind {
+    type Error = SvsmError;
+
+    fn try_from(kind: u32) -> Result<Self, Self::Error> {
+        match kind {
+            1 => Ok(Self::File),
+            2 => Ok(Self::Directory),
+            _ => Err(SvsmError::InvalidFormat),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct DirRecord {
+    name: FileName,
+    ino: usize,
+}
+
+#[derive(Debug)]
+struct Inode {
+    kind: InodeKind,
+    id: u64,
+    size: usize,
+    /// Block holding each block of data, or 0 for a hole.
+    blocks: Vec<u64>,
+    /// Blocks holding the inode and its indirect blocks on disk, empty if
+    /// the inode has not been written yet.
+    meta: Vec<u64>,
+    /// Entries of a directory.
+    entries: Vec<DirRecord>,
+    dirty: bool,
+    remove_in_progress: bool,
+}
+
+impl Inode {
+    fn new(kind: InodeKind, id: u64) -> Self {
+        Self {
+            kind,
+            id,
+            size: 0,
+            blocks: Vec::new(),
+            meta: Vec::new(),
+            entries: Vec::new(),
+            dirty: true,
+            remove_in_progress: false,
+        }
+    }
+
+    fn indirect_blocks(&self) -> usize {
+        self.blocks
+            .len()
+            .saturating_sub(INODE_DIRECT)
+            .div_ceil(PTRS_PER_BLOCK)
+    }
+}
+
+#[derive(Debug)]
+struct FsState {
+    generation: u64,
+    /// Superblock slot holding the current superblock.
+    sb_slot: u64,
+    next_id: u64,
+    /// Inodes by inode number.
+    inodes: Vec<Option<Inode>>,
+    /// Location of each inode map block.
+    imap: Vec<u64>,
+    imap_dirty: Vec<bool>,
+    /// Blocks in use by the committed or the pending state.
+    used: Vec<bool>,
+    free: usize,
+    /// Blocks which the current operation must leave free.
+    reserve: usize,
+    /// Blocks of the committed state which become free after the next
+    /// commit.
+    released: Vec<u64>,
+    /// Where to start searching for a free block.
+    next_alloc: usize,
+}
+
+impl FsState {
+    fn new(blocks: u64) -> Self {
+        let mut used = vec![false; blocks as usize];
+        used[..SB_SLOTS as usize].fill(true);
+        Self {
+            generation: 0,
+            sb_slot: SB_SLOTS - 1,
+            next_id: 0,
+            inodes: Vec::new(),
+            imap: Vec::new(),
+            imap_dirty: Vec::new(),
+            used,
+            free: blocks as usize - SB_SLOTS as usize,
+            reserve: RESERVED_BLOCKS,
+            released: Vec::new(),
+            next_alloc: SB_SLOTS as usize,
+        }
+    }
+
+    /// Marks a block referenced by the on-disk state as used.
+    fn claim(&mut self, block: u64) -> Result<(), SvsmError> {
+        match self.used.get_mut(block as usize) {
+            Some(used) if !*used => {
+                *used = true;
+                self.free -= 1;
+                Ok(())
+            }
+            _ => {
+                log::error!("diskfs: block {block} is invalid or referenced twice");
+                Err(SvsmError::InvalidFormat)
+            }
+        }
+    }
+
+    fn alloc(&mut self) -> Result<u64, SvsmError> {
+        if self.free <= self.reserve {
+            return Err(SvsmError::FileSystem(FsError::no_space()));
+        }
+        let len = self.used.len();
+        let block = (0..len)
+            .map(|i| (self.next_alloc + i) % len)
+            .find(|b| !self.used[*b])
+            .unwrap();
+        self.used[block] = true;
+        self.free -= 1;
+        self.next_alloc = block + 1;
+        Ok(block as u64)
+    }
+
+    /// Drops a reference to a block. The block can only be reused once the
+    /// state no longer referencing it has been committed.
+    fn release(&mut self, block: u64) {
+        if block != 0 {
+            self.released.push(block);
+        }
+    }
+
+    fn inode(&self, ino: usize, id: u64) -> Result<&Inode, SvsmError> {
+        self.inodes
+            .get(ino)
+            .and_then(Option::as_ref)
+            .filter(|inode| inode.id == id)
+            .ok_or(SvsmError::FileSystem(FsError::file_not_found()))
+    }
+
+    fn inode_mut(&mut self, ino: usize, id: u64) -> Result<&mut Inode, SvsmError> {
+        self.inodes
+            .get_mut(ino)
+            .and_then(Option::as_mut)
+            .filter(|inode| inode.id == id)
+            .ok_or(SvsmError::FileSystem(FsError::file_not_found()))
+    }
+
+    fn set_imap_dirty(&mut self, ino: usize) {
+        let imap_blocks = self.inodes.len().div_ceil(PTRS_PER_BLOCK);
+        self.imap.resize(imap_blocks, 0);
+        self.imap_dirty.resize(imap_blocks, true);
+        self.imap_dirty[ino / PTRS_PER_BLOCK] = true;
+    }
+}
+
+fn checksum(block: u64, data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    Sha256::new()
+        .chain_update(block.to_le_bytes())
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+fn seal(block: u64, buf: &mut [u8]) {
+    let sum = checksum(block, &buf[..META_PAYLOAD]);
+    buf[META_PAYLOAD..].copy_from_slice(&sum);
+}
+
+fn is_sealed(block: u64, buf: &[u8]) -> bool {
+    buf[META_PAYLOAD..] == checksum(block, &buf[..META_PAYLOAD])
+}
+
+fn write_ptrs(buf: &mut [u8], ptrs: &[u64]) {
+    for (chunk, ptr) in buf.chunks_exact_mut(size_of::<u64>()).zip(ptrs) {
+        chunk.copy_from_slice(&ptr.to_le_bytes());
+    }
+}
+
+fn read_ptrs(buf: &[u8], count: usize) -> impl Iterator<Item = u64> + '_ {
+    buf.chunks_exact(size_of::<u64>())
+        .take(count)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+}
+
+fn serialize_entries(entries: &[DirRecord]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        buf.extend_from_slice(&(entry.ino as u64).to_le_bytes());
+        buf.push(entry.name.len() as u8);
+        buf.extend_from_slice(entry.name.as_bytes());
+    }
+    buf
+}
+
+fn parse_entries(mut buf: &[u8]) -> Result<Vec<DirRecord>, SvsmError> {
+    let mut entries = Vec::new();
+    while !buf.is_empty() {
+        let (ino, rest) = u64::read_from_prefix(buf).map_err(|_| SvsmError::InvalidFormat)?;
+        let (&len, rest) = rest.split_first().ok_or(SvsmError::InvalidFormat)?;
+        let len = usize::from(len);
+        if rest.len() < len {
+            return Err(SvsmError::InvalidFormat);
+        }
+        let name = core::str::from_utf8(&rest[..len]).map_err(|_| SvsmError::InvalidFormat)?;
+        entries.push(DirRecord {
+            name: String::from(name),
+            ino: usize::try_from(ino).map_err(|_| SvsmError::InvalidFormat)?,
+        });
+        buf = &rest[len..];
+    }
+    Ok(entries)
+}
+
+fn check_name(name: &str) -> Result<(), SvsmError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name.contains('/') {
+        return Err(SvsmError::FileSystem(FsError::inval()));
+    }
+    Ok(())
+}
+
+/// A persistent filesystem in a fixed region of a block device.
+pub struct DiskFs {
+    dev: &'static dyn BlockDriver,
+    /// First device block of the region.
+    start_block: usize,
+    /// Size of the filesystem in blocks.
+    blocks: u64,
+    state: RWLock<FsState>,
+}
+
+impl core::fmt::Debug for DiskFs {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DiskFs")
+            .field("start_block", &self.start_block)
+            .field("blocks", &self.blocks)
+            .finish()
+    }
+}
+
+impl DiskFs {
+    /// Opens the filesystem stored in a region of `dev`, formatting the
+    /// region if it does not contain one yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `dev` - The block device to use.
+    /// * `offset` - Byte offset of the region, aligned to 4 KiB.
+    /// * `size` - Size of the region in bytes.
+    ///
+    /// # Returns
+    ///
+    /// The filesystem on success, `SvsmError::InvalidFormat` if the region
+    /// contains a damaged filesystem, or another error if the region can not
+    /// be used.
+    pub fn open(
+        dev: &'static dyn BlockDriver,
+        offset: usize,
+        size: usize,
+    ) -> Result<Arc<Self>, SvsmError> {
+        let block_size_log2 = usize::from(dev.block_size_log2());
+        let end = offset
+            .checked_add(size)
+            .ok_or(SvsmError::InvalidParameter)?;
+        let blocks = (size / FS_BLOCK_SIZE) as u64;
+        if block_size_log2 > PAGE_SHIFT
+            || offset % FS_BLOCK_SIZE != 0
+            || end > dev.size()
+            || blocks < MIN_BLOCKS
+        {
+            return Err(SvsmError::InvalidParameter);
+        }
+
+        let fs = Self {
+            dev,
+            start_block: offset >> block_size_log2,
+            blocks,
+            state: RWLock::new(FsState::new(blocks)),
+        };
+
+        {
+            let mut state = fs.state.lock_write();
+            match fs.load()? {
+                Some(loaded) => *state = loaded,
+                None => fs.format(&mut state)?,
+            }
+        }
+
+        Ok(Arc::new(fs))
+    }
+
+    /// Returns the root directory of the filesystem.
+    pub fn root(self: &Arc<Self>) -> Arc<dyn Directory> {
+        let id = self
+            .state
+            .lock_read()
+            .inode(ROOT_INO, 0)
+            .map_or(0, |i| i.id);
+        Arc::new(DiskDirectory {
+            fs: self.clone(),
+            ino: ROOT_INO,
+            id,
+        })
+    }
+
+    fn device_block(&self, block: u64) -> usize {
+        self.start_block + ((block as usize * FS_BLOCK_SIZE) >> self.dev.block_size_log2())
+    }
+
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<(), SvsmError> {
+        self.dev.read_blocks(self.device_block(block), buf)
+    }
+
+    fn write_block(&self, block: u64, buf: &[u8]) -> Result<(), SvsmError> {
+        self.dev.write_blocks(self.device_block(block), buf)
+    }
+
+    fn read_meta(&self, block: u64) -> Result<Vec<u8>, SvsmError> {
+        if block < SB_SLOTS || block >= self.blocks {
+            return Err(SvsmError::InvalidFormat);
+        }
+        let mut buf = vec![0u8; FS_BLOCK_SIZE];
+        self.read_block(block, &mut buf)?;
+        if !is_sealed(block, &buf) {
+            log::error!("diskfs: checksum mismatch in block {block}");
+            return Err(SvsmError::InvalidFormat);
+        }
+        Ok(buf)
+    }
+
+    /// Reads both superblock slots and returns the current superblock.
+    fn read_superblock(&self) -> Result<Option<(u64, SuperblockHeader, Vec<u64>)>, SvsmError> {
+        let mut found_magic = false;
+        let mut current: Option<(u64, SuperblockHeader, Vec<u64>)> = None;
+        let mut buf = vec![0u8; FS_BLOCK_SIZE];
+
+        for slot in 0..SB_SLOTS {
+            match self.read_block(slot, &mut buf) {
+                Ok(()) => (),
+                // The slot was being written when the guest stopped.
+                Err(SvsmError::Block(BlockDeviceError::Integrity)) => continue,
+                Err(e) => return Err(e),
+            }
+            let (header, rest) = SuperblockHeader::read_from_prefix(&buf).unwrap();
+            if header.magic != FS_MAGIC {
+                continue;
+            }
+            found_magic = true;
+
+            let inodes = usize::try_from(header.inodes).unwrap_or(usize::MAX);
+            if !is_sealed(slot, &buf)
+                || header.version != FS_VERSION
+                || header.blocks != self.blocks
+                || inodes > MAX_INODES
+            {
+                continue;
+            }
+            if current
+                .as_ref()
+                .is_none_or(|(_, cur, _)| header.generation > cur.generation)
+            {
+                let imap = read_ptrs(rest, inodes.div_ceil(PTRS_PER_BLOCK)).collect();
+                current = Some((slot, header, imap));
+            }
+        }
+
+        if current.is_none() && found_magic {
+            log::error!("diskfs: no valid superblock found");
+            return Err(SvsmError::InvalidFormat);
+        }
+
+        Ok(current)
+    }
+
+    /// Loads the committed state from the device.
+    ///
+    /// # Returns
+    ///
+    /// The state, or `None` if the region does not contain a filesystem.
+    fn load(&self) -> Result<Option<FsState>, SvsmError> {
+        let Some((slot, header, imap)) = self.read_superblock()? else {
+            return Ok(None);
+        };
+
+        let mut state = FsState::new(self.blocks);
+        state.generation = header.generation;
+        state.sb_slot = slot;
+        state.next_id = header.next_id;
+        state.inodes.resize_with(header.inodes as usize, || None);
+        state.imap_dirty = vec![false; imap.len()];
+
+        for (index, &imap_block) in imap.iter().enumerate() {
+            let buf = self.read_meta(imap_block)?;
+            state.claim(imap_block)?;
+            let first = index * PTRS_PER_BLOCK;
+            let count = min(PTRS_PER_BLOCK, state.inodes.len() - first);
+            for (ino, block) in (first..).zip(read_ptrs(&buf, count)) {
+                if block != 0 {
+                    state.inodes[ino] = Some(self.load_inode(&mut state, block)?);
+                }
+            }
+        }
+        state.imap = imap;
+
+        if state
+            .inodes
+            .get(ROOT_INO)
+            .and_then(Option::as_ref)
+            .is_none_or(|root| root.kind != InodeKind::Directory)
+        {
+            log::error!("diskfs: missing root directory");
+            return Err(SvsmError::InvalidFormat);
+        }
+
+        for ino in 0..state.inodes.len() {
+            let Some(inode) = state.inodes[ino].as_ref() else {
+                continue;
+            };
+            if inode.kind != InodeKind::Directory {
+                continue;
+            }
+            let mut data = vec![0u8; inode.size];
+            self.read_data(inode, &mut data, 0)?;
+            let entries = parse_entries(&data)?;
+            if entries
+                .iter()
+                .any(|e| state.inodes.get(e.ino).is_none_or(Option::is_none))
+            {
+                log::error!("diskfs: directory {ino} refers to a missing inode");
+                return Err(SvsmError::InvalidFormat);
+            }
+            state.inodes[ino].as_mut().unwrap().entries = entries;
+        }
+
+        Ok(Some(state))
+    }
+
+    fn load_inode(&self, state: &mut FsState, block: u64) -> Result<Inode, SvsmError> {
+        let buf = self.read_meta(block)?;
+        state.claim(block)?;
+
+        let (header, rest) = InodeHeader::read_from_prefix(&buf).unwrap();
+        let blocks = usize::try_from(header.blocks).unwrap_or(usize::MAX);
+        let size = usize::try_from(header.size).unwrap_or(usize::MAX);
+        if header.magic != INODE_MAGIC
+            || blocks > MAX_FILE_BLOCKS
+            || size.div_ceil(FS_BLOCK_SIZE) > blocks
+        {
+            return Err(SvsmError::InvalidFormat);
+        }
+
+        let mut inode = Inode::new(InodeKind::try_from(header.kind)?, header.id);
+        inode.size = size;
+        inode.dirty = false;
+        inode.meta.push(block);
+        inode
+            .blocks
+            .extend(read_ptrs(rest, min(blocks, INODE_DIRECT)));
+
+        let indirect: Vec<u64> =
+            read_ptrs(&rest[INODE_DIRECT * size_of::<u64>()..], INODE_INDIRECT)
+                .take(blocks.saturating_sub(INODE_DIRECT).div_ceil(PTRS_PER_BLOCK))
+                .collect();
+        for indirect_block in indirect {
+            let buf = self.read_meta(indirect_block)?;
+            state.claim(indirect_block)?;
+            inode.meta.push(indirect_block);
+            let count = min(PTRS_PER_BLOCK, blocks - inode.blocks.len());
+            inode.blocks.extend(read_ptrs(&buf, count));
+        }
+
+        for &data_block in inode.blocks.iter().filter(|b| **b != 0) {
+            state.claim(data_block)?;
+        }
+
+        Ok(inode)
+    }
+
+    fn format(&self, state: &mut FsState) -> Result<(), SvsmError> {
+        log::info!("diskfs: formatting {} blocks", self.blocks);
+        *state = FsState::new(self.blocks);
+        state.inodes.push(Some(Inode::new(InodeKind::Directory, 0)));
+        state.next_id = 1;
+        state.set_imap_dirty(ROOT_INO);
+        self.commit(state)
+    }
+
+    /// Writes all modified inodes and inode map blocks and a new superblock,
+    /// which makes all preceding changes visible atomically.
+    fn commit(&self, state: &mut FsState) -> Result<(), SvsmError> {
+        let mut buf = vec![0u8; FS_BLOCK_SIZE];
+
+        for ino in 0..state.inodes.len() {
+            if !state.inodes[ino].as_ref().is_some_and(|i| i.dirty) {
+                continue;
+            }
+
+            let indirect_count = state.inodes[ino].as_ref().unwrap().indirect_blocks();
+            let mut meta = Vec::new();
+            for _ in 0..=indirect_count {
+                meta.push(state.alloc()?);
+            }
+
+            let inode = state.inodes[ino].as_ref().unwrap();
+            for (index, &location) in meta[1..].iter().enumerate() {
+                let first = INODE_DIRECT + index * PTRS_PER_BLOCK;
+                let last = min(first + PTRS_PER_BLOCK, inode.blocks.len());
+                buf.fill(0);
+                write_ptrs(&mut buf, &inode.blocks[first..last]);
+                seal(location, &mut buf);
+                self.write_block(location, &buf)?;
+            }
+
+            let header = InodeHeader {
+                magic: INODE_MAGIC,
+                kind: inode.kind as u32,
+                id: inode.id,
+                size: inode.size as u64,
+                blocks: inode.blocks.len() as u64,
+            };
+            buf.fill(0);
+            buf[..size_of::<InodeHeader>()].copy_from_slice(header.as_bytes());
+            let ptrs = &mut buf[size_of::<InodeHeader>()..META_PAYLOAD];
+            let direct = &inode.blocks[..min(inode.blocks.len(), INODE_DIRECT)];
+            write_ptrs(ptrs, direct);
+            write_ptrs(&mut ptrs[INODE_DIRECT * size_of::<u64>()..], &meta[1..]);
+            seal(meta[0], &mut buf);
+            self.write_block(meta[0], &buf)?;
+
+            let inode = state.inodes[ino].as_mut().unwrap();
+            let old = core::mem::replace(&mut inode.meta, meta);
+            inode.dirty = false;
+            for block in old {
+                state.release(block);
+            }
+            state.set_imap_dirty(ino);
+        }
+
+        for index in 0..state.imap.len() {
+            if !state.imap_dirty[index] {
+                continue;
+            }
+            let location = state.alloc()?;
+            let first = index * PTRS_PER_BLOCK;
+            let last = min(first + PTRS_PER_BLOCK, state.inodes.len());
+            let ptrs: Vec<u64> = state.inodes[first..last]
+                .iter()
+                .map(|i| i.as_ref().map_or(0, |i| i.meta[0]))
+                .collect();
+            buf.fill(0);
+            write_ptrs(&mut buf, &ptrs);
+            seal(location, &mut buf);
+            self.write_block(location, &buf)?;
+
+            let old = core::mem::replace(&mut state.imap[index], location);
+            state.release(old);
+            state.imap_dirty[index] = false;
+        }
+        self.dev.flush()?;
+
+        let header = SuperblockHeader {
+            magic: FS_MAGIC,
+            version: FS_VERSION,
+            _reserved: 0,
+            generation: state.generation + 1,
+            blocks: self.blocks,
+            inodes: state.inodes.len() as u64,
+            next_id: state.next_id,
+        };
+        buf.fill(0);
+        buf[..size_of::<SuperblockHeader>()].copy_from_slice(header.as_bytes());
+        write_ptrs(&mut buf[size_of::<SuperblockHeader>()..], &state.imap);
+        let slot = state.sb_slot ^ 1;
+        seal(slot, &mut buf);
+        self.write_block(slot, &buf)?;
+        self.dev.flush()?;
+
+        state.generation = header.generation;
+        state.sb_slot = slot;
+        for block in core::mem::take(&mut state.released) {
+            state.used[block as usize] = false;
+            state.free += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Runs an operation modifying the filesystem and commits its changes.
+    /// If the operation or the commit fails, all changes are discarded.
+    fn modify<R>(
+        &self,
+        op: impl FnOnce(&mut FsState) -> Result<R, SvsmError>,
+    ) -> Result<R, SvsmError> {
+        let mut state = self.state.lock_write();
+        state.reserve = RESERVED_BLOCKS;
+        let result = op(&mut state).and_then(|r| self.commit(&mut state).map(|_| r));
+        if result.is_err() {
+            match self.load() {
+                Ok(Some(loaded)) => *state = loaded,
+                Ok(None) | Err(_) => log::error!("diskfs: failed to reload the filesystem"),
+            }
+        }
+        result
+    }
+
+    fn read_data(&self, inode: &Inode, buf: &mut [u8], offset: usize) -> Result<usize, SvsmError> {
+        let end = min(offset.saturating_add(buf.len()), inode.size);
+        let mut pos = offset;
+        let mut block_buf = vec![0u8; FS_BLOCK_SIZE];
+
+        while pos < end {
+            let index = pos / FS_BLOCK_SIZE;
+            let block_offset = pos % FS_BLOCK_SIZE;
+            let len = min(FS_BLOCK_SIZE - block_offset, end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match inode.blocks[index] {
+                0 => dst.fill(0),
+                block => {
+                    self.read_block(block, &mut block_buf)?;
+                    dst.copy_from_slice(&block_buf[block_offset..block_offset + len]);
+                }
+            }
+            pos += len;
+        }
+
+        Ok(end.saturating_sub(offset))
+    }
+
+    fn write_data(
+        &self,
+        state: &mut FsState,
+        ino: usize,
+        buf: &[u8],
+        offset: usize,
+    ) -> Result<usize, SvsmError> {
+        let end = offset
+            .checked_add(buf.len())
+            .ok_or(SvsmError::FileSystem(FsError::inval()))?;
+        let needed = end.div_ceil(FS_BLOCK_SIZE);
+        if needed > MAX_FILE_BLOCKS {
+            return Err(SvsmError::FileSystem(FsError::no_space()));
+        }
+
+        let inode = state.inodes[ino].as_mut().unwrap();
+        if inode.blocks.len() < needed {
+            inode.blocks.resize(needed, 0);
+        }
+
+        let mut pos = offset;
+        let mut block_buf = vec![0u8; FS_BLOCK_SIZE];
+        while pos < end {
+            let index = pos / FS_BLOCK_SIZE;
+            let block_offset = pos % FS_BLOCK_SIZE;
+            let len = min(FS_BLOCK_SIZE - block_offset, end - pos);
+            let old = state.inodes[ino].as_ref().unwrap().blocks[index];
+
+            if len == FS_BLOCK_SIZE || old == 0 {
+                block_buf.fill(0);
+            } else {
+                self.read_block(old, &mut block_buf)?;
+            }
+            block_buf[block_offset..block_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+
+            let new = state.alloc()?;
+            self.write_block(new, &block_buf)?;
+            state.release(old);
+            state.inodes[ino].as_mut().unwrap().blocks[index] = new;
+            pos += len;
+        }
+
+        let inode = state.inodes[ino].as_mut().unwrap();
+        inode.size = max(inode.size, end);
+        inode.dirty = true;
+
+        Ok(buf.len())
+    }
+
+    fn truncate_data(&self, state: &mut FsState, ino: usize, size: usize) -> Result<(), SvsmError> {
+        let inode = state.inodes[ino].as_mut().unwrap();
+        if size > inode.size {
+            return Err(SvsmError::FileSystem(FsError::inval()));
+        }
+
+        let keep = size.div_ceil(FS_BLOCK_SIZE);
+        let dropped = inode.blocks.split_off(keep);
+        inode.size = size;
+        inode.dirty = true;
+        for block in dropped {
+            state.release(block);
+        }
+
+        // Keep the bytes behind the end of the file zeroed.
+        let tail = size % FS_BLOCK_SIZE;
+        let last = state.inodes[ino].as_ref().unwrap().blocks.last().copied();
+        if let Some(old) = last.filter(|b| tail != 0 && *b != 0) {
+            let mut block_buf = vec![0u8; FS_BLOCK_SIZE];
+            self.read_block(old, &mut block_buf)?;
+            block_buf[tail..].fill(0);
+            let new = state.alloc()?;
+            self.write_block(new, &block_buf)?;
+            state.release(old);
+            *state.inodes[ino]
+                .as_mut()
+                .unwrap()
+                .blocks
+                .last_mut()
+                .unwrap() = new;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the entries of a directory to its data.
+    fn store_entries(&self, state: &mut FsState, ino: usize) -> Result<(), SvsmError> {
+        let data = serialize_entries(&state.inodes[ino].as_ref().unwrap().entries);
+        self.truncate_data(state, ino, 0)?;
+        self.write_data(state, ino, &data, 0)?;
+        Ok(())
+    }
+
+    fn create(
+        self: &Arc<Self>,
+        dir: usize,
+        dir_id: u64,
+        name: FileName,
+        kind: InodeKind,
+    ) -> Result<(usize, u64), SvsmError> {
+        check_name(&name)?;
+        self.modify(|state| {
+            let parent = state.inode(dir, dir_id)?;
+            if parent.remove_in_progress {
+                return Err(SvsmError::FileSystem(FsError::busy()));
+            }
+            if parent.entries.iter().any(|e| e.name == name) {
+                return Err(SvsmError::FileSystem(FsError::file_exists()));
+            }
+
+            let ino = match state.inodes.iter().position(Option::is_none) {
+                Some(ino) => ino,
+                None if state.inodes.len() < MAX_INODES => {
+                    state.inodes.push(None);
+                    state.inodes.len() - 1
+                }
+                None => return Err(SvsmError::FileSystem(FsError::no_space())),
+            };
+            let id = state.next_id;
+            state.next_id += 1;
+            state.inodes[ino] = Some(Inode::new(kind, id));
+
+            let parent = state.inode_mut(dir, dir_id)?;
+            parent.entries.push(DirRecord { name, ino });
+            self.store_entries(state, dir)?;
+
+            Ok((ino, id))
+        })
+    }
+
+    fn unlink(&self, dir: usize, dir_id: u64, name: &FileName) -> Result<(), SvsmError> {
+        self.modify(|state| {
+            state.reserve = 0;
+            let parent = state.inode_mut(dir, dir_id)?;
+            let pos = parent
+                .entries
+                .iter()
+                .position(|e| &e.name == name)
+                .ok_or(SvsmError::FileSystem(FsError::file_not_found()))?;
+            let ino = parent.entries[pos].ino;
+            if state.inodes[ino]
+                .as_ref()
+                .is_some_and(|i| !i.entries.is_empty())
+            {
+                return Err(SvsmError::FileSystem(FsError::not_empty()));
+            }
+
+            state.inode_mut(dir, dir_id)?.entries.swap_remove(pos);
+            self.store_entries(state, dir)?;
+
+            let inode = state.inodes[ino].take().unwrap();
+            for block in inode.blocks.into_iter().chain(inode.meta) {
+                state.release(block);
+            }
+            state.set_imap_dirty(ino);
+
+            Ok(())
+        })
+    }
+
+    fn lookup(
+        self: &Arc<Self>,
+        dir: usize,
+        dir_id: u64,
+        name: &FileName,
+    ) -> Result<DirEntry, SvsmError> {
+        let state = self.state.lock_read();
+        let entry = state
+            .inode(dir, dir_id)?
+            .entries
+            .iter()
+            .find(|e| &e.name == name)
+            .ok_or(SvsmError::FileSystem(FsError::file_not_found()))?;
+        let inode = state.inodes[entry.ino].as_ref().unwrap();
+
+        Ok(match inode.kind {
+            InodeKind::File => DirEntry::File(Arc::new(DiskFile {
+                fs: self.clone(),
+                ino: entry.ino,
+                id: inode.id,
+            })),
+            InodeKind::Directory => DirEntry::Directory(Arc::new(DiskDirectory {
+                fs: self.clone(),
+                ino: entry.ino,
+                id: inode.id,
+            })),
+        })
+    }
+}
+
+/// A file of a [`DiskFs`].
+#[derive(Debug)]
+struct DiskFile {
+    fs: Arc<DiskFs>,
+    ino: usize,
+    id: u64,
+}
+
+impl File for DiskFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize, SvsmError> {
+        let state = self.fs.state.lock_read();
+        self.fs
+            .read_data(state.inode(self.ino, self.id)?, buf, offset)
+    }
+
+    fn write(&self, buf: &[u8], offset: usize) -> Result<usize, SvsmError> {
+        self.fs.modify(|state| {
+            state.inode(self.ino, self.id)?;
+            self.fs.write_data(state, self.ino, buf, offset)
+        })
+    }
+
+    fn truncate(&self, size: usize) -> Result<usize, SvsmError> {
+        self.fs.modify(|state| {
+            state.inode(self.ino, self.id)?;
+            state.reserve = 0;
+            self.fs.truncate_data(state, self.ino, size)
+        })?;
+        Ok(size)
+    }
+
+    fn size(&self) -> usize {
+        let state = self.fs.state.lock_read();
+        state.inode(self.ino, self.id).map_or(0, |i| i.size)
+    }
+}
+
+/// A directory of a [`DiskFs`].
+#[derive(Debug)]
+struct DiskDirectory {
+    fs: Arc<DiskFs>,
+    ino: usize,
+    id: u64,
+}
+
+impl Directory for DiskDirectory {
+    fn id(&self) -> DirId {
+        // Every lookup returns a new object, but inode IDs are never reused.
+        DirId::new(Arc::as_ptr(&self.fs), self.id)
+    }
+
+    fn list(&self) -> Vec<FileName> {
+        let state = self.fs.state.lock_read();
+        state.inode(self.ino, self.id).map_or(Vec::new(), |dir| {
+            dir.entries.iter().map(|e| e.name.clone()).collect()
+        })
+    }
+
+    fn prepare_remove(&self) -> Result<(), SvsmError> {
+        let mut state = self.fs.state.lock_write();
+        let dir = state.inode_mut(self.ino, self.id)?;
+        if dir.remove_in_progress {
+            Err(SvsmError::FileSystem(FsError::busy()))
+        } else if !dir.entries.is_empty() {
+            Err(SvsmError::FileSystem(FsError::not_empty()))
+        } else {
+            dir.remove_in_progress = true;
+            Ok(())
+        }
+    }
+
+    fn lookup_entry(&self, name: &FileName) -> Result<DirEntry, SvsmError> {
+        self.fs.lookup(self.ino, self.id, name)
+    }
+
+    fn create_file(&self, name: FileName) -> Result<Arc<dyn File>, SvsmError> {
+        let (ino, id) = self.fs.create(self.ino, self.id, name, InodeKind::File)?;
+        Ok(Arc::new(DiskFile {
+            fs: self.fs.clone(),
+            ino,
+            id,
+        }))
+    }
+
+    fn create_directory(&self, name: FileName) -> Result<Arc<dyn Directory>, SvsmError> {
+        let (ino, id) = self
+            .fs
+            .create(self.ino, self.id, name, InodeKind::Directory)?;
+        Ok(Arc::new(DiskDirectory {
+            fs: self.fs.clone(),
+            ino,
+            id,
+        }))
+    }
+
+    fn unlink(&self, name: &FileName) -> Result<(), SvsmError> {
+        self.fs.unlink(self.ino, self.id, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::ramdisk::{pattern, ramdisk};
+    use crate::mm::alloc::{DEFAULT_TEST_MEMORY_SIZE, TestRootMem};
+
+    const DISK_SIZE: usize = 1024 * 1024;
+
+    fn read_file(dir: &Arc<dyn Directory>, name: &str) -> Vec<u8> {
+        let DirEntry::File(file) = dir.lookup_entry(&FileName::from(name)).unwrap() else {
+            panic!("{name} is not a file");
+        };
+        let mut buf = vec![0u8; file.size()];
+        assert_eq!(file.read(&mut buf, 0).unwrap(), buf.len());
+        buf
+    }
+
+    fn free_blocks(fs: &DiskFs) -> usize {
+        fs.state.lock_read().used.iter().filter(|u| !**u).count()
+    }
+
+    #[test]
+    fn persist_across_remount() {
+        let disk = ramdisk(DISK_SIZE);
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        let file = root.create_file(FileName::from("state")).unwrap();
+        file.write(b"hello", 0).unwrap();
+        file.write(b" world", 5).unwrap();
+        let sub = root.create_directory(FileName::from("sub")).unwrap();
+        let nested = sub.create_file(FileName::from("nested")).unwrap();
+        nested.write(&pattern(10000, 3), 100).unwrap();
+        drop((root, file, sub, nested, fs));
+
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        let mut list = root.list();
+        list.sort();
+        assert_eq!(list, [FileName::from("state"), FileName::from("sub")]);
+        assert_eq!(read_file(&root, "state"), b"hello world");
+
+        let DirEntry::Directory(sub) = root.lookup_entry(&FileName::from("sub")).unwrap() else {
+            panic!("sub is not a directory");
+        };
+        let data = read_file(&sub, "nested");
+        assert_eq!(data.len(), 10100);
+        assert!(data[..100].iter().all(|b| *b == 0));
+        assert_eq!(data[100..], pattern(10000, 3));
+    }
+
+    #[test]
+    fn large_file_and_truncate() {
+        let disk = ramdisk(DISK_SIZE);
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        let file = root.create_file(FileName::from("big")).unwrap();
+
+        // Large enough to need an indirect block.
+        let data = pattern((INODE_DIRECT + 10) * FS_BLOCK_SIZE / 4, 7);
+        file.write(&data, (INODE_DIRECT - 5) * FS_BLOCK_SIZE * 3 / 4)
+            .unwrap();
+        let offset = (INODE_DIRECT - 5) * FS_BLOCK_SIZE * 3 / 4;
+        let mut buf = vec![0u8; data.len()];
+        file.read(&mut buf, offset).unwrap();
+        assert_eq!(buf, data);
+
+        assert!(file.truncate(file.size() + 1).is_err());
+        assert_eq!(file.truncate(offset + 10).unwrap(), offset + 10);
+        drop((root, file, fs));
+
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        let DirEntry::File(file) = root.lookup_entry(&FileName::from("big")).unwrap() else {
+            panic!("big is not a file");
+        };
+        assert_eq!(file.size(), offset + 10);
+
+        // Growing the file again must not reveal the truncated data.
+        file.write(b"x", offset + 20).unwrap();
+        let mut buf = [0xffu8; 21];
+        file.read(&mut buf, offset).unwrap();
+        assert_eq!(buf[..10], data[..10]);
+        assert!(buf[10..20].iter().all(|b| *b == 0));
+        assert_eq!(buf[20], b'x');
+    }
+
+    #[test]
+    fn unlink_and_rmdir() {
+        let disk = ramdisk(DISK_SIZE);
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        let free = free_blocks(&fs);
+
+        let dir = root.create_directory(FileName::from("dir")).unwrap();
+        let file = dir.create_file(FileName::from("file")).unwrap();
+        file.write(&pattern(3 * FS_BLOCK_SIZE, 1), 0).unwrap();
+        assert!(matches!(
+            root.create_file(FileName::from("dir")),
+            Err(SvsmError::FileSystem(FsError::FileExists))
+        ));
+        assert!(matches!(
+            dir.prepare_remove(),
+            Err(SvsmError::FileSystem(FsError::NotEmpty))
+        ));
+
+        dir.unlink(&FileName::from("file")).unwrap();
+        assert!(file.write(b"stale", 0).is_err());
+        dir.prepare_remove().unwrap();
+        assert!(dir.create_file(FileName::from("late")).is_err());
+        root.unlink(&FileName::from("dir")).unwrap();
+        assert!(root.list().is_empty());
+
+        // Everything but the root directory has been released again.
+        assert_eq!(free_blocks(&fs), free);
+        assert_eq!(fs.state.lock_read().free, free);
+    }
+
+    #[test]
+    fn interrupted_update_keeps_previous_state() {
+        let disk = ramdisk(DISK_SIZE);
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        let file = root.create_file(FileName::from("file")).unwrap();
+        file.write(&pattern(2 * FS_BLOCK_SIZE, 1), 0).unwrap();
+        let superblocks = disk.with_data(|raw| raw[..2 * FS_BLOCK_SIZE].to_vec());
+
+        file.write(&pattern(2 * FS_BLOCK_SIZE, 2), 0).unwrap();
+        root.create_file(FileName::from("other")).unwrap();
+        drop((root, file, fs));
+
+        // Stop before any of the later superblocks got written.
+        disk.with_data(|raw| raw[..2 * FS_BLOCK_SIZE].copy_from_slice(&superblocks));
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        assert_eq!(root.list(), [FileName::from("file")]);
+        assert_eq!(read_file(&root, "file"), pattern(2 * FS_BLOCK_SIZE, 1));
+    }
+
+    #[test]
+    fn full_filesystem() {
+        let disk = ramdisk(DISK_SIZE);
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        let root = fs.root();
+        let keep = root.create_file(FileName::from("keep")).unwrap();
+        keep.write(b"data", 0).unwrap();
+
+        let file = root.create_file(FileName::from("fill")).unwrap();
+        let chunk = pattern(FS_BLOCK_SIZE, 5);
+        let mut offset = 0;
+        let err = loop {
+            match file.write(&chunk, offset) {
+                Ok(_) => offset += chunk.len(),
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(err, SvsmError::FileSystem(FsError::NoSpace)));
+        assert_eq!(file.size(), offset);
+
+        // The failed write did not leave anything behind.
+        drop((root, file, keep));
+        let root = fs.root();
+        assert_eq!(read_file(&root, "keep"), b"data");
+        root.unlink(&FileName::from("fill")).unwrap();
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        assert_eq!(fs.root().list(), [FileName::from("keep")]);
+    }
+
+    #[test]
+    fn corrupted_metadata() {
+        let disk = ramdisk(DISK_SIZE);
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        fs.root().create_file(FileName::from("file")).unwrap();
+        let root_block = fs.state.lock_read().inodes[ROOT_INO].as_ref().unwrap().meta[0];
+        drop(fs);
+
+        let offset = root_block as usize * FS_BLOCK_SIZE + 40;
+        disk.with_data(|raw| raw[offset] ^= 1);
+        assert!(matches!(
+            DiskFs::open(disk, 0, DISK_SIZE),
+            Err(SvsmError::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn mount_below_disk_directory() {
+        let _test_mem = TestRootMem::setup(DEFAULT_TEST_MEMORY_SIZE);
+        let _test_fs = TestFileSystemGuard::setup();
+
+        let disk = ramdisk(DISK_SIZE);
+        let fs = DiskFs::open(disk, 0, DISK_SIZE).unwrap();
+        mkdir("disk").unwrap();
+        mount("disk", fs.root()).unwrap();
+        mkdir("disk/sub").unwrap();
+
+        // Every lookup of `disk` returns a new directory object.
+        let other: Arc<dyn Directory> = Arc::new(RamDirectory::new());
+        mount("disk/sub", other.clone()).unwrap();
+        mount("disk/sub", other.clone()).unwrap_err();
+        create("disk/sub/file").unwrap();
+        assert_eq!(other.list(), [FileName::from("file")]);
+        rmdir("disk/sub").unwrap_err();
+
+        unmount("disk/sub").unwrap();
+        assert!(list_dir("disk/sub").unwrap().is_empty());
+        rmdir("disk/sub").unwrap();
+        unmount("disk").unwrap();
+        rmdir("disk").unwrap();
+    }
+
+    #[test]
+    fn invalid_region() {
+        let disk = ramdisk(DISK_SIZE);
+        assert!(DiskFs::open(disk, 512, 64 * 1024).is_err());
+        assert!(DiskFs::open(disk, 0, DISK_SIZE + FS_BLOCK_SIZE).is_err());
+        assert!(DiskFs::open(disk, 0, 4 * FS_BLOCK_SIZE).is_err());
+        assert!(DiskFs::open(disk, 64 * 1024, 256 * 1024).is_ok());
+    }
+}
@@ -14,6 +14,7 @@ use crate::mm::PageRef;
 use core::cmp::min;
 
 extern crate alloc;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -272,15 +273,40 @@ impl FileHandle {
     }
 }
 
+/// A filesystem attached to a directory of another filesystem.
+#[derive(Debug)]
+struct Mount {
+    /// Absolute path of the mount point, without leading or trailing
+    /// slashes.
+    path: String,
+    /// Directory containing the mount point. Holding it keeps its identity
+    /// from being reused.
+    parent: Arc<dyn Directory>,
+    /// Name of the mount point in `parent`.
+    name: FileName,
+    /// Root directory of the mounted filesystem.
+    root: Arc<dyn Directory>,
+}
+
+impl Mount {
+    fn covers(&self, parent: &Arc<dyn Directory>, name: &FileName) -> bool {
+        self.parent.id() == parent.id() && &self.name == name
+    }
+}
+
 /// Represents SVSM filesystem
 #[derive(Debug)]
 struct SvsmFs {
     root: Option<Arc<RamDirectory>>,
+    mounts: Vec<Mount>,
 }
 
 impl SvsmFs {
     const fn new() -> Self {
-        SvsmFs { root: None }
+        SvsmFs {
+            root: None,
+            mounts: Vec::new(),
+        }
     }
 
     /// Used to set the root directory of the SVSM filesystem.
@@ -297,6 +323,7 @@ impl SvsmFs {
     #[cfg(all(any(test, fuzzing), not(test_in_svsm)))]
     fn uninitialize(&mut self) {
         self.root = None;
+        self.mounts.clear();
     }
 
     /// Used to check if the filesystem is initialized.
@@ -317,6 +344,24 @@ impl SvsmFs {
         assert!(self.initialized());
         self.root.as_ref().unwrap().clone()
     }
+
+    /// Returns the root of the filesystem mounted on a directory entry.
+    ///
+    /// # Arguments
+    ///
+    /// - `parent`: directory containing the entry.
+    /// - `name`: name of the entry.
+    ///
+    /// # Returns
+    ///
+    /// [`Option<Arc<dyn Directory>>`]: root directory of the mounted
+    /// filesystem, or `None` if the entry is not a mount point.
+    fn mounted(&self, parent: &Arc<dyn Directory>, name: &FileName) -> Option<Arc<dyn Directory>> {
+        self.mounts
+            .iter()
+            .find(|m| m.covers(parent, name))
+            .map(|m| m.root.clone())
+    }
 }
 
 static FS_ROOT: RWLock<SvsmFs> = RWLock::new(SvsmFs::new());
@@ -396,6 +441,26 @@ fn split_path(path: &str) -> Result<impl DoubleEndedIterator<Item = &str>, SvsmE
     Ok(path_items)
 }
 
+/// Looks up an entry of a directory. If the entry is a mount point, the
+/// root directory of the filesystem mounted on it is returned instead.
+///
+/// # Arguments
+///
+/// - `dir`: directory to look up the entry in.
+/// - `name`: name of the entry.
+///
+/// # Returns
+///
+/// [`Result<DirEntry, SvsmError>`]: [`Result`] containing the entry if
+/// successful, or [`SvsmError`] if there is an error.
+fn lookup(dir: &Arc<dyn Directory>, name: &FileName) -> Result<DirEntry, SvsmError> {
+    let mounted = FS_ROOT.lock_read().mounted(dir, name);
+    match mounted {
+        Some(root) => Ok(DirEntry::Directory(root)),
+        None => dir.lookup_entry(name),
+    }
+}
+
 /// Used to perform a walk over the items in a path while checking
 /// each item is a directory, starting at the given directory.
 ///
@@ -416,7 +481,7 @@ where
 
     for item in path_items {
         let dir_name = FileName::from(item);
-        let dir_entry = current_dir.lookup_entry(&dir_name)?;
+        let dir_entry = lookup(&current_dir, &dir_name)?;
         current_dir = match dir_entry {
             DirEntry::File(_) => return Err(SvsmError::FileSystem(FsError::file_not_found())),
             DirEntry::Directory(dir) => dir,
@@ -472,8 +537,7 @@ where
 
     for item in path_items {
         let dir_name = FileName::from(item);
-        let lookup = current_dir.lookup_entry(&dir_name);
-        let dir_entry = match lookup {
+        let dir_entry = match lookup(&current_dir, &dir_name) {
             Ok(entry) => entry,
             Err(_) => DirEntry::Directory(current_dir.create_directory(dir_name)?),
         };
@@ -510,7 +574,7 @@ pub fn open_root(
     let file_name = FileName::from(path_items.next_back().unwrap());
     let current_dir = walk_path(root_dir, path_items)?;
 
-    let dir_entry = current_dir.lookup_entry(&file_name)?;
+    let dir_entry = lookup(&current_dir, &file_name)?;
 
     match dir_entry {
         DirEntry::Directory(_) => Err(SvsmError::FileSystem(FsError::file_not_found())),
@@ -716,7 +780,7 @@ pub fn unlink_root(root_dir: Arc<dyn Directory>, path: &str) -> Result<(), SvsmE
     let entry_name = FileName::from(path_items.next_back().unwrap());
     let dir = walk_path(root_dir, path_items)?;
 
-    match dir.lookup_entry(&entry_name)? {
+    match lookup(&dir, &entry_name)? {
         DirEntry::File(_) => dir.unlink(&entry_name),
         DirEntry::Directory(_) => Err(SvsmError::FileSystem(FsError::is_dir())),
     }
@@ -757,6 +821,10 @@ pub fn rmdir_root(root_dir: Arc<dyn Directory>, path: &str) -> Result<(), SvsmEr
     let entry_name = FileName::from(path_items.next_back().unwrap());
     let dir = walk_path(root_dir, path_items)?;
 
+    if FS_ROOT.lock_read().mounted(&dir, &entry_name).is_some() {
+        return Err(SvsmError::FileSystem(FsError::busy()));
+    }
+
     match dir.lookup_entry(&entry_name)? {
         DirEntry::File(_) => Err(SvsmError::FileSystem(FsError::is_file())),
         DirEntry::Directory(target) => {
@@ -785,6 +853,77 @@ pub fn rmdir(path: &str) -> Result<(), SvsmError> {
     rmdir_root(root_dir, path)
 }
 
+/// Attaches the filesystem with the given root directory to a path. The
+/// mount point must be an existing directory, whose contents are hidden
+/// until the filesystem is unmounted.
+///
+/// # Arguments
+///
+/// - `path`: absolute path of the mount point.
+/// - `root`: root directory of the filesystem to mount.
+///
+/// # Returns
+///
+/// [`Result<(), SvsmError>`]: [`Result`] containing the unit value if
+/// successful, [`SvsmError`] otherwise. Fails with [`FsError::Busy`] if
+/// another filesystem is already mounted on `path`.
+pub fn mount(path: &str, root: Arc<dyn Directory>) -> Result<(), SvsmError> {
+    let path_items: Vec<&str> = split_path(path)?.collect();
+    let (name, parent_items) = path_items.split_last().unwrap();
+    let name = FileName::from(*name);
+    let parent = walk_path_from_root(parent_items.iter().copied())?;
+
+    if let DirEntry::File(_) = lookup(&parent, &name)? {
+        return Err(SvsmError::FileSystem(FsError::is_file()));
+    }
+
+    let mut fs = FS_ROOT.lock_write();
+    if fs.mounted(&parent, &name).is_some() {
+        return Err(SvsmError::FileSystem(FsError::busy()));
+    }
+    fs.mounts.push(Mount {
+        path: path_items.join("/"),
+        parent,
+        name,
+        root,
+    });
+
+    Ok(())
+}
+
+/// Detaches the filesystem mounted on a path.
+///
+/// # Arguments
+///
+/// - `path`: absolute path of the mount point.
+///
+/// # Returns
+///
+/// [`Result<(), SvsmError>`]: [`Result`] containing the unit value if
+/// successful, [`SvsmError`] otherwise. Fails with [`FsError::Busy`] if
+/// other filesystems are mounted below `path`.
+pub fn unmount(path: &str) -> Result<(), SvsmError> {
+    let path = split_path(path)?.collect::<Vec<_>>().join("/");
+
+    let mut fs = FS_ROOT.lock_write();
+    let index = fs
+        .mounts
+        .iter()
+        .position(|m| m.path == path)
+        .ok_or(SvsmError::FileSystem(FsError::inval()))?;
+    let nested = fs.mounts.iter().any(|m| {
+        m.path
+            .strip_prefix(path.as_str())
+            .is_some_and(|rest| rest.starts_with('/'))
+    });
+    if nested {
+        return Err(SvsmError::FileSystem(FsError::busy()));
+    }
+    fs.mounts.remove(index);
+
+    Ok(())
+}
+
 /// Used to list the contents of a directory.
 ///
 /// # Argument
@@ -1121,4 +1260,75 @@ mod tests {
         // Cleanup
         unlink("file").unwrap();
     }
+
+    #[test]
+    fn mount_and_unmount() {
+        let _test_mem = TestRootMem::setup(DEFAULT_TEST_MEMORY_SIZE);
+        let _test_fs = TestFileSystemGuard::setup();
+
+        mkdir("mnt").unwrap();
+        create("mnt/hidden").unwrap();
+
+        // Mount points must be existing directories
+        let other: Arc<dyn Directory> = Arc::new(RamDirectory::new());
+        mount("missing", other.clone()).unwrap_err();
+        mount("mnt/hidden", other.clone()).unwrap_err();
+
+        mount("/mnt/", other.clone()).unwrap();
+        mount("mnt", other.clone()).unwrap_err();
+        assert!(list_dir("mnt").unwrap().is_empty());
+
+        // Paths below the mount point resolve in the mounted filesystem
+        mkdir("mnt/dir").unwrap();
+        let fh = create("mnt/dir/file").unwrap();
+        fh.write(b"data").unwrap();
+        assert_eq!(other.list(), [FileName::from("dir")]);
+        open_read("mnt/hidden").unwrap_err();
+
+        // The mount point can not be removed while in use
+        rmdir("mnt").unwrap_err();
+        unlink("mnt").unwrap_err();
+
+        // Nested mounts must be detached first
+        let nested: Arc<dyn Directory> = Arc::new(RamDirectory::new());
+        mount("mnt/dir", nested).unwrap();
+        assert!(list_dir("mnt/dir").unwrap().is_empty());
+        unmount("mnt").unwrap_err();
+        unmount("mnt/dir").unwrap();
+        assert_eq!(list_dir("mnt/dir").unwrap(), [FileName::from("file")]);
+
+        unmount("mnt").unwrap();
+        unmount("mnt").unwrap_err();
+        assert_eq!(list_dir("mnt").unwrap(), [FileName::from("hidden")]);
+
+        // Cleanup
+        unlink("mnt/hidden").unwrap();
+        rmdir("mnt").unwrap();
+    }
+
+    #[test]
+    fn find_dir_crosses_mounts() {
+        let _test_mem = TestRootMem::setup(DEFAULT_TEST_MEMORY_SIZE);
+        let _test_fs = TestFileSystemGuard::setup();
+
+        mkdir("a").unwrap();
+        mkdir("a/b").unwrap();
+        let other: Arc<dyn Directory> = Arc::new(RamDirectory::new());
+        mount("a/b", other.clone()).unwrap();
+        mkdir("a/b/c").unwrap();
+        create("a/b/c/file").unwrap();
+
+        // Walks starting below the global root still see the mount
+        let a = find_dir(opendir("/").unwrap(), "a").unwrap();
+        let c = find_dir(a.clone(), "b/c").unwrap();
+        assert_eq!(c.list(), [FileName::from("file")]);
+        open_root(a.clone(), "b/c/file", true, false).unwrap();
+        create_root(a.clone(), "b/new").unwrap();
+        assert_eq!(other.list(), [FileName::from("c"), FileName::from("new")]);
+
+        // Cleanup
+        unmount("a/b").unwrap();
+        rmdir("a/b").unwrap();
+        rmdir("a").unwrap();
+    }
 }
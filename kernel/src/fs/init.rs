@@ -5,16 +5,45 @@
 // Author: Joerg Roedel <jroedel@suse.de>
 
 use crate::address::{Address, PhysAddr};
+#[cfg(feature = "block")]
+use crate::block::{DATA_FS_REGION_OFFSET, DATA_FS_REGION_SIZE, secure_storage};
+use crate::crypto::digest::{Algorithm, Sha384};
 use crate::error::SvsmError;
 use crate::mm::ptguards::PerCPUPageMappingGuard;
+use crate::utils::immut_after_init::ImmutAfterInitCell;
 use packit::PackItArchiveDecoder;
 
 use super::*;
 
 extern crate alloc;
 use alloc::slice;
+#[cfg(feature = "block")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
-/// Used to create a SVSM RAM filesystem from a filesystem archive.
+/// Directory of the persistent filesystem which is mounted at `/var`.
+#[cfg(feature = "block")]
+const SHARED_DIR: &str = "var";
+/// Directory of the persistent filesystem holding the directories returned by
+/// [`persistent_private_dir`].
+#[cfg(feature = "block")]
+const PRIVATE_DIR: &str = "private";
+
+/// Root directory of the persistent filesystem, once it is mounted.
+#[cfg(feature = "block")]
+static PERSISTENT_ROOT: ImmutAfterInitCell<Arc<dyn Directory>> = ImmutAfterInitCell::uninit();
+
+/// SHA-384 digest of the filesystem archive unpacked by [`populate_ram_fs`].
+static FS_ARCHIVE_DIGEST: ImmutAfterInitCell<Vec<u8>> = ImmutAfterInitCell::uninit();
+
+/// Returns the SHA-384 digest of the filesystem archive, or `None` if no
+/// archive has been unpacked.
+pub fn fs_archive_digest() -> Option<&'static [u8]> {
+    FS_ARCHIVE_DIGEST.try_get_inner().ok().map(Vec::as_slice)
+}
+
+/// Used to create a SVSM RAM filesystem from a filesystem archive. The
+/// digest of the archive is kept for measuring it, see [`fs_archive_digest`].
 ///
 /// # Arguments
 ///
@@ -46,6 +75,7 @@ pub fn populate_ram_fs(kernel_fs_start: u64, kernel_fs_end: u64) -> Result<(), S
 
     // SAFETY: `vstart` is just mapped and the mapping covers the entire `size`
     let data: &[u8] = unsafe { slice::from_raw_parts(vstart.as_ptr(), size) };
+    FS_ARCHIVE_DIGEST.init(Sha384::digest(data))?;
     let archive = PackItArchiveDecoder::load(data)?;
 
     for file in archive {
@@ -65,3 +95,70 @@ pub fn populate_ram_fs(kernel_fs_start: u64, kernel_fs_end: u64) -> Result<(), S
 
     Ok(())
 }
+
+/// Returns the subdirectory `name` of `parent`, creating it if needed.
+#[cfg(feature = "block")]
+fn open_or_create_dir(
+    parent: &Arc<dyn Directory>,
+    name: &str,
+) -> Result<Arc<dyn Directory>, SvsmError> {
+    match parent.lookup_entry(&FileName::from(name)) {
+        Ok(DirEntry::Directory(dir)) => Ok(dir),
+        Ok(DirEntry::File(_)) => Err(SvsmError::FileSystem(FsError::is_file())),
+        Err(SvsmError::FileSystem(FsError::FileNotFound)) => {
+            parent.create_directory(FileName::from(name))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Mounts the persistent filesystem of the encrypted block device at `/var`.
+/// Only a subdirectory of the filesystem is mounted, the other one holds the
+/// directories returned by [`persistent_private_dir`].
+///
+/// # Returns
+/// [`Result<(), SvsmError>`]: A [`Result`] containing the unit value if
+/// successful or if there is no persistent storage, [`SvsmError`] otherwise.
+#[cfg(feature = "block")]
+pub fn mount_persistent_fs() -> Result<(), SvsmError> {
+    let Some(dev) = secure_storage() else {
+        return Ok(());
+    };
+
+    let size = core::cmp::min(
+        DATA_FS_REGION_SIZE,
+        dev.size().saturating_sub(DATA_FS_REGION_OFFSET),
+    );
+    let fs = DiskFs::open(dev, DATA_FS_REGION_OFFSET, size)?;
+    let root = fs.root();
+    let shared = open_or_create_dir(&root, SHARED_DIR)?;
+
+    match mkdir("var") {
+        Err(SvsmError::FileSystem(FsError::FileExists)) | Ok(()) => (),
+        Err(e) => return Err(e),
+    }
+    mount("var", shared)?;
+    PERSISTENT_ROOT.init(root)?;
+
+    log::info!("Persistent filesystem mounted at /var");
+
+    Ok(())
+}
+
+/// Returns the private directory `name` of the persistent filesystem,
+/// creating it if needed. The directory is not reachable from the root of the
+/// SVSM filesystem, so only the processes started with it as their root
+/// directory can access it.
+///
+/// # Returns
+/// [`Result<Option<Arc<dyn Directory>>, SvsmError>`]: A [`Result`] containing
+/// the directory, or `None` if the persistent filesystem is not mounted, or
+/// [`SvsmError`] if the directory can not be created.
+#[cfg(feature = "block")]
+pub fn persistent_private_dir(name: &str) -> Result<Option<Arc<dyn Directory>>, SvsmError> {
+    let Ok(root) = PERSISTENT_ROOT.try_get_inner() else {
+        return Ok(None);
+    };
+    let private = open_or_create_dir(root, PRIVATE_DIR)?;
+    open_or_create_dir(&private, name).map(Some)
+}
@@ -7,6 +7,8 @@
 mod api;
 mod buffer;
 mod console;
+#[cfg(feature = "block")]
+mod diskfs;
 mod filesystem;
 mod init;
 mod obj;
@@ -15,6 +17,11 @@ mod ramfs;
 pub use api::*;
 pub use buffer::*;
 pub use console::{ConsoleFile, stdout_open};
+#[cfg(feature = "block")]
+pub use diskfs::DiskFs;
 pub use filesystem::*;
-pub use init::populate_ram_fs;
+pub use init::{fs_archive_digest, populate_ram_fs};
+#[cfg(feature = "block")]
+pub use init::{mount_persistent_fs, persistent_private_dir};
 pub use obj::FsObj;
+pub use ramfs::RamDirectory;
@@ -7,7 +7,10 @@
 extern crate alloc;
 
 use super::{Buffer, DirEntry, Directory, FileHandle, FileName};
+use crate::address::VirtAddr;
 use crate::error::SvsmError;
+use crate::mm::mmap_user;
+use crate::mm::vm::VMFileMappingFlags;
 use crate::syscall::Obj;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -121,6 +124,20 @@ impl FsObj {
         fh.truncate(length)
     }
 
+    pub fn mmap(
+        &self,
+        addr: VirtAddr,
+        offset: usize,
+        size: usize,
+        flags: VMFileMappingFlags,
+    ) -> Result<VirtAddr, SvsmError> {
+        let FsObjEntry::File(fh) = &self.entry else {
+            return Err(SvsmError::NotSupported);
+        };
+
+        mmap_user(addr, Some(fh), offset, size, flags)
+    }
+
     pub fn readdir(&self) -> Result<Option<(FileName, DirEntry)>, SvsmError> {
         let FsObjEntry::Directory(dh) = &self.entry else {
             return Err(SvsmError::NotSupported);
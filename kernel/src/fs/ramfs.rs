@@ -435,7 +435,18 @@ impl RamDirectory {
     }
 }
 
+impl Default for RamDirectory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Directory for RamDirectory {
+    fn id(&self) -> DirId {
+        // Lookups return the same object for a directory.
+        DirId::new(self, 0)
+    }
+
     fn list(&self) -> Vec<FileName> {
         self.directory.lock_read().list()
     }
@@ -6,6 +6,9 @@
 
 //! API to send `SNP_GUEST_REQUEST` commands to the PSP
 
+extern crate alloc;
+
+use alloc::vec;
 use zerocopy::FromBytes;
 
 use crate::error::SvsmError;
@@ -75,6 +78,23 @@ pub fn get_regular_report(buffer: &mut [u8]) -> Result<usize, SvsmError> {
     get_report(buffer, None)
 }
 
+/// Request the launch measurement of the guest to the PSP.
+///
+/// The measurement is taken from a regular VMPL0 attestation report, see
+/// [`get_regular_report()`].
+///
+/// # Returns
+///
+/// The launch measurement on success, or an [`SvsmError`].
+pub fn get_launch_measurement() -> Result<[u8; 48], SvsmError> {
+    // A zeroed MSG_REPORT_REQ requests a VMPL0 report without user data.
+    let mut buffer = vec![0u8; REPORT_RESPONSE_SIZE];
+    get_regular_report(&mut buffer)?;
+    let (response, _rest) =
+        SnpReportResponse::ref_from_prefix(&buffer).map_err(|_| SvsmError::InvalidParameter)?;
+    Ok(*response.measurement())
+}
+
 /// Request an extended VMPL0 attestation report to the PSP.
 ///
 /// We say that it is extended because it requests a VMPL0 attestation report
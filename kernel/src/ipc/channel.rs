@@ -0,0 +1,437 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Message channels between tasks.
+//!
+//! A channel has two ends, each represented by a [`ChannelObj`]. Messages sent
+//! on one end are queued on the other end until they are received there. The
+//! queue of each end is bounded, so a sender can not exhaust kernel memory.
+//! Messages can carry objects, which allows passing object handles between
+//! processes. Instead of polling, the kernel can have the events of an end
+//! signalled to the event loop of a CPU.
+
+extern crate alloc;
+
+use crate::cpu::IrqGuard;
+use crate::cpu::percpu::current_task;
+use crate::error::SvsmError;
+use crate::events::EventSource;
+use crate::locking::SpinLock;
+use crate::syscall::Obj;
+use crate::task::{WaitQueue, block_current_task, wake_tasks};
+use crate::time::monotonic;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+use syscall::{CHANNEL_HANDLES_MAX, CHANNEL_MSG_MAX, ChannelEvents};
+
+/// Maximum number of messages queued on one end of a channel.
+pub const CHANNEL_QUEUE_MAX: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ChannelError {
+    /// No message is queued.
+    Empty,
+    /// The queue of the receiving end is full.
+    Full,
+    /// The other end of the channel has been closed.
+    Closed,
+    /// The message exceeds the size or handle limits of a channel.
+    TooLarge,
+    /// The next message does not fit into the receive buffers.
+    BufferTooSmall,
+}
+
+/// A message sent over a channel.
+#[derive(Debug, Default)]
+pub struct Message {
+    pub data: Vec<u8>,
+    pub objs: Vec<Arc<dyn Obj>>,
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    /// Messages queued for each end of the channel.
+    queues: [VecDeque<Message>; 2],
+    /// Whether each end of the channel has been closed.
+    closed: [bool; 2],
+    /// Tasks polling either end of the channel.
+    waiters: WaitQueue,
+    /// Event sources signalled, together with the CPU to signal them to,
+    /// when the events of each end change.
+    notifiers: [Option<(Arc<EventSource>, usize)>; 2],
+}
+
+impl ChannelState {
+    fn notifier(&self, side: usize) -> Option<(Arc<EventSource>, usize)> {
+        self.notifiers[side].clone()
+    }
+    fn events(&self, side: usize) -> ChannelEvents {
+        let peer = side ^ 1;
+        let mut events = ChannelEvents::empty();
+        if !self.queues[side].is_empty() {
+            events |= ChannelEvents::READABLE;
+        }
+        if self.closed[peer] {
+            events |= ChannelEvents::CLOSED;
+        } else if self.queues[peer].len() < CHANNEL_QUEUE_MAX {
+            events |= ChannelEvents::WRITABLE;
+        }
+        events
+    }
+}
+
+/// One end of a channel.
+#[derive(Debug)]
+pub struct ChannelObj {
+    state: Arc<SpinLock<ChannelState>>,
+    side: usize,
+}
+
+impl ChannelObj {
+    /// Creates a new channel.
+    ///
+    /// # Returns
+    ///
+    /// Both ends of the new channel.
+    pub fn new_pair() -> (Self, Self) {
+        let state = Arc::new(SpinLock::new(ChannelState::default()));
+        (
+            Self {
+                state: state.clone(),
+                side: 0,
+            },
+            Self { state, side: 1 },
+        )
+    }
+
+    fn peer(&self) -> usize {
+        self.side ^ 1
+    }
+
+    /// Returns whether `other` is an end of the same channel as `self`.
+    pub fn same_channel(&self, other: &ChannelObj) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+
+    /// Queues a message on the other end of the channel without blocking.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, or an error if the message exceeds the channel
+    /// limits, the queue of the other end is full or the other end has been
+    /// closed.
+    pub fn send(&self, msg: Message) -> Result<(), SvsmError> {
+        if msg.data.len() > CHANNEL_MSG_MAX || msg.objs.len() > CHANNEL_HANDLES_MAX {
+            return Err(ChannelError::TooLarge.into());
+        }
+
+        let mut state = self.state.lock();
+        let peer = self.peer();
+        if state.closed[peer] {
+            return Err(ChannelError::Closed.into());
+        }
+        if state.queues[peer].len() >= CHANNEL_QUEUE_MAX {
+            return Err(ChannelError::Full.into());
+        }
+        state.queues[peer].push_back(msg);
+        let woken = state.waiters.wakeup_all();
+        let notifier = state.notifier(peer);
+        drop(state);
+
+        wake_tasks(woken);
+        notify(notifier);
+        Ok(())
+    }
+
+    /// Removes the next message from the queue of this end without blocking.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_data` - Maximum size of the message data accepted.
+    /// * `max_objs` - Maximum number of objects accepted.
+    ///
+    /// # Returns
+    ///
+    /// The message, or an error if no message is queued or the next message
+    /// exceeds the given limits. In the latter case the message stays queued.
+    pub fn recv(&self, max_data: usize, max_objs: usize) -> Result<Message, SvsmError> {
+        let mut state = self.state.lock();
+        let Some(next) = state.queues[self.side].front() else {
+            return Err(if state.closed[self.peer()] {
+                ChannelError::Closed
+            } else {
+                ChannelError::Empty
+            }
+            .into());
+        };
+        if next.data.len() > max_data || next.objs.len() > max_objs {
+            return Err(ChannelError::BufferTooSmall.into());
+        }
+        let msg = state.queues[self.side].pop_front().unwrap();
+
+        // Senders may be waiting for the queue to drain.
+        let woken = state.waiters.wakeup_all();
+        let notifier = state.notifier(self.peer());
+        drop(state);
+
+        wake_tasks(woken);
+        notify(notifier);
+        Ok(msg)
+    }
+
+    /// Puts a message returned by [`Self::recv`] back to the front of the
+    /// queue of this end, e.g. because it could not be delivered. The queue
+    /// may exceed [`CHANNEL_QUEUE_MAX`] if messages have been sent since.
+    pub fn requeue(&self, msg: Message) {
+        self.state.lock().queues[self.side].push_front(msg);
+    }
+
+    /// Signals `source` to the CPU with the index `cpu_index` whenever the
+    /// other end sends or receives a message or is closed, replacing any
+    /// previous notifier. The handler of the event must check the events of
+    /// this end, see [`Self::events`].
+    pub fn set_notifier(&self, source: Arc<EventSource>, cpu_index: usize) {
+        self.state.lock().notifiers[self.side] = Some((source, cpu_index));
+    }
+
+    /// Returns the events currently pending on this end of the channel.
+    pub fn events(&self) -> ChannelEvents {
+        self.state.lock().events(self.side)
+    }
+
+    /// Blocks the current task until at least one of `events` is pending on
+    /// this end of the channel. [`ChannelEvents::CLOSED`] is always
+    /// considered pending once the other end has been closed.
+    ///
+    /// # Returns
+    ///
+    /// The pending events out of `events` and [`ChannelEvents::CLOSED`].
+    pub fn poll(&self, events: ChannelEvents) -> ChannelEvents {
+        self.poll_until(events, None)
+    }
+
+    /// Like [`Self::poll`], but gives up once `timeout` has elapsed.
+    ///
+    /// # Returns
+    ///
+    /// The pending events out of `events` and [`ChannelEvents::CLOSED`],
+    /// which are empty if the timeout has elapsed.
+    pub fn poll_timeout(&self, events: ChannelEvents, timeout: Duration) -> ChannelEvents {
+        self.poll_until(events, Some(monotonic() + timeout))
+    }
+
+    fn poll_until(&self, events: ChannelEvents, deadline: Option<Duration>) -> ChannelEvents {
+        let events = events | ChannelEvents::CLOSED;
+        loop {
+            let mut pending = ChannelEvents::empty();
+            let mut timer = None;
+            block_current_task(|| {
+                // Interrupts must be disabled before joining the wait queue,
+                // see `Task::wait_for_exit()`.
+                let guard = IrqGuard::new();
+                let mut state = self.state.lock();
+                pending = state.events(self.side) & events;
+                if !pending.is_empty() {
+                    return None;
+                }
+                match deadline {
+                    None => state.waiters.wait_for_event(current_task()),
+                    Some(deadline) => {
+                        let left = deadline.saturating_sub(monotonic());
+                        if left.is_zero() {
+                            return None;
+                        }
+                        timer = Some(state.waiters.wait_for_event_timeout(current_task(), left));
+                    }
+                }
+                Some(guard)
+            });
+            drop(timer);
+
+            // Tasks are woken on any change of the channel state, so check
+            // the events again after waking up.
+            if !pending.is_empty() || deadline.is_some_and(|d| monotonic() >= d) {
+                return pending;
+            }
+        }
+    }
+}
+
+fn notify(notifier: Option<(Arc<EventSource>, usize)>) {
+    if let Some((source, cpu_index)) = notifier {
+        source.signal(cpu_index);
+    }
+}
+
+impl Drop for ChannelObj {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.closed[self.side] = true;
+        let queued = core::mem::take(&mut state.queues[self.side]);
+        let woken = state.waiters.wakeup_all();
+        state.notifiers[self.side] = None;
+        let notifier = state.notifier(self.peer());
+        drop(state);
+
+        // Queued messages may hold other channel ends, so drop them without
+        // holding the lock.
+        drop(queued);
+        wake_tasks(woken);
+        notify(notifier);
+    }
+}
+
+impl Obj for ChannelObj {
+    fn as_channel(&self) -> Option<&ChannelObj> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn message(data: &[u8]) -> Message {
+        Message {
+            data: data.to_vec(),
+            objs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn send_and_receive() {
+        let (a, b) = ChannelObj::new_pair();
+        assert_eq!(a.events(), ChannelEvents::WRITABLE);
+
+        a.send(message(b"ping")).unwrap();
+        assert_eq!(
+            b.events(),
+            ChannelEvents::READABLE | ChannelEvents::WRITABLE
+        );
+        assert_eq!(a.events(), ChannelEvents::WRITABLE);
+        assert!(matches!(
+            b.recv(3, 0),
+            Err(SvsmError::Channel(ChannelError::BufferTooSmall))
+        ));
+        assert_eq!(b.recv(CHANNEL_MSG_MAX, 0).unwrap().data, b"ping");
+        assert!(matches!(
+            b.recv(CHANNEL_MSG_MAX, 0),
+            Err(SvsmError::Channel(ChannelError::Empty))
+        ));
+
+        b.send(message(b"pong")).unwrap();
+        assert_eq!(a.recv(CHANNEL_MSG_MAX, 0).unwrap().data, b"pong");
+
+        let too_large = Message {
+            data: vec![0; CHANNEL_MSG_MAX + 1],
+            objs: Vec::new(),
+        };
+        assert!(matches!(
+            a.send(too_large),
+            Err(SvsmError::Channel(ChannelError::TooLarge))
+        ));
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let (a, b) = ChannelObj::new_pair();
+        for i in 0..CHANNEL_QUEUE_MAX {
+            a.send(message(&[i as u8])).unwrap();
+        }
+        assert_eq!(a.events(), ChannelEvents::empty());
+        assert!(matches!(
+            a.send(message(b"")),
+            Err(SvsmError::Channel(ChannelError::Full))
+        ));
+
+        assert_eq!(b.recv(1, 0).unwrap().data, [0]);
+        assert_eq!(a.events(), ChannelEvents::WRITABLE);
+    }
+
+    #[test]
+    fn requeue_keeps_order() {
+        let (a, b) = ChannelObj::new_pair();
+        a.send(message(b"first")).unwrap();
+        a.send(message(b"second")).unwrap();
+
+        let msg = b.recv(CHANNEL_MSG_MAX, 0).unwrap();
+        b.requeue(msg);
+        assert_eq!(b.recv(CHANNEL_MSG_MAX, 0).unwrap().data, b"first");
+        assert_eq!(b.recv(CHANNEL_MSG_MAX, 0).unwrap().data, b"second");
+    }
+
+    #[test]
+    fn closed_peer() {
+        let (a, b) = ChannelObj::new_pair();
+        a.send(message(b"last")).unwrap();
+        drop(a);
+
+        assert_eq!(b.events(), ChannelEvents::READABLE | ChannelEvents::CLOSED);
+        assert!(matches!(
+            b.send(message(b"")),
+            Err(SvsmError::Channel(ChannelError::Closed))
+        ));
+        // Queued messages can still be received.
+        assert_eq!(b.recv(4, 0).unwrap().data, b"last");
+        assert!(matches!(
+            b.recv(4, 0),
+            Err(SvsmError::Channel(ChannelError::Closed))
+        ));
+    }
+
+    #[test]
+    fn pass_objects() {
+        let (a, b) = ChannelObj::new_pair();
+        let (c, d) = ChannelObj::new_pair();
+        let msg = Message {
+            data: Vec::new(),
+            objs: vec![Arc::new(d)],
+        };
+        a.send(msg).unwrap();
+        assert!(matches!(
+            b.recv(0, 0),
+            Err(SvsmError::Channel(ChannelError::BufferTooSmall))
+        ));
+
+        let mut msg = b.recv(0, 1).unwrap();
+        let d = msg.objs.pop().unwrap();
+        let d = d.as_channel().unwrap();
+        assert!(d.same_channel(&c));
+        assert!(!d.same_channel(&a));
+        c.send(message(b"via")).unwrap();
+        assert_eq!(d.recv(3, 0).unwrap().data, b"via");
+    }
+
+    #[test]
+    #[cfg_attr(not(test_in_svsm), ignore = "Can only be run inside guest")]
+    fn notify_event_loop() {
+        use crate::cpu::percpu::this_cpu;
+        use crate::events::handle_events;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let source = EventSource::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+
+        let (a, b) = ChannelObj::new_pair();
+        b.set_notifier(Arc::new(source), this_cpu().get_cpu_index());
+        a.send(message(b"ping")).unwrap();
+        handle_events();
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        // Only operations on the other end are notified.
+        b.recv(4, 0).unwrap();
+        handle_events();
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        drop(a);
+        handle_events();
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+}
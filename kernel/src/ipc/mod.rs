@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Inter-process communication between user-mode tasks and the kernel.
+
+mod channel;
+
+pub use channel::{CHANNEL_QUEUE_MAX, ChannelError, ChannelObj, Message};
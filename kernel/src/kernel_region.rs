@@ -6,6 +6,10 @@
 
 use crate::address::PhysAddr;
 use crate::address::VirtAddr;
+use crate::cpu::percpu::this_cpu;
+use crate::error::SvsmError;
+use crate::mm::alloc::add_memory_zone;
+use crate::mm::dynamic_map_addr;
 use crate::mm::pagetable::PTEntry;
 use crate::mm::pagetable::PTEntryFlags;
 use crate::mm::pagetable::PTPage;
@@ -172,3 +176,42 @@ pub unsafe fn expand_kernel_heap(
 
     allocated_pages
 }
+
+/// Adds the part of the full kernel region `kernel_region` which the kernel
+/// heap does not cover to the page allocator as a memory zone. The heap can
+/// only be expanded in 2 MB steps, so the end of the kernel region is left
+/// unused otherwise.
+///
+/// # Safety
+/// The caller is required to ensure that `kernel_region` is the full kernel
+/// region described by the boot parameters and that the launch information
+/// reflects the final size of the kernel heap.
+pub unsafe fn add_unused_kernel_region(
+    launch_info: &KernelLaunchInfo,
+    kernel_region: MemoryRegion<PhysAddr>,
+    platform: &dyn SvsmPlatform,
+) -> Result<(), SvsmError> {
+    // A memory zone needs at least one page besides its metadata.
+    let heap_end = PhysAddr::from(launch_info.kernel_region_phys_end);
+    if kernel_region.end() < heap_end + 2 * PAGE_SIZE {
+        return Ok(());
+    }
+
+    let region = MemoryRegion::from_addresses(heap_end, kernel_region.end());
+    let vaddr = dynamic_map_addr(region.start()).ok_or(SvsmError::InvalidAddress)?;
+    dynamic_map_addr(region.end() - 1).ok_or(SvsmError::InvalidAddress)?;
+    let vregion = MemoryRegion::new(vaddr, region.len());
+    this_cpu()
+        .get_pgtable()
+        .map_region_4k(vregion, region.start(), PTEntryFlags::data(), false)?;
+
+    // SAFETY: the memory is part of the kernel region, so it is not
+    // accessible to the guest, and nothing else uses it.
+    unsafe {
+        platform.validate_virtual_page_range(vregion, PageValidateOp::Validate)?;
+        add_memory_zone(region.start(), vaddr, region.len() / PAGE_SIZE)?;
+    }
+
+    log::info!("Unused kernel region {region:#018x} added to the page allocator");
+    Ok(())
+}
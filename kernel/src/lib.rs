@@ -26,12 +26,14 @@ pub mod cpu;
 pub mod crypto;
 pub mod debug;
 pub mod error;
+pub mod events;
 pub mod fs;
 pub mod fw_cfg;
 pub mod greq;
 pub mod hyperv;
 pub mod insn_decode;
 pub mod io;
+pub mod ipc;
 pub mod kernel_region;
 pub mod locking;
 pub mod mm;
@@ -43,7 +45,10 @@ pub mod sev;
 pub mod svsm_paging;
 pub mod syscall;
 pub mod task;
+#[cfg(any(feature = "vtpm", feature = "vtpm-user"))]
+pub mod tcg;
 pub mod tdx;
+pub mod time;
 pub mod types;
 pub mod utils;
 #[cfg(feature = "virtio-drivers")]
@@ -51,7 +56,7 @@ pub mod virtio;
 pub mod vmm;
 #[cfg(feature = "vsock")]
 pub mod vsock;
-#[cfg(all(feature = "vtpm", not(test)))]
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
 pub mod vtpm;
 
 #[test]
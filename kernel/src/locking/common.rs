@@ -3,14 +3,14 @@
 // Copyright (c) 2024 SUSE LLC
 //
 // Author: Joerg Roedel <jroedel@suse.de>
-use crate::cpu::{IrqGuard, TprGuard};
+use crate::cpu::{IrqGuard, PreemptGuard, TprGuard};
 use core::marker::PhantomData;
 
 /// Abstracts TPR and interrupt state handling when taking and releasing
 /// locks. There are three implemenations:
 ///
-///   * [IrqUnsafeLocking] implements the methods as no-ops and does not change
-///     any IRQ or TPR state.
+///   * [IrqUnsafeLocking] does not change any IRQ or TPR state. It only
+///     disables preemption of the current task while the lock is held.
 ///   * [IrqGuardLocking] actually disables and enables IRQs in the methods,
 ///     ensuring that no interrupt can be taken while the lock is held.
 ///   * [TprGuardLocking] raises and lowers TPR while the lock is held,
@@ -28,15 +28,24 @@ pub trait IrqLocking {
     fn acquire_lock() -> Self;
 }
 
-/// Implements the IRQ state handling methods as no-ops. Locks defined with
-/// this state handler are not safe with respect to reentrancy due to
-/// interrupt delivery.
+/// Implements the IRQ state handling methods without changing the IRQ state.
+/// Locks defined with this state handler are not safe with respect to
+/// reentrancy due to interrupt delivery. They disable preemption, so that the
+/// holder can not be switched out in favor of a task contending for the lock
+/// on the same CPU.
 #[derive(Debug, Default)]
-pub struct IrqUnsafeLocking;
+pub struct IrqUnsafeLocking {
+    /// PreemptGuard to keep track of the preemption state. PreemptGuard
+    /// implements Drop, which will enable preemption again when the struct
+    /// goes out of scope.
+    _guard: PreemptGuard,
+}
 
 impl IrqLocking for IrqUnsafeLocking {
     fn acquire_lock() -> Self {
-        Self {}
+        Self {
+            _guard: PreemptGuard::new(),
+        }
     }
 }
 
@@ -5,10 +5,12 @@
 // Author: Joerg Roedel <jroedel@suse.de>
 
 pub mod common;
+pub mod mutex;
 pub mod rwlock;
 pub mod spinlock;
 
 pub use common::{IrqGuardLocking, IrqLocking, TprGuardLocking};
+pub use mutex::{Mutex, MutexGuard};
 pub use rwlock::{
     RWLock, RWLockAnyTpr, RWLockIrqSafe, RWLockTpr, ReadLockGuard, ReadLockGuardAnyTpr,
     ReadLockGuardIrqSafe, WriteLockGuard, WriteLockGuardAnyTpr, WriteLockGuardIrqSafe,
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+use super::SpinLock;
+use crate::cpu::IrqGuard;
+use crate::task::{WaitQueue, block_current_task, current_task, wake_tasks};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+#[derive(Debug)]
+struct MutexState {
+    locked: bool,
+    /// Tasks waiting for the lock.
+    waiters: WaitQueue,
+}
+
+/// A mutual exclusion lock whose waiters block instead of spinning, so that
+/// it can be held while the holder waits for I/O or for another task. Unlike
+/// a [`SpinLock`], it can only be taken by tasks which are allowed to block,
+/// unless it is free.
+///
+/// # Examples
+///
+/// ```
+/// use svsm::locking::Mutex;
+///
+/// let mutex = Mutex::new(0);
+/// *mutex.lock() += 1;
+/// assert_eq!(*mutex.lock(), 1);
+/// ```
+#[derive(Debug)]
+pub struct Mutex<T> {
+    state: SpinLock<MutexState>,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: Mutex guarantees mutually exclusive access to the wrapped data.
+unsafe impl<T: Send> Send for Mutex<T> {}
+// SAFETY: Mutex guarantees mutually exclusive access to the wrapped data.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates a new unlocked `Mutex` protecting `data`.
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: SpinLock::new(MutexState {
+                locked: false,
+                waiters: WaitQueue::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Acquires the lock, blocking the current task until it is available.
+    ///
+    /// # Returns
+    ///
+    /// A [`MutexGuard`] which releases the lock when dropped.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if let Some(guard) = self.try_lock() {
+            return guard;
+        }
+
+        while block_current_task(|| {
+            // Interrupts must be disabled before joining the wait queue, see
+            // `Task::wait_for_exit()`.
+            let guard = IrqGuard::new();
+            let mut state = self.state.lock();
+            if !state.locked {
+                state.locked = true;
+                return None;
+            }
+            state.waiters.wait_for_event(current_task());
+            Some(guard)
+        }) {}
+
+        MutexGuard { mutex: self }
+    }
+
+    /// Tries to acquire the lock without blocking.
+    ///
+    /// # Returns
+    ///
+    /// A [`MutexGuard`] if the lock was free, `None` otherwise.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if state.locked {
+            return None;
+        }
+        state.locked = true;
+        Some(MutexGuard { mutex: self })
+    }
+}
+
+/// A guard providing exclusive access to the data protected by a [`Mutex`].
+/// The lock is released when the guard is dropped.
+#[derive(Debug)]
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let woken = {
+            let mut state = self.mutex.state.lock();
+            state.locked = false;
+            state.waiters.wakeup()
+        };
+        if let Some(task) = woken {
+            wake_tasks([task]);
+        }
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard holds the lock, so there are no other references
+        // to the data.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The guard holds the lock, so there are no other references
+        // to the data.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mutex() {
+        let mutex = Mutex::new(0);
+
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert_eq!(*guard, 1);
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+
+        let guard = mutex.try_lock().unwrap();
+        assert_eq!(*guard, 1);
+    }
+}
@@ -109,8 +109,18 @@ pub fn virt_to_frame(vaddr: VirtAddr) -> PageFrame {
     }
 }
 
+/// Returns the virtual address at which `paddr` is mapped.
+///
+/// # Panics
+///
+/// Panics if `paddr` is neither part of a fixed mapping, nor of a memory
+/// zone, nor of a deposited page.
 #[cfg(target_os = "none")]
 pub fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    use crate::mm::alloc::zone_phys_to_virt;
+    use crate::mm::deposit::is_deposited_region;
+    use crate::utils::MemoryRegion;
+
     if let Some(addr) = FIXED_MAPPING.kernel_mapping.phys_to_virt(paddr) {
         return addr;
     }
@@ -119,10 +129,29 @@ pub fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
             return addr;
         }
     }
+    // Memory added at runtime is only mapped while it is part of a memory
+    // zone or deposited, other addresses in the range of the dynamic mapping
+    // must not be translated.
+    if let Some(addr) = zone_phys_to_virt(paddr) {
+        return addr;
+    }
+    if is_deposited_region(&MemoryRegion::new(paddr, 1)) {
+        if let Some(addr) = dynamic_map_addr(paddr) {
+            return addr;
+        }
+    }
 
     panic!("Invalid physical address {:#018x}", paddr);
 }
 
+/// Returns the address at which `paddr` is mapped once it has been added to
+/// the memory of the SVSM at runtime, or `None` if it is out of the range of
+/// such memory.
+pub fn dynamic_map_addr(paddr: PhysAddr) -> Option<VirtAddr> {
+    let offset = usize::try_from(u64::from(paddr)).ok()?;
+    (offset < SVSM_DYNAMIC_MAP_END - SVSM_DYNAMIC_MAP_BASE).then(|| SVSM_DYNAMIC_MAP_BASE + offset)
+}
+
 #[cfg(not(target_os = "none"))]
 pub fn virt_to_page_frame(vaddr: VirtAddr) -> PhysAddr {
     use crate::address::Address;
@@ -184,6 +213,17 @@ pub const SVSM_GLOBAL_MAPPING_END: VirtAddr = SVSM_GLOBAL_MAPPING_BASE.const_add
 /// Mapping address for Hyper-V hypercall page.
 pub const SVSM_HYPERCALL_CODE_PAGE: VirtAddr = SVSM_GLOBAL_MAPPING_BASE.const_sub(PAGE_SIZE);
 
+/// Start of the mapping of the memory which is added to the SVSM at runtime,
+/// either deposited by the guest or left over by the kernel heap. Such memory
+/// is mapped at this address plus its physical address.
+pub const SVSM_DYNAMIC_MAP_BASE: VirtAddr = SVSM_GLOBAL_BASE.const_add(320 * SIZE_1G);
+
+/// End of the mapping of memory added at runtime, which limits the physical
+/// addresses of such memory. The window covers the first 128 GiB of physical
+/// memory; deposits of guest memory above this limit are rejected and unused
+/// kernel memory above it is not handed to the allocator.
+pub const SVSM_DYNAMIC_MAP_END: VirtAddr = SVSM_DYNAMIC_MAP_BASE.const_add(128 * SIZE_1G);
+
 /// PerCPU mappings level 3 index
 pub const PGTABLE_LVL3_IDX_PERCPU: usize = 510;
 
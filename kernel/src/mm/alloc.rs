@@ -8,20 +8,18 @@ use crate::address::{Address, PhysAddr, VirtAddr};
 use crate::cpu::mem::{unsafe_copy_bytes, write_bytes};
 use crate::error::SvsmError;
 use crate::fs::Buffer;
-use crate::locking::SpinLock;
-use crate::mm::virt_to_phys;
+use crate::locking::{LockGuard, SpinLock};
+use crate::mm::{SVSM_DYNAMIC_MAP_BASE, SVSM_DYNAMIC_MAP_END, virt_to_phys};
 use crate::types::{PAGE_SHIFT, PAGE_SIZE};
 #[cfg_attr(verus_keep_ghost, allow(unused_imports))]
 use crate::utils::tcb_ptr::{ptr_read, ptr_write};
-use crate::utils::{align_down, align_up, zero_mem_region};
+use crate::utils::{MemoryRegion, align_down, align_up, zero_mem_region};
 use core::alloc::{GlobalAlloc, Layout};
 use core::mem::{MaybeUninit, size_of};
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use core::{cmp, ptr, slice};
 
-#[cfg(any(test, fuzzing))]
-use crate::locking::LockGuard;
-
 use verus_stub::*;
 
 #[cfg(verus_keep_ghost)]
@@ -1456,6 +1454,12 @@ impl HeapMemoryRegion {
         }
     }
 
+    /// Checks whether none of the pages of the region are allocated.
+    #[verus_verify(external_body)]
+    fn is_free(&self) -> bool {
+        self.free_pages == self.nr_pages
+    }
+
     /// Initializes memory by marking certain pages as reserved and the rest
     /// as allocated. It then frees all pages and organizes them into their
     /// respective order buckets.
@@ -1717,22 +1721,128 @@ pub fn print_memory_info(info: &MemInfo) {
     );
 }
 
+/// Maximum number of memory zones, including the root memory region.
+pub const MAX_MEMORY_ZONES: usize = 32;
+
+/// Memory zones managed by the page allocator, each of which is a separate
+/// [`HeapMemoryRegion`]. The first zone is the root memory region set up at
+/// boot. Further zones are added at runtime and are unused while their page
+/// count is zero.
+static MEMORY_ZONES: [SpinLock<HeapMemoryRegion>; MAX_MEMORY_ZONES] =
+    [const { SpinLock::new(HeapMemoryRegion::new()) }; MAX_MEMORY_ZONES];
+
 /// Static spinlock-protected instance of [`HeapMemoryRegion`] representing the
 /// root memory region.
-static ROOT_MEM: SpinLock<HeapMemoryRegion> = SpinLock::new(HeapMemoryRegion::new());
+static ROOT_MEM: &SpinLock<HeapMemoryRegion> = &MEMORY_ZONES[0];
+
+/// Address range of a memory zone, readable without taking the lock of the
+/// zone. A zone is unused while its page count is zero.
+#[derive(Debug)]
+struct ZoneBounds {
+    start_phys: AtomicU64,
+    start_virt: AtomicUsize,
+    page_count: AtomicUsize,
+}
+
+impl ZoneBounds {
+    const fn new() -> Self {
+        Self {
+            start_phys: AtomicU64::new(0),
+            start_virt: AtomicUsize::new(0),
+            page_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Publishes the range of `zone`. Must be called with the lock of the
+    /// zone held, after the zone has been set up or before it is torn down.
+    fn set(&self, zone: &HeapMemoryRegion) {
+        self.page_count.store(0, Ordering::Release);
+        if zone.page_count != 0 {
+            self.start_phys.store(u64::from(zone.start_phys), Ordering::Relaxed);
+            self.start_virt.store(zone.start_virt.bits(), Ordering::Relaxed);
+            self.page_count.store(zone.page_count, Ordering::Release);
+        }
+    }
+
+    /// Returns the physical and virtual start addresses and the size of the
+    /// zone, or `None` if it is unused.
+    fn get(&self) -> Option<(PhysAddr, VirtAddr, usize)> {
+        let page_count = self.page_count.load(Ordering::Acquire);
+        (page_count != 0).then(|| {
+            (
+                PhysAddr::from(self.start_phys.load(Ordering::Relaxed)),
+                VirtAddr::from(self.start_virt.load(Ordering::Relaxed)),
+                page_count * PAGE_SIZE,
+            )
+        })
+    }
+}
+
+/// Bounds of the zones in [`MEMORY_ZONES`] with the same index.
+static ZONE_BOUNDS: [ZoneBounds; MAX_MEMORY_ZONES] =
+    [const { ZoneBounds::new() }; MAX_MEMORY_ZONES];
+
+/// Returns the virtual address of `paddr` if it is part of a memory zone.
+///
+/// No lock is taken, so this can be used while a zone is locked, e.g. when
+/// walking page tables which have been allocated from a zone. The caller
+/// must own memory at `paddr`, which keeps its zone from being removed.
+pub fn zone_phys_to_virt(paddr: PhysAddr) -> Option<VirtAddr> {
+    ZONE_BOUNDS.iter().find_map(|bounds| {
+        let (start_phys, start_virt, len) = bounds.get()?;
+        (paddr >= start_phys && paddr - start_phys < len)
+            .then(|| start_virt + (paddr - start_phys))
+    })
+}
+
+/// Returns the locked memory zone which contains `vaddr`, or `None` if the
+/// address is not part of any zone. The zone is looked up in
+/// [`ZONE_BOUNDS`], so only its own lock is taken.
+fn memory_zone(vaddr: VirtAddr) -> Option<LockGuard<'static, HeapMemoryRegion>> {
+    let index = ZONE_BOUNDS.iter().position(|bounds| {
+        bounds
+            .get()
+            .is_some_and(|(_, start_virt, len)| vaddr >= start_virt && vaddr - start_virt < len)
+    })?;
+    // The zone may have changed since its bounds were read.
+    let zone = MEMORY_ZONES[index].lock();
+    zone.get_virt_offset(vaddr).is_some().then_some(zone)
+}
+
+/// Performs an allocation in the memory zones, starting with the root memory
+/// region and moving on to the next zone as long as the allocation fails
+/// because a zone is exhausted.
+fn allocate_from_zones<F>(alloc: F) -> Result<VirtAddr, AllocError>
+where
+    F: Fn(&mut HeapMemoryRegion) -> Result<VirtAddr, AllocError>,
+{
+    let mut result = Err(AllocError::OutOfMemory);
+    for zone in MEMORY_ZONES.iter() {
+        let mut zone = zone.lock();
+        if zone.page_count == 0 {
+            continue;
+        }
+        result = alloc(&mut zone);
+        if !matches!(result, Err(AllocError::OutOfMemory)) {
+            break;
+        }
+    }
+    result
+}
 
-/// Allocates a single memory page from the root memory region.
+/// Allocates a single memory page from the memory zones.
 ///
 /// # Returns
 ///
 /// Result containing the virtual address of the allocated page or an
 /// `SvsmError` if allocation fails.
 pub fn allocate_page() -> Result<VirtAddr, SvsmError> {
-    Ok(ROOT_MEM.lock().allocate_page()?)
+    let result = allocate_from_zones(|zone| zone.allocate_page());
+    Ok(result.or_else(allocate_deposited_page)?)
 }
 
-/// Allocates multiple memory pages with a specified order from the root
-/// memory region.
+/// Allocates multiple memory pages with a specified order from the memory
+/// zones.
 ///
 /// # Arguments
 ///
@@ -1743,7 +1853,12 @@ pub fn allocate_page() -> Result<VirtAddr, SvsmError> {
 /// Result containing the virtual address of the allocated pages or an
 /// `SvsmError` if allocation fails.
 pub fn allocate_pages(order: usize) -> Result<VirtAddr, SvsmError> {
-    Ok(ROOT_MEM.lock().allocate_pages(order)?)
+    let result = allocate_from_zones(|zone| zone.allocate_pages(order));
+    if order == 0 {
+        Ok(result.or_else(allocate_deposited_page)?)
+    } else {
+        Ok(result?)
+    }
 }
 
 /// Allocate a zeroed page.
@@ -1753,7 +1868,16 @@ pub fn allocate_pages(order: usize) -> Result<VirtAddr, SvsmError> {
 /// Result containing the virtual address of the allocated zeroed page or an
 /// `SvsmError` if allocation fails.
 pub fn allocate_zeroed_page() -> Result<VirtAddr, SvsmError> {
-    Ok(ROOT_MEM.lock().allocate_zeroed_page()?)
+    let result = allocate_from_zones(|zone| zone.allocate_zeroed_page());
+    Ok(result.or_else(|err| {
+        let vaddr = allocate_deposited_page(err)?;
+        // SAFETY: the page has just been allocated, so nothing else refers
+        // to it.
+        unsafe {
+            zero_mem_region(vaddr, vaddr + PAGE_SIZE);
+        }
+        Ok::<_, AllocError>(vaddr)
+    })?)
 }
 
 /// Allocate a file page.
@@ -1763,7 +1887,7 @@ pub fn allocate_zeroed_page() -> Result<VirtAddr, SvsmError> {
 /// Result containing the virtual address of the allocated file page or an
 /// `SvsmError` if allocation fails.
 pub fn allocate_file_page() -> Result<VirtAddr, SvsmError> {
-    let vaddr = ROOT_MEM.lock().allocate_file_page()?;
+    let vaddr = allocate_from_zones(|zone| zone.allocate_file_page())?;
 
     // SAFETY: we trust allocate_file_page() to return a pointer to a valid
     // page. vaddr + PAGE_SIZE also correctly points to the end of the
@@ -1776,29 +1900,213 @@ pub fn allocate_file_page() -> Result<VirtAddr, SvsmError> {
 }
 
 fn get_file_page(vaddr: VirtAddr) -> Result<(), SvsmError> {
-    Ok(ROOT_MEM.lock().get_file_page(vaddr)?)
+    let mut zone = memory_zone(vaddr).ok_or(AllocError::InvalidHeapAddress(vaddr))?;
+    Ok(zone.get_file_page(vaddr)?)
 }
 
 fn put_file_page(vaddr: VirtAddr) -> Result<(), SvsmError> {
-    Ok(ROOT_MEM.lock().put_file_page(vaddr)?)
+    let mut zone = memory_zone(vaddr).ok_or(AllocError::InvalidHeapAddress(vaddr))?;
+    Ok(zone.put_file_page(vaddr)?)
 }
 
 /// Free the page at the given virtual address.
 pub fn free_page(vaddr: VirtAddr) {
-    ROOT_MEM.lock().free_page(vaddr)
+    if let Some(mut zone) = memory_zone(vaddr) {
+        zone.free_page(vaddr)
+    } else if is_deposited_page(vaddr) {
+        DEPOSITED_PAGES.lock().push(vaddr);
+    } else {
+        ROOT_MEM.lock().free_page(vaddr)
+    }
 }
 
 /// Free multiple pages at the given virtual address.
 pub fn free_multiple_pages(vaddr: VirtAddr, count: usize) {
-    let mut mem = ROOT_MEM.lock();
     for offset in 0..count {
-        mem.free_page(vaddr + offset * PAGE_SIZE);
+        free_page(vaddr + offset * PAGE_SIZE);
     }
 }
 
-/// Retrieve information about the root memory
+/// Retrieve information about the memory of all zones
 pub fn memory_info() -> MemInfo {
-    ROOT_MEM.lock().memory_info()
+    memory_zones_info().fold(MemInfo::default(), |mut info, zone| {
+        for i in 0..MAX_ORDER {
+            info.total_pages[i] += zone.mem_info.total_pages[i];
+            info.free_pages[i] += zone.mem_info.free_pages[i];
+        }
+        info
+    })
+}
+
+/// Information about a memory zone.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryZoneInfo {
+    /// Physical memory of the zone, including its metadata pages
+    pub region: MemoryRegion<PhysAddr>,
+    /// Information about the pages of the zone
+    pub mem_info: MemInfo,
+}
+
+/// Retrieve information about each memory zone in use, starting with the
+/// root memory region.
+pub fn memory_zones_info() -> impl Iterator<Item = MemoryZoneInfo> {
+    MEMORY_ZONES.iter().filter_map(|zone| {
+        let zone = zone.lock();
+        (zone.page_count != 0).then(|| MemoryZoneInfo {
+            region: MemoryRegion::new(zone.start_phys, zone.page_count * PAGE_SIZE),
+            mem_info: zone.memory_info(),
+        })
+    })
+}
+
+/// Adds a memory zone of `page_count` pages at the physical address `pstart`,
+/// which are mapped at `vstart`. The top pages of the zone hold its metadata,
+/// the remaining pages are free.
+///
+/// # Safety
+///
+/// The memory must be validated, mapped at `vstart`, inaccessible to the
+/// guest and not in use by anything else. It must stay so until the zone is
+/// removed with [`remove_memory_zone()`].
+pub unsafe fn add_memory_zone(
+    pstart: PhysAddr,
+    vstart: VirtAddr,
+    page_count: usize,
+) -> Result<(), SvsmError> {
+    // A zone needs at least one page besides its metadata
+    if page_count < 2 || !pstart.is_page_aligned() || !vstart.is_page_aligned() {
+        return Err(SvsmError::Mem);
+    }
+
+    let (index, mut zone) = MEMORY_ZONES
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(index, zone)| (index, zone.lock()))
+        .find(|(_, zone)| zone.page_count == 0)
+        .ok_or(SvsmError::Mem)?;
+    zone.start_phys = pstart;
+    zone.start_virt = vstart;
+    zone.page_count = page_count;
+    zone.init_memory(0);
+    ZONE_BOUNDS[index].set(&zone);
+
+    Ok(())
+}
+
+/// Removes the memory zone which starts at `vstart`, unless any of its pages
+/// are allocated. The root memory region can not be removed.
+///
+/// # Returns
+///
+/// `true` if the zone has been removed. Its memory is not used by the
+/// allocator anymore.
+pub fn remove_memory_zone(vstart: VirtAddr) -> bool {
+    let zone = MEMORY_ZONES
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(index, zone)| (index, zone.lock()))
+        .find(|(_, zone)| zone.page_count != 0 && zone.start_virt == vstart);
+    match zone {
+        Some((index, mut zone)) if zone.is_free() => {
+            *zone = HeapMemoryRegion::new();
+            ZONE_BOUNDS[index].set(&zone);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Pages deposited by the guest. They serve single-page allocations once all
+/// memory zones are exhausted. Free pages are linked through their first
+/// word, so that the list can be managed without allocating memory.
+#[derive(Debug)]
+struct DepositedPages {
+    /// First page on the free list
+    free_list: Option<VirtAddr>,
+    free_count: usize,
+    page_count: usize,
+}
+
+impl DepositedPages {
+    const fn new() -> Self {
+        Self {
+            free_list: None,
+            free_count: 0,
+            page_count: 0,
+        }
+    }
+
+    fn push(&mut self, vaddr: VirtAddr) {
+        let next = self.free_list.map_or(0, |next| next.bits());
+        // SAFETY: only free deposited pages are pushed, which are mapped and
+        // not referenced by anything else.
+        unsafe {
+            vaddr.as_mut_ptr::<usize>().write(next);
+        }
+        self.free_list = Some(vaddr);
+        self.free_count += 1;
+    }
+
+    fn pop(&mut self) -> Option<VirtAddr> {
+        let vaddr = self.free_list?;
+        // SAFETY: all pages on the free list are mapped and hold the address
+        // of the next page on the list.
+        let next = unsafe { vaddr.as_ptr::<usize>().read() };
+        self.free_list = (next != 0).then(|| VirtAddr::from(next));
+        self.free_count -= 1;
+        Some(vaddr)
+    }
+}
+
+static DEPOSITED_PAGES: SpinLock<DepositedPages> = SpinLock::new(DepositedPages::new());
+
+fn is_deposited_page(vaddr: VirtAddr) -> bool {
+    (SVSM_DYNAMIC_MAP_BASE..SVSM_DYNAMIC_MAP_END).contains(&vaddr)
+}
+
+/// Allocates a deposited page if the memory zones failed with `err` because
+/// they are exhausted.
+fn allocate_deposited_page(err: AllocError) -> Result<VirtAddr, AllocError> {
+    match err {
+        AllocError::OutOfMemory => DEPOSITED_PAGES.lock().pop().ok_or(err),
+        _ => Err(err),
+    }
+}
+
+/// Adds a page deposited by the guest to the free memory.
+///
+/// # Safety
+///
+/// The page must be validated, mapped at the address which
+/// [`dynamic_map_addr()`](crate::mm::dynamic_map_addr) returns for it and not
+/// accessible to the guest.
+pub unsafe fn add_deposited_page(vaddr: VirtAddr) {
+    assert!(is_deposited_page(vaddr) && vaddr.is_page_aligned());
+    let mut pages = DEPOSITED_PAGES.lock();
+    pages.push(vaddr);
+    pages.page_count += 1;
+}
+
+/// Removes a free deposited page from the memory of the SVSM, so that it can
+/// be returned to the guest.
+///
+/// # Returns
+///
+/// The virtual address of the page, or `None` if no deposited page is free.
+pub fn take_deposited_page() -> Option<VirtAddr> {
+    let mut pages = DEPOSITED_PAGES.lock();
+    let vaddr = pages.pop()?;
+    pages.page_count -= 1;
+    Some(vaddr)
+}
+
+/// Returns the number of deposited pages and the number of those which are
+/// free.
+pub fn deposited_pages_info() -> (usize, usize) {
+    let pages = DEPOSITED_PAGES.lock();
+    (pages.page_count, pages.free_count)
 }
 
 /// Represents a slab memory page, used for efficient allocation of
@@ -1836,7 +2144,7 @@ impl<const N: usize> SlabPage<N> {
         if !self.vaddr.is_null() {
             return Ok(());
         }
-        self.vaddr = ROOT_MEM.lock().allocate_slab_page::<N>()?;
+        self.vaddr = allocate_from_zones(|zone| zone.allocate_slab_page::<N>())?;
         self.free = self.get_capacity() as u16;
 
         Ok(())
@@ -2374,10 +2682,17 @@ unsafe impl GlobalAlloc for SvsmAllocator {
         let virt_addr = VirtAddr::from(ptr);
         let size = layout.size();
 
-        let info = {
-            let mem = ROOT_MEM.lock();
-            let pfn = mem.get_pfn(virt_addr).expect("Freeing unknown memory");
-            mem.read_page_info(pfn)
+        let info = match memory_zone(virt_addr) {
+            Some(zone) => {
+                let pfn = zone.get_pfn(virt_addr).unwrap();
+                zone.read_page_info(pfn)
+            }
+            None => {
+                // Deposited pages are only used for single-page allocations.
+                assert!(is_deposited_page(virt_addr), "Freeing unknown memory");
+                free_page(virt_addr);
+                return;
+            }
         };
 
         match info {
@@ -2411,6 +2726,7 @@ pub fn root_mem_init(pstart: PhysAddr, vstart: VirtAddr, page_count: usize, allo
         region.start_virt = vstart;
         region.page_count = page_count;
         region.init_memory(allocated);
+        ZONE_BOUNDS[0].set(&region);
         // drop lock here so slab initialization does not deadlock
     }
 
@@ -2441,9 +2757,13 @@ pub fn layout_from_size(size: usize) -> Result<Layout, AllocError> {
 pub fn layout_from_ptr(ptr: *mut u8) -> Option<Layout> {
     let va = VirtAddr::from(ptr);
 
-    let root = ROOT_MEM.lock();
-    let pfn = root.get_pfn(va).ok()?;
-    let info = root.read_page_info(pfn);
+    let Some(zone) = memory_zone(va) else {
+        // Deposited pages only serve single-page allocations.
+        return (is_deposited_page(va) && va.is_page_aligned())
+            .then(|| Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap());
+    };
+    let pfn = zone.get_pfn(va).ok()?;
+    let info = zone.read_page_info(pfn);
 
     match info {
         PageInfo::Allocated(ai) => {
@@ -2532,6 +2852,7 @@ impl Drop for TestRootMem<'_> {
         // allocation of the memory region.
         unsafe { dealloc(root_mem.start_virt.as_mut_ptr::<u8>(), layout) };
         *root_mem = HeapMemoryRegion::new();
+        ZONE_BOUNDS[0].set(&root_mem);
 
         // Reset the Slabs
         *SLAB_PAGE_SLAB.lock() = SlabPageSlab::new();
@@ -2554,6 +2875,103 @@ mod test {
         drop(test_mem_lock);
     }
 
+    /// Tests the free list of deposited pages, which is linked through the
+    /// pages themselves.
+    #[test]
+    fn test_deposited_page_list() {
+        let mut pages = [[0usize; 4]; 3];
+        let addrs = pages
+            .each_mut()
+            .map(|page| VirtAddr::from(page.as_mut_ptr()));
+
+        let mut list = DepositedPages::new();
+        assert_eq!(list.pop(), None);
+        for vaddr in addrs {
+            list.push(vaddr);
+        }
+        assert_eq!(list.free_count, 3);
+
+        assert_eq!(list.pop(), Some(addrs[2]));
+        list.push(addrs[2]);
+        assert_eq!(list.pop(), Some(addrs[2]));
+        assert_eq!(list.pop(), Some(addrs[1]));
+        assert_eq!(list.pop(), Some(addrs[0]));
+        assert_eq!(list.pop(), None);
+        assert_eq!(list.free_count, 0);
+    }
+
+    /// Tests that allocations from deposited pages have a single-page
+    /// layout.
+    #[test]
+    fn test_deposited_page_layout() {
+        let page = SVSM_DYNAMIC_MAP_BASE + PAGE_SIZE;
+        assert_eq!(
+            layout_from_ptr(page.as_mut_ptr()),
+            Some(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap())
+        );
+        assert_eq!(layout_from_ptr((page + 8).as_mut_ptr()), None);
+        assert_eq!(layout_from_ptr(SVSM_DYNAMIC_MAP_END.as_mut_ptr()), None);
+    }
+
+    /// Tests that a memory zone added at runtime serves allocations once the
+    /// root memory region is exhausted, and that it can only be removed while
+    /// none of its pages are allocated.
+    #[test]
+    #[cfg_attr(test_in_svsm, ignore = "Can only be run on the host")]
+    fn test_memory_zones() {
+        use alloc::alloc::{alloc, dealloc};
+        use alloc::vec::Vec;
+
+        let _test_mem = TestRootMem::setup(DEFAULT_TEST_MEMORY_SIZE);
+
+        let page_count = 16;
+        let layout = Layout::from_size_align(page_count * PAGE_SIZE, PAGE_SIZE).unwrap();
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { alloc(layout) };
+        assert!(!ptr.is_null());
+        let vstart = VirtAddr::from(ptr);
+        let zone_region = MemoryRegion::new(vstart, page_count * PAGE_SIZE);
+
+        // SAFETY: the memory has just been allocated and is only used by the
+        // zone.
+        unsafe {
+            add_memory_zone(PhysAddr::from(vstart.bits()), vstart, page_count).unwrap();
+        }
+        assert_eq!(memory_zones_info().count(), 2);
+        let pend = PhysAddr::from(zone_region.end().bits());
+        assert_eq!(zone_phys_to_virt(pend - 1), Some(zone_region.end() - 1));
+
+        // Exhaust the root memory region.
+        let mut root_pages = Vec::new();
+        for order in (0..MAX_ORDER).rev() {
+            while let Ok(vaddr) = ROOT_MEM.lock().allocate_pages(order) {
+                root_pages.push(vaddr);
+            }
+        }
+
+        // One page of the zone holds its metadata.
+        let zone_pages = (1..page_count)
+            .map(|_| allocate_page().unwrap())
+            .collect::<Vec<_>>();
+        assert!(zone_pages.iter().all(|vaddr| zone_region.contains(*vaddr)));
+        assert!(allocate_page().is_err());
+
+        assert!(!remove_memory_zone(vstart));
+        for vaddr in zone_pages {
+            free_page(vaddr);
+        }
+        assert!(remove_memory_zone(vstart));
+        assert_eq!(memory_zones_info().count(), 1);
+        assert_eq!(zone_phys_to_virt(pend - 1), None);
+
+        for vaddr in root_pages {
+            free_page(vaddr);
+        }
+        // SAFETY: the memory has been allocated with the same layout and is
+        // not part of a zone anymore.
+        unsafe { dealloc(ptr, layout) };
+    }
+
     /// Tests the allocation and deallocation of a single page, verifying the
     /// memory information.
     #[test]
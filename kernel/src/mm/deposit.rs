@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Memory deposited by the guest.
+//!
+//! The guest can give 4K and 2M pages of its memory to the SVSM. While a page
+//! is deposited, it is mapped at [`dynamic_map_addr()`] and no longer counts
+//! as guest memory, so the guest can not pass it to requests which access
+//! guest memory.
+
+extern crate alloc;
+
+use super::address_space::dynamic_map_addr;
+use super::pagetable::PTEntryFlags;
+use super::ptguards::mapped_regions;
+use crate::address::{Address, PhysAddr, VirtAddr};
+use crate::cpu::flush_tlb_global_sync_page;
+use crate::cpu::percpu::this_cpu;
+use crate::error::SvsmError;
+use crate::locking::RWLock;
+use crate::types::PageSize;
+use crate::utils::MemoryRegion;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+
+/// Physical addresses and sizes of the deposited pages
+static DEPOSITED_PAGES: RWLock<BTreeMap<PhysAddr, PageSize>> = RWLock::new(BTreeMap::new());
+
+/// Returns `true` if `region` overlaps with a page in `pages`.
+fn overlaps_deposited_page(
+    pages: &BTreeMap<PhysAddr, PageSize>,
+    region: &MemoryRegion<PhysAddr>,
+) -> bool {
+    pages
+        .range(..region.end())
+        .next_back()
+        .is_some_and(|(paddr, size)| *paddr + usize::from(*size) > region.start())
+}
+
+/// Marks the page of size `size` at `paddr` as deposited and maps it.
+///
+/// # Returns
+///
+/// The virtual address of the page, or an error if the page overlaps with
+/// deposited memory or memory mapped by a
+/// [`PerCPUPageMappingGuard`](super::PerCPUPageMappingGuard), is out of the
+/// range of deposited memory or can not be mapped.
+pub fn map_deposited_page(paddr: PhysAddr, size: PageSize) -> Result<VirtAddr, SvsmError> {
+    let len = usize::from(size);
+    if !paddr.is_aligned(len) {
+        return Err(SvsmError::InvalidAddress);
+    }
+    let vaddr = dynamic_map_addr(paddr).ok_or(SvsmError::InvalidAddress)?;
+    dynamic_map_addr(paddr + (len - 1)).ok_or(SvsmError::InvalidAddress)?;
+
+    {
+        // Holding the lock of the mapped regions keeps any CPU from mapping
+        // the page until it is marked as deposited.
+        let region = MemoryRegion::new(paddr, len);
+        let mapped = mapped_regions();
+        if mapped.iter().any(|r| r.overlap(&region)) {
+            return Err(SvsmError::InvalidAddress);
+        }
+        let mut pages = DEPOSITED_PAGES.lock_write();
+        if overlaps_deposited_page(&pages, &region) {
+            return Err(SvsmError::InvalidAddress);
+        }
+        pages.insert(paddr, size);
+    }
+
+    let region = MemoryRegion::new(vaddr, len);
+    let pgtable = this_cpu().get_pgtable();
+    let result = match size {
+        PageSize::Regular => pgtable.map_region_4k(region, paddr, PTEntryFlags::data(), false),
+        PageSize::Huge => pgtable.map_region_2m(region, paddr, PTEntryFlags::data(), false),
+    };
+    if let Err(err) = result {
+        DEPOSITED_PAGES.lock_write().remove(&paddr);
+        return Err(err);
+    }
+
+    Ok(vaddr)
+}
+
+/// Unmaps the deposited page at `paddr` and returns it to guest memory.
+pub fn unmap_deposited_page(paddr: PhysAddr) {
+    let vaddr = dynamic_map_addr(paddr).expect("Unmapping page which is not deposited");
+    let size = DEPOSITED_PAGES
+        .lock_read()
+        .get(&paddr)
+        .copied()
+        .expect("Unmapping page which is not deposited");
+
+    let pgtable = this_cpu().get_pgtable();
+    match size {
+        PageSize::Regular => pgtable.unmap_4k(vaddr),
+        PageSize::Huge => pgtable.unmap_2m(vaddr),
+    }
+    flush_tlb_global_sync_page(vaddr, size);
+
+    DEPOSITED_PAGES.lock_write().remove(&paddr);
+}
+
+/// Returns `true` if `region` overlaps with a deposited page.
+pub fn is_deposited_region(region: &MemoryRegion<PhysAddr>) -> bool {
+    overlaps_deposited_page(&DEPOSITED_PAGES.lock_read(), region)
+}
+
+/// Returns the physical addresses of the deposited 2M pages.
+pub fn deposited_huge_pages() -> Vec<PhysAddr> {
+    DEPOSITED_PAGES
+        .lock_read()
+        .iter()
+        .filter_map(|(paddr, size)| (*size == PageSize::Huge).then_some(*paddr))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PAGE_SIZE, PAGE_SIZE_2M};
+
+    #[test]
+    fn overlapping_pages() {
+        let mut pages = BTreeMap::new();
+        pages.insert(PhysAddr::from(PAGE_SIZE_2M), PageSize::Huge);
+        pages.insert(PhysAddr::from(3 * PAGE_SIZE_2M), PageSize::Regular);
+
+        let overlaps = |paddr: usize| {
+            overlaps_deposited_page(&pages, &MemoryRegion::new(PhysAddr::from(paddr), PAGE_SIZE))
+        };
+        assert!(!overlaps(PAGE_SIZE_2M - PAGE_SIZE));
+        assert!(overlaps(PAGE_SIZE_2M));
+        assert!(overlaps(2 * PAGE_SIZE_2M - PAGE_SIZE));
+        assert!(!overlaps(2 * PAGE_SIZE_2M));
+        assert!(overlaps(3 * PAGE_SIZE_2M));
+        assert!(!overlaps(3 * PAGE_SIZE_2M + PAGE_SIZE));
+
+        let huge = MemoryRegion::new(PhysAddr::from(2 * PAGE_SIZE_2M), PAGE_SIZE_2M);
+        assert!(!overlaps_deposited_page(&pages, &huge));
+    }
+}
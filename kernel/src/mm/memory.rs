@@ -6,6 +6,7 @@
 
 extern crate alloc;
 
+use super::deposit::is_deposited_region;
 use super::pagetable::LAUNCH_VMSA_ADDR;
 
 use crate::address::{Address, PhysAddr};
@@ -108,6 +109,9 @@ pub fn valid_phys_address(paddr: PhysAddr) -> bool {
     if page_addr == LAUNCH_VMSA_ADDR {
         return false;
     }
+    if is_deposited_region(&MemoryRegion::new(page_addr, PAGE_SIZE)) {
+        return false;
+    }
 
     MEMORY_MAP
         .lock_read()
@@ -126,6 +130,9 @@ pub fn valid_phys_region(region: &MemoryRegion<PhysAddr>) -> bool {
     if region.overlap(&MemoryRegion::new(LAUNCH_VMSA_ADDR, PAGE_SIZE)) {
         return false;
     }
+    if is_deposited_region(region) {
+        return false;
+    }
 
     MEMORY_MAP
         .lock_read()
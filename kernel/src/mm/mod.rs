@@ -6,6 +6,7 @@
 
 pub mod address_space;
 pub mod alloc;
+pub mod deposit;
 pub mod global_memory;
 pub mod guestmem;
 pub mod mappings;
@@ -4,24 +4,62 @@
 //
 // Author: Joerg Roedel <jroedel@suse.de>
 
+extern crate alloc;
+
+use super::deposit::is_deposited_region;
 use super::pagetable::PTEntryFlags;
 use crate::address::{Address, PhysAddr, VirtAddr};
+use crate::cpu::PreemptGuard;
 use crate::cpu::percpu::this_cpu;
 use crate::cpu::tlb::flush_tlb_global_percpu_range;
 use crate::error::SvsmError;
+use crate::locking::{LockGuardIrqSafe, SpinLockIrqSafe};
 use crate::mm::virtualrange::VRangeAlloc;
 use crate::types::{PAGE_SIZE, PAGE_SIZE_2M, PageSize};
-use crate::utils::align_up;
+use crate::utils::{MemoryRegion, align_up};
+use alloc::vec;
+use alloc::vec::Vec;
 use core::marker::PhantomData;
 use core::mem;
 use core::ops::{Deref, DerefMut};
 use zerocopy::FromBytes;
 
+/// Physical memory mapped by a [`PerCPUPageMappingGuard`] on any CPU.
+static MAPPED_REGIONS: SpinLockIrqSafe<Vec<MemoryRegion<PhysAddr>>> =
+    SpinLockIrqSafe::new(Vec::new());
+
+/// Returns the locked list of the physical memory currently mapped by a
+/// [`PerCPUPageMappingGuard`] on any CPU. No new mapping is created while the
+/// lock is held.
+pub fn mapped_regions() -> LockGuardIrqSafe<'static, Vec<MemoryRegion<PhysAddr>>> {
+    MAPPED_REGIONS.lock()
+}
+
+fn unregister_mapped_regions(regions: &[MemoryRegion<PhysAddr>]) {
+    let mut mapped = MAPPED_REGIONS.lock();
+    for region in regions {
+        if let Some(pos) = mapped
+            .iter()
+            .position(|r| r.start() == region.start() && r.end() == region.end())
+        {
+            mapped.swap_remove(pos);
+        }
+    }
+}
+
 /// Guard for a per-CPU page mapping to ensure adequate cleanup if drop.
+///
+/// Tasks sharing a CPU also share its page table, so preemption is disabled
+/// while the mapping is created or removed. A task is never moved to another
+/// CPU when it is preempted, so the mapping stays valid until it is dropped.
+///
+/// The mapped physical memory is tracked in [`mapped_regions()`] for as long
+/// as the guard exists, so that it is not deposited to the SVSM meanwhile.
 #[derive(Debug)]
 #[must_use = "if unused the mapping will immediately be unmapped"]
 pub struct PerCPUPageMappingGuard {
     mapping: VRangeAlloc,
+    phys: Vec<MemoryRegion<PhysAddr>>,
 }
 
 impl PerCPUPageMappingGuard {
@@ -47,6 +85,31 @@ impl PerCPUPageMappingGuard {
         paddr_start: PhysAddr,
         paddr_end: PhysAddr,
         alignment: usize,
+    ) -> Result<Self, SvsmError> {
+        Self::create_checked(paddr_start, paddr_end, alignment, false)
+    }
+
+    /// Creates a new [`PerCPUPageMappingGuard`] for guest memory in the
+    /// specified physical address range, like [`Self::create()`] with an
+    /// alignment of 0.
+    ///
+    /// The caller checks that the range is guest memory. The guest may
+    /// deposit the memory to the SVSM after that check, so it is checked
+    /// again not to be deposited, atomically with respect to deposits.
+    ///
+    /// # Returns
+    ///
+    /// The [`PerCPUPageMappingGuard`], or `SvsmError::InvalidAddress` if the
+    /// range has been deposited.
+    pub fn create_guest(paddr_start: PhysAddr, paddr_end: PhysAddr) -> Result<Self, SvsmError> {
+        Self::create_checked(paddr_start, paddr_end, 0, true)
+    }
+
+    fn create_checked(
+        paddr_start: PhysAddr,
+        paddr_end: PhysAddr,
+        alignment: usize,
+        guest: bool,
     ) -> Result<Self, SvsmError> {
         let align_mask = (PAGE_SIZE << alignment) - 1;
         let size = paddr_end - paddr_start;
@@ -58,21 +121,45 @@ impl PerCPUPageMappingGuard {
         let huge = ((paddr_start.bits() & (PAGE_SIZE_2M - 1)) == 0)
             && ((paddr_end.bits() & (PAGE_SIZE_2M - 1)) == 0);
 
+        let phys = vec![MemoryRegion::from_addresses(paddr_start, paddr_end)];
+        {
+            let mut mapped = MAPPED_REGIONS.lock();
+            if guest && is_deposited_region(&phys[0]) {
+                return Err(SvsmError::InvalidAddress);
+            }
+            mapped.extend_from_slice(&phys);
+        }
+
+        let _guard = PreemptGuard::new();
         let mapping = if huge {
-            let range = VRangeAlloc::new_2m(size, 0)?;
-            this_cpu()
-                .get_pgtable()
-                .map_region_2m(range.region(), paddr_start, flags, false)?;
-            range
+            VRangeAlloc::new_2m(size, 0).and_then(|range| {
+                this_cpu().get_pgtable().map_region_2m(
+                    range.region(),
+                    paddr_start,
+                    flags,
+                    false,
+                )?;
+                Ok(range)
+            })
         } else {
-            let range = VRangeAlloc::new_4k(size, 0)?;
-            this_cpu()
-                .get_pgtable()
-                .map_region_4k(range.region(), paddr_start, flags, false)?;
-            range
+            VRangeAlloc::new_4k(size, 0).and_then(|range| {
+                this_cpu().get_pgtable().map_region_4k(
+                    range.region(),
+                    paddr_start,
+                    flags,
+                    false,
+                )?;
+                Ok(range)
+            })
         };
 
-        Ok(Self { mapping })
+        match mapping {
+            Ok(mapping) => Ok(Self { mapping, phys }),
+            Err(e) => {
+                unregister_mapped_regions(&phys);
+                Err(e)
+            }
+        }
     }
 
     /// Creates a new [`PerCPUPageMappingGuard`] for a 4KB page at the
@@ -104,8 +191,18 @@ impl PerCPUPageMappingGuard {
         let mapping = VRangeAlloc::new_4k(pages.len() * PAGE_SIZE, 0)?;
         let flags = PTEntryFlags::data();
 
+        let phys = pages
+            .iter()
+            .map(|(paddr, _)| MemoryRegion::new(*paddr, PAGE_SIZE))
+            .collect::<Vec<_>>();
+        MAPPED_REGIONS.lock().extend_from_slice(&phys);
+        // Dropping the guard unmaps the pages mapped so far if mapping fails.
+        let guard = Self { mapping, phys };
+
+        let _preempt = PreemptGuard::new();
         let pgtable = this_cpu().get_pgtable();
-        for (vaddr, (paddr, shared)) in mapping
+        for (vaddr, (paddr, shared)) in guard
+            .mapping
             .region()
             .iter_pages(PageSize::Regular)
             .zip(pages.iter().copied())
@@ -114,13 +211,14 @@ impl PerCPUPageMappingGuard {
             pgtable.map_4k(vaddr, paddr, flags, shared)?;
         }
 
-        Ok(Self { mapping })
+        Ok(guard)
     }
 }
 
 impl Drop for PerCPUPageMappingGuard {
     fn drop(&mut self) {
         let region = self.mapping.region();
+        let _guard = PreemptGuard::new();
         let size = if self.mapping.huge() {
             this_cpu().get_pgtable().unmap_region_2m(region);
             PageSize::Huge
@@ -130,6 +228,7 @@ impl Drop for PerCPUPageMappingGuard {
         };
 
         flush_tlb_global_percpu_range(region, size);
+        unregister_mapped_regions(&self.phys);
     }
 }
 
@@ -85,7 +85,7 @@ pub enum PlatformPageType {
 /// This defines a platform abstraction to permit the SVSM to run on different
 /// underlying architectures.
 pub trait SvsmPlatform: Sync {
-    #[cfg(test)]
+    /// Returns the type of the platform.
     fn platform_type(&self) -> SvsmPlatformType;
 
     /// Halts the system required by the platform.  Interrupt state is under
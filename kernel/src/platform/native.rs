@@ -43,7 +43,6 @@ use syscall::GlobalFeatureFlags;
 #[cfg(debug_assertions)]
 use crate::mm::virt_to_phys;
 
-#[cfg(test)]
 use bootdefs::platform::SvsmPlatformType;
 
 #[derive(Clone, Copy, Debug)]
@@ -60,7 +59,6 @@ impl NativePlatform {
 }
 
 impl SvsmPlatform for NativePlatform {
-    #[cfg(test)]
     fn platform_type(&self) -> SvsmPlatformType {
         SvsmPlatformType::Native
     }
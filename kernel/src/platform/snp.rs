@@ -52,7 +52,6 @@ use crate::sev::{
 };
 use crate::utils::MemoryRegion;
 use crate::utils::immut_after_init::ImmutAfterInitCell;
-#[cfg(test)]
 use bootdefs::platform::SvsmPlatformType;
 use core::arch::x86_64::CpuidResult;
 use core::mem::MaybeUninit;
@@ -124,7 +123,6 @@ unsafe extern "C" {
 }
 
 impl SvsmPlatform for SnpPlatform {
-    #[cfg(test)]
     fn platform_type(&self) -> SvsmPlatformType {
         SvsmPlatformType::Snp
     }
@@ -39,7 +39,6 @@ use core::mem::MaybeUninit;
 use core::sync::atomic::Ordering;
 use syscall::GlobalFeatureFlags;
 
-#[cfg(test)]
 use bootdefs::platform::SvsmPlatformType;
 
 static GHCI_IO_DRIVER: GHCIIOPort = GHCIIOPort::new();
@@ -55,7 +54,6 @@ impl TdpPlatform {
 }
 
 impl SvsmPlatform for TdpPlatform {
-    #[cfg(test)]
     fn platform_type(&self) -> SvsmPlatformType {
         SvsmPlatformType::Tdp
     }
@@ -21,8 +21,8 @@ use crate::mm::guestmem::{copy_slice_to_guest, read_bytes_from_guest, read_from_
 use crate::protocols::uefivars::uefi_mm_get_manifest;
 use crate::protocols::{RequestParams, errors::SvsmReqError};
 use crate::utils::MemoryRegion;
-#[cfg(all(feature = "vtpm", not(test)))]
-use crate::vtpm::vtpm_get_manifest;
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
+use crate::vtpm::{vtpm_get_event_log, vtpm_get_manifest};
 
 use crate::sev::ghcb::GhcbError;
 use crate::types::PAGE_SHIFT;
@@ -38,8 +38,15 @@ const GUID_HEADER_ENTRY_SIZE: usize = 24;
 const SVSM_ATTEST_SERVICES: u32 = 0;
 const SVSM_ATTEST_SINGLE_SERVICE: u32 = 1;
 
-#[cfg(all(feature = "vtpm", not(test)))]
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
 const SVSM_ATTEST_VTPM_GUID: Uuid = uuid!("c476f1eb-0123-45a5-9641-b4e7dde5bfe3");
+/// Service whose manifest is the TCG event log of the measurements of the
+/// SVSM launch state in the vTPM PCRs. The GUID is specific to COCONUT-SVSM,
+/// the SVSM specification does not define such a service. The log is empty
+/// until the vTPM has been started up, see
+/// `Documentation/docs/protocols/vtpm-event-log.md`.
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
+const SVSM_ATTEST_VTPM_EVENT_LOG_GUID: Uuid = uuid!("e910c67a-d283-48f1-b2d4-130001df434b");
 #[cfg(all(feature = "uefivars", not(test)))]
 const SVSM_ATTEST_UEFI_MM_GUID: Uuid = uuid!("a4453a59-9e1b-4787-a033-1986d6adbe55");
 
@@ -525,7 +532,7 @@ fn attest_single_service(
     get_attestation_report(hash.as_slice(), manifest, params, &ops.op)
 }
 
-#[cfg(all(feature = "vtpm", not(test)))]
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
 fn attest_single_vtpm(
     params: &mut RequestParams,
     ops: &AttestSingleServiceOp,
@@ -547,8 +554,10 @@ fn attest_multiple_services(params: &mut RequestParams) -> Result<(), SvsmReqErr
     #[allow(unused_mut)]
     let mut services = GuidTable::new();
 
-    #[cfg(all(feature = "vtpm", not(test)))]
+    #[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
     services.push(SVSM_ATTEST_VTPM_GUID, vtpm_get_manifest()?);
+    #[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
+    services.push(SVSM_ATTEST_VTPM_EVENT_LOG_GUID, vtpm_get_event_log());
 
     #[cfg(all(feature = "uefivars", not(test)))]
     services.push(SVSM_ATTEST_UEFI_MM_GUID, uefi_mm_get_manifest()?);
@@ -578,8 +587,12 @@ fn attest_single_service_handler(params: &mut RequestParams) -> Result<(), SvsmR
     // Extract the GUID from the Attest Single Service Operation structure.
     // The GUID is used to determine the specific service to be attested.
     match attest_op.get_guid() {
-        #[cfg(all(feature = "vtpm", not(test)))]
+        #[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
         SVSM_ATTEST_VTPM_GUID => attest_single_vtpm(params, &attest_op),
+        #[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
+        SVSM_ATTEST_VTPM_EVENT_LOG_GUID => {
+            attest_single_service(vtpm_get_event_log().as_slice(), params, &attest_op)
+        }
         #[cfg(all(feature = "uefivars", not(test)))]
         SVSM_ATTEST_UEFI_MM_GUID => {
             attest_single_service(uefi_mm_get_manifest()?.as_slice(), params, &attest_op)
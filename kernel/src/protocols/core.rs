@@ -9,8 +9,12 @@ use crate::cpu::percpu::{PERCPU_AREAS, PERCPU_VMSAS, this_cpu};
 use crate::cpu::{flush_tlb_global_sync, flush_tlb_global_sync_page};
 use crate::error::SvsmError;
 use crate::locking::RWLock;
+use crate::mm::alloc::{
+    add_deposited_page, add_memory_zone, remove_memory_zone, take_deposited_page,
+};
+use crate::mm::deposit::{deposited_huge_pages, map_deposited_page, unmap_deposited_page};
 use crate::mm::virtualrange::{VIRT_ALIGN_2M, VIRT_ALIGN_4K};
-use crate::mm::{GuestPtr, valid_phys_region, writable_phys_addr};
+use crate::mm::{GuestPtr, dynamic_map_addr, valid_phys_region, virt_to_phys, writable_phys_addr};
 use crate::mm::{PerCPUMapping, PerCPUPageMappingGuard};
 #[cfg(all(feature = "uefivars", not(test)))]
 use crate::protocols::SVSM_UEFI_MM_PROTOCOL;
@@ -22,7 +26,7 @@ use crate::protocols::uefivars::{UEFI_MM_PROTOCOL_VERSION_MAX, UEFI_MM_PROTOCOL_
 use crate::protocols::{
     RequestParams, SVSM_APIC_PROTOCOL, SVSM_ATTEST_PROTOCOL, SVSM_CORE_PROTOCOL,
 };
-#[cfg(all(feature = "vtpm", not(test)))]
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
 use crate::protocols::{
     SVSM_VTPM_PROTOCOL,
     vtpm::{VTPM_PROTOCOL_VERSION_MAX, VTPM_PROTOCOL_VERSION_MIN},
@@ -67,6 +71,17 @@ struct PValidateRequest {
     resv: u32,
 }
 
+/// Header of the list of pages passed to SVSM_REQ_CORE_DEPOSIT_MEM and
+/// SVSM_REQ_CORE_WITHDRAW_MEM. It is followed by the entries, which hold the
+/// guest physical addresses of the pages, with bit 0 set for 2M pages.
+#[repr(C, packed)]
+#[derive(Copy, Clone, FromBytes, IntoBytes)]
+struct MemoryListHeader {
+    entries: u16,
+    next: u16,
+    resv: u32,
+}
+
 /// # Safety
 /// The caller must only call this function on a page that was committed for
 /// use as a guest VMSA.
@@ -214,14 +229,298 @@ fn core_delete_vcpu(params: &RequestParams) -> Result<(), SvsmReqError> {
     res
 }
 
-fn core_deposit_mem(_params: &RequestParams) -> Result<(), SvsmReqError> {
-    log::info!("Request SVSM_REQ_CORE_DEPOSIT_MEM not yet supported");
-    Err(SvsmReqError::unsupported_call())
+/// Reserved bits per deposit-entry. Bit 0 selects the page size.
+const DEPOSIT_RSVD_MASK: u64 = 0xffeu64;
+
+/// Takes a guest page away from the guest and validates it for use by the
+/// SVSM.
+fn validate_deposited_page(vaddr: VirtAddr, size: PageSize) -> Result<(), SvsmReqError> {
+    // Validate the page afresh, so that it can not alias another page which
+    // is still accessible to the guest or the hypervisor.
+    // SAFETY: the page belongs to the guest and is not used by the SVSM yet,
+    // so changing its validation state cannot affect memory safety.
+    unsafe {
+        pvalidate(vaddr, size, PvalidateOp::Invalid).or_else(|err| match err {
+            SvsmError::SevSnp(SevSnpError::FAIL_UNCHANGED(_)) => Ok(()),
+            _ => Err(err),
+        })?;
+        pvalidate(vaddr, size, PvalidateOp::Valid)?;
+    }
+
+    // Validation keeps the permissions of the guest, so they must be revoked
+    // explicitly.
+    let result = rmp_revoke_guest_access(vaddr, size);
+    flush_tlb_global_sync_page(vaddr, size);
+    Ok(result?)
+}
+
+/// Hands a validated deposited page to the page allocator. 4K pages serve
+/// single-page allocations, while each 2M page becomes a memory zone of its
+/// own, so that it can be withdrawn once none of its pages are allocated.
+///
+/// # Safety
+///
+/// The page must be validated, mapped at its address in the dynamic memory
+/// map and not accessible to the guest.
+unsafe fn add_deposited_memory(
+    paddr: PhysAddr,
+    vaddr: VirtAddr,
+    size: PageSize,
+) -> Result<(), SvsmError> {
+    match size {
+        PageSize::Regular => {
+            // SAFETY: the caller guarantees the requirements.
+            unsafe { add_deposited_page(vaddr) };
+            Ok(())
+        }
+        // SAFETY: the caller guarantees the requirements.
+        PageSize::Huge => unsafe { add_memory_zone(paddr, vaddr, PAGE_SIZE_2M / PAGE_SIZE) },
+    }
+}
+
+/// Returns `true` if the page at `region` contains the page of the memory
+/// list at `list`, which the SVSM keeps accessing while it processes the
+/// list.
+fn overlaps_memory_list(region: &MemoryRegion<PhysAddr>, list: PhysAddr) -> bool {
+    region.overlap(&MemoryRegion::new(list.page_align(), PAGE_SIZE))
+}
+
+/// Returns `true` if the calling area of any CPU is part of `region`.
+fn overlaps_caa(region: &MemoryRegion<PhysAddr>) -> bool {
+    PERCPU_AREAS
+        .iter()
+        .any(|cpu| cpu.guest_caa().is_some_and(|caa| region.contains(caa)))
+}
+
+/// Deposits the page described by `entry` of the memory list at `list`.
+fn core_deposit_one(entry: u64, list: PhysAddr) -> Result<(), SvsmReqError> {
+    // Reject entry when reserved bits are set
+    if (entry & DEPOSIT_RSVD_MASK) != 0 {
+        return Err(SvsmReqError::invalid_parameter());
+    }
+
+    let (page_size_bytes, size) = match entry & 1 {
+        0 => (PAGE_SIZE, PageSize::Regular),
+        _ => (PAGE_SIZE_2M, PageSize::Huge),
+    };
+
+    let paddr = PhysAddr::from(entry).page_align();
+    if !paddr.is_aligned(page_size_bytes) {
+        return Err(SvsmReqError::invalid_parameter());
+    }
+
+    let region =
+        MemoryRegion::checked_new(paddr, page_size_bytes).ok_or(SvsmReqError::invalid_address())?;
+    if !valid_phys_region(&region) {
+        log::debug!("Invalid phys address: {paddr:#x}");
+        return Err(SvsmReqError::invalid_address());
+    }
+    if overlaps_memory_list(&region, list) || overlaps_caa(&region) {
+        return Err(SvsmReqError::invalid_address());
+    }
+
+    // Fails if the page overlaps with deposited memory or memory which any
+    // CPU has mapped, e.g. for processing a request.
+    let vaddr = map_deposited_page(paddr, size)?;
+    if let Err(err) = validate_deposited_page(vaddr, size) {
+        unmap_deposited_page(paddr);
+        return Err(err);
+    }
+
+    // SAFETY: the page has just been validated and mapped at its address in
+    // the dynamic memory map, and the guest has no access to it anymore.
+    if let Err(err) = unsafe { add_deposited_memory(paddr, vaddr, size) } {
+        // All memory zones are in use, so the page goes back to the guest.
+        log::warn!("Failed to add deposited page {paddr:#x}: {err:?}");
+        // SAFETY: the page has not been used by the SVSM.
+        unsafe { rmp_grant_guest_access(vaddr, size)? };
+        unmap_deposited_page(paddr);
+        return Err(SvsmReqError::invalid_request());
+    }
+
+    Ok(())
 }
 
-fn core_withdraw_mem(_params: &RequestParams) -> Result<(), SvsmReqError> {
-    log::info!("Request SVSM_REQ_CORE_WITHDRAW_MEM not yet supported");
-    Err(SvsmReqError::unsupported_call())
+/// Maps the memory list whose guest physical address is passed in RCX of a
+/// deposit or withdraw request.
+///
+/// # Returns
+///
+/// The mapping of the list, a pointer to its header and the maximum number
+/// of entries which fit into the mapped page.
+fn map_memory_list(
+    params: &RequestParams,
+) -> Result<(PerCPUPageMappingGuard, GuestPtr<MemoryListHeader>, u16), SvsmReqError> {
+    let gpa = PhysAddr::from(params.rcx);
+    let header_region = MemoryRegion::checked_new(gpa, size_of::<MemoryListHeader>())
+        .ok_or(SvsmReqError::invalid_address())?;
+
+    if !gpa.is_aligned(8) || !valid_phys_region(&header_region) {
+        return Err(SvsmReqError::invalid_parameter());
+    }
+
+    let offset = gpa.page_offset();
+    let guard =
+        PerCPUPageMappingGuard::create_guest(gpa.page_align(), gpa.page_align() + PAGE_SIZE)?;
+    let header = GuestPtr::<MemoryListHeader>::new(guard.virt_addr() + offset);
+
+    let max_entries: u16 = ((PAGE_SIZE - offset - size_of::<MemoryListHeader>())
+        / size_of::<u64>())
+    .try_into()
+    .unwrap();
+
+    Ok((guard, header, max_entries))
+}
+
+/// Checks that the first `entries` entries of the memory list at `gpa` are
+/// guest memory.
+fn check_memory_list(gpa: PhysAddr, entries: u16) -> Result<(), SvsmReqError> {
+    let entries_len = (usize::from(entries) + 1) * size_of::<u64>();
+    let region =
+        MemoryRegion::checked_new(gpa, entries_len).ok_or(SvsmReqError::invalid_address())?;
+    if !valid_phys_region(&region) {
+        return Err(SvsmReqError::invalid_parameter());
+    }
+    Ok(())
+}
+
+fn core_deposit_mem(params: &RequestParams) -> Result<(), SvsmReqError> {
+    // Take lock to prevent races with CREATE_VCPU calls
+    let _lock = PVALIDATE_LOCK.lock_read();
+
+    let (_guard, list, max_entries) = map_memory_list(params)?;
+    // SAFETY: the list header has been mapped by map_memory_list().
+    let mut header = unsafe { list.read()? };
+
+    let entries = header.entries;
+    let next = header.next;
+    if entries == 0 || entries > max_entries || entries <= next {
+        return Err(SvsmReqError::invalid_parameter());
+    }
+    check_memory_list(PhysAddr::from(params.rcx), entries)?;
+
+    let mut loop_result = Ok(());
+
+    let guest_entries = list.offset(1).cast::<u64>();
+    for i in next..entries {
+        // SAFETY: guest_entries follows the list header in the mapped page,
+        // and i is below the validated number of entries.
+        let entry = match unsafe { guest_entries.offset(i as isize).read() } {
+            Ok(v) => v,
+            Err(e) => {
+                loop_result = Err(e.into());
+                break;
+            }
+        };
+
+        loop_result = core_deposit_one(entry, PhysAddr::from(params.rcx));
+        match loop_result {
+            Ok(()) => header.next += 1,
+            Err(SvsmReqError::RequestError(..)) => break,
+            Err(SvsmReqError::FatalError(..)) => return loop_result,
+        }
+    }
+
+    // SAFETY: the list header has been mapped by map_memory_list().
+    if let Err(e) = unsafe { list.write_ref(&header) } {
+        loop_result = Err(e.into());
+    }
+
+    loop_result
+}
+
+/// Takes a free deposited page away from the page allocator, preferring 4K
+/// pages over the memory zones of 2M pages.
+///
+/// # Returns
+///
+/// The physical and virtual address and the size of the page, or `None` if
+/// no deposited page is free.
+fn take_free_deposited_page() -> Option<(PhysAddr, VirtAddr, PageSize)> {
+    if let Some(vaddr) = take_deposited_page() {
+        return Some((virt_to_phys(vaddr), vaddr, PageSize::Regular));
+    }
+
+    deposited_huge_pages().into_iter().find_map(|paddr| {
+        let vaddr = dynamic_map_addr(paddr)?;
+        remove_memory_zone(vaddr).then_some((paddr, vaddr, PageSize::Huge))
+    })
+}
+
+/// Returns a free deposited page to the guest after its address has been
+/// stored in the memory list at `entry`.
+///
+/// # Returns
+///
+/// `false` if no deposited page is free.
+fn core_withdraw_one(entry: GuestPtr<u64>) -> Result<bool, SvsmReqError> {
+    let Some((paddr, vaddr, size)) = take_free_deposited_page() else {
+        return Ok(false);
+    };
+    let entry_value = match size {
+        PageSize::Regular => u64::from(paddr),
+        PageSize::Huge => u64::from(paddr) | 1,
+    };
+
+    // SAFETY: the page is not used by the SVSM anymore, and the memory list
+    // entry is part of the mapped memory list.
+    let result = unsafe {
+        zero_mem_region(vaddr, vaddr + usize::from(size));
+        entry
+            .write(entry_value)
+            .map_err(SvsmReqError::from)
+            .and_then(|()| Ok(rmp_grant_guest_access(vaddr, size)?))
+    };
+    if let Err(err) = result {
+        // SAFETY: the guest has not been granted access to the page, so it
+        // can be used again.
+        if let Err(e) = unsafe { add_deposited_memory(paddr, vaddr, size) } {
+            log::error!("Failed to restore deposited page {paddr:#x}: {e:?}");
+        }
+        return Err(err);
+    }
+
+    unmap_deposited_page(paddr);
+    Ok(true)
+}
+
+fn core_withdraw_mem(params: &RequestParams) -> Result<(), SvsmReqError> {
+    let _lock = PVALIDATE_LOCK.lock_read();
+
+    let (_guard, list, max_entries) = map_memory_list(params)?;
+    // SAFETY: the list header has been mapped by map_memory_list().
+    let mut header = unsafe { list.read()? };
+
+    // The guest passes the number of entries the list can hold. The SVSM
+    // returns up to that many free deposited pages and sets the next field
+    // to the number of pages returned.
+    let entries = header.entries;
+    if entries == 0 || entries > max_entries {
+        return Err(SvsmReqError::invalid_parameter());
+    }
+    check_memory_list(PhysAddr::from(params.rcx), entries)?;
+
+    let mut loop_result = Ok(());
+
+    header.next = 0;
+    let guest_entries = list.offset(1).cast::<u64>();
+    for i in 0..entries {
+        match core_withdraw_one(guest_entries.offset(i as isize)) {
+            Ok(true) => header.next += 1,
+            Ok(false) => break,
+            Err(err) => {
+                loop_result = Err(err);
+                break;
+            }
+        }
+    }
+
+    // SAFETY: the list header has been mapped by map_memory_list().
+    if let Err(e) = unsafe { list.write_ref(&header) } {
+        loop_result = Err(e.into());
+    }
+
+    loop_result
 }
 
 fn protocol_supported(version: u32, version_min: u32, version_max: u32) -> u64 {
@@ -264,7 +563,7 @@ fn core_query_protocol(params: &mut RequestParams) -> Result<(), SvsmReqError> {
             ATTEST_PROTOCOL_VERSION_MIN,
             ATTEST_PROTOCOL_VERSION_MAX,
         ),
-        #[cfg(all(feature = "vtpm", not(test)))]
+        #[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
         SVSM_VTPM_PROTOCOL => protocol_supported(
             version,
             VTPM_PROTOCOL_VERSION_MIN,
@@ -534,3 +833,25 @@ pub fn core_protocol_request(request: u32, params: &mut RequestParams) -> Result
         _ => Err(SvsmReqError::unsupported_call()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deposit_own_memory_list() {
+        let list = PhysAddr::from(0x10_0000u64 + 0x238);
+
+        // The page of the list itself
+        let page = MemoryRegion::new(PhysAddr::from(0x10_0000u64), PAGE_SIZE);
+        assert!(overlaps_memory_list(&page, list));
+        // A 2M page containing the list
+        let huge = MemoryRegion::new(PhysAddr::null(), PAGE_SIZE_2M);
+        assert!(overlaps_memory_list(&huge, list));
+        // The pages before and after the list
+        let before = MemoryRegion::new(PhysAddr::from(0x0f_f000u64), PAGE_SIZE);
+        assert!(!overlaps_memory_list(&before, list));
+        let after = MemoryRegion::new(PhysAddr::from(0x10_1000u64), PAGE_SIZE);
+        assert!(!overlaps_memory_list(&after, list));
+    }
+}
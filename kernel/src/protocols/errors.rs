@@ -39,6 +39,26 @@ impl From<SvsmResultCode> for u64 {
     }
 }
 
+impl TryFrom<u64> for SvsmResultCode {
+    type Error = ();
+
+    fn try_from(code: u64) -> Result<Self, Self::Error> {
+        Ok(match code {
+            0x0000_0000 => SvsmResultCode::SUCCESS,
+            0x8000_0000 => SvsmResultCode::INCOMPLETE,
+            0x8000_0001 => SvsmResultCode::UNSUPPORTED_PROTOCOL,
+            0x8000_0002 => SvsmResultCode::UNSUPPORTED_CALL,
+            0x8000_0003 => SvsmResultCode::INVALID_ADDRESS,
+            0x8000_0004 => SvsmResultCode::INVALID_FORMAT,
+            0x8000_0005 => SvsmResultCode::INVALID_PARAMETER,
+            0x8000_0006 => SvsmResultCode::INVALID_REQUEST,
+            0x8000_0007 => SvsmResultCode::BUSY,
+            0x8000_1000..=0xffff_ffff => SvsmResultCode::PROTOCOL_BASE(code - 0x8000_1000),
+            _ => return Err(()),
+        })
+    }
+}
+
 const SVSM_ERR_APIC_CANNOT_REGISTER: u64 = 0;
 
 #[derive(Debug, Clone, Copy)]
@@ -10,7 +10,10 @@ pub mod core;
 pub mod errors;
 #[cfg(all(feature = "uefivars", not(test)))]
 pub mod uefivars;
-#[cfg(all(feature = "vtpm", not(test)))]
+#[cfg(feature = "uefivars")]
+pub mod uefivars_journal;
+pub mod user;
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
 pub mod vtpm;
 
 extern crate alloc;
@@ -21,18 +21,25 @@ extern crate alloc;
 use alloc::vec::Vec;
 use bitfield_struct::bitfield;
 use core::mem::size_of;
-use zerocopy::{Immutable, IntoBytes};
+use zerocopy::{FromBytes, Immutable, IntoBytes};
 
 use virtfw_libefi::efivar::ids::PK;
 use virtfw_varstore::mm::core::{MmCoreHeader, core_request_dispatch};
 use virtfw_varstore::store::EfiVarStore;
 
 use crate::address::{Address, PhysAddr};
-use crate::locking::SpinLock;
-use crate::mm::guestmem::{copy_slice_to_guest, read_bytes_from_guest, read_from_guest};
+use crate::block::nvstore::NvStore;
+use crate::block::{UEFI_VARS_REGION_OFFSET, UEFI_VARS_REGION_SIZE, secure_storage};
+use crate::error::SvsmError;
+use crate::locking::{Mutex, SpinLock};
+use crate::mm::guestmem::{copy_slice_to_guest, read_bytes_from_guest};
 use crate::mm::valid_phys_address;
 use crate::protocols::RequestParams;
 use crate::protocols::errors::SvsmReqError;
+use crate::protocols::uefivars_journal::{
+    EFI_DEVICE_ERROR, EFI_OUT_OF_RESOURCES, VarJournal, get_variable_request, is_not_found,
+    set_return_status,
+};
 
 const SVSM_UEFI_MM_REQUEST: u32 = 1;
 
@@ -43,6 +50,111 @@ pub const UEFI_MM_PROTOCOL_VERSION_MAX: u32 = 1;
 const UEFI_MM_BUFFER_LIMIT: usize = 256 * 1024;
 
 static STORE: SpinLock<EfiVarStore> = SpinLock::new(EfiVarStore::new());
+/// Serializes the MM requests, so that the journal can be written to the
+/// block device without holding `STORE`, and no request observes an update
+/// which is undone because it could not be persisted.
+static PERSISTENT: Mutex<Option<PersistentVars>> = Mutex::new(None);
+
+/// Non-volatile variable updates, persisted on the block device.
+#[derive(Debug)]
+struct PersistentVars {
+    nvstore: NvStore,
+    journal: VarJournal,
+    /// The variables present before the journal is replayed.
+    base: EfiVarStore,
+}
+
+impl PersistentVars {
+    /// Opens the UEFI variable region of the encrypted block device.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if there is no persistent storage, otherwise the journal loaded
+    /// from the device.
+    fn open() -> Result<Option<Self>, SvsmError> {
+        let Some(dev) = secure_storage() else {
+            return Ok(None);
+        };
+
+        let nvstore = NvStore::open(dev, UEFI_VARS_REGION_OFFSET, UEFI_VARS_REGION_SIZE)?;
+        let journal = match nvstore.load()? {
+            Some(image) => VarJournal::from_bytes(&image)?,
+            None => VarJournal::new(),
+        };
+
+        let mut base = EfiVarStore::new();
+        reset_store(&mut base);
+
+        Ok(Some(Self {
+            nvstore,
+            journal,
+            base,
+        }))
+    }
+
+    /// Replays the recorded variable updates into `store`.
+    fn replay(&self, store: &mut EfiVarStore) {
+        for entry in self.journal.entries() {
+            let Ok(mmcore) = MmCoreHeader::read_from_bytes(&entry.header) else {
+                log::warn!("uefivars: skipping malformed journal entry");
+                continue;
+            };
+            core_request_dispatch(store, &mmcore.guid, &entry.request);
+        }
+    }
+
+    /// Records a variable update and writes the journal to the device.
+    ///
+    /// # Returns
+    ///
+    /// The EFI status to report to the guest if the journal could not be
+    /// written, in which case the update is not recorded.
+    fn record(&mut self, header: &[u8], request: &[u8], response: &[u8]) -> Result<(), u64> {
+        let Some(entry) = VarJournal::recorded_entry(header, request, response) else {
+            return Ok(());
+        };
+        let base = &mut self.base;
+        let mut journal = self.journal.clone();
+        journal.push(entry, |guid, name| variable_exists(base, guid, name));
+
+        let image = journal.to_bytes();
+        if image.len() > self.nvstore.capacity() {
+            // Like a full flash store, only fail the writes which need more
+            // space.
+            log::warn!("uefivars: persistent variable store is full");
+            return Err(EFI_OUT_OF_RESOURCES);
+        }
+        self.nvstore.store(&image).map_err(|e| {
+            log::error!("uefivars: failed to save variable store: {e:?}");
+            EFI_DEVICE_ERROR
+        })?;
+        self.journal = journal;
+        Ok(())
+    }
+}
+
+/// Returns whether `store` holds the variable with `guid` and UCS-2 `name`.
+fn variable_exists(store: &mut EfiVarStore, guid: &[u8; 16], name: &[u8]) -> bool {
+    let (header, request) = get_variable_request(guid, name);
+    let Ok(mmcore) = MmCoreHeader::read_from_bytes(&header) else {
+        return true;
+    };
+    !is_not_found(&core_request_dispatch(store, &mmcore.guid, &request))
+}
+
+/// Resets `store` to the variables present on every boot.
+fn reset_store(store: &mut EfiVarStore) {
+    store.reset();
+
+    #[cfg(all(feature = "secureboot", not(test)))]
+    {
+        // hard coded configuration for now.
+        store.enroll_pk_mgmt();
+        store.enroll_kek_microsoft();
+        store.enroll_db_microsoft_uefi();
+        store.enroll_dbx_native();
+    }
+}
 
 fn check_buffer(addr: u64, size: usize) -> Result<(), SvsmReqError> {
     let paddr = PhysAddr::from(addr);
@@ -74,16 +186,30 @@ fn uefi_mm_request(params: &RequestParams) -> Result<(), SvsmReqError> {
     log::debug!("uefi mm buffer: 0x{addr:x} +0x{size:x}");
 
     let paddr = PhysAddr::from(addr);
-    let mmcore = read_from_guest::<MmCoreHeader>(paddr)?;
     let boffset = size_of::<MmCoreHeader>();
+    let header = read_bytes_from_guest(paddr, boffset)?;
+    let mmcore =
+        MmCoreHeader::read_from_bytes(&header).map_err(|_| SvsmReqError::invalid_parameter())?;
     let bsize = mmcore.size as usize;
     if bsize > size - boffset {
         return Err(SvsmReqError::invalid_parameter());
     }
 
     let req = read_bytes_from_guest(paddr + boffset, bsize)?;
-    let rsp = core_request_dispatch(&mut STORE.lock(), &mmcore.guid, &req);
+    let mut persistent = PERSISTENT.lock();
+    let mut rsp = core_request_dispatch(&mut STORE.lock(), &mmcore.guid, &req);
     assert!(rsp.len() <= bsize);
+    if let Some(persistent) = persistent.as_mut() {
+        if let Err(status) = persistent.record(&header, &req, &rsp) {
+            // The update is not persistent, so undo it and report the
+            // failure to the guest.
+            let mut store = STORE.lock();
+            reset_store(&mut store);
+            persistent.replay(&mut store);
+            set_return_status(&mut rsp, status);
+        }
+    }
+    drop(persistent);
     copy_slice_to_guest(&rsp, paddr + boffset)?;
 
     Ok(())
@@ -100,24 +226,30 @@ pub fn uefi_mm_protocol_request(
 }
 
 pub fn uefi_mm_protocol_init() -> Result<(), SvsmReqError> {
+    let mut persistent = PERSISTENT.lock();
+    *persistent = PersistentVars::open().unwrap_or_else(|e| {
+        log::error!("uefivars: failed to open persistent variable store: {e:?}");
+        None
+    });
+
     let mut store = STORE.lock();
-    store.reset();
+    reset_store(&mut store);
 
-    #[cfg(all(feature = "secureboot", not(test)))]
-    {
-        // hard coded configuration for now.
-        store.enroll_pk_mgmt();
-        store.enroll_kek_microsoft();
-        store.enroll_db_microsoft_uefi();
-        store.enroll_dbx_native();
+    match persistent.as_ref() {
+        Some(p) => {
+            p.replay(&mut store);
+            log::info!(
+                "uefivars: restored {} variable updates",
+                p.journal.entries().len()
+            );
+        }
+        // In case a TPM is present, shim's fallback.efi will setup efi
+        // boot variables then reboot.  This is not going to work without
+        // persistent storage for the UEFI variable store.  So turn off that
+        // behaviour (via EFI variable).
+        None => store.quirk_disable_shim_reboot(true),
     }
 
-    // In case a TPM is present, shim's fallback.efi will setup efi
-    // boot variables then reboot.  This is not going to work until we
-    // have persistence support for the UEFI variable store.  So turn
-    // off that behaviour for now (via EFI variable).
-    store.quirk_disable_shim_reboot(true);
-
     Ok(())
 }
 
@@ -145,13 +277,15 @@ struct UefiMmManifest {
 }
 
 pub fn uefi_mm_get_manifest() -> Result<Vec<u8>, SvsmReqError> {
+    // PERSISTENT may block, so it must not be taken while holding STORE.
+    let persistent = PERSISTENT.lock().is_some();
     let store = STORE.lock();
 
     let pk = store.get(&PK.into());
     let sb = pk.is_ok();
 
     let flags = UefiMmManifestFlags::new()
-        .with_persistent_nv_vars(false)
+        .with_persistent_nv_vars(persistent)
         .with_secureboot_enabled(sb)
         .with_secureboot_db_update(false);
     let manifest = UefiMmManifest { version: 0, flags };
@@ -0,0 +1,571 @@
+// SPDX-License-Identifier: MIT
+//
+
+//! Journal of UEFI variable updates.
+//!
+//! The UEFI variable store is made persistent by recording every successful
+//! MM SetVariable request which modifies a non-volatile variable, and by
+//! replaying the recorded requests against a freshly initialized store on
+//! the next boot. Replaying the original requests lets the variable store
+//! implementation apply its usual semantics, including the verification of
+//! authenticated variables.
+//!
+//! To keep the journal bounded, the entries of a variable are dropped as
+//! soon as a later request fully replaces it, and appending writes to a
+//! variable which is not authenticated are merged into its last entry. A
+//! deletion is only recorded while it still has an effect on replay, that
+//! is if earlier entries of the variable remain or the variable exists
+//! before the journal is replayed.
+//!
+//! Time-based authenticated writes can not be merged, because their
+//! signature covers the written data only. The entries of `KEK` are also
+//! kept while a later write of a signature database may have been verified
+//! against them, and the entries of `PK` are never dropped because each of
+//! them is signed by the previous platform key. Other authenticated
+//! variables are signed by their own creator, so their entries do not pin
+//! each other.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::mem::offset_of;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::error::SvsmError;
+
+/// `gEfiSmmVariableProtocolGuid` in its in-memory byte order.
+const SMM_VARIABLE_GUID: [u8; 16] = [
+    0x33, 0xd5, 0x32, 0xed, 0xe6, 0x99, 0x09, 0x42, 0x9c, 0xc0, 0x2d, 0x72, 0xcd, 0xd9, 0x98, 0xa7,
+];
+const SMM_VARIABLE_FUNCTION_GET_VARIABLE: u64 = 1;
+const SMM_VARIABLE_FUNCTION_SET_VARIABLE: u64 = 2;
+
+const EFI_SUCCESS: u64 = 0;
+pub const EFI_DEVICE_ERROR: u64 = 0x8000_0000_0000_0007;
+pub const EFI_OUT_OF_RESOURCES: u64 = 0x8000_0000_0000_0009;
+const EFI_NOT_FOUND: u64 = 0x8000_0000_0000_000e;
+
+/// `EFI_GLOBAL_VARIABLE` in its in-memory byte order.
+const EFI_GLOBAL_VARIABLE_GUID: [u8; 16] = [
+    0x61, 0xdf, 0xe4, 0x8b, 0xca, 0x93, 0xd2, 0x11, 0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c,
+];
+/// `EFI_IMAGE_SECURITY_DATABASE_GUID` in its in-memory byte order. The
+/// signature databases `db`, `dbx`, `dbt` and `dbr` use this GUID.
+const EFI_IMAGE_SECURITY_DATABASE_GUID: [u8; 16] = [
+    0xcb, 0xb2, 0x19, 0xd7, 0x3a, 0x3d, 0x96, 0x45, 0xa3, 0xbc, 0xda, 0xd0, 0x0e, 0x67, 0x65, 0x6f,
+];
+/// `PK` as UCS-2 string, including the terminating NUL.
+const PK_NAME: [u8; 6] = [b'P', 0, b'K', 0, 0, 0];
+/// `KEK` as UCS-2 string, including the terminating NUL.
+const KEK_NAME: [u8; 8] = [b'K', 0, b'E', 0, b'K', 0, 0, 0];
+
+const EFI_VARIABLE_NON_VOLATILE: u32 = 0x01;
+const EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS: u32 = 0x20;
+const EFI_VARIABLE_APPEND_WRITE: u32 = 0x40;
+
+const JOURNAL_VERSION: u32 = 1;
+
+/// `SMM_VARIABLE_COMMUNICATE_HEADER` followed by the fixed part of
+/// `SMM_VARIABLE_COMMUNICATE_ACCESS_VARIABLE`, as sent by edk2.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, FromBytes, KnownLayout, Immutable)]
+struct SmmVariableAccess {
+    function: u64,
+    return_status: u64,
+    guid: [u8; 16],
+    data_size: u64,
+    name_size: u64,
+    attributes: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, KnownLayout, Immutable)]
+struct JournalEntryHeader {
+    header_len: u32,
+    request_len: u32,
+}
+
+/// A recorded MM request, consisting of the raw MM communication header and
+/// the request buffer following it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub header: Vec<u8>,
+    pub request: Vec<u8>,
+}
+
+impl JournalEntry {
+    fn access(&self) -> Option<SmmVariableAccess> {
+        SmmVariableAccess::read_from_prefix(&self.request)
+            .ok()
+            .map(|(access, _)| access)
+    }
+
+    /// Returns the GUID and UCS-2 name identifying the variable.
+    fn key(&self) -> Option<([u8; 16], &[u8])> {
+        let access = self.access()?;
+        let name_size = usize::try_from(access.name_size).ok()?;
+        let start = size_of::<SmmVariableAccess>();
+        let name = self.request.get(start..start.checked_add(name_size)?)?;
+        Some((access.guid, name))
+    }
+
+    fn attributes(&self) -> u32 {
+        self.access().map_or(0, |access| access.attributes)
+    }
+
+    fn is_append(&self) -> bool {
+        self.attributes() & EFI_VARIABLE_APPEND_WRITE != 0
+    }
+
+    fn is_authenticated(&self) -> bool {
+        self.attributes() & EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS != 0
+    }
+
+    fn is_delete(&self) -> bool {
+        self.access()
+            .is_some_and(|access| access.attributes == 0 || access.data_size == 0)
+    }
+
+    /// Appends `data` to the data written by this entry.
+    fn append_data(&mut self, data: &[u8]) -> Option<()> {
+        let access = self.access()?;
+        let start =
+            size_of::<SmmVariableAccess>().checked_add(usize::try_from(access.name_size).ok()?)?;
+        let end = start.checked_add(usize::try_from(access.data_size).ok()?)?;
+        let data_size = access
+            .data_size
+            .checked_add(u64::try_from(data.len()).ok()?)?;
+        if end > self.request.len() || self.header.len() < size_of::<u64>() * 3 {
+            return None;
+        }
+
+        self.request.truncate(end);
+        self.request.extend_from_slice(data);
+        self.request[offset_of!(SmmVariableAccess, data_size)..][..size_of::<u64>()]
+            .copy_from_slice(&data_size.to_le_bytes());
+        // The MM communication header holds the size of the request after
+        // the GUID.
+        let request_len = self.request.len() as u64;
+        self.header[SMM_VARIABLE_GUID.len()..][..size_of::<u64>()]
+            .copy_from_slice(&request_len.to_le_bytes());
+        Some(())
+    }
+
+    /// Returns the data written by this entry.
+    fn data(&self) -> Option<&[u8]> {
+        let access = self.access()?;
+        let start =
+            size_of::<SmmVariableAccess>().checked_add(usize::try_from(access.name_size).ok()?)?;
+        let len = usize::try_from(access.data_size).ok()?;
+        self.request.get(start..start.checked_add(len)?)
+    }
+}
+
+/// Returns whether writes of the variable `signed` may have been verified
+/// against the keys held by the variable `signer`. The key exchange keys
+/// sign the signature databases, and the platform key signs the key exchange
+/// keys as well.
+fn may_verify(signer: ([u8; 16], &[u8]), signed: ([u8; 16], &[u8])) -> bool {
+    let (guid, name) = signer;
+    if guid != EFI_GLOBAL_VARIABLE_GUID {
+        return false;
+    }
+    let is_db = signed.0 == EFI_IMAGE_SECURITY_DATABASE_GUID;
+    let is_kek = signed == (EFI_GLOBAL_VARIABLE_GUID, &KEK_NAME[..]);
+    match name {
+        n if n == PK_NAME => is_db || is_kek,
+        n if n == KEK_NAME => is_db,
+        _ => false,
+    }
+}
+
+/// Builds an MM GetVariable request which only queries whether the variable
+/// with `guid` and UCS-2 `name` exists, together with the raw MM
+/// communication header to send it with.
+pub fn get_variable_request(guid: &[u8; 16], name: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut request = Vec::new();
+    request.extend_from_slice(&SMM_VARIABLE_FUNCTION_GET_VARIABLE.to_le_bytes());
+    request.extend_from_slice(&0u64.to_le_bytes());
+    request.extend_from_slice(guid);
+    request.extend_from_slice(&0u64.to_le_bytes());
+    request.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    request.extend_from_slice(&0u32.to_le_bytes());
+    request.extend_from_slice(name);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&SMM_VARIABLE_GUID);
+    header.extend_from_slice(&(request.len() as u64).to_le_bytes());
+    (header, request)
+}
+
+/// Returns whether the `response` to a request built with
+/// [`get_variable_request`] reports that the variable does not exist.
+pub fn is_not_found(response: &[u8]) -> bool {
+    SmmVariableAccess::read_from_prefix(response)
+        .is_ok_and(|(status, _)| status.return_status == EFI_NOT_FOUND)
+}
+
+/// Sets the status returned to the guest in the `response` to an MM
+/// variable request.
+pub fn set_return_status(response: &mut [u8], status: u64) {
+    let offset = offset_of!(SmmVariableAccess, return_status);
+    if let Some(field) = response.get_mut(offset..offset + size_of::<u64>()) {
+        field.copy_from_slice(&status.to_le_bytes());
+    }
+}
+
+/// Ordered list of variable updates which need to be replayed on boot.
+#[derive(Clone, Debug, Default)]
+pub struct VarJournal {
+    entries: Vec<JournalEntry>,
+}
+
+impl VarJournal {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Parses a journal previously serialized with [`VarJournal::to_bytes`].
+    pub fn from_bytes(mut buf: &[u8]) -> Result<Self, SvsmError> {
+        let (version, rest) = u32::read_from_prefix(buf).map_err(|_| SvsmError::InvalidFormat)?;
+        if version != JOURNAL_VERSION {
+            return Err(SvsmError::InvalidFormat);
+        }
+        buf = rest;
+
+        let mut entries = Vec::new();
+        while !buf.is_empty() {
+            let (hdr, rest) =
+                JournalEntryHeader::read_from_prefix(buf).map_err(|_| SvsmError::InvalidFormat)?;
+            let header_len = hdr.header_len as usize;
+            let request_len = hdr.request_len as usize;
+            if rest.len() < header_len + request_len {
+                return Err(SvsmError::InvalidFormat);
+            }
+            let (header, rest) = rest.split_at(header_len);
+            let (request, rest) = rest.split_at(request_len);
+            entries.push(JournalEntry {
+                header: header.to_vec(),
+                request: request.to_vec(),
+            });
+            buf = rest;
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Serializes the journal for storage.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(JOURNAL_VERSION.as_bytes());
+        for entry in self.entries.iter() {
+            let hdr = JournalEntryHeader {
+                header_len: entry.header.len() as u32,
+                request_len: entry.request.len() as u32,
+            };
+            buf.extend_from_slice(hdr.as_bytes());
+            buf.extend_from_slice(&entry.header);
+            buf.extend_from_slice(&entry.request);
+        }
+        buf
+    }
+
+    /// Returns the journal entry for an MM request if the request needs to
+    /// be replayed on the next boot.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The raw MM communication header of the request.
+    /// * `request` - The request buffer following the header.
+    /// * `response` - The response produced by the variable store.
+    pub fn recorded_entry(header: &[u8], request: &[u8], response: &[u8]) -> Option<JournalEntry> {
+        if header.get(..SMM_VARIABLE_GUID.len()) != Some(&SMM_VARIABLE_GUID[..]) {
+            return None;
+        }
+        let (access, _) = SmmVariableAccess::read_from_prefix(request).ok()?;
+        let (status, _) = SmmVariableAccess::read_from_prefix(response).ok()?;
+        if access.function != SMM_VARIABLE_FUNCTION_SET_VARIABLE
+            || status.return_status != EFI_SUCCESS
+        {
+            return None;
+        }
+
+        let entry = JournalEntry {
+            header: header.to_vec(),
+            request: request.to_vec(),
+        };
+        // Deletions are recorded regardless of the attributes, because the
+        // deleted variable may have been non-volatile.
+        if entry.attributes() & EFI_VARIABLE_NON_VOLATILE == 0 && !entry.is_delete() {
+            return None;
+        }
+        entry.key()?;
+
+        Some(entry)
+    }
+
+    /// Adds `entry` to the journal and compacts the entries of its variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The entry to add.
+    /// * `exists_on_boot` - Returns whether the variable with the given GUID
+    ///   and UCS-2 name exists before the journal is replayed. It is only
+    ///   called if `entry` deletes the variable.
+    pub fn push(
+        &mut self,
+        entry: JournalEntry,
+        exists_on_boot: impl FnOnce(&[u8; 16], &[u8]) -> bool,
+    ) {
+        let Some((guid, name)) = entry.key() else {
+            return;
+        };
+        let key = Some((guid, name));
+
+        if entry.is_append() {
+            if !entry.is_authenticated() {
+                // Appending to a variable which is not authenticated only
+                // concatenates the data, unless the variable was deleted.
+                let last = self.entries.iter_mut().rev().find(|e| e.key() == key);
+                if let Some(last) = last.filter(|e| !e.is_authenticated() && !e.is_delete()) {
+                    if let Some(()) = entry.data().and_then(|data| last.append_data(data)) {
+                        return;
+                    }
+                }
+            }
+        } else if !(guid == EFI_GLOBAL_VARIABLE_GUID && name == PK_NAME) {
+            // Authenticated writes of other variables may have been verified
+            // against authenticated entries of this variable which precede
+            // them.
+            let last_verified = self.entries.iter().rposition(|e| {
+                e.is_authenticated()
+                    && e.key().is_some_and(|other| {
+                        other != (guid, name) && may_verify((guid, name), other)
+                    })
+            });
+            let mut index = 0;
+            self.entries.retain(|e| {
+                let superseded = e.key() == key
+                    && (!e.is_authenticated() || last_verified.is_none_or(|i| index > i));
+                index += 1;
+                !superseded
+            });
+
+            // Deleting a variable which only exists because of the dropped
+            // entries leaves nothing to replay.
+            if entry.is_delete()
+                && !entry.is_authenticated()
+                && !self.entries.iter().any(|e| e.key() == key)
+                && !exists_on_boot(&guid, name)
+            {
+                return;
+            }
+        }
+
+        self.entries.push(entry);
+    }
+
+    /// Records an MM request if it needs to be replayed on the next boot.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the journal changed and needs to be written back.
+    #[cfg(test)]
+    pub fn record(&mut self, header: &[u8], request: &[u8], response: &[u8]) -> bool {
+        match Self::recorded_entry(header, request, response) {
+            Some(entry) => {
+                self.push(entry, |_, _| true);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: [u8; 24] = {
+        let mut h = [0u8; 24];
+        let mut i = 0;
+        while i < 16 {
+            h[i] = SMM_VARIABLE_GUID[i];
+            i += 1;
+        }
+        h
+    };
+
+    fn set_variable(name: &str, attributes: u32, data: &[u8], status: u64) -> (Vec<u8>, Vec<u8>) {
+        set_guid_variable(&[0x11; 16], name, attributes, data, status)
+    }
+
+    fn set_guid_variable(
+        guid: &[u8; 16],
+        name: &str,
+        attributes: u32,
+        data: &[u8],
+        status: u64,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let name: Vec<u8> = name
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let mut req = Vec::new();
+        req.extend_from_slice(&SMM_VARIABLE_FUNCTION_SET_VARIABLE.to_le_bytes());
+        req.extend_from_slice(&0u64.to_le_bytes());
+        req.extend_from_slice(guid);
+        req.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        req.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        req.extend_from_slice(&attributes.to_le_bytes());
+        req.extend_from_slice(&name);
+        req.extend_from_slice(data);
+
+        let mut rsp = req.clone();
+        rsp[8..16].copy_from_slice(&status.to_le_bytes());
+        (req, rsp)
+    }
+
+    fn record(journal: &mut VarJournal, name: &str, attributes: u32, data: &[u8]) -> bool {
+        let (req, rsp) = set_variable(name, attributes, data, EFI_SUCCESS);
+        journal.record(&HEADER, &req, &rsp)
+    }
+
+    #[test]
+    fn volatile_and_failed_writes_are_ignored() {
+        let mut journal = VarJournal::new();
+        assert!(!record(&mut journal, "Volatile", 0x06, b"data"));
+
+        let (req, rsp) = set_variable("Failed", 0x07, b"data", 0x8000_0000_0000_0002);
+        assert!(!journal.record(&HEADER, &req, &rsp));
+
+        let (req, rsp) = set_variable("OtherProto", 0x07, b"data", EFI_SUCCESS);
+        assert!(!journal.record(&[0u8; 24], &req, &rsp));
+
+        assert!(journal.entries().is_empty());
+    }
+
+    #[test]
+    fn replaced_variables_are_compacted() {
+        let mut journal = VarJournal::new();
+        assert!(record(&mut journal, "Boot0001", 0x07, b"one"));
+        assert!(record(&mut journal, "BootOrder", 0x07, b"order"));
+        assert!(record(&mut journal, "Boot0001", 0x07, b"two"));
+        assert_eq!(journal.entries().len(), 2);
+        assert!(journal.entries()[1].request.ends_with(b"two"));
+
+        // Deleting the variable replaces the last write as well.
+        assert!(record(&mut journal, "Boot0001", 0, b""));
+        assert_eq!(journal.entries().len(), 2);
+    }
+
+    #[test]
+    fn appends_are_merged() {
+        let mut journal = VarJournal::new();
+        assert!(record(&mut journal, "Log", 0x07, b"base"));
+        assert!(record(&mut journal, "Other", 0x07, b"other"));
+        assert!(record(&mut journal, "Log", 0x47, b"more"));
+        assert!(record(&mut journal, "Log", 0x47, b"most"));
+        assert_eq!(journal.entries().len(), 2);
+
+        let entry = &journal.entries()[0];
+        assert_eq!(entry.data(), Some(&b"basemoremost"[..]));
+        assert_eq!(entry.attributes(), 0x07);
+        let (mmcore, _) = u64::read_from_prefix(&entry.header[16..]).unwrap();
+        assert_eq!(mmcore, entry.request.len() as u64);
+
+        // An append to a deleted variable creates it again.
+        assert!(record(&mut journal, "Log", 0, b""));
+        assert!(record(&mut journal, "Log", 0x47, b"new"));
+        assert_eq!(journal.entries().len(), 3);
+    }
+
+    #[test]
+    fn authenticated_writes_are_compacted() {
+        let mut journal = VarJournal::new();
+        assert!(record(&mut journal, "dbx", 0x27, b"auth1"));
+        assert!(record(&mut journal, "dbx", 0x67, b"auth2"));
+        assert!(record(&mut journal, "dbx", 0x67, b"auth3"));
+        assert_eq!(journal.entries().len(), 3);
+
+        assert!(record(&mut journal, "dbx", 0x27, b"auth4"));
+        assert_eq!(journal.entries().len(), 1);
+        assert!(journal.entries()[0].request.ends_with(b"auth4"));
+    }
+
+    #[test]
+    fn deletes_without_effect_are_dropped() {
+        let mut journal = VarJournal::new();
+        let mut push = |name: &str, attributes: u32, data: &[u8], exists_on_boot: bool| {
+            let (req, rsp) = set_variable(name, attributes, data, EFI_SUCCESS);
+            let entry = VarJournal::recorded_entry(&HEADER, &req, &rsp).unwrap();
+            journal.push(entry, |_, _| exists_on_boot);
+        };
+        push("Temp", 0x07, b"temp", false);
+        push("Boot0001", 0x07, b"one", true);
+        push("Temp", 0, b"", false);
+        push("Boot0001", 0, b"", true);
+        push("Never", 0, b"", false);
+
+        // Only the deletion of the variable present on boot remains.
+        assert_eq!(journal.entries().len(), 1);
+        let (req, _) = set_variable("Boot0001", 0, b"", EFI_SUCCESS);
+        assert_eq!(journal.entries()[0].request, req);
+    }
+
+    #[test]
+    fn verifying_entries_are_kept() {
+        let mut journal = VarJournal::new();
+        let mut record_guid = |guid: &[u8; 16], name: &str, data: &[u8]| {
+            let (req, rsp) = set_guid_variable(guid, name, 0x27, data, EFI_SUCCESS);
+            assert!(journal.record(&HEADER, &req, &rsp));
+            journal.entries().len()
+        };
+        // db may have been verified against the first KEK.
+        assert_eq!(record_guid(&EFI_GLOBAL_VARIABLE_GUID, "KEK", b"kek1"), 1);
+        assert_eq!(
+            record_guid(&EFI_IMAGE_SECURITY_DATABASE_GUID, "db", b"db1"),
+            2
+        );
+        assert_eq!(record_guid(&EFI_GLOBAL_VARIABLE_GUID, "KEK", b"kek2"), 3);
+        assert_eq!(record_guid(&EFI_GLOBAL_VARIABLE_GUID, "KEK", b"kek3"), 3);
+        // Private authenticated variables are not signed by the KEK.
+        assert_eq!(record_guid(&[0x11; 16], "Private", b"private"), 4);
+        assert_eq!(record_guid(&EFI_GLOBAL_VARIABLE_GUID, "KEK", b"kek4"), 4);
+        assert_eq!(record_guid(&[0x11; 16], "Private", b"private2"), 4);
+        assert!(journal.entries()[2].request.ends_with(b"kek4"));
+
+        // Each PK is signed by the previous one.
+        let mut pk = |data: &[u8]| {
+            let (req, rsp) =
+                set_guid_variable(&EFI_GLOBAL_VARIABLE_GUID, "PK", 0x27, data, EFI_SUCCESS);
+            assert!(journal.record(&HEADER, &req, &rsp));
+        };
+        pk(b"pk1");
+        pk(b"pk2");
+        assert_eq!(journal.entries().len(), 6);
+    }
+
+    #[test]
+    fn serialization_roundtrip() {
+        let mut journal = VarJournal::new();
+        record(&mut journal, "Boot0001", 0x07, b"one");
+        record(&mut journal, "Boot0002", 0x07, b"two");
+
+        let bytes = journal.to_bytes();
+        let parsed = VarJournal::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.entries(), journal.entries());
+
+        assert!(VarJournal::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(VarJournal::from_bytes(&[]).is_err());
+        let empty = VarJournal::from_bytes(&[1, 0, 0, 0]).unwrap();
+        assert!(empty.entries().is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Forwarding of SVSM protocol requests to user-mode handlers.
+//!
+//! A user-mode process registers as the handler of a protocol and receives a
+//! [`ProtocolObj`], which is one end of a channel. For each request of the
+//! protocol, the kernel sends a [`ProtocolRequest`] over this channel together
+//! with one end of a new reply channel, and waits for the [`ProtocolReply`] on
+//! the other end. A registered handler takes precedence over the in-kernel
+//! implementation of a protocol. Only processes started by the kernel may
+//! register handlers, see [`Task::is_kernel_launched`].
+//!
+//! [`Task::is_kernel_launched`]: crate::task::Task::is_kernel_launched
+
+extern crate alloc;
+
+use super::errors::{SvsmReqError, SvsmResultCode};
+use super::{RequestParams, SVSM_APIC_PROTOCOL, SVSM_CORE_PROTOCOL};
+use crate::error::SvsmError;
+use crate::events::EventSource;
+use crate::ipc::{ChannelError, ChannelObj, Message};
+use crate::locking::SpinLock;
+use crate::syscall::{Obj, ObjError};
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use syscall::{ChannelEvents, ProtocolReply, ProtocolRequest};
+use zerocopy::{FromBytes, IntoBytes};
+
+/// Kernel ends of the channels to the registered protocol handlers.
+static HANDLERS: SpinLock<BTreeMap<u32, Arc<ChannelObj>>> = SpinLock::new(BTreeMap::new());
+
+/// Number of entries in [`HANDLERS`], which lets requests skip the lock
+/// while no handler is registered.
+static HANDLER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Signalled when a handler closes its end of the request channel.
+static HANDLER_EXIT: SpinLock<Option<Arc<EventSource>>> = SpinLock::new(None);
+
+/// Time a handler has to reply to a request before the request fails. The
+/// guest is blocked meanwhile, so a hung handler must not stall it forever.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The user-mode end of the request channel of a protocol handler. Besides
+/// the channel operations, it grants access to guest memory.
+#[derive(Debug)]
+pub struct ProtocolObj {
+    protocol: u32,
+    channel: ChannelObj,
+}
+
+impl ProtocolObj {
+    /// Registers a new handler for `protocol`.
+    ///
+    /// # Returns
+    ///
+    /// The handler end of the request channel, or an error if the protocol
+    /// must be handled by the kernel or already has a handler. Handlers
+    /// which have closed their end of the channel are replaced.
+    pub fn register(protocol: u32) -> Result<Self, SvsmError> {
+        if matches!(protocol, SVSM_CORE_PROTOCOL | SVSM_APIC_PROTOCOL) {
+            return Err(SvsmError::NotSupported);
+        }
+
+        let mut handlers = HANDLERS.lock();
+        if handlers
+            .get(&protocol)
+            .is_some_and(|h| !h.events().contains(ChannelEvents::CLOSED))
+        {
+            return Err(ObjError::Busy.into());
+        }
+
+        let (kernel_end, handler_end) = ChannelObj::new_pair();
+        handlers.insert(protocol, Arc::new(kernel_end));
+        HANDLER_COUNT.store(handlers.len(), Ordering::Relaxed);
+        log::info!("Registered user-mode handler for protocol {protocol}");
+
+        Ok(Self {
+            protocol,
+            channel: handler_end,
+        })
+    }
+
+    /// Returns the protocol this object handles.
+    pub fn protocol(&self) -> u32 {
+        self.protocol
+    }
+
+    /// Removes the handler as soon as it exits, rather than on the next
+    /// request of its protocol. The handler is removed by the event loop of
+    /// the CPU with the index `cpu_index`.
+    pub fn watch_exit(&self, cpu_index: usize) -> Result<(), SvsmError> {
+        let source = {
+            let mut exit = HANDLER_EXIT.lock();
+            match exit.as_ref() {
+                Some(source) => source.clone(),
+                None => exit
+                    .insert(Arc::new(EventSource::new(remove_exited_handlers)?))
+                    .clone(),
+            }
+        };
+        if let Some(handler) = HANDLERS.lock().get(&self.protocol) {
+            handler.set_notifier(source, cpu_index);
+        }
+        Ok(())
+    }
+}
+
+fn remove_exited_handlers() {
+    let mut handlers = HANDLERS.lock();
+    handlers.retain(|protocol, handler| {
+        let closed = handler.events().contains(ChannelEvents::CLOSED);
+        if closed {
+            log::warn!("Handler of protocol {protocol} has exited");
+        }
+        !closed
+    });
+    HANDLER_COUNT.store(handlers.len(), Ordering::Relaxed);
+}
+
+impl Obj for ProtocolObj {
+    fn as_channel(&self) -> Option<&ChannelObj> {
+        Some(&self.channel)
+    }
+
+    fn as_protocol(&self) -> Option<&ProtocolObj> {
+        Some(self)
+    }
+}
+
+fn forward_request(
+    handler: &ChannelObj,
+    protocol: u32,
+    request: u32,
+    params: &mut RequestParams,
+) -> Result<(), SvsmReqError> {
+    let (reply_channel, handler_reply_channel) = ChannelObj::new_pair();
+    let req = ProtocolRequest {
+        protocol,
+        request,
+        sev_features: params.sev_features,
+        rcx: params.rcx,
+        rdx: params.rdx,
+        r8: params.r8,
+    };
+    let msg = Message {
+        data: req.as_bytes().to_vec(),
+        objs: vec![Arc::new(handler_reply_channel)],
+    };
+    match handler.send(msg) {
+        Ok(()) => {}
+        Err(SvsmError::Channel(ChannelError::Full)) => return Err(SvsmReqError::busy()),
+        Err(SvsmError::Channel(ChannelError::Closed)) => {
+            log::warn!("Handler of protocol {protocol} has exited");
+            return Err(SvsmReqError::unsupported_protocol());
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    if reply_channel
+        .poll_timeout(ChannelEvents::READABLE, REPLY_TIMEOUT)
+        .is_empty()
+    {
+        log::warn!("Handler of protocol {protocol} timed out on request {request}");
+        return Err(SvsmReqError::busy());
+    }
+    let Ok(msg) = reply_channel.recv(size_of::<ProtocolReply>(), 0) else {
+        log::warn!("Handler of protocol {protocol} did not reply to request {request}");
+        return Err(SvsmReqError::invalid_request());
+    };
+    let reply = ProtocolReply::read_from_bytes(&msg.data).map_err(|_| {
+        log::warn!("Handler of protocol {protocol} sent a malformed reply");
+        SvsmReqError::invalid_request()
+    })?;
+
+    params.rcx = reply.rcx;
+    params.rdx = reply.rdx;
+    params.r8 = reply.r8;
+
+    match SvsmResultCode::try_from(reply.result) {
+        Ok(SvsmResultCode::SUCCESS) => Ok(()),
+        Ok(code) => Err(SvsmReqError::RequestError(code)),
+        Err(()) => {
+            log::warn!(
+                "Handler of protocol {protocol} returned invalid result {:#x}",
+                reply.result
+            );
+            Err(SvsmReqError::invalid_request())
+        }
+    }
+}
+
+/// Forwards a request to the user-mode handler of `protocol`, if there is
+/// one, and waits up to [`REPLY_TIMEOUT`] for its reply.
+///
+/// # Returns
+///
+/// `None` if no running handler is registered for `protocol`, otherwise the
+/// result of the request.
+pub fn user_protocol_request(
+    protocol: u32,
+    request: u32,
+    params: &mut RequestParams,
+) -> Option<Result<(), SvsmReqError>> {
+    if HANDLER_COUNT.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+
+    let handler = {
+        let mut handlers = HANDLERS.lock();
+        let handler = handlers.get(&protocol)?;
+        if handler.events().contains(ChannelEvents::CLOSED) {
+            // Fall back to the in-kernel implementation, if any.
+            log::warn!("Handler of protocol {protocol} has exited");
+            handlers.remove(&protocol);
+            HANDLER_COUNT.store(handlers.len(), Ordering::Relaxed);
+            return None;
+        }
+        handler.clone()
+    };
+    Some(forward_request(&handler, protocol, request, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_handler() {
+        assert!(matches!(
+            ProtocolObj::register(SVSM_CORE_PROTOCOL),
+            Err(SvsmError::NotSupported)
+        ));
+
+        let protocol = 0x1000;
+        let handler = ProtocolObj::register(protocol).unwrap();
+        assert_eq!(handler.protocol(), protocol);
+        assert_eq!(HANDLER_COUNT.load(Ordering::Relaxed), 1);
+        assert!(matches!(
+            ProtocolObj::register(protocol),
+            Err(SvsmError::Obj(ObjError::Busy))
+        ));
+
+        // A handler which exited can be replaced.
+        drop(handler);
+        let mut params = RequestParams::default();
+        assert!(user_protocol_request(protocol, 0, &mut params).is_none());
+        assert_eq!(HANDLER_COUNT.load(Ordering::Relaxed), 0);
+        ProtocolObj::register(protocol).unwrap();
+    }
+}
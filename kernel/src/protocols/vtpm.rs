@@ -13,10 +13,10 @@ use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
 use crate::{
     address::{Address, PhysAddr},
-    mm::guestmem::{copy_slice_to_guest, read_bytes_from_guest, read_from_guest},
+    mm::guestmem::{copy_slice_to_guest, read_bytes_from_guest, read_from_guest, write_to_guest},
     protocols::{RequestParams, errors::SvsmReqError},
     types::PAGE_SIZE,
-    vtpm::{TcgTpmSimulatorInterface, VtpmProtocolInterface, vtpm_get_locked},
+    vtpm::{VtpmInterface, vtpm_poweron, vtpm_signal_cancel, vtpm_supported_commands, with_vtpm},
 };
 
 pub const VTPM_PROTOCOL_VERSION_MIN: u32 = 1;
@@ -31,7 +31,13 @@ pub const VTPM_PROTOCOL_VERSION_MAX: u32 = 1;
 #[repr(u32)]
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum TpmPlatformCommand {
+    SignalPowerOn = 1,
+    SignalPowerOff = 2,
     SendCommand = 8,
+    SignalCancelOn = 9,
+    SignalCancelOff = 10,
+    SignalNvOn = 11,
+    SignalReset = 17,
 }
 
 impl TryFrom<u32> for TpmPlatformCommand {
@@ -39,7 +45,13 @@ impl TryFrom<u32> for TpmPlatformCommand {
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
         let cmd = match value {
-            x if x == TpmPlatformCommand::SendCommand as u32 => TpmPlatformCommand::SendCommand,
+            x if x == Self::SignalPowerOn as u32 => Self::SignalPowerOn,
+            x if x == Self::SignalPowerOff as u32 => Self::SignalPowerOff,
+            x if x == Self::SendCommand as u32 => Self::SendCommand,
+            x if x == Self::SignalCancelOn as u32 => Self::SignalCancelOn,
+            x if x == Self::SignalCancelOff as u32 => Self::SignalCancelOff,
+            x if x == Self::SignalNvOn as u32 => Self::SignalNvOn,
+            x if x == Self::SignalReset as u32 => Self::SignalReset,
             other => {
                 log::warn!("Failed to convert {other} to a TPM platform command");
                 return Err(SvsmReqError::invalid_parameter());
@@ -52,9 +64,8 @@ impl TryFrom<u32> for TpmPlatformCommand {
 
 fn vtpm_platform_commands_supported_bitmap() -> u64 {
     let mut bitmap: u64 = 0;
-    let vtpm = vtpm_get_locked();
 
-    for cmd in vtpm.get_supported_commands() {
+    for cmd in vtpm_supported_commands() {
         bitmap |= 1u64 << *cmd as u32;
     }
 
@@ -62,12 +73,15 @@ fn vtpm_platform_commands_supported_bitmap() -> u64 {
 }
 
 fn is_vtpm_platform_command_supported(cmd: TpmPlatformCommand) -> bool {
-    let vtpm = vtpm_get_locked();
-    vtpm.get_supported_commands().contains(&cmd)
+    vtpm_supported_commands().contains(&cmd)
 }
 
 const SEND_COMMAND_REQ_INBUF_SIZE: usize = PAGE_SIZE - 9;
 
+/// Highest TPM locality the guest can run commands in. Locality 4 is reserved
+/// for the trusted hardware of the platform.
+const TPM_LOCALITY_MAX: u8 = 3;
+
 // vTPM protocol services (SVSM spec, table 14)
 const SVSM_VTPM_QUERY: u32 = 0;
 const SVSM_VTPM_COMMAND: u32 = 1;
@@ -78,7 +92,7 @@ const SVSM_VTPM_COMMAND: u32 = 1;
 struct TpmSendCommandRequest {
     /// MSSIM platform command ID
     command: u32,
-    /// Locality usage for the vTPM is not defined yet (must be zero)
+    /// TPM locality the command is executed in
     locality: u8,
     /// Size of the input buffer
     inbuf_size: u32,
@@ -100,9 +114,7 @@ impl TpmSendCommandRequest {
     }
 
     fn validate(&self) -> bool {
-        // TODO: Before implementing locality, we need to agree what it means
-        // to the platform
-        self.locality == 0
+        self.locality <= TPM_LOCALITY_MAX
             && self.command == TpmPlatformCommand::SendCommand as u32
             && self.inbuf_size as usize <= SEND_COMMAND_REQ_INBUF_SIZE
     }
@@ -115,10 +127,7 @@ impl TpmSendCommandRequest {
             .get(..length)
             .ok_or_else(SvsmReqError::invalid_parameter)?;
 
-        let vtpm = vtpm_get_locked();
-        let response = vtpm.send_tpm_command(tpm_cmd, self.locality)?;
-
-        Ok(response)
+        with_vtpm(|vtpm| vtpm.send_tpm_command(tpm_cmd, self.locality))
     }
 }
 
@@ -210,11 +219,39 @@ fn vtpm_command_request(params: &RequestParams) -> Result<(), SvsmReqError> {
             tpm_send_command_request(&mut buffer[..])?;
             copy_slice_to_guest(&buffer[..], paddr)?;
         }
+        // The cancel flag must reach the TPM command it is meant to
+        // interrupt, so it does not wait for the TPM like the other
+        // signals.
+        TpmPlatformCommand::SignalCancelOn | TpmPlatformCommand::SignalCancelOff => {
+            vtpm_signal_cancel(cmd == TpmPlatformCommand::SignalCancelOn)?;
+            write_to_guest(&0u32, paddr)?;
+        }
+        signal => {
+            with_vtpm(|vtpm| vtpm_platform_signal(vtpm, signal))?;
+            // Platform signals have no response data.
+            write_to_guest(&0u32, paddr)?;
+        }
     };
 
     Ok(())
 }
 
+/// Delivers a platform signal other than TPM_SEND_COMMAND to the vTPM
+fn vtpm_platform_signal(
+    vtpm: &mut dyn VtpmInterface,
+    signal: TpmPlatformCommand,
+) -> Result<(), SvsmReqError> {
+    match signal {
+        TpmPlatformCommand::SignalPowerOn => vtpm_poweron(vtpm, false),
+        TpmPlatformCommand::SignalPowerOff => vtpm.signal_poweroff(),
+        TpmPlatformCommand::SignalNvOn => vtpm.signal_nvon(),
+        TpmPlatformCommand::SignalReset => vtpm_poweron(vtpm, true),
+        TpmPlatformCommand::SendCommand
+        | TpmPlatformCommand::SignalCancelOn
+        | TpmPlatformCommand::SignalCancelOff => Err(SvsmReqError::invalid_parameter()),
+    }
+}
+
 pub fn vtpm_protocol_request(request: u32, params: &mut RequestParams) -> Result<(), SvsmReqError> {
     match request {
         SVSM_VTPM_QUERY => vtpm_query_request(params),
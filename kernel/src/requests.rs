@@ -8,10 +8,14 @@ extern crate alloc;
 
 use crate::cpu::ipi::wait_for_ipi_block;
 use crate::cpu::percpu::{PERCPU_AREAS, this_cpu};
+use crate::events::handle_events;
 use crate::protocols::apic::apic_protocol_request;
 use crate::protocols::core::core_protocol_request;
 use crate::protocols::errors::{SvsmReqError, SvsmResultCode};
-use crate::task::{KernelThreadStartInfo, go_idle, set_affinity, start_kernel_thread};
+use crate::protocols::user::user_protocol_request;
+use crate::task::{
+    KernelThreadStartInfo, current_task, go_idle, set_affinity, start_kernel_thread,
+};
 use crate::vmm::{GuestExitMessage, GuestRegister, enter_guest};
 
 use crate::protocols::attest::attest_protocol_request;
@@ -20,7 +24,7 @@ use crate::protocols::{
 };
 #[cfg(all(feature = "uefivars", not(test)))]
 use crate::protocols::{SVSM_UEFI_MM_PROTOCOL, uefivars::uefi_mm_protocol_request};
-#[cfg(all(feature = "vtpm", not(test)))]
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
 use crate::protocols::{SVSM_VTPM_PROTOCOL, vtpm::vtpm_protocol_request};
 
 use alloc::vec::Vec;
@@ -80,10 +84,14 @@ fn request_loop_once(
     protocol: u32,
     request: u32,
 ) -> Result<(), SvsmReqError> {
+    if let Some(result) = user_protocol_request(protocol, request, params) {
+        return result;
+    }
+
     match protocol {
         SVSM_CORE_PROTOCOL => core_protocol_request(request, params),
         SVSM_ATTEST_PROTOCOL => attest_protocol_request(request, params),
-        #[cfg(all(feature = "vtpm", not(test)))]
+        #[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
         SVSM_VTPM_PROTOCOL => vtpm_protocol_request(request, params),
         SVSM_APIC_PROTOCOL => apic_protocol_request(request, params),
         #[cfg(all(feature = "uefivars", not(test)))]
@@ -112,6 +120,9 @@ fn request_loop_main(cpu_index: usize) {
     // Send this task to the correct CPU.
     set_affinity(cpu_index);
 
+    // The guest is waiting while this task is not running.
+    current_task().set_request_task();
+
     log::info!("Launching request-processing task on CPU {cpu_index}");
 
     // Suppress the use of IPIs before entering the guest, and ensure that all
@@ -128,9 +139,13 @@ fn request_loop_main(cpu_index: usize) {
 
         match msg {
             GuestExitMessage::NoMappings => {
+                // Events are still handled while the guest can not run. Events
+                // signalled while the handlers run wake the task again.
+                handle_events();
                 log::debug!("No VMSA or CAA! Halting");
                 go_idle();
             }
+            GuestExitMessage::Events => handle_events(),
             GuestExitMessage::Svsm((protocol, request, mut params)) => {
                 guest_regs = process_request(protocol, request, &mut params);
             }
@@ -9,6 +9,7 @@ use crate::error::SvsmError;
 use crate::mm::page_visibility::SharedBox;
 use crate::mm::virt_to_phys;
 use crate::sev::ghcb::GHCB;
+use crate::task::preempt_on_irq_return;
 
 use bitfield_struct::bitfield;
 use core::arch::asm;
@@ -211,4 +212,8 @@ pub unsafe extern "C" fn process_hv_events(hv_doorbell: *const HVDoorbell) {
         (*hv_doorbell).process_pending_events();
     }
     cpu.irqs_pop_nesting();
+
+    // Interrupts delivered through the doorbell may end the time slice of
+    // the current task as well.
+    preempt_on_irq_return();
 }
@@ -33,6 +33,8 @@ use svsm::cpu::shadow_stack::{
 use svsm::cpu::smp::ApStartContextRef;
 use svsm::cpu::smp::start_secondary_cpus;
 use svsm::cpu::sse::sse_init;
+#[cfg(feature = "attest")]
+use svsm::crypto::keys::{ProvisioningFailurePolicy, provision_keys};
 use svsm::debug::gdbstub::svsm_gdbstub::{debug_break, gdbstub_start};
 use svsm::debug::stacktrace::print_stack;
 use svsm::debug::symbols::init_symbols;
@@ -41,11 +43,12 @@ use svsm::enable_shadow_stacks;
 use svsm::error::SvsmError;
 use svsm::fs::{initialize_fs, populate_ram_fs};
 use svsm::hyperv::hyperv_setup;
+use svsm::kernel_region::add_unused_kernel_region;
 use svsm::kernel_region::expand_kernel_heap;
 use svsm::kernel_region::new_kernel_region;
 use svsm::mm::FixedAddressMappingRange;
 use svsm::mm::PageBox;
-use svsm::mm::alloc::{free_multiple_pages, memory_info, print_memory_info, root_mem_init};
+use svsm::mm::alloc::{free_multiple_pages, memory_zones_info, print_memory_info, root_mem_init};
 use svsm::mm::global_memory::init_global_ranges;
 use svsm::mm::init_kernel_mapping_info;
 use svsm::mm::memory::init_memory_map;
@@ -67,13 +70,14 @@ use svsm::sev::secrets_page_mut;
 use svsm::svsm_paging::enumerate_early_boot_regions;
 use svsm::svsm_paging::invalidate_early_boot_memory;
 use svsm::task::{KernelThreadStartInfo, schedule_init, start_kernel_task};
+use svsm::time::time_init;
 use svsm::types::PAGE_SIZE;
 use svsm::utils::MemoryRegion;
 use svsm::utils::ScopedMut;
 use svsm::utils::round_to_pages;
 #[cfg(all(feature = "virtio-drivers", any(feature = "block", feature = "vsock")))]
 use svsm::virtio::probe_mmio_slots;
-#[cfg(all(feature = "vtpm", not(test)))]
+#[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
 use svsm::vtpm::vtpm_init;
 
 use alloc::string::String;
@@ -412,8 +416,10 @@ unsafe fn svsm_start(
 
     dump_cpuid_table();
 
-    let mem_info = memory_info();
-    print_memory_info(&mem_info);
+    for zone in memory_zones_info() {
+        log::info!("Memory zone {:#018x}:", zone.region);
+        print_memory_info(&zone.mem_info);
+    }
 
     boot_stack_info();
 
@@ -506,11 +512,26 @@ fn svsm_init(launch_info: &KernelLaunchInfo) {
 
     init_memory_map(&boot_params, launch_info).expect("Failed to init guest memory map");
 
+    let full_kernel_region = boot_params
+        .find_kernel_region()
+        .expect("Failed to find memory region for SVSM kernel");
+    // SAFETY: the kernel region is taken from the boot parameters, and the
+    // launch info has been updated when the kernel heap was expanded.
+    let result =
+        unsafe { add_unused_kernel_region(launch_info, full_kernel_region, &**SVSM_PLATFORM) };
+    if let Err(e) = result {
+        log::warn!("Failed to add unused kernel region to the page allocator: {e:?}");
+    }
+
     populate_ram_fs(launch_info.kernel_fs_start, launch_info.kernel_fs_end)
         .expect("Failed to unpack FS archive");
 
     init_capabilities();
 
+    if let Err(e) = time_init() {
+        log::error!("Failed to initialize timekeeping: {e:?}");
+    }
+
     let cpus = boot_params
         .load_cpu_info()
         .expect("Failed to load ACPI tables");
@@ -548,14 +569,37 @@ fn svsm_init(launch_info: &KernelLaunchInfo) {
 
     #[cfg(feature = "attest")]
     {
-        let mut proxy = AttestationDriver::try_from(Tee::Snp).unwrap();
-        let _data = proxy.attest().unwrap();
+        let policy = if boot_params.continue_unprovisioned() {
+            ProvisioningFailurePolicy::ContinueUnprovisioned
+        } else {
+            ProvisioningFailurePolicy::Halt
+        };
+        // The attestation server checks the version of the persistent state
+        // to protect it against rollback.
+        #[cfg(feature = "block")]
+        let state_version = svsm::block::secure_storage_version()
+            .expect("Failed to read the version of the persistent state");
+        #[cfg(not(feature = "block"))]
+        let state_version = None;
+
+        let secret =
+            AttestationDriver::try_from(Tee::Snp).and_then(|mut proxy| proxy.attest(state_version));
+        if secret.is_ok() {
+            log::info!("attestation successful");
+        }
+        provision_keys(secret, policy).expect("Failed to provision keys");
+    }
 
-        // Nothing to do with data at the moment, simply print a success message.
-        log::info!("attestation successful");
+    // The storage key provisioned through attestation keys the encrypted
+    // storage used by the persistent services.
+    #[cfg(feature = "block")]
+    svsm::block::initialize_secure_storage().expect("Failed to initialize encrypted storage");
+    #[cfg(feature = "block")]
+    if let Err(e) = svsm::fs::mount_persistent_fs() {
+        log::error!("Failed to mount persistent filesystem, /var is not available: {e:?}");
     }
 
-    #[cfg(all(feature = "vtpm", not(test)))]
+    #[cfg(all(any(feature = "vtpm", feature = "vtpm-user"), not(test)))]
     vtpm_init().expect("vTPM failed to initialize");
 
     #[cfg(all(feature = "uefivars", not(test)))]
@@ -587,7 +631,8 @@ fn svsm_init(launch_info: &KernelLaunchInfo) {
         use svsm::requests::request_loop_start;
         use svsm::task::exec_user;
 
-        match exec_user("/init", opendir("/").expect("Failed to find FS root")) {
+        let root = opendir("/").expect("Failed to find FS root");
+        match exec_user("/init", root, &["/init"], &[]) {
             Ok(_) => (),
             Err(e) => log::info!("Failed to launch /init: {e:?}"),
         }
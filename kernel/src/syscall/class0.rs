@@ -4,34 +4,166 @@
 //
 // Author: Joerg Roedel <jroedel@suse.de>
 
-use super::obj::obj_close;
-use crate::address::VirtAddr;
-use crate::cpu::percpu::current_task;
+extern crate alloc;
+
+use super::obj::{obj_close, obj_get};
+use crate::address::{Address, VirtAddr};
+use crate::cpu::percpu::{PERCPU_AREAS, current_task};
 use crate::fs::find_dir;
 use crate::mm::guestmem::UserPtr;
+use crate::mm::vm::VMFileMappingFlags;
+use crate::mm::{USER_MEM_END, USER_MEM_START, mmap_user, munmap_user};
 use crate::task::exec_user;
-use crate::task::terminate;
+use crate::task::{
+    UserThreadStartInfo, futex_wait, futex_wake, start_user_thread, terminate, wait_for_termination,
+};
+use crate::time::{clocks_available, monotonic, realtime, sleep};
+use crate::types::PAGE_SIZE;
+use crate::utils::is_aligned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::max;
 use core::ffi::c_char;
-use syscall::SysCallError;
+use core::time::Duration;
+use syscall::{ClockId, MMapFlags, SysCallError, THREAD_CPU_CURRENT};
 
 pub fn sys_exit(exit_code: u32) -> ! {
+    let task = current_task();
     log::info!(
         "Terminating task {}, exit_code {exit_code}",
-        current_task().get_task_name()
+        task.get_task_name()
     );
+    task.set_exit_code(exit_code);
+    drop(task);
     terminate();
 }
 
-pub fn sys_exec(file: usize, root: usize, _flags: usize) -> Result<u64, SysCallError> {
+/// Maximum number of strings in the argument or environment array of
+/// [`sys_exec`].
+const EXEC_STRINGS_MAX: usize = 256;
+
+/// Reads a NULL-terminated array of C strings from user space. A NULL array
+/// is treated as empty.
+fn read_c_string_array(addr: usize) -> Result<Vec<String>, SysCallError> {
+    let mut strings = Vec::new();
+    if addr == 0 {
+        return Ok(strings);
+    }
+
+    let array = UserPtr::<usize>::new(VirtAddr::from(addr));
+    for index in 0..=EXEC_STRINGS_MAX {
+        let ptr = array.offset(index as isize).read()?;
+        if ptr == 0 {
+            return Ok(strings);
+        }
+        strings.push(UserPtr::<c_char>::new(VirtAddr::from(ptr)).read_c_string()?);
+    }
+    Err(SysCallError::EINVAL)
+}
+
+pub fn sys_exec(
+    file: usize,
+    root: usize,
+    _flags: usize,
+    argv: usize,
+    envp: usize,
+) -> Result<u64, SysCallError> {
     let user_file_ptr = UserPtr::<c_char>::new(VirtAddr::from(file));
     let user_root_ptr = UserPtr::<c_char>::new(VirtAddr::from(root));
 
     let file_str = user_file_ptr.read_c_string()?;
     let root_str = user_root_ptr.read_c_string()?;
-    let real_root = find_dir(current_task().rootdir(), &root_str)?;
-    let tid = exec_user(&file_str, real_root)?;
+    let argv = read_c_string_array(argv)?;
+    let envp = read_c_string_array(envp)?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+
+    let task = current_task();
+    let real_root = find_dir(task.rootdir(), &root_str)?;
+    let child = exec_user(&file_str, real_root, &argv, &envp)?;
+    let tid = child.get_task_id();
+    task.add_child(child);
+
+    Ok(tid.into())
+}
+
+pub fn sys_wait(tid: u32) -> Result<u64, SysCallError> {
+    let child = current_task()
+        .take_child(tid)
+        .ok_or(SysCallError::ENOTFOUND)?;
+    wait_for_termination(child.clone());
+    Ok(child.exit_code().into())
+}
+
+pub fn sys_thread_create(
+    entry: usize,
+    stack: usize,
+    arg: usize,
+    cpu_index: usize,
+) -> Result<u64, SysCallError> {
+    let user_range = USER_MEM_START..USER_MEM_END;
+    if !user_range.contains(&VirtAddr::from(entry))
+        || !user_range.contains(&VirtAddr::from(stack))
+        || !is_aligned(stack + 8, 16)
+    {
+        return Err(SysCallError::EINVAL);
+    }
+    let cpu_index = match cpu_index {
+        THREAD_CPU_CURRENT => None,
+        index if index < PERCPU_AREAS.len() => Some(index),
+        _ => return Err(SysCallError::EINVAL),
+    };
+
+    let start_info = UserThreadStartInfo {
+        entry,
+        stack,
+        start_parameter: arg,
+    };
+    let thread = start_user_thread(start_info, cpu_index)?;
+    let tid = thread.get_task_id();
+    current_task().add_child(thread);
+
+    Ok(tid.into())
+}
+
+/// Checks that `addr` points to an aligned futex in the user address space.
+fn check_futex_addr(addr: usize) -> Result<VirtAddr, SysCallError> {
+    let addr = VirtAddr::from(addr);
+    // An aligned futex starting below the page-aligned end of the user
+    // address space also ends within it.
+    if !is_aligned(addr.bits(), size_of::<u32>()) || !(USER_MEM_START..USER_MEM_END).contains(&addr)
+    {
+        return Err(SysCallError::EINVAL);
+    }
+    Ok(addr)
+}
+
+pub fn sys_futex_wait(addr: usize, expected: u32) -> Result<u64, SysCallError> {
+    let addr = check_futex_addr(addr)?;
+    match futex_wait(addr, expected) {
+        Ok(true) => Ok(0),
+        Ok(false) => Err(SysCallError::EAGAIN),
+        Err(_) => Err(SysCallError::EFAULT),
+    }
+}
+
+pub fn sys_futex_wake(addr: usize, count: u32) -> Result<u64, SysCallError> {
+    let addr = check_futex_addr(addr)?;
+    Ok(futex_wake(addr, count as usize) as u64)
+}
 
-    Ok(tid.get_task_id().into())
+pub fn sys_clock_gettime(clock: u32) -> Result<u64, SysCallError> {
+    let time = match ClockId::try_from(clock).map_err(|_| SysCallError::EINVAL)? {
+        ClockId::Monotonic if clocks_available() => monotonic(),
+        ClockId::Monotonic => return Err(SysCallError::ENOTSUPP),
+        ClockId::Realtime => realtime().ok_or(SysCallError::ENOTSUPP)?,
+    };
+    Ok(u64::try_from(time.as_nanos()).unwrap_or(u64::MAX))
+}
+
+pub fn sys_sleep(nsecs: u64) -> Result<u64, SysCallError> {
+    sleep(Duration::from_nanos(nsecs))?;
+    Ok(0)
 }
 
 pub fn sys_close(obj_id: u32) -> Result<u64, SysCallError> {
@@ -40,3 +172,70 @@ pub fn sys_close(obj_id: u32) -> Result<u64, SysCallError> {
     let _ = obj_close(obj_id.into());
     Ok(0)
 }
+
+/// Checks that a page-aligned range lies within the user address space.
+fn check_user_range(addr: VirtAddr, size: usize) -> Result<(), SysCallError> {
+    let end = addr.bits().checked_add(size).ok_or(SysCallError::EINVAL)?;
+    if !addr.is_page_aligned() || addr < USER_MEM_START || end > USER_MEM_END.bits() {
+        return Err(SysCallError::EINVAL);
+    }
+    Ok(())
+}
+
+pub fn sys_mmap(
+    addr: usize,
+    size: usize,
+    flags: usize,
+    obj_id: u32,
+    offset: usize,
+) -> Result<u64, SysCallError> {
+    let flags = MMapFlags::from_bits(flags).ok_or(SysCallError::EINVAL)?;
+    let size = size
+        .checked_next_multiple_of(PAGE_SIZE)
+        .filter(|size| *size != 0)
+        .ok_or(SysCallError::EINVAL)?;
+    let addr = VirtAddr::from(addr);
+
+    let mut vm_flags = VMFileMappingFlags::empty();
+    for (flag, vm_flag) in [
+        (MMapFlags::READ, VMFileMappingFlags::Read),
+        (MMapFlags::WRITE, VMFileMappingFlags::Write),
+        (MMapFlags::EXEC, VMFileMappingFlags::Execute),
+        (MMapFlags::PRIVATE, VMFileMappingFlags::Private),
+        (MMapFlags::FIXED, VMFileMappingFlags::Fixed),
+    ] {
+        if flags.contains(flag) {
+            vm_flags |= vm_flag;
+        }
+    }
+
+    let addr = if flags.contains(MMapFlags::FIXED) {
+        check_user_range(addr, size)?;
+        addr
+    } else {
+        // Only use the hint for where to start searching, but keep the
+        // first page unmapped so that null pointers keep faulting.
+        check_user_range(addr, 0)?;
+        max(addr, USER_MEM_START + PAGE_SIZE)
+    };
+
+    let vaddr = if flags.contains(MMapFlags::ANONYMOUS) {
+        if offset != 0 {
+            return Err(SysCallError::EINVAL);
+        }
+        mmap_user(addr, None, 0, size, vm_flags)?
+    } else {
+        let obj = obj_get(obj_id.into())?;
+        let fs_obj = obj.as_fs().ok_or(SysCallError::ENOTSUPP)?;
+        fs_obj.mmap(addr, offset, size, vm_flags)?
+    };
+
+    Ok(vaddr.bits() as u64)
+}
+
+pub fn sys_munmap(addr: usize) -> Result<u64, SysCallError> {
+    let addr = VirtAddr::from(addr);
+    check_user_range(addr, PAGE_SIZE)?;
+    munmap_user(addr)?;
+    Ok(0)
+}
@@ -4,8 +4,22 @@
 //
 // Author: Chuanxiao Dong <chuanxiao.dong@intel.com>
 
+extern crate alloc;
+
+use super::obj::{obj_add, obj_get};
+use crate::address::{Address, PhysAddr, VirtAddr};
+use crate::cpu::percpu::this_cpu;
+use crate::error::SvsmError;
+use crate::mm::guestmem::{copy_slice_from_guest, copy_slice_to_guest};
+use crate::mm::{copy_from_user, copy_to_user};
 use crate::platform::CAPS;
 use crate::platform::capabilities::Cap;
+use crate::protocols::user::ProtocolObj;
+use crate::task::current_task;
+use crate::types::PAGE_SIZE;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::cmp::min;
 use syscall::SysCallError;
 
 pub fn sys_capabilities(index: u32) -> Result<u64, SysCallError> {
@@ -16,3 +30,92 @@ pub fn sys_capabilities(index: u32) -> Result<u64, SysCallError> {
     };
     Ok(CAPS.get(cap))
 }
+
+/// Only processes started by the kernel are trusted with guest requests and
+/// the scheduling priority of request tasks.
+fn check_kernel_launched() -> Result<(), SysCallError> {
+    if current_task().is_kernel_launched() {
+        Ok(())
+    } else {
+        Err(SysCallError::EPERM)
+    }
+}
+
+pub fn sys_protocol_register(protocol: u32) -> Result<u64, SysCallError> {
+    check_kernel_launched()?;
+    let obj = ProtocolObj::register(protocol)?;
+    obj.watch_exit(this_cpu().get_cpu_index())?;
+    let id = obj_add(Arc::new(obj))?;
+    // Guest requests wait for the handler, so schedule it like the request
+    // loops.
+    current_task().set_request_task();
+    Ok(u32::from(id).into())
+}
+
+pub fn sys_vtpm_register() -> Result<u64, SysCallError> {
+    #[cfg(all(feature = "vtpm-user", not(test)))]
+    {
+        check_kernel_launched()?;
+        let channel = crate::vtpm::user::register()?;
+        let id = obj_add(Arc::new(channel))?;
+        current_task().set_request_task();
+        Ok(u32::from(id).into())
+    }
+    #[cfg(not(all(feature = "vtpm-user", not(test))))]
+    {
+        Err(SysCallError::ENOTSUPP)
+    }
+}
+
+/// Copies between guest memory and a user buffer on behalf of a protocol
+/// handler. The copy is split into chunks which do not cross a guest page
+/// boundary.
+fn copy_guest_chunks(
+    obj_id: u32,
+    gpa: usize,
+    user_addr: usize,
+    size: usize,
+    mut copy: impl FnMut(PhysAddr, VirtAddr, &mut [u8]) -> Result<(), SvsmError>,
+) -> Result<u64, SysCallError> {
+    // Only protocol handlers may access guest memory.
+    obj_get(obj_id.into())?
+        .as_protocol()
+        .ok_or(SysCallError::EPERM)?;
+    gpa.checked_add(size).ok_or(SysCallError::EINVAL)?;
+    user_addr.checked_add(size).ok_or(SysCallError::EINVAL)?;
+
+    let (gpa, user_addr) = (PhysAddr::from(gpa), VirtAddr::from(user_addr));
+    let mut buffer = vec![0u8; min(size, PAGE_SIZE)];
+    let mut done = 0;
+    while done < size {
+        let chunk_gpa = gpa + done;
+        let len = min(size - done, PAGE_SIZE - chunk_gpa.page_offset());
+        copy(chunk_gpa, user_addr + done, &mut buffer[..len])?;
+        done += len;
+    }
+    Ok(size as u64)
+}
+
+pub fn sys_guest_read(
+    obj_id: u32,
+    gpa: usize,
+    user_addr: usize,
+    size: usize,
+) -> Result<u64, SysCallError> {
+    copy_guest_chunks(obj_id, gpa, user_addr, size, |gpa, user_addr, buf| {
+        copy_slice_from_guest(gpa, buf)?;
+        copy_to_user(buf, user_addr)
+    })
+}
+
+pub fn sys_guest_write(
+    obj_id: u32,
+    gpa: usize,
+    user_addr: usize,
+    size: usize,
+) -> Result<u64, SysCallError> {
+    copy_guest_chunks(obj_id, gpa, user_addr, size, |gpa, user_addr, buf| {
+        copy_from_user(user_addr, buf)?;
+        copy_slice_to_guest(buf, gpa)
+    })
+}
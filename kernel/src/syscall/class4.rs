@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+extern crate alloc;
+
+use super::obj::{obj_add, obj_close, obj_get};
+use crate::address::VirtAddr;
+use crate::ipc::{ChannelObj, Message};
+use crate::mm::guestmem::UserPtr;
+use crate::mm::{copy_from_user, copy_to_user};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use syscall::SysCallError::*;
+use syscall::*;
+
+pub fn sys_channel_create(ends: usize) -> Result<u64, SysCallError> {
+    let (a, b) = ChannelObj::new_pair();
+    let a = obj_add(Arc::new(a))?;
+    let b = obj_add(Arc::new(b)).inspect_err(|_| {
+        let _ = obj_close(a);
+    })?;
+
+    let user_ends_ptr = UserPtr::<[u32; 2]>::new(VirtAddr::from(ends));
+    user_ends_ptr.write([a.into(), b.into()]).inspect_err(|_| {
+        let _ = obj_close(a);
+        let _ = obj_close(b);
+    })?;
+
+    Ok(0)
+}
+
+pub fn sys_channel_send(obj_id: u32, msg: usize) -> Result<u64, SysCallError> {
+    let obj = obj_get(obj_id.into())?;
+    let channel = obj.as_channel().ok_or(ENOTSUPP)?;
+    let desc = UserPtr::<ChannelMsg>::new(VirtAddr::from(msg)).read()?;
+
+    let data_len = usize::try_from(desc.data_len).map_err(|_| EINVAL)?;
+    let num_handles = usize::try_from(desc.num_handles).map_err(|_| EINVAL)?;
+    if data_len > CHANNEL_MSG_MAX || num_handles > CHANNEL_HANDLES_MAX {
+        return Err(EINVAL);
+    }
+
+    let mut data = vec![0u8; data_len];
+    copy_from_user(VirtAddr::from(desc.data), &mut data)?;
+
+    let user_handles_ptr = UserPtr::<u32>::new(VirtAddr::from(desc.handles));
+    let mut objs = Vec::with_capacity(num_handles);
+    for i in 0..num_handles {
+        let id = user_handles_ptr.offset(i.try_into().unwrap()).read()?;
+        let obj = obj_get(id.into())?;
+        // An end of a channel queued on the channel itself would keep the
+        // channel alive forever.
+        if obj.as_channel().is_some_and(|c| c.same_channel(channel)) {
+            return Err(EINVAL);
+        }
+        objs.push(obj);
+    }
+
+    channel.send(Message { data, objs })?;
+    Ok(0)
+}
+
+pub fn sys_channel_recv(obj_id: u32, msg: usize) -> Result<u64, SysCallError> {
+    let obj = obj_get(obj_id.into())?;
+    let channel = obj.as_channel().ok_or(ENOTSUPP)?;
+    let user_desc_ptr = UserPtr::<ChannelMsg>::new(VirtAddr::from(msg));
+    let desc = user_desc_ptr.read()?;
+
+    let max_data = usize::try_from(desc.data_len).unwrap_or(usize::MAX);
+    let max_handles = usize::try_from(desc.num_handles).unwrap_or(usize::MAX);
+    let message = channel.recv(max_data, max_handles)?;
+
+    // A message which can not be delivered stays queued, so it is not lost
+    // because of a bad user buffer or a full object table.
+    deliver_message(&message, desc, user_desc_ptr).inspect_err(|_| channel.requeue(message))
+}
+
+/// Copies a received message to the buffers described by `desc` and adds
+/// its objects to the object table of the current task. Nothing is added
+/// to the object table on failure.
+fn deliver_message(
+    message: &Message,
+    mut desc: ChannelMsg,
+    user_desc_ptr: UserPtr<ChannelMsg>,
+) -> Result<u64, SysCallError> {
+    copy_to_user(&message.data, VirtAddr::from(desc.data))?;
+
+    let user_handles_ptr = UserPtr::<u32>::new(VirtAddr::from(desc.handles));
+    let mut ids = Vec::with_capacity(message.objs.len());
+    let result = message.objs.iter().enumerate().try_for_each(|(i, obj)| {
+        let id = obj_add(obj.clone())?;
+        ids.push(id);
+        user_handles_ptr.offset(i.try_into().unwrap()).write(id.into())
+    });
+
+    desc.data_len = message.data.len() as u64;
+    desc.num_handles = message.objs.len() as u64;
+    if let Err(e) = result.and_then(|()| user_desc_ptr.write(desc)) {
+        for id in ids {
+            let _ = obj_close(id);
+        }
+        return Err(e.into());
+    }
+
+    Ok(message.data.len() as u64)
+}
+
+pub fn sys_channel_poll(obj_id: u32, events: u32) -> Result<u64, SysCallError> {
+    let obj = obj_get(obj_id.into())?;
+    let channel = obj.as_channel().ok_or(ENOTSUPP)?;
+    let events = ChannelEvents::from_bits(events).ok_or(EINVAL)?;
+
+    Ok(channel.poll(events).bits().into())
+}
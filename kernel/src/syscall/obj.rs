@@ -9,6 +9,8 @@ extern crate alloc;
 use crate::cpu::percpu::current_task;
 use crate::error::SvsmError;
 use crate::fs::FsObj;
+use crate::ipc::ChannelObj;
+use crate::protocols::user::ProtocolObj;
 use alloc::sync::Arc;
 
 #[derive(Clone, Copy, Debug)]
@@ -28,6 +30,14 @@ pub trait Obj: Send + Sync + core::fmt::Debug {
     fn as_fs(&self) -> Option<&FsObj> {
         None
     }
+
+    fn as_channel(&self) -> Option<&ChannelObj> {
+        None
+    }
+
+    fn as_protocol(&self) -> Option<&ProtocolObj> {
+        None
+    }
 }
 
 /// ObjHandle is a unique identifier for an object in the current process.
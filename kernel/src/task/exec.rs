@@ -11,15 +11,25 @@ use crate::address::{Address, VirtAddr};
 use crate::error::SvsmError;
 use crate::fs::{Directory, open_read};
 use crate::mm::USER_MEM_END;
-use crate::mm::vm::VMFileMappingFlags;
-use crate::task::{create_user_task, current_task, finish_user_task, schedule};
+use crate::mm::ptguards::PerCPUPageMappingGuard;
+use crate::mm::vm::{Mapping, VMFileMappingFlags, VMalloc};
+use crate::task::{
+    UserThreadStartInfo, create_user_task, current_task, finish_user_task, schedule,
+};
 use crate::types::PAGE_SIZE;
 use crate::utils::align_up;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use elf::{Elf64File, Elf64PhdrFlags};
 
 use alloc::string::String;
 
+/// Size of the user-mode stack of a new task.
+const USER_STACK_SIZE: usize = 64 * 1024;
+
+/// Maximum size of the argument block on the user-mode stack.
+pub const EXEC_ARGS_MAX: usize = 16 * 1024;
+
 fn convert_elf_phdr_flags(flags: Elf64PhdrFlags) -> VMFileMappingFlags {
     let mut vm_flags = VMFileMappingFlags::Fixed;
 
@@ -44,16 +54,109 @@ fn task_name(binary: &str) -> String {
     }
 }
 
+/// Builds the argument block placed at the top of the user-mode stack of a
+/// new task. The block ends at `top`, which must be 16-byte aligned, and has
+/// the following layout, with all values being 64 bits wide:
+///
+/// ```text
+/// base:  argc
+///        argv[0] .. argv[argc - 1]
+///        NULL
+///        envp[0] .. envp[envc - 1]
+///        NULL
+///        argument and environment strings, each NUL-terminated
+///        zero padding up to a multiple of 16 bytes
+/// ```
+///
+/// The pointers in `argv` and `envp` point to the strings in the block.
+/// The start address of the block is passed to the entry point in `%rdi`.
+///
+/// # Returns
+///
+/// The start address and the contents of the block, or [`SvsmError::Mem`]
+/// if it exceeds [`EXEC_ARGS_MAX`].
+fn build_arg_block(
+    top: VirtAddr,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<(VirtAddr, Vec<u8>), SvsmError> {
+    let ptrs_size = (argv.len() + envp.len() + 3) * size_of::<u64>();
+    let strings_size: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let size = align_up(ptrs_size + strings_size, 16);
+    if size > EXEC_ARGS_MAX {
+        return Err(SvsmError::Mem);
+    }
+    let base = top - size;
+
+    let mut ptrs = Vec::with_capacity(ptrs_size);
+    let mut strings = Vec::with_capacity(size - ptrs_size);
+    ptrs.extend_from_slice(&(argv.len() as u64).to_ne_bytes());
+    for list in [argv, envp] {
+        for arg in list {
+            let addr = base + ptrs_size + strings.len();
+            ptrs.extend_from_slice(&(addr.bits() as u64).to_ne_bytes());
+            strings.extend_from_slice(arg.as_bytes());
+            strings.push(0);
+        }
+        ptrs.extend_from_slice(&0u64.to_ne_bytes());
+    }
+
+    ptrs.append(&mut strings);
+    ptrs.resize(size, 0);
+    Ok((base, ptrs))
+}
+
+/// Copies `data` to `offset` within the pages of `mapping`.
+fn write_mapping(mapping: &Mapping, offset: usize, data: &[u8]) -> Result<(), SvsmError> {
+    let start = offset & !(PAGE_SIZE - 1);
+    let end = align_up(offset + data.len(), PAGE_SIZE);
+    let pages = (start..end)
+        .step_by(PAGE_SIZE)
+        .map(|page| mapping.map(page).map(|paddr| (paddr, false)))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(SvsmError::Mem)?;
+
+    let guard = PerCPUPageMappingGuard::create_4k_pages(&pages)?;
+    let dst = guard.virt_addr() + (offset - start);
+    // SAFETY: `dst` points into the pages just mapped, which cover all of
+    // `data`. The pages belong to a new mapping which is not used yet.
+    unsafe {
+        dst.as_mut_ptr::<u8>()
+            .copy_from_nonoverlapping(data.as_ptr(), data.len());
+    }
+    Ok(())
+}
+
 /// Loads and executes an ELF binary in user-mode.
 ///
 /// # Arguments
 ///
 /// * binary: Path to file in the file-system
+/// * root: Root directory of the new task
+/// * argv: Arguments passed to the new task, by convention starting with the
+///   name of the binary
+/// * envp: Environment of the new task, usually `NAME=value` strings
+///
+/// The arguments are placed on the user-mode stack as described in
+/// [`build_arg_block`].
+///
+/// A task started from a kernel task, rather than through the `EXEC` system
+/// call, is marked as launched by the kernel, see
+/// [`Task::is_kernel_launched`].
+///
+/// [`Task::is_kernel_launched`]: super::Task::is_kernel_launched
 ///
 /// # Returns
 ///
 /// [`Ok(TaskPointer)`] on success, [`Err(SvsmError)`] on failure.
-pub fn exec_user(binary: &str, root: Arc<dyn Directory>) -> Result<TaskPointer, SvsmError> {
+pub fn exec_user(
+    binary: &str,
+    root: Arc<dyn Directory>,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<TaskPointer, SvsmError> {
+    let (arg_base, arg_block) = build_arg_block(USER_MEM_END, argv, envp)?;
+
     let fh = open_read(binary)?;
     let file_size = fh.size();
 
@@ -74,7 +177,13 @@ pub fn exec_user(binary: &str, root: Arc<dyn Directory>) -> Result<TaskPointer,
     let virt_base = alloc_info.range.vaddr_begin;
     let entry = elf_bin.get_entry(virt_base);
 
-    let new_task = create_user_task(entry.try_into().unwrap(), root, task_name(binary))?;
+    let start_info = UserThreadStartInfo {
+        entry: entry.try_into().unwrap(),
+        stack: (arg_base - 8).bits(),
+        start_parameter: arg_base.bits(),
+    };
+    let kernel_launched = !current_task.is_user_task();
+    let new_task = create_user_task(start_info, root, task_name(binary), kernel_launched)?;
 
     for seg in elf_bin.image_load_segment_iter(virt_base) {
         let virt_start = VirtAddr::from(seg.vaddr_range.vaddr_begin);
@@ -109,14 +218,55 @@ pub fn exec_user(binary: &str, root: Arc<dyn Directory>) -> Result<TaskPointer,
     // Make sure the mapping is gone before calling schedule
     drop(vstart);
 
-    // Setup 64k of task stack
-    let user_stack_size: usize = 64 * 1024;
-    let stack_flags: VMFileMappingFlags = VMFileMappingFlags::Fixed | VMFileMappingFlags::Write;
-    let stack_addr = USER_MEM_END - user_stack_size;
-    new_task.mmap_user(stack_addr, None, 0, user_stack_size, stack_flags)?;
+    // Setup 64k of task stack with the arguments at the top
+    let stack = VMalloc::new_mapping(USER_STACK_SIZE, VMFileMappingFlags::Write)?;
+    write_mapping(&stack, USER_STACK_SIZE - arg_block.len(), &arg_block)?;
+    new_task.insert_user_mapping(USER_MEM_END - USER_STACK_SIZE, stack)?;
 
     finish_user_task(new_task.clone());
     schedule();
 
     Ok(new_task)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn read_u64(block: &[u8], offset: usize) -> u64 {
+        u64::from_ne_bytes(block[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn arg_block_layout() {
+        let top = VirtAddr::from(0x10000usize);
+        let (base, block) = build_arg_block(top, &["init", "-v"], &["A=1"]).unwrap();
+        assert_eq!(base + block.len(), top);
+        assert!(base.is_aligned(16));
+
+        let string_at = |ptr: u64| {
+            let offset = ptr as usize - base.bits();
+            let len = block[offset..].iter().position(|c| *c == 0).unwrap();
+            core::str::from_utf8(&block[offset..offset + len]).unwrap()
+        };
+        assert_eq!(read_u64(&block, 0), 2);
+        assert_eq!(string_at(read_u64(&block, 8)), "init");
+        assert_eq!(string_at(read_u64(&block, 16)), "-v");
+        assert_eq!(read_u64(&block, 24), 0);
+        assert_eq!(string_at(read_u64(&block, 32)), "A=1");
+        assert_eq!(read_u64(&block, 40), 0);
+    }
+
+    #[test]
+    fn arg_block_empty_and_too_large() {
+        let top = VirtAddr::from(0x10000usize);
+        let (base, block) = build_arg_block(top, &[], &[]).unwrap();
+        assert_eq!(block.len(), 32);
+        assert_eq!(base, top - 32);
+        assert!(block.iter().all(|b| *b == 0));
+
+        let long = String::from_utf8(vec![b'x'; EXEC_ARGS_MAX]).unwrap();
+        assert!(build_arg_block(top, &[&long], &[]).is_err());
+    }
+}
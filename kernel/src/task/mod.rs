@@ -11,14 +11,17 @@ mod tasks;
 mod waiting;
 
 pub use schedule::{
-    RunQueue, TASKLIST, create_user_task, current_task, finish_user_task, go_idle, is_current_task,
-    schedule, schedule_init, scheduler_idle, set_affinity, start_kernel_task, start_kernel_thread,
-    terminate, wait_for_termination, wake_and_schedule_task,
+    RunQueue, TASKLIST, block_current_task, create_user_task, current_task, finish_user_task,
+    futex_wait, futex_wake, go_idle, is_current_task, preempt_on_irq_return, schedule,
+    schedule_init, scheduler_idle, set_affinity, start_kernel_task, start_kernel_thread,
+    start_user_thread, terminate, time_slice_expired, wait_for_termination, wake_and_schedule_task,
+    wake_tasks,
 };
 
 pub use tasks::{
-    INITIAL_TASK_ID, KernelThreadStartInfo, TASK_FLAG_SHARE_PT, Task, TaskContext, TaskError,
-    TaskListAdapter, TaskPointer, TaskRunListAdapter, TaskState, is_task_fault,
+    INITIAL_TASK_ID, KernelThreadStartInfo, TASK_EXIT_ABNORMAL, TASK_FLAG_SHARE_PT, Task,
+    TaskContext, TaskError, TaskListAdapter, TaskPointer, TaskRunListAdapter, TaskState,
+    UserThreadStartInfo, is_task_fault,
 };
 
 pub use exec::exec_user;
@@ -20,8 +20,25 @@
 //!   again. It is owned by a wait object when in this state.
 //! * [`TERMINATED`] The task is about to be destroyed and owned by the [`RunQueue`].
 //!
-//! The scheduler is cooperative. A task runs until it voluntarily calls the
-//! [`schedule()`] function.
+//! A task runs until it calls the [`schedule()`] function, blocks or
+//! terminates, or until it is preempted. When other tasks are waiting to
+//! run, the current task gets a time slice, and once the slice has ended, the
+//! task is preempted on return from the next interrupt. This applies to tasks
+//! running at kernel and at user level alike, but only if the interrupted
+//! context could also have called [`schedule()`] itself: interrupts must have
+//! been enabled without any IRQ-disable nesting, TPR must not have been
+//! raised and no lock must be held (see [`PreemptGuard`]). Otherwise the
+//! preemption is attempted again shortly after.
+//!
+//! Tasks processing requests of the guest (see [`Task::set_request_task`])
+//! are preferred to keep the guest responsive:
+//!
+//! * Runnable request tasks are queued ahead of all other tasks.
+//! * A request task which becomes runnable preempts any other task right
+//!   away.
+//! * Other tasks only get a short time slice while a request task is waiting
+//!   to run, so that they still make progress without delaying requests for
+//!   long.
 //!
 //! Only when a task is in [`RUNNING`] or [`TERMINATED`] state it is assigned to a
 //! specific CPU. Tasks in the [`BLOCKED`] state have no CPU assigned and will run
@@ -31,6 +48,7 @@
 //! [`RUNNING`]: super::tasks::TaskState::RUNNING
 //! [`BLOCKED`]: super::tasks::TaskState::BLOCKED
 //! [`TERMINATED`]: super::tasks::TaskState::TERMINATED
+//! [`PreemptGuard`]: crate::cpu::PreemptGuard
 
 extern crate alloc;
 
@@ -38,6 +56,7 @@ use super::tasks::TASK_ACTIVE_OFFSET;
 use super::tasks::TASK_CUR_CPU_OFFSET;
 use super::{
     INITIAL_TASK_ID, KernelThreadStartInfo, Task, TaskListAdapter, TaskPointer, TaskRunListAdapter,
+    UserThreadStartInfo,
 };
 use crate::address::{Address, VirtAddr};
 use crate::cpu::IrqGuard;
@@ -59,15 +78,43 @@ use crate::fs::Directory;
 use crate::locking::SpinLock;
 use crate::mm::SVSM_CONTEXT_SWITCH_SHADOW_STACK;
 use crate::platform::SVSM_PLATFORM;
+use crate::time::{monotonic, poll_timers, set_time_slice_end};
 use alloc::string::String;
 use alloc::sync::Arc;
 use core::arch::global_asm;
 use core::mem::offset_of;
 use core::ptr;
 use core::ptr::null_mut;
+use core::time::Duration;
 use cpuarch::x86apic::ApicIcr;
 use intrusive_collections::LinkedList;
 
+/// Time a task runs before it is preempted in favor of other runnable tasks.
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+/// Time slice of tasks other than request tasks while a request task is
+/// waiting to run.
+const TIME_SLICE_BACKGROUND: Duration = Duration::from_millis(1);
+
+/// Delay after which a preemption is attempted again if the current task
+/// could not be preempted.
+const PREEMPT_RETRY: Duration = Duration::from_micros(500);
+
+/// Returns the length of the time slice of a task while other tasks are
+/// waiting to run.
+///
+/// # Arguments
+///
+/// * `request_task` - Whether the task is a request task.
+/// * `request_task_waiting` - Whether a request task is waiting to run.
+fn time_slice(request_task: bool, request_task_waiting: bool) -> Duration {
+    if request_task_waiting && !request_task {
+        TIME_SLICE_BACKGROUND
+    } else {
+        TIME_SLICE
+    }
+}
+
 /// A RunQueue implementation that uses an RBTree to efficiently sort the priority
 /// of tasks within the queue.
 #[derive(Debug, Default)]
@@ -83,6 +130,12 @@ pub struct RunQueue {
 
     /// Pointer to a task that should be woken when returning from idle
     wake_from_idle: Option<TaskPointer>,
+
+    /// End of the time slice of the current task, if one has been started
+    slice_end: Option<Duration>,
+
+    /// Whether the current task should be preempted as soon as possible
+    need_resched: bool,
 }
 
 impl RunQueue {
@@ -95,6 +148,8 @@ impl RunQueue {
             current_task: None,
             idle_task: None,
             wake_from_idle: None,
+            slice_end: None,
+            need_resched: false,
         }
     }
 
@@ -116,13 +171,93 @@ impl RunQueue {
     }
 
     /// Place a task back onto the run queue so it can be scheduled again.
+    /// Request tasks are queued behind other request tasks, but ahead of all
+    /// other tasks.
     fn enqueue_task(&mut self, task: TaskPointer) {
         // Callers are expected to place the task into the RUNNING state
         // before a task is queued.
         debug_assert!(task.is_running());
-        if !task.is_idle_task() {
+        if task.is_idle_task() {
+            return;
+        }
+        if !task.is_request_task() {
             self.run_list.push_back(task);
+            return;
+        }
+
+        // A request task preempts any other task, except for the idle task,
+        // which switches to it on its own.
+        if self
+            .current_task
+            .as_ref()
+            .is_some_and(|current| !current.is_request_task() && !current.is_idle_task())
+        {
+            self.need_resched = true;
+        }
+
+        let mut cursor = self.run_list.front_mut();
+        while cursor.get().is_some_and(|queued| queued.is_request_task()) {
+            cursor.move_next();
         }
+        cursor.insert_before(task);
+    }
+
+    fn request_task_waiting(&self) -> bool {
+        self.run_list
+            .front()
+            .get()
+            .is_some_and(|task| task.is_request_task())
+    }
+
+    /// Returns the time slice of the current task, or `None` if it can run
+    /// until it gives up the CPU because no other task is waiting to run.
+    fn time_slice(&self) -> Option<Duration> {
+        let current = self.current_task.as_ref()?;
+        if current.is_idle_task() || self.run_list.is_empty() {
+            return None;
+        }
+        Some(time_slice(
+            current.is_request_task(),
+            self.request_task_waiting(),
+        ))
+    }
+
+    /// Sets the end of the time slice of the current task. Must be called on
+    /// the CPU which owns the run queue.
+    fn set_slice_end(&mut self, end: Option<Duration>) {
+        self.slice_end = end;
+        set_time_slice_end(end);
+    }
+
+    /// Starts the time slice of a task which has just been selected to run.
+    /// Must be called on the CPU which owns the run queue.
+    fn start_time_slice(&mut self) {
+        let end = self.time_slice().map(|slice| monotonic() + slice);
+        if end.is_some() || self.slice_end.is_some() {
+            self.set_slice_end(end);
+        }
+    }
+
+    /// Makes sure that the current task is preempted in time after tasks
+    /// have been queued. Must be called on the CPU which owns the run queue.
+    fn update_time_slice(&mut self) {
+        let end = if self.need_resched {
+            // Preempt the current task right away, unless an attempt is due
+            // anyway.
+            let now = monotonic();
+            if self.slice_end.is_some_and(|end| end <= now + PREEMPT_RETRY) {
+                return;
+            }
+            now
+        } else if self.slice_end.is_none() {
+            let Some(slice) = self.time_slice() else {
+                return;
+            };
+            monotonic() + slice
+        } else {
+            return;
+        };
+        self.set_slice_end(Some(end));
     }
 
     /// Prepare to run a task by marking it runnable and placing it into the
@@ -167,20 +302,33 @@ impl RunQueue {
     ///
     /// Panics if there is no current task.
     pub fn schedule_prepare(&mut self, reschedule: bool) -> Option<(TaskPointer, TaskPointer)> {
-        let current = if reschedule {
+        let (current, next) = if reschedule {
+            let current = self.current_task.take().unwrap();
+
+            // Request tasks are queued ahead of other tasks, so a request
+            // task would be selected again right away. Give the other tasks a
+            // chance to run, unless another request task is runnable.
+            let other = if current.is_request_task() && !self.request_task_waiting() {
+                self.run_list.pop_front()
+            } else {
+                None
+            };
+
             // Remove current and put it back into the RunQueue.  This is
             // important to make sure the last runnable task keeps running,
             // even if it calls schedule()
-            let current = self.current_task.take().unwrap();
             self.enqueue_task(current.clone());
-            current
+            let next = other.unwrap_or_else(|| self.get_next_task());
+            (current, next)
         } else {
-            self.current_task.as_ref().unwrap().clone()
+            let current = self.current_task.as_ref().unwrap().clone();
+            (current, self.get_next_task())
         };
 
-        // Get next task and update current_task state
-        let next = self.get_next_task();
+        // Update current_task state and start the time slice of the next task
         self.current_task = Some(next.clone());
+        self.need_resched = false;
+        self.start_time_slice();
 
         // Check if task switch is needed
         if current != next {
@@ -354,23 +502,65 @@ pub fn start_kernel_thread(start_info: KernelThreadStartInfo) -> Result<TaskPoin
     Ok(task)
 }
 
+/// Creates and starts a new user-mode thread of the currently running user
+/// task.
+///
+/// # Arguments
+///
+/// * `start_info` - User-mode entry point, stack and start parameter of the
+///   new thread.
+/// * `cpu_index` - Index of the CPU to run the thread on, or `None` to run it
+///   on the current CPU. The thread starts on the current CPU and moves to
+///   `cpu_index` before it first enters user mode.
+///
+/// # Returns
+///
+/// A new instance of [`TaskPointer`] on success, [`SvsmError`] on failure.
+pub fn start_user_thread(
+    start_info: UserThreadStartInfo,
+    cpu_index: Option<usize>,
+) -> Result<TaskPointer, SvsmError> {
+    let current_task = current_task();
+    let cpu = this_cpu();
+    // The thread is queued on this CPU, which its page table was populated
+    // for. It moves itself to another CPU with `set_affinity()`, which
+    // serializes with the scheduler like any other affinity change.
+    let start_cpu = cpu_index.filter(|index| *index != cpu.get_cpu_index());
+    let task = Task::create_user_thread(
+        cpu,
+        start_info,
+        start_cpu,
+        current_task.get_task_name().clone(),
+        current_task,
+    )?;
+    TASKLIST.lock().list().push_back(task.clone());
+
+    cpu.runqueue_mut().prepare_run_task(task.clone());
+    schedule();
+
+    Ok(task)
+}
+
 /// Creates and initializes the kernel state of a new user task. The task is
 /// not added to the TASKLIST or run-queue yet.
 ///
 /// # Arguments
 ///
-/// * user_entry: The user-space entry point.
+/// * start_info: The user-space entry point, stack and start parameter.
+/// * kernel_launched: Whether the kernel, rather than a user task, starts
+///   the new task.
 ///
 /// # Returns
 ///
 /// A new instance of [`TaskPointer`] on success, [`SvsmError`] on failure.
 pub fn create_user_task(
-    user_entry: usize,
+    start_info: UserThreadStartInfo,
     root: Arc<dyn Directory>,
     name: String,
+    kernel_launched: bool,
 ) -> Result<TaskPointer, SvsmError> {
     let cpu = this_cpu();
-    Task::create_user(cpu, user_entry, root, name)
+    Task::create_user(cpu, start_info, root, name, kernel_launched)
 }
 
 /// Finished user-space task creation by putting the task on the global
@@ -415,6 +605,93 @@ pub fn wait_for_termination(task: TaskPointer) {
     }
 }
 
+/// Blocks the current task on the futex at `addr` in its user address space
+/// until it is woken by [`futex_wake`], provided that the futex still holds
+/// `expected`.
+///
+/// # Returns
+///
+/// `Ok(true)` if the task has been blocked and woken again, `Ok(false)` if
+/// the value of the futex has changed, or an error if the futex could not be
+/// read.
+pub fn futex_wait(addr: VirtAddr, expected: u32) -> Result<bool, SvsmError> {
+    // Waiting may require a task switch, so ensure that this is safe.
+    preemption_checks();
+
+    let Some(guard) = current_task().futex_wait(addr, expected)? else {
+        return Ok(false);
+    };
+    select_new_task(false, Some(guard));
+    Ok(true)
+}
+
+/// Wakes up to `count` tasks blocked on the futex at `addr` in the user
+/// address space of the current task.
+///
+/// # Returns
+///
+/// The number of tasks that have been woken.
+pub fn futex_wake(addr: VirtAddr, count: usize) -> usize {
+    let woken = current_task().futex_wake(addr, count);
+    let num = woken.len();
+    if num == 0 {
+        return 0;
+    }
+
+    wake_tasks(woken);
+    schedule();
+    num
+}
+
+/// Blocks the current task until it is woken through a wait queue.
+///
+/// `prepare` must either join a wait queue with interrupts disabled and
+/// return the [`IrqGuard`] used to do so, or return `None` if the current
+/// task does not need to wait. See [`Task::wait_for_exit`] for an example.
+///
+/// # Returns
+///
+/// `true` if the current task has been blocked, `false` otherwise.
+pub fn block_current_task(prepare: impl FnOnce() -> Option<IrqGuard>) -> bool {
+    // Waiting may require a task switch, so ensure that this is safe.
+    preemption_checks();
+
+    let Some(guard) = prepare() else {
+        return false;
+    };
+    select_new_task(false, Some(guard));
+    true
+}
+
+/// Makes tasks removed from a wait queue runnable without switching away
+/// from the current task. Each task is queued on the CPU it has been running
+/// on last, which preserves affinity set with [`set_affinity`].
+pub fn wake_tasks(tasks: impl IntoIterator<Item = TaskPointer>) {
+    let cpu = this_cpu();
+    let mut local = false;
+    for task in tasks {
+        let cpu_index = task.cpu_index();
+        if cpu_index == cpu.get_cpu_index() {
+            cpu.runqueue_mut().prepare_run_task(task);
+            local = true;
+            continue;
+        }
+
+        let target_cpu = PERCPU_AREAS.get_by_cpu_index(cpu_index);
+        target_cpu.runqueue_mut().prepare_run_task(task);
+
+        // Send a scheduler interrupt to the target CPU so that if it is idle,
+        // it wakes and runs the task.
+        let icr = ApicIcr::new()
+            .with_vector(SCHEDULE_VECTOR as u8)
+            .with_destination(target_cpu.apic_id());
+        apic_post_irq(icr.into());
+    }
+    if local {
+        cpu.runqueue_mut().update_time_slice();
+    }
+}
+
 /// Terminates the current task.
 ///
 /// # Panic
@@ -580,20 +857,84 @@ pub unsafe fn schedule_init() {
 
 /// Enters an idle state if there is no task that can run.
 pub fn scheduler_idle() {
+    // Without timer interrupts, nothing would wake this CPU when a timer
+    // expires, so it keeps polling instead of halting.
+    let timers_pending = poll_timers();
+
     // All decisions must be made with interrupts disabled to ensure that the
     // scheduler state does not change before committing to go idle.
     let guard = IrqGuard::new();
 
-    let queue_empty = this_cpu().runqueue_mut().run_list.front().is_null();
+    let cpu = this_cpu();
+    let runqueue = cpu.runqueue();
+    let queue_empty = runqueue.run_list.front().is_null();
+
+    // Pending events are handled by the task which waits for wake from idle,
+    // so the CPU must not halt until that task has run.
+    let events_pending = runqueue.wake_from_idle.is_some() && cpu.shared().events_pending();
+    drop(runqueue);
 
-    if queue_empty {
+    if queue_empty && !timers_pending && !events_pending {
         SVSM_PLATFORM.idle_halt(&guard);
     }
 }
 
+/// Checks whether the current context may switch to another task.
+fn preemption_allowed() -> bool {
+    irq_nesting_count() == 0 && (raw_get_tpr() == 0 || !SVSM_PLATFORM.use_interrupts())
+}
+
 fn preemption_checks() {
-    assert!(irq_nesting_count() == 0);
-    assert!(raw_get_tpr() == 0 || !SVSM_PLATFORM.use_interrupts());
+    assert!(preemption_allowed());
+}
+
+/// Called in interrupt context when the time slice of the current task has
+/// ended. If other tasks are waiting to run, the current task is preempted
+/// on return from the interrupt.
+pub fn time_slice_expired() {
+    let mut rq = this_cpu().runqueue_mut();
+    if rq.run_list.is_empty() {
+        rq.slice_end = None;
+        return;
+    }
+
+    rq.need_resched = true;
+    // The current task may not be preemptible on return from the interrupt,
+    // e.g. because it holds a lock, so try again shortly after.
+    rq.set_slice_end(Some(monotonic() + PREEMPT_RETRY));
+}
+
+/// Preempts the current task if required. Must be called with interrupts
+/// disabled right before returning from an interrupt to a context which had
+/// interrupts enabled, after the IRQ-disable nesting of the interrupt has
+/// been dropped.
+pub fn preempt_on_irq_return() {
+    let cpu = this_cpu();
+    // The interrupted context must have been able to call schedule() itself,
+    // and must not hold any lock.
+    let preemptible = preemption_allowed() && cpu.preempt_count() == 0;
+
+    let mut rq = cpu.runqueue_mut();
+    if rq.current_task.is_none() {
+        // Scheduling has not started on this CPU yet.
+        return;
+    }
+    if !preemptible || !rq.need_resched {
+        // Another CPU may have queued tasks to run on this CPU.
+        rq.update_time_slice();
+        return;
+    }
+    drop(rq);
+
+    // Interrupts were enabled in the interrupted context, so they are
+    // enabled again when switching back to the preempted task, like for any
+    // other task switch.
+    cpu.irqs_push_nesting(true);
+    let prev_task = switch_to_next_task(true);
+    cpu.irqs_enable();
+
+    // Interrupts are disabled again on the return path.
+    drop(prev_task);
 }
 
 /// # Safety
@@ -626,10 +967,27 @@ fn select_new_task(reschedule: bool, irq_guard: Option<IrqGuard>) {
     // now.
     let guard = irq_guard.unwrap_or_default();
 
+    let prev_task = switch_to_next_task(reschedule);
+
+    // Drop the interrupt guard before allowing the previous task reference to
+    // go out of scope.  This ensures that the task destructor will run with
+    // interrupts enabled.
+    drop(guard);
+    drop(prev_task);
+}
+
+/// Switches to the next task on the run queue of the current CPU, if it is
+/// not the current task. Must be called with interrupts disabled.
+///
+/// # Returns
+///
+/// The task that ran most recently on this CPU before the current task was
+/// resumed, which must only be dropped once interrupts are enabled.
+fn switch_to_next_task(reschedule: bool) -> Option<TaskPointer> {
     let work = this_cpu().schedule_prepare(reschedule);
 
     // !!! Runqueue lock must be released here !!!
-    let prev_task = if let Some((current, next)) = work {
+    if let Some((current, next)) = work {
         // Ensure that the current stack bounds of the current CPU are adjusted
         // to reflect the task being scheduled.
         this_cpu().set_current_stack(next.stack_bounds());
@@ -674,13 +1032,7 @@ fn select_new_task(reschedule: bool, irq_guard: Option<IrqGuard>) {
         }
     } else {
         None
-    };
-
-    // Drop the interrupt guard before allowing the previous task reference to
-    // go out of scope.  This ensures that the task destructor will run with
-    // interrupts enabled.
-    drop(guard);
-    drop(prev_task);
+    }
 }
 
 pub fn wake_and_schedule_task(task: TaskPointer) {
@@ -896,12 +1248,16 @@ mod test {
     use super::set_affinity;
     use super::start_kernel_task;
     use super::wait_for_termination;
+    use super::{TIME_SLICE, TIME_SLICE_BACKGROUND, time_slice};
     use crate::cpu::percpu::{PERCPU_AREAS, this_cpu};
+    use crate::platform::SVSM_PLATFORM;
     use alloc::string::String;
-    use core::sync::atomic::AtomicU32;
+    use core::hint::spin_loop;
     use core::sync::atomic::Ordering;
+    use core::sync::atomic::{AtomicBool, AtomicU32};
 
     static EMPTY_TASK_COUNTER: AtomicU32 = AtomicU32::new(0);
+    static SPIN_RELEASE: AtomicBool = AtomicBool::new(false);
 
     fn empty_task(parameter: usize) {
         // Move to a different processor if the caller requested it.
@@ -973,4 +1329,40 @@ mod test {
         // Move this thread back to its starting point.
         set_affinity(cpu_index);
     }
+
+    fn spinning_task(_: usize) {
+        while !SPIN_RELEASE.load(Ordering::Acquire) {
+            spin_loop();
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(test_in_svsm), ignore = "Can only be run inside guest")]
+    fn test_preemption() {
+        if !SVSM_PLATFORM.use_interrupts() {
+            return;
+        }
+        SPIN_RELEASE.store(false, Ordering::Relaxed);
+
+        // Start a task that never gives up the CPU on its own.
+        let task = start_kernel_task(
+            KernelThreadStartInfo::new(spinning_task, 0),
+            String::from("test preemption task"),
+        )
+        .expect("Failed to start test preemption task");
+
+        // This task only runs again once the spinning task has been
+        // preempted.
+        SPIN_RELEASE.store(true, Ordering::Release);
+        wait_for_termination(task);
+    }
+
+    #[test]
+    #[cfg_attr(not(test_in_svsm), ignore = "Can only be run inside guest")]
+    fn test_time_slice() {
+        assert_eq!(time_slice(false, false), TIME_SLICE);
+        assert_eq!(time_slice(true, false), TIME_SLICE);
+        assert_eq!(time_slice(true, true), TIME_SLICE);
+        assert_eq!(time_slice(false, true), TIME_SLICE_BACKGROUND);
+    }
 }
@@ -6,17 +6,21 @@
 // Author: Joerg Roedel <joerg.roedel@amd.com>
 
 extern crate alloc;
+use alloc::collections::btree_map::BTreeMap;
 use alloc::sync::Arc;
+use core::sync::atomic::AtomicU64;
 
 use crate::address::VirtAddr;
 use crate::error::SvsmError;
-use crate::locking::SpinLock;
+use crate::locking::{SpinLock, SpinLockIrqSafe};
 use crate::mm::pagetable::PTEntryFlags;
 use crate::mm::vm::{Mapping, VMR, VMReserved};
 use crate::mm::{SIZE_LEVEL3, SVSM_PERTASK_BASE, SVSM_PERTASK_END, alloc::AllocError};
 use crate::utils::MemoryRegion;
 use crate::utils::bitmap_allocator::{BitmapAllocator, BitmapAllocator1024};
 
+use super::WaitQueue;
+
 static KTASK_VADDR_BITMAP: SpinLock<BitmapAllocator1024> =
     SpinLock::new(BitmapAllocator1024::new_empty());
 
@@ -61,6 +65,14 @@ pub struct TaskMM {
 
     /// Task virtual memory range for use at CPL 3 - None for kernel tasks
     vm_user_range: Option<VMR>,
+
+    /// Tasks waiting on a futex, keyed by the user virtual address of the
+    /// futex. Queues are removed once their last waiter has been woken.
+    futex_queues: SpinLockIrqSafe<BTreeMap<VirtAddr, WaitQueue>>,
+
+    /// Number of futex wake operations in this address space. Incremented
+    /// with `futex_queues` locked.
+    futex_wakes: AtomicU64,
 }
 
 impl TaskMM {
@@ -107,6 +119,8 @@ impl TaskMM {
             _ktask_region: ktask_region,
             vm_kernel_range,
             vm_user_range: user_vmr,
+            futex_queues: SpinLockIrqSafe::new(BTreeMap::new()),
+            futex_wakes: AtomicU64::new(0),
         })
     }
 
@@ -127,6 +141,16 @@ impl TaskMM {
     pub fn user_range(&self) -> Option<&VMR> {
         self.vm_user_range.as_ref()
     }
+
+    /// Return a reference to the futex wait queues of this address space.
+    pub fn futex_queues(&self) -> &SpinLockIrqSafe<BTreeMap<VirtAddr, WaitQueue>> {
+        &self.futex_queues
+    }
+
+    /// Return a reference to the futex wake counter of this address space.
+    pub fn futex_wakes(&self) -> &AtomicU64 {
+        &self.futex_wakes
+    }
 }
 
 /// Guard a per-task kernel mapping and unmap it when going out of scope.
@@ -9,12 +9,14 @@ extern crate alloc;
 use alloc::collections::btree_map::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt;
 use core::mem::offset_of;
 use core::mem::size_of;
 use core::num::NonZeroUsize;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
 
@@ -38,6 +40,7 @@ use crate::fs::{Directory, FileHandle, opendir, stdout_open};
 use crate::locking::RWLock;
 use crate::locking::SpinLock;
 use crate::locking::SpinLockIrqSafe;
+use crate::mm::guestmem::UserPtr;
 use crate::mm::pagetable::{PTEntryFlags, PageTable};
 use crate::mm::vm::{Mapping, VMFileMappingFlags, VMKernelStack, VMR};
 use crate::mm::{
@@ -50,10 +53,11 @@ use crate::types::{SVSM_USER_CS, SVSM_USER_DS};
 use crate::utils::{MemoryRegion, is_aligned};
 use intrusive_collections::{LinkedListAtomicLink, intrusive_adapter};
 
-use super::WaitQueue;
 use super::schedule::complete_task_switch;
+use super::schedule::set_affinity;
 use super::schedule::terminate;
 use super::task_mm::{TaskKernelMapping, TaskMM};
+use super::waiting::{WaitQueue, WaitToken};
 
 pub const INITIAL_TASK_ID: u32 = 1;
 
@@ -126,10 +130,22 @@ impl KernelThreadStartInfo {
     }
 }
 
+/// Initial user-mode state of a new user task.
+#[derive(Debug, Clone, Copy)]
+pub struct UserThreadStartInfo {
+    /// User-mode entry point.
+    pub entry: usize,
+    /// Initial user-mode stack pointer. `stack + 8` must be 16-byte aligned,
+    /// as if the entry point had been called.
+    pub stack: usize,
+    /// Value passed to the entry point in `%rdi`.
+    pub start_parameter: usize,
+}
+
 #[derive(Debug)]
 enum ThreadStartInfo {
     Kernel(KernelThreadStartInfo),
-    User(usize),
+    User(UserThreadStartInfo),
 }
 
 #[derive(PartialEq, Debug, Copy, Clone, Default)]
@@ -175,6 +191,10 @@ impl From<TaskError> for SvsmError {
 
 pub const TASK_FLAG_SHARE_PT: u16 = 0x01;
 
+/// Exit code of tasks which terminated without setting one, for example
+/// because of a fault.
+pub const TASK_EXIT_ABNORMAL: u32 = syscall::EXIT_ABNORMAL;
+
 #[derive(Debug, Default)]
 struct TaskIDAllocator {
     next_id: AtomicU32,
@@ -232,6 +252,9 @@ struct TaskSchedState {
     /// Whether this is an idle task
     idle_task: AtomicBool,
 
+    /// Whether this task processes requests of the guest
+    request_task: AtomicBool,
+
     /// Whether this task is currently active on any CPU
     active: AtomicBool,
 
@@ -240,15 +263,20 @@ struct TaskSchedState {
 
     /// CPU this task is currently assigned to
     cpu_index: AtomicUsize,
+
+    /// Token of the current or last wait of the task, see [`WaitToken`]
+    wait_token: AtomicU64,
 }
 
 impl TaskSchedState {
     fn new(cpu_index: usize) -> Self {
         Self {
             idle_task: AtomicBool::new(false),
+            request_task: AtomicBool::new(false),
             active: AtomicBool::new(false),
             state: AtomicU32::new(TaskState::PENDING.into()),
             cpu_index: AtomicUsize::new(cpu_index),
+            wait_token: AtomicU64::new(0),
         }
     }
 
@@ -321,6 +349,20 @@ pub struct Task {
 
     /// Queue of tasks waiting for completion of this task.
     wait_queue: SpinLockIrqSafe<WaitQueue>,
+
+    /// Exit code reported to the parent once the task has terminated.
+    exit_code: AtomicU32,
+
+    /// User tasks started by this task which have not been waited for.
+    children: SpinLock<Vec<TaskPointer>>,
+
+    /// Whether the process was started by the kernel rather than by another
+    /// user task. Shared among threads within the same process.
+    kernel_launched: bool,
+
+    /// CPU a new user thread moves to before it first enters user mode, see
+    /// [`start_user_thread`](super::start_user_thread).
+    start_cpu: Option<usize>,
 }
 
 // Expose the offsets of critical task fields to assembly.
@@ -388,6 +430,13 @@ struct CreateTaskArguments {
 
     // Share state with another task (aka create a thread)
     thread_of: Option<TaskPointer>,
+
+    // Whether the kernel started the process. Ignored for threads, which
+    // inherit it from `thread_of`.
+    kernel_launched: bool,
+
+    // CPU a new user thread moves to when it first runs.
+    start_cpu: Option<usize>,
 }
 
 impl Task {
@@ -396,13 +445,18 @@ impl Task {
 
         cpu.populate_page_table(&mut pgtable);
 
-        let (task_mm, objtree) = {
+        let (task_mm, objtree, kernel_launched) = {
             if let Some(parent_thread) = args.thread_of {
-                (parent_thread.mm.clone(), parent_thread.objs.clone())
+                (
+                    parent_thread.mm.clone(),
+                    parent_thread.objs.clone(),
+                    parent_thread.kernel_launched,
+                )
             } else {
                 (
                     Arc::new(TaskMM::create(args.vm_user_range)?),
                     Arc::new(RWLock::new(BTreeMap::new())),
+                    args.kernel_launched,
                 )
             }
         };
@@ -454,7 +508,7 @@ impl Task {
 
         // Call the correct stack creation routine for this task.
         let (stack, raw_bounds, rsp_offset) = match args.start_info {
-            ThreadStartInfo::User(entry) => Self::allocate_utask_stack(cpu, entry, xsa_addr)?,
+            ThreadStartInfo::User(ref info) => Self::allocate_utask_stack(cpu, info, xsa_addr)?,
             ThreadStartInfo::Kernel(ref info) => Self::allocate_ktask_stack(
                 cpu,
                 info.entry,
@@ -495,6 +549,10 @@ impl Task {
             runlist_link: LinkedListAtomicLink::default(),
             objs: objtree,
             wait_queue: SpinLockIrqSafe::new(WaitQueue::new()),
+            exit_code: AtomicU32::new(TASK_EXIT_ABNORMAL),
+            children: SpinLock::new(Vec::new()),
+            kernel_launched,
+            start_cpu: args.start_cpu,
         }))
     }
 
@@ -509,15 +567,18 @@ impl Task {
             vm_user_range: None,
             rootdir: opendir("/")?,
             thread_of: None,
+            kernel_launched: true,
+            start_cpu: None,
         };
         Self::create_common(cpu, create_args)
     }
 
     pub fn create_user(
         cpu: &PerCpu,
-        user_entry: usize,
+        start_info: UserThreadStartInfo,
         root: Arc<dyn Directory>,
         name: String,
+        kernel_launched: bool,
     ) -> Result<TaskPointer, SvsmError> {
         let vm_user_range = VMR::new(USER_MEM_START, USER_MEM_END, PTEntryFlags::USER)?;
         // SAFETY: the user address range is fully aligned to top-level paging
@@ -526,11 +587,13 @@ impl Task {
             vm_user_range.initialize_lazy()?;
         }
         let create_args = CreateTaskArguments {
-            start_info: ThreadStartInfo::User(user_entry),
+            start_info: ThreadStartInfo::User(start_info),
             name,
             vm_user_range: Some(vm_user_range),
             rootdir: root,
             thread_of: None,
+            kernel_launched,
+            start_cpu: None,
         };
         Self::create_common(cpu, create_args)
     }
@@ -561,6 +624,47 @@ impl Task {
             vm_user_range: None,
             rootdir: opendir("/")?,
             thread_of: Some(thread),
+            kernel_launched: false,
+            start_cpu: None,
+        };
+        Self::create_common(cpu, create_args)
+    }
+
+    /// Create a new user-mode thread for an existing user task. The thread
+    /// shares the user address space, the object table and the root
+    /// directory of the task.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpu` - Reference to the `[PerCpu]` structure of the local CPU.
+    /// * `start_info` - User-mode entry point, stack and start parameter.
+    /// * `start_cpu` - Index of the CPU the thread moves to before it first
+    ///   enters user mode, or `None` to stay on the CPU it is queued on.
+    /// * `name` - User-visible name of the thread.
+    /// * `thread` - Pointer to existing user task for which the new thread
+    ///   will be created.
+    ///
+    /// # Returns
+    ///
+    /// `Some(TaskPointer)` with the new thread on success, `Err(SvsmError)` on failure.
+    pub fn create_user_thread(
+        cpu: &PerCpu,
+        start_info: UserThreadStartInfo,
+        start_cpu: Option<usize>,
+        name: String,
+        thread: TaskPointer,
+    ) -> Result<TaskPointer, SvsmError> {
+        if thread.mm.user_range().is_none() {
+            return Err(SvsmError::Mem);
+        }
+        let create_args = CreateTaskArguments {
+            start_info: ThreadStartInfo::User(start_info),
+            name,
+            vm_user_range: None,
+            rootdir: thread.rootdir(),
+            thread_of: Some(thread),
+            kernel_launched: false,
+            start_cpu,
         };
         Self::create_common(cpu, create_args)
     }
@@ -602,6 +706,48 @@ impl Task {
             .set_state(TaskState::BLOCKED);
     }
 
+    /// Starts a new wait of the task.
+    ///
+    /// # Returns
+    ///
+    /// The token which a waker must pass to [`Self::end_wait`].
+    pub(super) fn begin_wait(&self) -> WaitToken {
+        let token = WaitToken::next(self.sched_state.wait_token.load(Ordering::Relaxed));
+        self.sched_state
+            .wait_token
+            .store(token.pending(), Ordering::Release);
+        token
+    }
+
+    /// Ends the wait identified by `token`, either because the event the
+    /// task waited for has occurred or because the wait timed out.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the wait was still pending and the caller must make the
+    /// task runnable again, `false` if the wait has already ended.
+    pub(super) fn end_wait(&self, token: WaitToken, timed_out: bool) -> bool {
+        self.sched_state
+            .wait_token
+            .compare_exchange(
+                token.pending(),
+                token.ended(timed_out),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Checks whether the wait identified by `token` is still pending.
+    pub(super) fn is_waiting(&self, token: WaitToken) -> bool {
+        self.sched_state.wait_token.load(Ordering::Acquire) == token.pending()
+    }
+
+    /// Checks whether the last wait of the task ended because it timed out.
+    pub fn wait_timed_out(&self) -> bool {
+        WaitToken::is_timed_out(self.sched_state.wait_token.load(Ordering::Acquire))
+    }
+
     pub fn is_running(&self) -> bool {
         self.sched_state.get_state() == TaskState::RUNNING
     }
@@ -614,6 +760,35 @@ impl Task {
         self.sched_state.get_state() == TaskState::TERMINATED
     }
 
+    /// Sets the exit code reported to the parent of the task.
+    pub fn set_exit_code(&self, exit_code: u32) {
+        self.exit_code.store(exit_code, Ordering::Relaxed);
+    }
+
+    /// Returns the exit code of the task, which is [`TASK_EXIT_ABNORMAL`]
+    /// unless the task set one before terminating.
+    pub fn exit_code(&self) -> u32 {
+        self.exit_code.load(Ordering::Relaxed)
+    }
+
+    /// Registers a user task started by this task, so that it can be waited
+    /// for with [`Task::take_child`].
+    pub fn add_child(&self, child: TaskPointer) {
+        self.children.lock().push(child);
+    }
+
+    /// Removes the child with the given task ID from the children of this
+    /// task.
+    ///
+    /// # Returns
+    ///
+    /// The child task, or `None` if this task has no child with the ID.
+    pub fn take_child(&self, id: u32) -> Option<TaskPointer> {
+        let mut children = self.children.lock();
+        let index = children.iter().position(|c| c.get_task_id() == id)?;
+        Some(children.swap_remove(index))
+    }
+
     pub fn set_idle_task(&self) {
         self.sched_state.idle_task.store(true, Ordering::Relaxed);
     }
@@ -622,6 +797,37 @@ impl Task {
         self.sched_state.idle_task.load(Ordering::Relaxed)
     }
 
+    /// Marks the task as processing requests of the guest. The scheduler
+    /// runs such tasks ahead of other tasks, see [`RunQueue`].
+    ///
+    /// [`RunQueue`]: super::RunQueue
+    pub fn set_request_task(&self) {
+        self.sched_state.request_task.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_request_task(&self) -> bool {
+        self.sched_state.request_task.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the process of this task was started by the kernel,
+    /// see [`exec_user`]. Only such processes are trusted to handle guest
+    /// requests.
+    ///
+    /// [`exec_user`]: super::exec_user
+    pub fn is_kernel_launched(&self) -> bool {
+        self.kernel_launched
+    }
+
+    /// Returns whether this task runs in user mode.
+    pub fn is_user_task(&self) -> bool {
+        self.mm.user_range().is_some()
+    }
+
+    /// Returns the index of the CPU this task has been running on last.
+    pub fn cpu_index(&self) -> usize {
+        self.sched_state.cpu_index.load(Ordering::Relaxed)
+    }
+
     pub fn fault(&self, vaddr: VirtAddr, write: bool) -> Result<(), SvsmError> {
         let vmr = self
             .mm
@@ -704,7 +910,7 @@ impl Task {
 
     fn allocate_utask_stack(
         cpu: &PerCpu,
-        user_entry: usize,
+        start_info: &UserThreadStartInfo,
         xsa_addr: usize,
     ) -> Result<(Mapping, MemoryRegion<VirtAddr>, usize), SvsmError> {
         let (mapping, bounds) = Task::allocate_stack_common()?;
@@ -747,10 +953,11 @@ impl Task {
             // Setup IRQ return frame.  User-mode tasks always run with
             // interrupts enabled.
             let mut iret_frame = X86ExceptionContext::default();
-            iret_frame.frame.rip = user_entry;
+            iret_frame.regs.rdi = start_info.start_parameter;
+            iret_frame.frame.rip = start_info.entry;
             iret_frame.frame.cs = (SVSM_USER_CS | 3).into();
             iret_frame.frame.flags = iret_rflags;
-            iret_frame.frame.rsp = (USER_MEM_END - 8).into();
+            iret_frame.frame.rsp = start_info.stack;
             iret_frame.frame.ss = (SVSM_USER_DS | 3).into();
             debug_assert!(is_aligned(iret_frame.frame.rsp + 8, 16));
 
@@ -816,6 +1023,63 @@ impl Task {
         Some(guard)
     }
 
+    /// Prepares the current task to wait on the futex at `addr` in the user
+    /// address space of this task. The current task only joins the wait
+    /// queue of the futex if the futex still holds `expected`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Some(IrqGuard))` if the caller must wait, `Ok(None)` if the value
+    /// of the futex has changed or a futex of this address space may have
+    /// been woken concurrently, or an error if the futex could not be read.
+    pub fn futex_wait(&self, addr: VirtAddr, expected: u32) -> Result<Option<IrqGuard>, SvsmError> {
+        let current_task = this_cpu().current_task();
+
+        // Reading user memory may fault, so the futex value is read before
+        // the wait queues are locked. A waker which changes the value after
+        // it has been read increments the wake counter in `futex_wake()`,
+        // which is re-checked with the wait queues locked, so its wakeup can
+        // not be missed.
+        let wakes = self.mm.futex_wakes().load(Ordering::Acquire);
+        if UserPtr::<u32>::new(addr).read()? != expected {
+            return Ok(None);
+        }
+
+        // Interrupts must be disabled while joining the wait queue for the
+        // same reason as in `wait_for_exit()`.
+        let guard = IrqGuard::new();
+
+        let mut queues = self.mm.futex_queues().lock();
+        if self.mm.futex_wakes().load(Ordering::Relaxed) != wakes {
+            return Ok(None);
+        }
+
+        queues.entry(addr).or_default().wait_for_event(current_task);
+        drop(queues);
+
+        Ok(Some(guard))
+    }
+
+    /// Removes up to `count` tasks from the wait queue of the futex at
+    /// `addr` in the user address space of this task.
+    ///
+    /// # Returns
+    ///
+    /// The removed tasks, which the caller must schedule.
+    pub fn futex_wake(&self, addr: VirtAddr, count: usize) -> Vec<TaskPointer> {
+        let mut queues = self.mm.futex_queues().lock();
+        self.mm.futex_wakes().fetch_add(1, Ordering::Release);
+        let Some(queue) = queues.get_mut(&addr) else {
+            return Vec::new();
+        };
+
+        let woken: Vec<TaskPointer> = core::iter::from_fn(|| queue.wakeup()).take(count).collect();
+        if queue.is_empty() {
+            queues.remove(&addr);
+        }
+        woken
+    }
+
     pub fn mmap_common(
         vmr: &VMR,
         addr: VirtAddr,
@@ -872,6 +1136,17 @@ impl Task {
         Self::mmap_common(vmr, addr, file, offset, size, flags)
     }
 
+    /// Inserts an existing mapping at a fixed address into the user address
+    /// space of the task.
+    pub fn insert_user_mapping(
+        &self,
+        addr: VirtAddr,
+        mapping: Mapping,
+    ) -> Result<VirtAddr, SvsmError> {
+        let vmr = self.mm.user_range().ok_or(SvsmError::Mem)?;
+        vmr.insert_at(addr, mapping)
+    }
+
     pub fn munmap_kernel(&self, addr: VirtAddr) -> Result<(), SvsmError> {
         self.mm.kernel_range().remove(addr)?;
         Ok(())
@@ -1018,6 +1293,12 @@ unsafe fn setup_user_task(prev: usize, xsa_addr: u64) {
         // Needs to be the first function called here.
         setup_new_task_common(xsa_addr, complete_task_switch(prev));
     }
+    // A thread started for another CPU moves there before it enters user
+    // mode. The task switch code then updates the per-CPU parts of its page
+    // table.
+    if let Some(cpu_index) = current_task().start_cpu {
+        set_affinity(cpu_index);
+    }
     task_attach_console();
 }
 
@@ -4,26 +4,140 @@
 //
 // Author: Joerg Roedel <jroedel@suse.de>
 
+extern crate alloc;
+
+use super::schedule::wake_tasks;
 use super::tasks::TaskPointer;
+use crate::time::Timer;
+use alloc::collections::vec_deque::VecDeque;
+use core::time::Duration;
+
+/// Identifies a single wait of a task. A wait can end only once, either
+/// through its wait queue or through a timeout, so a task which timed out is
+/// not woken again by a stale entry in the wait queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct WaitToken(u64);
+
+impl WaitToken {
+    const PENDING: u64 = 1 << 0;
+    const TIMED_OUT: u64 = 1 << 1;
+    const FLAGS: u64 = Self::PENDING | Self::TIMED_OUT;
+
+    /// Returns the token of the wait following the wait with the state
+    /// `state`.
+    pub(super) fn next(state: u64) -> Self {
+        Self((state & !Self::FLAGS).wrapping_add(Self::FLAGS + 1))
+    }
+
+    /// State of a task while it waits.
+    pub(super) fn pending(self) -> u64 {
+        self.0 | Self::PENDING
+    }
 
+    /// State of a task after the wait has ended.
+    pub(super) fn ended(self, timed_out: bool) -> u64 {
+        if timed_out {
+            self.0 | Self::TIMED_OUT
+        } else {
+            self.0
+        }
+    }
+
+    pub(super) fn is_timed_out(state: u64) -> bool {
+        state & Self::FLAGS == Self::TIMED_OUT
+    }
+}
+
+/// Queue of blocked tasks waiting for an event. Tasks are woken in the order
+/// in which they started waiting.
 #[derive(Debug, Default)]
 pub struct WaitQueue {
-    waiter: Option<TaskPointer>,
+    waiters: VecDeque<(TaskPointer, WaitToken)>,
 }
 
 impl WaitQueue {
     pub const fn new() -> Self {
-        Self { waiter: None }
+        Self {
+            waiters: VecDeque::new(),
+        }
     }
 
-    pub fn wait_for_event(&mut self, current_task: TaskPointer) {
-        assert!(self.waiter.is_none());
+    fn join(&mut self, current_task: TaskPointer) -> WaitToken {
+        // Drop the entries of waits which have timed out.
+        self.waiters.retain(|(task, token)| task.is_waiting(*token));
 
+        let token = current_task.begin_wait();
         current_task.set_task_blocked();
-        self.waiter = Some(current_task);
+        self.waiters.push_back((current_task, token));
+        token
     }
 
+    pub fn wait_for_event(&mut self, current_task: TaskPointer) {
+        self.join(current_task);
+    }
+
+    /// Like [`Self::wait_for_event`], but the task is also woken once
+    /// `timeout` has elapsed. The returned timer must be kept until the task
+    /// has been woken, and [`Task::wait_timed_out`] tells the task whether
+    /// the wait timed out.
+    ///
+    /// [`Task::wait_timed_out`]: super::Task::wait_timed_out
+    pub fn wait_for_event_timeout(
+        &mut self,
+        current_task: TaskPointer,
+        timeout: Duration,
+    ) -> Timer {
+        let token = self.join(current_task.clone());
+        Timer::one_shot(timeout, move || {
+            if current_task.end_wait(token, true) {
+                wake_tasks([current_task.clone()]);
+            }
+        })
+    }
+
+    /// Removes the task which has been waiting the longest from the queue.
+    /// The caller is responsible for scheduling the returned task.
     pub fn wakeup(&mut self) -> Option<TaskPointer> {
-        self.waiter.take()
+        while let Some((task, token)) = self.waiters.pop_front() {
+            if task.end_wait(token, false) {
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    /// Removes all tasks from the queue. The caller is responsible for
+    /// scheduling the returned tasks.
+    pub fn wakeup_all(&mut self) -> VecDeque<TaskPointer> {
+        self.waiters
+            .drain(..)
+            .filter(|(task, token)| task.end_wait(*token, false))
+            .map(|(task, _)| task)
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters
+            .iter()
+            .all(|(task, token)| !task.is_waiting(*token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_tokens() {
+        let first = WaitToken::next(0);
+        assert!(!WaitToken::is_timed_out(first.pending()));
+        assert!(!WaitToken::is_timed_out(first.ended(false)));
+        assert!(WaitToken::is_timed_out(first.ended(true)));
+
+        // Every ended state leads to a new token.
+        let second = WaitToken::next(first.ended(true));
+        assert_ne!(second, first);
+        assert_eq!(second, WaitToken::next(first.ended(false)));
+        assert_ne!(second.pending(), first.pending());
     }
 }
@@ -0,0 +1,244 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Encoding of the TPM and TCG data structures used to measure the SVSM
+//! launch state into the vTPM. They do not depend on a vTPM backend, so they
+//! are also built for host tests.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+pub const TPM_ALG_SHA1: u16 = 0x0004;
+pub const TPM_ALG_SHA256: u16 = 0x000B;
+pub const TPM_ALG_SHA384: u16 = 0x000C;
+pub const TPM_ALG_SHA512: u16 = 0x000D;
+pub const TPM_ALG_SM3_256: u16 = 0x0012;
+pub const TPM_ALG_SHA3_256: u16 = 0x0027;
+pub const TPM_ALG_SHA3_384: u16 = 0x0028;
+pub const TPM_ALG_SHA3_512: u16 = 0x0029;
+
+/// Event types (TCG PC Client Platform Firmware Profile, section 10.4.1)
+pub const EV_POST_CODE: u32 = 0x1;
+pub const EV_NO_ACTION: u32 = 0x3;
+pub const EV_S_CRTM_CONTENTS: u32 = 0x7;
+
+/// Signature of the Specification ID Version Event
+const SPEC_ID_EVENT_SIGNATURE: &[u8; 16] = b"Spec ID Event03\0";
+
+/// Returns the digest size of the hash algorithm `alg`, or `None` if it is
+/// not a hash algorithm.
+pub fn tpm_digest_size(alg: u16) -> Option<usize> {
+    match alg {
+        TPM_ALG_SHA1 => Some(20),
+        TPM_ALG_SHA256 | TPM_ALG_SM3_256 | TPM_ALG_SHA3_256 => Some(32),
+        TPM_ALG_SHA384 | TPM_ALG_SHA3_384 => Some(48),
+        TPM_ALG_SHA512 | TPM_ALG_SHA3_512 => Some(64),
+        _ => None,
+    }
+}
+
+/// Digest of one PCR bank (TPMT_HA)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TpmDigest {
+    /// Hash algorithm of the bank
+    pub alg: u16,
+    /// Digest computed with `alg`
+    pub digest: Vec<u8>,
+}
+
+/// Parses the response of a successful TPM2_PCR_Event command.
+///
+/// # Returns
+///
+/// The digests the PCR banks have been extended with, or `None` if the
+/// response is malformed.
+pub fn parse_pcr_event_response(response: &[u8]) -> Option<Vec<TpmDigest>> {
+    // The response header is followed by the parameter size (UINT32) and
+    // the digests (TPML_DIGEST_VALUES), which start with their count
+    // (UINT32).
+    let params = response.get(14..)?;
+    let (count, mut params) = params.split_first_chunk::<4>()?;
+
+    let mut digests = Vec::new();
+    for _ in 0..u32::from_be_bytes(*count) {
+        let (alg, rest) = params.split_first_chunk::<2>()?;
+        let alg = u16::from_be_bytes(*alg);
+        let (digest, rest) = rest.split_at_checked(tpm_digest_size(alg)?)?;
+        digests.push(TpmDigest {
+            alg,
+            digest: digest.to_vec(),
+        });
+        params = rest;
+    }
+
+    Some(digests)
+}
+
+/// An event measured into a PCR
+#[derive(Debug)]
+pub struct TcgEvent {
+    pub pcr: u32,
+    pub event_type: u32,
+    /// Data whose digest is extended into the PCR
+    pub data: Vec<u8>,
+}
+
+/// Encodes the event log of `events`, each extended with the digests at the
+/// same index of `digests`. The log uses the crypto agile format of the TCG
+/// PC Client Platform Firmware Profile.
+pub fn event_log(events: &[TcgEvent], digests: &[Vec<TpmDigest>]) -> Vec<u8> {
+    // All events are extended into the same banks.
+    let Some(banks) = digests.first() else {
+        return Vec::new();
+    };
+
+    let mut log = Vec::new();
+
+    // The Specification ID Version Event (TCG_EfiSpecIDEvent) is logged in
+    // the SHA1 format (TCG_PCClientPCREvent) and lists the digests in the
+    // following events.
+    let mut spec_id = Vec::new();
+    spec_id.extend_from_slice(SPEC_ID_EVENT_SIGNATURE);
+    spec_id.extend_from_slice(&0u32.to_le_bytes()); // platformClass: client
+    spec_id.extend_from_slice(&[
+        0, // specVersionMinor
+        2, // specVersionMajor
+        0, // specErrata
+        2, // uintnSize: UINT64
+    ]);
+    spec_id.extend_from_slice(&(banks.len() as u32).to_le_bytes());
+    for bank in banks {
+        spec_id.extend_from_slice(&bank.alg.to_le_bytes());
+        spec_id.extend_from_slice(&(bank.digest.len() as u16).to_le_bytes());
+    }
+    spec_id.push(0); // vendorInfoSize
+
+    log.extend_from_slice(&0u32.to_le_bytes());
+    log.extend_from_slice(&EV_NO_ACTION.to_le_bytes());
+    log.extend_from_slice(&[0; 20]);
+    log.extend_from_slice(&(spec_id.len() as u32).to_le_bytes());
+    log.extend_from_slice(&spec_id);
+
+    // Every measurement is logged as a TCG_PCR_EVENT2.
+    for (event, digests) in events.iter().zip(digests) {
+        log.extend_from_slice(&event.pcr.to_le_bytes());
+        log.extend_from_slice(&event.event_type.to_le_bytes());
+        log.extend_from_slice(&(digests.len() as u32).to_le_bytes());
+        for digest in digests {
+            log.extend_from_slice(&digest.alg.to_le_bytes());
+            log.extend_from_slice(&digest.digest);
+        }
+        log.extend_from_slice(&(event.data.len() as u32).to_le_bytes());
+        log.extend_from_slice(&event.data);
+    }
+
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn digests() -> Vec<TpmDigest> {
+        vec![
+            TpmDigest {
+                alg: TPM_ALG_SHA1,
+                digest: vec![0x11; 20],
+            },
+            TpmDigest {
+                alg: TPM_ALG_SHA256,
+                digest: vec![0x22; 32],
+            },
+        ]
+    }
+
+    fn pcr_event_response(digests: &[TpmDigest]) -> Vec<u8> {
+        let mut params = Vec::new();
+        params.extend_from_slice(&(digests.len() as u32).to_be_bytes());
+        for digest in digests {
+            params.extend_from_slice(&digest.alg.to_be_bytes());
+            params.extend_from_slice(&digest.digest);
+        }
+
+        let mut response = vec![0x80, 0x02];
+        response.extend_from_slice(&(14 + params.len() as u32 + 5).to_be_bytes());
+        response.extend_from_slice(&0u32.to_be_bytes());
+        response.extend_from_slice(&(params.len() as u32).to_be_bytes());
+        response.extend_from_slice(&params);
+        // Authorization area of the password session
+        response.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x00]);
+        response
+    }
+
+    #[test]
+    fn parse_pcr_event() {
+        let digests = digests();
+        let response = pcr_event_response(&digests);
+        assert_eq!(parse_pcr_event_response(&response), Some(digests));
+    }
+
+    #[test]
+    fn parse_malformed_pcr_event() {
+        let response = pcr_event_response(&digests());
+        // Truncated header
+        assert_eq!(parse_pcr_event_response(&response[..12]), None);
+        // Truncated digest
+        assert_eq!(parse_pcr_event_response(&response[..18 + 2 + 10]), None);
+
+        // Unknown hash algorithm
+        let unknown = [TpmDigest {
+            alg: 0x0001,
+            digest: vec![0; 20],
+        }];
+        assert_eq!(
+            parse_pcr_event_response(&pcr_event_response(&unknown)),
+            None
+        );
+    }
+
+    #[test]
+    fn empty_event_log() {
+        assert!(event_log(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn encode_event_log() {
+        let events = [TcgEvent {
+            pcr: 13,
+            event_type: EV_POST_CODE,
+            data: vec![0xaa; 3],
+        }];
+        let log = event_log(&events, &[digests()]);
+
+        // Specification ID Version Event
+        let (header, rest) = log.split_at(32);
+        assert_eq!(header[..4], 0u32.to_le_bytes());
+        assert_eq!(header[4..8], EV_NO_ACTION.to_le_bytes());
+        assert_eq!(header[8..28], [0; 20]);
+        let spec_id_len = u32::from_le_bytes(header[28..32].try_into().unwrap()) as usize;
+        assert_eq!(spec_id_len, 16 + 4 + 4 + 4 + 2 * 4 + 1);
+        let (spec_id, rest) = rest.split_at(spec_id_len);
+        assert_eq!(&spec_id[..16], SPEC_ID_EVENT_SIGNATURE);
+        assert_eq!(spec_id[24..28], 2u32.to_le_bytes());
+        assert_eq!(spec_id[28..30], TPM_ALG_SHA1.to_le_bytes());
+        assert_eq!(spec_id[30..32], 20u16.to_le_bytes());
+        assert_eq!(spec_id[32..34], TPM_ALG_SHA256.to_le_bytes());
+        assert_eq!(spec_id[34..36], 32u16.to_le_bytes());
+        assert_eq!(spec_id[36], 0);
+
+        // TCG_PCR_EVENT2 of the measurement
+        let mut event = Vec::new();
+        event.extend_from_slice(&13u32.to_le_bytes());
+        event.extend_from_slice(&EV_POST_CODE.to_le_bytes());
+        event.extend_from_slice(&2u32.to_le_bytes());
+        event.extend_from_slice(&TPM_ALG_SHA1.to_le_bytes());
+        event.extend_from_slice(&[0x11; 20]);
+        event.extend_from_slice(&TPM_ALG_SHA256.to_le_bytes());
+        event.extend_from_slice(&[0x22; 32]);
+        event.extend_from_slice(&3u32.to_le_bytes());
+        event.extend_from_slice(&[0xaa; 3]);
+        assert_eq!(rest, event);
+    }
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Kernel timekeeping.
+//!
+//! The monotonic clock counts the time since [`time_init`] using the TSC.
+//! Its frequency is taken from CPUID or calibrated against the PIT at boot.
+//! If the frequency can not be determined, the SVSM runs without clocks:
+//! they can not be read, sleeping fails and timers never expire.
+//!
+//! The real-time clock is read once from the CMOS RTC at boot and advanced by
+//! the monotonic clock afterwards. It is unavailable if no RTC was found.
+//!
+//! None of these clocks can be trusted. The RTC is emulated by the host, the
+//! TSC frequency is reported by the host or measured against its PIT, and
+//! without SecureTSC, which the SVSM does not support, the host controls the
+//! TSC value seen by each vCPU as well. The host can therefore set the time,
+//! speed it up, slow it down or make it go backwards. The clocks must only
+//! be used where such manipulation merely causes a denial of service, for
+//! example for timeouts, and never for security decisions like the validity
+//! of certificates or the expiry of keys.
+
+mod rtc;
+mod timer;
+mod tsc;
+
+pub use timer::{
+    Timer, TimerCallback, TimerQueue, handle_timer_interrupt, poll_timers, set_time_slice_end,
+};
+
+use crate::cpu::IrqGuard;
+use crate::cpu::msr::rdtsc;
+use crate::error::SvsmError;
+use crate::platform::SVSM_PLATFORM;
+use crate::task::{WaitQueue, block_current_task, current_task};
+use crate::utils::immut_after_init::ImmutAfterInitCell;
+use core::time::Duration;
+
+const NSEC_PER_SEC: u128 = 1_000_000_000;
+
+#[derive(Clone, Copy, Debug)]
+struct Clock {
+    /// TSC value at boot
+    tsc_base: u64,
+    /// TSC frequency in Hz
+    tsc_freq_hz: u64,
+    /// Time since the Unix epoch at boot
+    realtime_base: Option<Duration>,
+}
+
+#[unsafe(link_section = crate::ro_after_init_section!())]
+static CLOCK: ImmutAfterInitCell<Clock> = ImmutAfterInitCell::uninit();
+
+fn ticks_to_duration(ticks: u64, freq_hz: u64) -> Duration {
+    let nsecs = u128::from(ticks) * NSEC_PER_SEC / u128::from(freq_hz);
+    Duration::new((nsecs / NSEC_PER_SEC) as u64, (nsecs % NSEC_PER_SEC) as u32)
+}
+
+fn tsc_frequency() -> Option<u64> {
+    tsc::cpuid_tsc_frequency()
+        .or_else(tsc::hypervisor_tsc_frequency)
+        .or_else(|| tsc::pit_tsc_frequency(SVSM_PLATFORM.get_io_port()))
+}
+
+/// Initializes the monotonic and real-time clocks and the timers. Must be
+/// called on the BSP before the `ro_after_init` section is made read-only.
+pub fn time_init() -> Result<(), SvsmError> {
+    let Some(tsc_freq_hz) = tsc_frequency() else {
+        log::error!("Failed to determine the TSC frequency");
+        return Err(SvsmError::NotSupported);
+    };
+    let realtime_base = rtc::read_rtc(SVSM_PLATFORM.get_io_port());
+    let tsc_base = rdtsc();
+
+    log::info!(
+        "TSC frequency {}.{:03} MHz",
+        tsc_freq_hz / 1_000_000,
+        tsc_freq_hz / 1000 % 1000
+    );
+    if realtime_base.is_none() {
+        log::info!("No RTC found, real-time clock unavailable");
+    }
+
+    CLOCK.init(Clock {
+        tsc_base,
+        tsc_freq_hz,
+        realtime_base,
+    })?;
+    timer::timer_init()
+}
+
+/// Returns whether the clocks have been initialized.
+pub fn clocks_available() -> bool {
+    CLOCK.try_get_inner().is_ok()
+}
+
+/// Returns the time elapsed since the clocks were initialized, or zero if
+/// they are not initialized.
+pub fn monotonic() -> Duration {
+    match CLOCK.try_get_inner() {
+        Ok(clock) => ticks_to_duration(rdtsc().wrapping_sub(clock.tsc_base), clock.tsc_freq_hz),
+        Err(_) => Duration::ZERO,
+    }
+}
+
+/// Returns the time since the Unix epoch, or `None` if the real-time clock
+/// is not available.
+pub fn realtime() -> Option<Duration> {
+    let base = CLOCK.try_get_inner().ok()?.realtime_base?;
+    Some(base + monotonic())
+}
+
+/// Blocks the current task for at least `duration`.
+///
+/// # Returns
+///
+/// `Ok(())` after the time has elapsed, or `SvsmError::NotSupported` if the
+/// clocks are not available, in which case the task would never wake up.
+pub fn sleep(duration: Duration) -> Result<(), SvsmError> {
+    if !clocks_available() {
+        return Err(SvsmError::NotSupported);
+    }
+
+    // Nothing wakes the task through this queue, so it only wakes up when
+    // the wait times out.
+    let mut queue = WaitQueue::new();
+    let mut timer = None;
+    block_current_task(|| {
+        // Interrupts must be disabled before joining the wait queue, see
+        // `Task::wait_for_exit()`.
+        let guard = IrqGuard::new();
+        timer = Some(queue.wait_for_event_timeout(current_task(), duration));
+        Some(guard)
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick_conversion() {
+        assert_eq!(ticks_to_duration(0, 1_000_000), Duration::ZERO);
+        assert_eq!(
+            ticks_to_duration(3_500_000_000, 2_000_000_000),
+            Duration::from_millis(1750)
+        );
+        // Must not overflow for large tick counts.
+        assert_eq!(
+            ticks_to_duration(u64::MAX, 1_000_000_000),
+            Duration::from_nanos(u64::MAX)
+        );
+    }
+}
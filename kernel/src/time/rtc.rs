@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Reading of the wall-clock time from the CMOS real-time clock.
+
+use crate::io::IOPort;
+use core::time::Duration;
+
+const CMOS_ADDR_PORT: u16 = 0x70;
+const CMOS_DATA_PORT: u16 = 0x71;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+/// Century register used by QEMU and most firmware, see the CENTURY field of
+/// the ACPI FADT.
+const RTC_CENTURY: u8 = 0x32;
+
+/// Update in progress
+const RTC_STATUS_A_UIP: u8 = 1 << 7;
+/// Hours are in 24-hour format
+const RTC_STATUS_B_24H: u8 = 1 << 1;
+/// Values are binary instead of BCD
+const RTC_STATUS_B_BINARY: u8 = 1 << 2;
+/// PM flag of the hours register in 12-hour format
+const RTC_HOURS_PM: u8 = 1 << 7;
+
+/// Number of attempts to read a consistent time.
+const RTC_READ_RETRIES: usize = 10;
+
+fn cmos_read(io: &dyn IOPort, reg: u8) -> u8 {
+    io.outb(CMOS_ADDR_PORT, reg);
+    io.inb(CMOS_DATA_PORT)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RtcTime {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+impl RtcTime {
+    fn read(io: &dyn IOPort) -> Self {
+        Self {
+            seconds: cmos_read(io, RTC_SECONDS),
+            minutes: cmos_read(io, RTC_MINUTES),
+            hours: cmos_read(io, RTC_HOURS),
+            day: cmos_read(io, RTC_DAY),
+            month: cmos_read(io, RTC_MONTH),
+            year: cmos_read(io, RTC_YEAR),
+            century: cmos_read(io, RTC_CENTURY),
+        }
+    }
+
+    /// Converts the register values into the time since the Unix epoch.
+    ///
+    /// # Returns
+    ///
+    /// The time since the Unix epoch, or `None` if the registers do not hold
+    /// a valid date.
+    fn to_unix(self, status_b: u8) -> Option<Duration> {
+        let binary = status_b & RTC_STATUS_B_BINARY != 0;
+        let decode = |v: u8| if binary { Some(v) } else { bcd_to_binary(v) };
+
+        let pm = status_b & RTC_STATUS_B_24H == 0 && self.hours & RTC_HOURS_PM != 0;
+        let mut hours = decode(self.hours & !RTC_HOURS_PM)?;
+        if status_b & RTC_STATUS_B_24H == 0 {
+            // 12-hour format counts 12, 1, ..., 11.
+            hours %= 12;
+            if pm {
+                hours += 12;
+            }
+        }
+
+        let century = decode(self.century)
+            .filter(|c| (19..=99).contains(c))
+            .unwrap_or(20);
+        let year = u64::from(century) * 100 + u64::from(decode(self.year)?);
+        let (month, day) = (decode(self.month)?, decode(self.day)?);
+        let (minutes, seconds) = (decode(self.minutes)?, decode(self.seconds)?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 {
+            return None;
+        }
+        if minutes > 59 || seconds > 59 || year < 1970 {
+            return None;
+        }
+
+        let days = days_since_epoch(year, month.into(), day.into());
+        let secs =
+            days * 86400 + u64::from(hours) * 3600 + u64::from(minutes) * 60 + u64::from(seconds);
+        Some(Duration::from_secs(secs))
+    }
+}
+
+fn bcd_to_binary(v: u8) -> Option<u8> {
+    let (high, low) = (v >> 4, v & 0xf);
+    (high < 10 && low < 10).then_some(high * 10 + low)
+}
+
+/// Returns the number of days between 1970-01-01 and the given date of the
+/// proleptic Gregorian calendar.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // Count years from March, so that the leap day is the last day of the
+    // year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719468 is the number of days from 0000-03-01 to 1970-01-01.
+    era * 146097 + day_of_era - 719468
+}
+
+/// Reads the current wall-clock time from the CMOS RTC.
+///
+/// # Returns
+///
+/// The time since the Unix epoch, or `None` if no RTC with a valid time was
+/// found.
+pub fn read_rtc(io: &dyn IOPort) -> Option<Duration> {
+    // The registers are inconsistent while the RTC updates them, so read
+    // them until two reads outside of an update agree.
+    let mut last = None;
+    for _ in 0..RTC_READ_RETRIES {
+        while cmos_read(io, RTC_STATUS_A) & RTC_STATUS_A_UIP != 0 {}
+        let time = RtcTime::read(io);
+        if last == Some(time) {
+            return time.to_unix(cmos_read(io, RTC_STATUS_B));
+        }
+        last = Some(time);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rtc_time(
+        (century, year, month, day): (u8, u8, u8, u8),
+        (hours, minutes, seconds): (u8, u8, u8),
+    ) -> RtcTime {
+        RtcTime {
+            seconds,
+            minutes,
+            hours,
+            day,
+            month,
+            year,
+            century,
+        }
+    }
+
+    #[test]
+    fn epoch_days() {
+        assert_eq!(days_since_epoch(1970, 1, 1), 0);
+        assert_eq!(days_since_epoch(2000, 3, 1), 11017);
+        assert_eq!(days_since_epoch(2024, 2, 29), 19782);
+    }
+
+    #[test]
+    fn rtc_formats() {
+        // 2024-02-29 13:45:30 UTC
+        let expected = Some(Duration::from_secs(1709214330));
+
+        let bcd_24h = rtc_time((0x20, 0x24, 0x02, 0x29), (0x13, 0x45, 0x30));
+        assert_eq!(bcd_24h.to_unix(RTC_STATUS_B_24H), expected);
+
+        let binary = rtc_time((20, 24, 2, 29), (13, 45, 30));
+        assert_eq!(
+            binary.to_unix(RTC_STATUS_B_24H | RTC_STATUS_B_BINARY),
+            expected
+        );
+
+        let bcd_12h = rtc_time((0x20, 0x24, 0x02, 0x29), (RTC_HOURS_PM | 0x01, 0x45, 0x30));
+        assert_eq!(bcd_12h.to_unix(0), expected);
+
+        let invalid = rtc_time((0xff, 0xff, 0xff, 0xff), (0xff, 0xff, 0xff));
+        assert_eq!(invalid.to_unix(RTC_STATUS_B_24H), None);
+    }
+}
@@ -0,0 +1,416 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Kernel timers.
+//!
+//! Every CPU keeps a queue of the timers started on it, ordered by their
+//! deadline on the monotonic clock. The local APIC timer of the CPU is
+//! programmed in one-shot mode to raise an interrupt at the earliest
+//! deadline, and the callbacks of all expired timers run in that interrupt.
+//! On platforms on which the SVSM can not receive interrupts, the idle loop
+//! polls the timers instead.
+//!
+//! Besides the timers, the queue holds the end of the time slice of the task
+//! running on the CPU. It does not need any allocation, so the scheduler can
+//! update it on every task switch.
+
+extern crate alloc;
+
+use super::monotonic;
+use super::tsc::hypervisor_timing_info;
+use crate::cpu::IrqGuard;
+use crate::cpu::idt::common::TIMER_VECTOR;
+use crate::cpu::percpu::{PERCPU_AREAS, this_cpu};
+use crate::cpu::x86::{apic_timer_count, apic_timer_start, apic_timer_stop};
+use crate::error::SvsmError;
+use crate::platform::SVSM_PLATFORM;
+use crate::task::time_slice_expired;
+use crate::utils::immut_after_init::ImmutAfterInitCell;
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use core::fmt;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+const NSEC_PER_SEC: u128 = 1_000_000_000;
+
+/// Shortest period of periodic timers.
+const TIMER_PERIOD_MIN: Duration = Duration::from_micros(100);
+
+/// Duration of the APIC timer calibration.
+const APIC_TIMER_CALIBRATION: Duration = Duration::from_millis(10);
+
+/// Frequency of the local APIC timer in Hz. Only initialized if the SVSM can
+/// receive timer interrupts.
+#[unsafe(link_section = crate::ro_after_init_section!())]
+static APIC_TIMER_FREQ_HZ: ImmutAfterInitCell<u64> = ImmutAfterInitCell::uninit();
+
+/// Function called when a timer expires. It runs in interrupt context, so it
+/// must not block.
+pub type TimerCallback = Box<dyn FnMut() + Send>;
+
+struct TimerEntry {
+    /// Interval of periodic timers
+    period: Option<Duration>,
+    /// Set when the timer is cancelled, so that a timer whose callback is
+    /// running is not re-armed.
+    cancelled: Arc<AtomicBool>,
+    callback: TimerCallback,
+}
+
+impl fmt::Debug for TimerEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimerEntry")
+            .field("period", &self.period)
+            .field("cancelled", &self.cancelled)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Queue of the timers of a CPU, ordered by deadline.
+#[derive(Debug, Default)]
+pub struct TimerQueue {
+    /// Timers by deadline and ID
+    timers: BTreeMap<(Duration, u64), TimerEntry>,
+    next_id: u64,
+    /// End of the time slice of the current task
+    slice_end: Option<Duration>,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            next_id: 0,
+            slice_end: None,
+        }
+    }
+
+    fn insert(&mut self, deadline: Duration, entry: TimerEntry) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.timers.insert((deadline, id), entry);
+        id
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.timers.retain(|(_, timer_id), _| *timer_id != id);
+    }
+
+    /// Removes the timer with the earliest deadline if it has expired at
+    /// `now`.
+    fn pop_expired(&mut self, now: Duration) -> Option<(Duration, u64, TimerEntry)> {
+        let entry = self.timers.first_entry()?;
+        let (deadline, id) = *entry.key();
+        (deadline <= now).then(|| (deadline, id, entry.remove()))
+    }
+
+    /// Clears the end of the time slice if it has been reached at `now`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the time slice has ended, `false` otherwise.
+    fn take_expired_slice(&mut self, now: Duration) -> bool {
+        let expired = self.slice_end.is_some_and(|end| end <= now);
+        if expired {
+            self.slice_end = None;
+        }
+        expired
+    }
+
+    fn next_deadline(&self) -> Option<Duration> {
+        let timer = self
+            .timers
+            .first_key_value()
+            .map(|((deadline, _), _)| *deadline);
+        match (timer, self.slice_end) {
+            (Some(timer), Some(slice_end)) => Some(timer.min(slice_end)),
+            (timer, slice_end) => timer.or(slice_end),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+}
+
+/// Returns the deadline following `deadline` of a periodic timer. Periods
+/// which were missed entirely are skipped.
+fn next_period(deadline: Duration, period: Duration, now: Duration) -> Duration {
+    let next = deadline + period;
+    if next > now { next } else { now + period }
+}
+
+fn timer_interrupts() -> bool {
+    APIC_TIMER_FREQ_HZ.try_get_inner().is_ok()
+}
+
+/// Programs the APIC timer of the current CPU to expire at the earliest
+/// deadline of its timer queue.
+fn program_timer() {
+    let Ok(freq_hz) = APIC_TIMER_FREQ_HZ.try_get_inner() else {
+        return;
+    };
+    let next = this_cpu().shared().timers().lock().next_deadline();
+    match next {
+        Some(deadline) => {
+            let delta = deadline.saturating_sub(monotonic());
+            let count = delta.as_nanos() * u128::from(*freq_hz) / NSEC_PER_SEC;
+            // A deadline beyond the range of the timer is reached through
+            // several timer interrupts.
+            apic_timer_start(TIMER_VECTOR as u8, count.clamp(1, u32::MAX.into()) as u32);
+        }
+        None => apic_timer_stop(),
+    }
+}
+
+/// Runs the callbacks of the expired timers of the current CPU.
+fn run_expired_timers() {
+    let cpu = this_cpu().shared();
+    loop {
+        let now = monotonic();
+        let Some((deadline, id, mut entry)) = cpu.timers().lock().pop_expired(now) else {
+            break;
+        };
+        if entry.cancelled.load(Ordering::Acquire) {
+            continue;
+        }
+
+        (entry.callback)();
+
+        if let Some(period) = entry.period {
+            // Re-arm the timer with its ID, so that it can still be
+            // cancelled. The cancellation flag must be checked with the queue
+            // locked, because `Timer::drop()` removes the timer from the queue
+            // after setting the flag.
+            let mut timers = cpu.timers().lock();
+            if !entry.cancelled.load(Ordering::Acquire) {
+                timers
+                    .timers
+                    .insert((next_period(deadline, period, monotonic()), id), entry);
+            }
+        }
+    }
+}
+
+/// Handles an interrupt of the APIC timer.
+pub fn handle_timer_interrupt() {
+    run_expired_timers();
+    let slice_expired = this_cpu()
+        .shared()
+        .timers()
+        .lock()
+        .take_expired_slice(monotonic());
+    if slice_expired {
+        time_slice_expired();
+    }
+    program_timer();
+}
+
+/// Sets the end of the time slice of the task running on the current CPU, or
+/// clears it if `end` is `None`. The scheduler is notified in interrupt
+/// context once the time slice has ended. Does nothing if timers can not
+/// raise interrupts.
+pub fn set_time_slice_end(end: Option<Duration>) {
+    if !timer_interrupts() {
+        return;
+    }
+
+    // The time slice must be set and programmed on the same CPU.
+    let _guard = IrqGuard::new();
+    let mut timers = this_cpu().shared().timers().lock();
+    timers.slice_end = end;
+    let earliest = end.is_some() && timers.next_deadline() == end;
+    drop(timers);
+
+    // A cleared time slice may leave the APIC timer programmed for its end,
+    // which only causes a spurious timer interrupt.
+    if earliest {
+        program_timer();
+    }
+}
+
+/// Runs the expired timers of the current CPU if timers can not raise
+/// interrupts.
+///
+/// # Returns
+///
+/// `true` if the caller must keep polling because timers are pending,
+/// `false` otherwise.
+pub fn poll_timers() -> bool {
+    if timer_interrupts() {
+        return false;
+    }
+    run_expired_timers();
+    !this_cpu().shared().timers().lock().is_empty()
+}
+
+/// A timer which calls a function once or periodically on the CPU which
+/// started it. The timer is cancelled when it is dropped.
+#[derive(Debug)]
+pub struct Timer {
+    cpu_index: usize,
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Timer {
+    fn start(delay: Duration, period: Option<Duration>, callback: TimerCallback) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let entry = TimerEntry {
+            period,
+            cancelled: cancelled.clone(),
+            callback,
+        };
+
+        // The timer must be queued and programmed on the same CPU.
+        let _guard = IrqGuard::new();
+        let cpu = this_cpu().shared();
+        let deadline = monotonic() + delay;
+        let mut timers = cpu.timers().lock();
+        let id = timers.insert(deadline, entry);
+        let earliest = timers.next_deadline() == Some(deadline);
+        drop(timers);
+
+        if earliest {
+            program_timer();
+        }
+
+        Self {
+            cpu_index: cpu.cpu_index(),
+            id,
+            cancelled,
+        }
+    }
+
+    /// Starts a timer which calls `callback` once after `delay`.
+    pub fn one_shot(delay: Duration, callback: impl FnMut() + Send + 'static) -> Self {
+        Self::start(delay, None, Box::new(callback))
+    }
+
+    /// Starts a timer which calls `callback` every `period`, starting one
+    /// `period` from now. Periods shorter than 100µs are extended to 100µs.
+    pub fn periodic(period: Duration, callback: impl FnMut() + Send + 'static) -> Self {
+        let period = period.max(TIMER_PERIOD_MIN);
+        Self::start(period, Some(period), Box::new(callback))
+    }
+
+    /// Cancels the timer. The callback does not run anymore after this
+    /// function returns, unless it is already running on the CPU of the
+    /// timer.
+    pub fn cancel(self) {}
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+        PERCPU_AREAS
+            .get_by_cpu_index(self.cpu_index)
+            .timers()
+            .lock()
+            .remove(self.id);
+    }
+}
+
+/// Returns the frequency of the APIC timer in Hz reported by the hypervisor
+/// timing information leaf.
+fn hypervisor_apic_timer_frequency() -> Option<u64> {
+    match hypervisor_timing_info()?.ebx {
+        0 => None,
+        khz => Some(u64::from(khz) * 1000),
+    }
+}
+
+/// Measures the frequency of the APIC timer in Hz against the monotonic
+/// clock.
+fn calibrate_apic_timer() -> Option<u64> {
+    let _guard = IrqGuard::new();
+    apic_timer_start(TIMER_VECTOR as u8, u32::MAX);
+    let start = monotonic();
+    while monotonic() - start < APIC_TIMER_CALIBRATION {
+        spin_loop();
+    }
+    let count = apic_timer_count();
+    let elapsed = monotonic() - start;
+    apic_timer_stop();
+
+    let ticks = u128::from(u32::MAX - count);
+    match (ticks * NSEC_PER_SEC / elapsed.as_nanos()) as u64 {
+        0 => None,
+        freq_hz => Some(freq_hz),
+    }
+}
+
+/// Determines the frequency of the APIC timer. Without it, or if the
+/// platform does not deliver interrupts to the SVSM, timers are polled.
+pub fn timer_init() -> Result<(), SvsmError> {
+    if !SVSM_PLATFORM.use_interrupts() {
+        log::info!("Timer interrupts unavailable, polling timers");
+        return Ok(());
+    }
+
+    match hypervisor_apic_timer_frequency().or_else(calibrate_apic_timer) {
+        Some(freq_hz) => {
+            log::info!("APIC timer frequency {} kHz", freq_hz / 1000);
+            APIC_TIMER_FREQ_HZ.init(freq_hz)?;
+        }
+        None => log::warn!("Failed to calibrate the APIC timer, polling timers"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(period: Option<Duration>) -> TimerEntry {
+        TimerEntry {
+            period,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            callback: Box::new(|| {}),
+        }
+    }
+
+    #[test]
+    fn timer_queue_order() {
+        let ms = Duration::from_millis;
+        let mut queue = TimerQueue::new();
+        let late = queue.insert(ms(30), entry(None));
+        let early = queue.insert(ms(10), entry(None));
+        let cancelled = queue.insert(ms(20), entry(None));
+        queue.remove(cancelled);
+        assert_eq!(queue.next_deadline(), Some(ms(10)));
+
+        assert!(queue.pop_expired(ms(5)).is_none());
+        let (deadline, id, _) = queue.pop_expired(ms(40)).unwrap();
+        assert_eq!((deadline, id), (ms(10), early));
+        let (deadline, id, _) = queue.pop_expired(ms(40)).unwrap();
+        assert_eq!((deadline, id), (ms(30), late));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn time_slice_deadline() {
+        let ms = Duration::from_millis;
+        let mut queue = TimerQueue::new();
+        queue.insert(ms(30), entry(None));
+        queue.slice_end = Some(ms(10));
+        assert_eq!(queue.next_deadline(), Some(ms(10)));
+
+        // The time slice does not count as a timer.
+        assert!(queue.pop_expired(ms(20)).is_none());
+        assert!(!queue.take_expired_slice(ms(5)));
+        assert!(queue.take_expired_slice(ms(20)));
+        assert_eq!(queue.next_deadline(), Some(ms(30)));
+    }
+
+    #[test]
+    fn periodic_deadlines() {
+        let ms = Duration::from_millis;
+        assert_eq!(next_period(ms(10), ms(10), ms(12)), ms(20));
+        // Missed periods are skipped.
+        assert_eq!(next_period(ms(10), ms(10), ms(55)), ms(65));
+    }
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Determination of the TSC frequency.
+
+use crate::cpu::msr::rdtsc;
+use crate::io::IOPort;
+use crate::platform::cpuid;
+use core::arch::x86_64::CpuidResult;
+
+/// Input frequency of the PIT in Hz.
+const PIT_FREQ_HZ: u64 = 1_193_182;
+/// Duration of the PIT calibration in milliseconds.
+const PIT_CALIBRATION_MS: u64 = 10;
+/// Number of TSC ticks after which the PIT is considered to be missing.
+const PIT_TIMEOUT_TICKS: u64 = 1 << 34;
+
+const PIT_CH2_PORT: u16 = 0x42;
+const PIT_CMD_PORT: u16 = 0x43;
+/// NMI status and control port, which gates PIT channel 2 and reports its
+/// output.
+const PIT_GATE_PORT: u16 = 0x61;
+const PIT_GATE_CH2: u8 = 1 << 0;
+const PIT_GATE_SPEAKER: u8 = 1 << 1;
+const PIT_GATE_CH2_OUT: u8 = 1 << 5;
+
+/// Returns the TSC frequency in Hz reported by CPUID leaf 0x15, if the
+/// leaf enumerates both the TSC/crystal ratio and the crystal frequency.
+pub fn cpuid_tsc_frequency() -> Option<u64> {
+    let max_leaf = cpuid(0, 0)?.eax;
+    if max_leaf < 0x15 {
+        return None;
+    }
+    let leaf = cpuid(0x15, 0)?;
+    if leaf.eax == 0 || leaf.ebx == 0 || leaf.ecx == 0 {
+        return None;
+    }
+    Some(u64::from(leaf.ecx) * u64::from(leaf.ebx) / u64::from(leaf.eax))
+}
+
+/// Returns the timing information leaf 0x40000010 of the hypervisor, which
+/// reports the TSC frequency in EAX and the APIC timer frequency in EBX, both
+/// in kHz.
+pub fn hypervisor_timing_info() -> Option<CpuidResult> {
+    let max_leaf = cpuid(0x4000_0000, 0)?.eax;
+    if max_leaf < 0x4000_0010 {
+        return None;
+    }
+    cpuid(0x4000_0010, 0)
+}
+
+/// Returns the TSC frequency in Hz reported by the hypervisor timing
+/// information leaf.
+pub fn hypervisor_tsc_frequency() -> Option<u64> {
+    match hypervisor_timing_info()?.eax {
+        0 => None,
+        khz => Some(u64::from(khz) * 1000),
+    }
+}
+
+/// Measures the TSC frequency in Hz against PIT channel 2.
+pub fn pit_tsc_frequency(io: &dyn IOPort) -> Option<u64> {
+    let gate = io.inb(PIT_GATE_PORT);
+    io.outb(PIT_GATE_PORT, (gate & !PIT_GATE_SPEAKER) | PIT_GATE_CH2);
+
+    // Channel 2, low byte then high byte, mode 0 (interrupt on terminal
+    // count), binary counting. The output goes high at terminal count.
+    io.outb(PIT_CMD_PORT, 0b1011_0000);
+    let count = PIT_FREQ_HZ * PIT_CALIBRATION_MS / 1000;
+    io.outb(PIT_CH2_PORT, count as u8);
+    io.outb(PIT_CH2_PORT, (count >> 8) as u8);
+
+    let start = rdtsc();
+    let mut end = start;
+    while io.inb(PIT_GATE_PORT) & PIT_GATE_CH2_OUT == 0 {
+        end = rdtsc();
+        if end.wrapping_sub(start) > PIT_TIMEOUT_TICKS {
+            break;
+        }
+    }
+    io.outb(PIT_GATE_PORT, gate);
+
+    let ticks = end.wrapping_sub(start);
+    if ticks == 0 || ticks > PIT_TIMEOUT_TICKS {
+        return None;
+    }
+    Some(ticks * 1000 / PIT_CALIBRATION_MS)
+}
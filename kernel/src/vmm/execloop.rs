@@ -7,6 +7,7 @@
 use super::{GuestExitMessage, GuestRegister, set_guest_register};
 use crate::cpu::percpu::{GuestVmsaRef, this_cpu};
 use crate::cpu::{IrqGuard, flush_tlb_global_sync};
+use crate::events::events_pending;
 use crate::mm::GuestPtr;
 use crate::protocols::RequestParams;
 use crate::protocols::errors::SvsmReqError;
@@ -86,6 +87,9 @@ pub fn enter_guest(mut regs: &[GuestRegister]) -> GuestExitMessage {
         let caa_addr = vmsa_ref.caa();
         let vmsa = vmsa_ref.vmsa();
 
+        // The guest waits for the response to a request while its registers
+        // are being updated.
+        let response_pending = !regs.is_empty();
         for reg in regs {
             set_guest_register(vmsa, reg);
         }
@@ -102,6 +106,14 @@ pub fn enter_guest(mut regs: &[GuestRegister]) -> GuestExitMessage {
         // guest APIC state.
         let guard = IrqGuard::new();
 
+        // Pending events are handled before the guest is entered, unless it
+        // waits for a response. Once interrupts are disabled, events
+        // signalled by other CPUs cause the guest to exit again, so they are
+        // handled on the next iteration.
+        if !response_pending && events_pending() {
+            return GuestExitMessage::Events;
+        }
+
         // Update APIC interrupt emulation state if required.
         cpu.update_apic_emulation(vmsa, caa_addr);
 
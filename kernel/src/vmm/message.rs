@@ -9,5 +9,6 @@ use crate::protocols::RequestParams;
 #[derive(Clone, Copy, Debug)]
 pub enum GuestExitMessage {
     NoMappings,
+    Events,
     Svsm((u32, u32, RequestParams)),
 }
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Measurement of the SVSM launch state into the vTPM.
+//!
+//! The SVSM starts up the TPM itself and extends [`LAUNCH_PCR`] with the
+//! SEV-SNP launch measurement and the digest of the filesystem archive,
+//! before the guest can send any TPM command. This is repeated whenever the
+//! guest powers on or resets the TPM, so the guest can not clear these
+//! measurements. The matching event log uses the crypto agile format of the
+//! TCG PC Client Platform Firmware Profile and covers all PCR banks.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootdefs::platform::SvsmPlatformType;
+
+use crate::error::SvsmError;
+use crate::fs::fs_archive_digest;
+use crate::greq::services::get_launch_measurement;
+use crate::locking::RWLock;
+use crate::platform::SVSM_PLATFORM;
+use crate::protocols::errors::SvsmReqError;
+use crate::tcg::{EV_POST_CODE, EV_S_CRTM_CONTENTS, TcgEvent, event_log};
+use crate::utils::immut_after_init::ImmutAfterInitCell;
+use crate::vtpm::VtpmInterface;
+use crate::vtpm::tss;
+
+/// PCR holding the measurements of the SVSM launch state. The firmware
+/// measures into PCRs 0 to 7, so one of the PCRs reserved for the operating
+/// system is used, which the guest can not reset.
+pub const LAUNCH_PCR: u32 = 13;
+
+static LAUNCH_EVENTS: ImmutAfterInitCell<Vec<TcgEvent>> = ImmutAfterInitCell::uninit();
+
+/// Event log of the measurements in the PCRs
+static EVENT_LOG: RWLock<Vec<u8>> = RWLock::new(Vec::new());
+
+/// Collects the launch state which [`measure_launch_state()`] measures.
+pub fn init_launch_events() -> Result<(), SvsmReqError> {
+    let mut events = Vec::new();
+
+    if SVSM_PLATFORM.platform_type() == SvsmPlatformType::Snp {
+        let measurement = get_launch_measurement().map_err(|e| {
+            log::error!("vTPM: failed to get the launch measurement: {e:?}");
+            SvsmReqError::incomplete()
+        })?;
+        events.push(TcgEvent {
+            pcr: LAUNCH_PCR,
+            event_type: EV_S_CRTM_CONTENTS,
+            data: measurement.to_vec(),
+        });
+    }
+
+    if let Some(digest) = fs_archive_digest() {
+        events.push(TcgEvent {
+            pcr: LAUNCH_PCR,
+            event_type: EV_POST_CODE,
+            data: digest.to_vec(),
+        });
+    }
+
+    LAUNCH_EVENTS.init(events).map_err(SvsmError::from)?;
+    Ok(())
+}
+
+/// Starts up the TPM and measures the launch state into its PCRs. Nothing
+/// is measured if the TPM had already been started up since its last reset.
+pub fn measure_launch_state(vtpm: &mut dyn VtpmInterface) -> Result<(), SvsmReqError> {
+    if !tss::startup(vtpm)? {
+        return Ok(());
+    }
+
+    let events = LAUNCH_EVENTS.try_get_inner().map_err(SvsmError::from)?;
+    let digests = events
+        .iter()
+        .map(|event| tss::pcr_event(vtpm, event.pcr, &event.data))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    *EVENT_LOG.lock_write() = event_log(events, &digests);
+    Ok(())
+}
+
+/// Returns the event log of the launch measurements, which is empty if
+/// nothing has been measured.
+pub fn launch_event_log() -> Vec<u8> {
+    EVENT_LOG.lock_read().clone()
+}
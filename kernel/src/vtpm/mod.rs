@@ -7,16 +7,29 @@
 //! This crate defines the Virtual TPM interfaces and shows what
 //! TPM backends are supported
 
+pub mod ek_templates;
+mod measure;
 /// TPM 2.0 Reference Implementation
+#[cfg(feature = "vtpm")]
 pub mod tcgtpm;
+mod tss;
+/// TPM 2.0 Reference Implementation running as a user-mode module
+#[cfg(feature = "vtpm-user")]
+pub mod user;
 
 extern crate alloc;
 
 use alloc::vec::Vec;
 
-use crate::vtpm::tcgtpm::TcgTpm as Vtpm;
-use crate::{locking::LockGuard, protocols::vtpm::TpmPlatformCommand};
-use crate::{locking::SpinLock, protocols::errors::SvsmReqError};
+#[cfg(feature = "vtpm")]
+use crate::locking::SpinLock;
+use crate::protocols::{errors::SvsmReqError, vtpm::TpmPlatformCommand};
+use crate::types::PAGE_SIZE;
+#[cfg(feature = "vtpm")]
+use crate::vtpm::tcgtpm::TcgTpm;
+
+/// Maximum size of TPM commands and responses
+pub const TPM_BUFFER_MAX_SIZE: usize = PAGE_SIZE;
 
 /// Basic services required to perform the VTPM Protocol
 pub trait VtpmProtocolInterface {
@@ -42,7 +55,7 @@ pub trait TcgTpmSimulatorInterface: VtpmProtocolInterface {
     ///
     /// A [`Result`] containing the response received from the TPM on success,
     /// or an error.
-    fn send_tpm_command(&self, command: &[u8], locality: u8) -> Result<Vec<u8>, SvsmReqError>;
+    fn send_tpm_command(&mut self, command: &[u8], locality: u8) -> Result<Vec<u8>, SvsmReqError>;
 
     /// Power-on the TPM, which also triggers a reset
     ///
@@ -53,6 +66,9 @@ pub trait TcgTpmSimulatorInterface: VtpmProtocolInterface {
     ///                 Otherwise, it will fail.
     fn signal_poweron(&mut self, only_reset: bool) -> Result<(), SvsmReqError>;
 
+    /// Power-off the TPM. Powering it on again resets it.
+    fn signal_poweroff(&mut self) -> Result<(), SvsmReqError>;
+
     /// In a system where the NV memory used by the TPM is not within the TPM,
     /// the NV may not always be available. This function indicates that NV
     /// is available.
@@ -95,26 +111,102 @@ pub trait VtpmInterface: TcgTpmSimulatorInterface {
     fn get_ekpub(&mut self) -> Result<Vec<u8>, SvsmReqError>;
 }
 
-static VTPM: SpinLock<Vtpm> = SpinLock::new(Vtpm::new());
+#[cfg(feature = "vtpm")]
+static VTPM: SpinLock<TcgTpm> = SpinLock::new(TcgTpm::new());
+
+/// Runs `f` with the TPM backend in use. The user-mode vTPM is used once it
+/// has been started by [`vtpm_init()`], otherwise the in-kernel TPM. Either
+/// backend is locked while `f` runs, so that the platform commands of
+/// different vCPUs do not interleave.
+pub fn with_vtpm<R>(f: impl FnOnce(&mut dyn VtpmInterface) -> R) -> R {
+    #[cfg(feature = "vtpm-user")]
+    if user::is_started() {
+        let _guard = user::lock_commands();
+        return f(&mut user::UserTpm);
+    }
+    #[cfg(feature = "vtpm")]
+    {
+        f(&mut *VTPM.lock())
+    }
+    #[cfg(not(feature = "vtpm"))]
+    {
+        let _guard = user::lock_commands();
+        f(&mut user::UserTpm)
+    }
+}
+
+/// Returns the platform commands supported by the TPM backend in use,
+/// without waiting for a running TPM command.
+pub fn vtpm_supported_commands() -> &'static [TpmPlatformCommand] {
+    #[cfg(feature = "vtpm-user")]
+    if user::is_started() {
+        return user::TPM_CMDS_SUPPORTED;
+    }
+    #[cfg(feature = "vtpm")]
+    {
+        tcgtpm::TPM_CMDS_SUPPORTED
+    }
+    #[cfg(not(feature = "vtpm"))]
+    {
+        user::TPM_CMDS_SUPPORTED
+    }
+}
+
+/// Sets or clears the cancel flag of the TPM backend in use, which makes a
+/// long-running TPM command abort with TPM_RC_CANCELED. Unlike the other
+/// platform commands, the cancel flag does not wait for the running TPM
+/// command, which it is meant to interrupt.
+pub fn vtpm_signal_cancel(cancel: bool) -> Result<(), SvsmReqError> {
+    #[cfg(feature = "vtpm-user")]
+    if user::is_started() {
+        return user::signal_cancel(cancel);
+    }
+    #[cfg(feature = "vtpm")]
+    {
+        tcgtpm::signal_cancel(cancel);
+        Ok(())
+    }
+    #[cfg(not(feature = "vtpm"))]
+    {
+        user::signal_cancel(cancel)
+    }
+}
 
 /// Initialize the TPM by calling the init() implementation of the
-/// [`VtpmInterface`]
+/// [`VtpmInterface`]. If the filesystem contains the user-mode vTPM module,
+/// the module is started instead and given a bounded time to register. The
+/// TPM is then started up and the launch state of the SVSM is measured into
+/// its PCRs.
 pub fn vtpm_init() -> Result<(), SvsmReqError> {
-    let mut vtpm = VTPM.lock();
-    if vtpm.is_powered_on() {
-        return Ok(());
+    measure::init_launch_events()?;
+    #[cfg(feature = "vtpm-user")]
+    if user::start()? {
+        user::wait_for_registration()?;
+        return with_vtpm(|vtpm| measure::measure_launch_state(vtpm));
     }
-    vtpm.init()?;
-    Ok(())
+    with_vtpm(|vtpm| {
+        if vtpm.is_powered_on() {
+            return Ok(());
+        }
+        vtpm.init()?;
+        measure::measure_launch_state(vtpm)
+    })
+}
+
+/// Power on or reset the TPM and measure the launch state of the SVSM into
+/// its PCRs again, see [`TcgTpmSimulatorInterface::signal_poweron()`].
+pub fn vtpm_poweron(vtpm: &mut dyn VtpmInterface, only_reset: bool) -> Result<(), SvsmReqError> {
+    vtpm.signal_poweron(only_reset)?;
+    measure::measure_launch_state(vtpm)
 }
 
-pub fn vtpm_get_locked<'a>() -> LockGuard<'a, Vtpm> {
-    VTPM.lock()
+/// Get the TCG event log of the measurements of the SVSM launch state.
+pub fn vtpm_get_event_log() -> Vec<u8> {
+    measure::launch_event_log()
 }
 
 /// Get the TPM manifest i.e the EK public key by calling the get_ekpub() implementation of the
 /// [`VtpmInterface`]
 pub fn vtpm_get_manifest() -> Result<Vec<u8>, SvsmReqError> {
-    let mut vtpm = VTPM.lock();
-    vtpm.get_ekpub()
+    with_vtpm(|vtpm| vtpm.get_ekpub())
 }
@@ -11,8 +11,7 @@
 #[cfg(target_os = "none")]
 mod wrapper;
 
-pub mod ek_templates;
-mod tss;
+mod nv;
 
 extern crate alloc;
 
@@ -20,17 +19,17 @@ use alloc::vec::Vec;
 
 use core::ffi::c_void;
 use libtcgtpm::bindings::{
-    _plat__LocalitySet, _plat__NVDisable, _plat__NVEnable, _plat__RunCommand, _plat__SetNvAvail,
-    _plat__Signal_PowerOn, _plat__Signal_Reset, TPM_Manufacture, TPM_TearDown,
+    _plat__ClearCancel, _plat__LocalitySet, _plat__NVDisable, _plat__NVEnable, _plat__RunCommand,
+    _plat__SetCancel, _plat__SetNvAvail, _plat__Signal_PowerOff, _plat__Signal_PowerOn,
+    _plat__Signal_Reset, TPM_Manufacture, TPM_TearDown,
 };
 
 use crate::{
     address::VirtAddr,
     protocols::{errors::SvsmReqError, vtpm::TpmPlatformCommand},
-    types::PAGE_SIZE,
     vtpm::{
-        TcgTpmSimulatorInterface, VtpmInterface, VtpmProtocolInterface,
-        tcgtpm::ek_templates::DEFAULT_PUBLIC_AREA,
+        TPM_BUFFER_MAX_SIZE, TcgTpmSimulatorInterface, VtpmInterface, VtpmProtocolInterface,
+        ek_templates::DEFAULT_PUBLIC_AREA, tcgtpm::nv::TpmNvStorage, tss,
     },
 };
 
@@ -38,6 +37,7 @@ use crate::{
 pub struct TcgTpm {
     is_powered_on: bool,
     ekpub: Option<Vec<u8>>,
+    nv_storage: Option<TpmNvStorage>,
 }
 
 impl TcgTpm {
@@ -45,6 +45,7 @@ impl TcgTpm {
         TcgTpm {
             is_powered_on: false,
             ekpub: None,
+            nv_storage: None,
         }
     }
 
@@ -75,9 +76,71 @@ impl TcgTpm {
             }
         }
     }
+
+    /// Loads the NV memory from the block device, if there is one.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a previously saved NV state has been restored, in which
+    /// case the TPM must not be manufactured again. A stored NV state which
+    /// can not be restored is an error and is left untouched on the device.
+    fn restore_nv(&mut self) -> Result<bool, SvsmReqError> {
+        self.nv_storage = TpmNvStorage::open().map_err(|e| {
+            log::error!("vTPM: failed to open NV storage: {e:?}");
+            SvsmReqError::incomplete()
+        })?;
+
+        let Some(storage) = self.nv_storage.as_mut() else {
+            return Ok(false);
+        };
+        match storage.restore() {
+            Ok(restored) => Ok(restored),
+            Err(e) => {
+                log::error!("vTPM: failed to restore NV state: {e:?}");
+                // Never overwrite the stored state with a newly manufactured
+                // TPM.
+                self.nv_storage = None;
+                Err(SvsmReqError::incomplete())
+            }
+        }
+    }
+
+    /// Writes the NV memory back to the block device if it changed.
+    fn sync_nv(&mut self) {
+        if let Some(storage) = self.nv_storage.as_mut() {
+            if let Err(e) = storage.sync() {
+                log::error!("vTPM: failed to save NV state: {e:?}");
+            }
+        }
+    }
 }
 
-const TPM_CMDS_SUPPORTED: &[TpmPlatformCommand] = &[TpmPlatformCommand::SendCommand];
+pub(super) const TPM_CMDS_SUPPORTED: &[TpmPlatformCommand] = &[
+    TpmPlatformCommand::SignalPowerOn,
+    TpmPlatformCommand::SignalPowerOff,
+    TpmPlatformCommand::SendCommand,
+    TpmPlatformCommand::SignalCancelOn,
+    TpmPlatformCommand::SignalCancelOff,
+    TpmPlatformCommand::SignalNvOn,
+    TpmPlatformCommand::SignalReset,
+];
+
+/// Sets or clears the cancel flag, which makes a long-running TPM command
+/// abort with TPM_RC_CANCELED. This does not take the lock of the TPM, so
+/// that it reaches a command running on another vCPU.
+pub(super) fn signal_cancel(cancel: bool) {
+    // SAFETY: FFI calls. No Parameters or return values. The calls only
+    // store the cancel flag, which the TPM library polls while it runs a
+    // command. The TPM simulator sets it from its platform thread in the
+    // same way.
+    unsafe {
+        if cancel {
+            _plat__SetCancel();
+        } else {
+            _plat__ClearCancel();
+        }
+    }
+}
 
 impl VtpmProtocolInterface for TcgTpm {
     fn get_supported_commands(&self) -> &[TpmPlatformCommand] {
@@ -85,10 +148,8 @@ impl VtpmProtocolInterface for TcgTpm {
     }
 }
 
-pub const TPM_BUFFER_MAX_SIZE: usize = PAGE_SIZE;
-
 impl TcgTpmSimulatorInterface for TcgTpm {
-    fn send_tpm_command(&self, command: &[u8], locality: u8) -> Result<Vec<u8>, SvsmReqError> {
+    fn send_tpm_command(&mut self, command: &[u8], locality: u8) -> Result<Vec<u8>, SvsmReqError> {
         if !self.is_powered_on {
             return Err(SvsmReqError::invalid_request());
         }
@@ -133,6 +194,8 @@ impl TcgTpmSimulatorInterface for TcgTpm {
             response_ffi.set_len(response_ffi_size as usize);
         }
 
+        self.sync_nv();
+
         Ok(response_ffi)
     }
 
@@ -163,6 +226,18 @@ impl TcgTpmSimulatorInterface for TcgTpm {
         Ok(())
     }
 
+    fn signal_poweroff(&mut self) -> Result<(), SvsmReqError> {
+        if !self.is_powered_on {
+            return Ok(());
+        }
+        // SAFETY: FFI call. No Parameters or return values.
+        unsafe { _plat__Signal_PowerOff() };
+        self.is_powered_on = false;
+        self.sync_nv();
+
+        Ok(())
+    }
+
     fn signal_nvon(&self) -> Result<(), SvsmReqError> {
         if !self.is_powered_on {
             return Err(SvsmReqError::invalid_request());
@@ -193,8 +268,11 @@ impl VtpmInterface for TcgTpm {
         // 2. Make sure it does not fail if it is re-manufactured
         // 3. Teardown to indicate it needs to be manufactured
         // 4. Manufacture it for the first time
-        // 5. Power it on indicating it requires startup. By default, OVMF will start
-        //    and selftest it.
+        // 5. Power it on indicating it requires startup. vtpm_init() starts it
+        //    up before measuring the SVSM launch state, OVMF selftests it.
+        //
+        // Steps 1-4 are skipped when a previously manufactured NV state is
+        // restored from the block device.
 
         // SAFETY: FFI call. Parameters and return values are checked.
         let mut rc = unsafe { _plat__NVEnable(VirtAddr::null().as_mut_ptr::<c_void>(), 0) };
@@ -203,26 +281,29 @@ impl VtpmInterface for TcgTpm {
             return Err(SvsmReqError::incomplete());
         }
 
-        rc = self.manufacture(1)?;
-        if rc != 0 {
-            // SAFETY: FFI call. Parameter checked, no return value.
-            unsafe { _plat__NVDisable(core::ptr::without_provenance_mut::<c_void>(1), 0) };
-            return Err(SvsmReqError::incomplete());
-        }
+        if !self.restore_nv()? {
+            rc = self.manufacture(1)?;
+            if rc != 0 {
+                // SAFETY: FFI call. Parameter checked, no return value.
+                unsafe { _plat__NVDisable(core::ptr::without_provenance_mut::<c_void>(1), 0) };
+                return Err(SvsmReqError::incomplete());
+            }
 
-        rc = self.manufacture(0)?;
-        if rc != 1 {
-            return Err(SvsmReqError::incomplete());
-        }
+            rc = self.manufacture(0)?;
+            if rc != 1 {
+                return Err(SvsmReqError::incomplete());
+            }
 
-        self.teardown()?;
-        rc = self.manufacture(1)?;
-        if rc != 0 {
-            return Err(SvsmReqError::incomplete());
+            self.teardown()?;
+            rc = self.manufacture(1)?;
+            if rc != 0 {
+                return Err(SvsmReqError::incomplete());
+            }
         }
 
         self.signal_poweron(false)?;
         self.signal_nvon()?;
+        self.sync_nv();
 
         log::info!("VTPM: TPM 2.0 Reference Implementation initialized");
 
@@ -0,0 +1,117 @@
+// SPDX-License-Identifier: MIT
+//
+
+//! Persistence of the TPM 2.0 Reference Implementation NV memory.
+//!
+//! The reference implementation keeps its whole NV state in the `s_NV`
+//! array. This module loads that array from the block device before the TPM
+//! is powered on and writes it back whenever a command modified it.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use libtcgtpm::bindings::s_NV;
+
+use crate::block::nvstore::NvStore;
+use crate::block::{VTPM_NV_REGION_OFFSET, VTPM_NV_REGION_SIZE, secure_storage};
+use crate::error::SvsmError;
+
+/// Returns the NV memory of the TPM.
+///
+/// # Safety
+///
+/// The caller must ensure that the TPM library is not executing while the
+/// returned slice is alive. This is guaranteed for callers holding the vTPM
+/// lock.
+unsafe fn nv_memory() -> &'static mut [u8] {
+    let nv = &raw mut s_NV;
+    // SAFETY: `s_NV` is a plain byte array which is only accessed by the TPM
+    // library, which the caller guarantees to not be running.
+    unsafe { &mut *nv }
+}
+
+#[derive(Debug, Clone)]
+pub struct TpmNvStorage {
+    store: NvStore,
+    /// Copy of the NV memory as it was last written to the device.
+    shadow: Vec<u8>,
+}
+
+impl TpmNvStorage {
+    /// Opens the vTPM NV region of the encrypted block device.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if no persistent storage is available, the storage on success,
+    /// or an error if the region can not be used.
+    pub fn open() -> Result<Option<Self>, SvsmError> {
+        let Some(dev) = secure_storage() else {
+            return Ok(None);
+        };
+
+        let store = NvStore::open(dev, VTPM_NV_REGION_OFFSET, VTPM_NV_REGION_SIZE)?;
+        // SAFETY: the TPM is not running while it is being initialized.
+        let nv_size = unsafe { nv_memory() }.len();
+        if store.capacity() < nv_size {
+            log::error!(
+                "vTPM NV region too small: {} < {nv_size} bytes",
+                store.capacity()
+            );
+            return Err(SvsmError::InvalidParameter);
+        }
+
+        Ok(Some(Self {
+            store,
+            shadow: Vec::new(),
+        }))
+    }
+
+    /// Restores the NV memory from the device.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(true)` if a previously saved NV image has been restored,
+    /// `Ok(false)` if the device does not hold one yet, or an error if the
+    /// stored image does not match the NV layout of this TPM. The image is
+    /// never replaced in that case, so the TPM keys it holds are kept.
+    pub fn restore(&mut self) -> Result<bool, SvsmError> {
+        let Some(image) = self.store.load()? else {
+            return Ok(false);
+        };
+
+        // SAFETY: the TPM is not running while it is being initialized.
+        let nv = unsafe { nv_memory() };
+        if image.len() != nv.len() {
+            log::error!(
+                "Stored vTPM NV image has unexpected size {} != {} bytes",
+                image.len(),
+                nv.len()
+            );
+            return Err(SvsmError::InvalidFormat);
+        }
+        nv.copy_from_slice(&image);
+        self.shadow = image;
+
+        log::info!(
+            "vTPM: restored NV state (generation {})",
+            self.store.generation()
+        );
+        Ok(true)
+    }
+
+    /// Writes the NV memory to the device if it changed since it was last
+    /// saved.
+    pub fn sync(&mut self) -> Result<(), SvsmError> {
+        // SAFETY: the caller holds the vTPM lock, so no TPM command is running.
+        let nv = unsafe { nv_memory() };
+        if self.shadow.as_slice() == &nv[..] {
+            return Ok(());
+        }
+
+        self.store.store(nv)?;
+        self.shadow.clear();
+        self.shadow.extend_from_slice(nv);
+
+        Ok(())
+    }
+}
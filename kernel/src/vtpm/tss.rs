@@ -10,13 +10,15 @@
 extern crate alloc;
 
 use crate::protocols::errors::SvsmReqError;
-use crate::vtpm::{
-    SvsmVTpmError,
-    tcgtpm::{TPM_BUFFER_MAX_SIZE, TcgTpmSimulatorInterface},
-};
+use crate::tcg::{TpmDigest, parse_pcr_event_response};
+use crate::vtpm::{SvsmVTpmError, TPM_BUFFER_MAX_SIZE, TcgTpmSimulatorInterface};
 use alloc::vec::Vec;
 
 pub const TPM_RC_SUCCESS: u32 = 0;
+pub const TPM_RC_INITIALIZE: u32 = 0x100;
+
+/// Maximum size of the event data of TPM2_PCR_Event (TPM2B_EVENT)
+pub const TPM_MAX_EVENT_SIZE: usize = 1024;
 
 // PREREQUISITE: CMD must be at least 10 bytes long.
 // A TPM command result contains
@@ -97,8 +99,8 @@ fn create_mtauth_ek_cmd(tpmt_public: &[u8]) -> Vec<u8> {
 /// Returns:
 ///
 /// The command response on success, or an error.
-pub fn checked_send<T: TcgTpmSimulatorInterface>(
-    vtpm: &T,
+pub fn checked_send<T: TcgTpmSimulatorInterface + ?Sized>(
+    vtpm: &mut T,
     cmd: &mut [u8],
     set_len: bool,
 ) -> Result<Vec<u8>, SvsmVTpmError> {
@@ -131,8 +133,8 @@ pub fn checked_send<T: TcgTpmSimulatorInterface>(
 /// Returns:
 ///
 /// A TPMT_PUBLIC of the key created from the template.
-pub fn create_ek<T: TcgTpmSimulatorInterface>(
-    vtpm: &T,
+pub fn create_ek<T: TcgTpmSimulatorInterface + ?Sized>(
+    vtpm: &mut T,
     tpmt_public: &[u8],
 ) -> Result<Vec<u8>, SvsmVTpmError> {
     let mut cmd = create_mtauth_ek_cmd(tpmt_public);
@@ -144,3 +146,73 @@ pub fn create_ek<T: TcgTpmSimulatorInterface>(
     let size_of_tpmt_public = u16::from_be_bytes([response[18], response[19]]) as usize;
     Ok(response.drain(20..(20 + size_of_tpmt_public)).collect())
 }
+
+/// Uses `vtpm` to run TPM2_Startup(TPM_SU_CLEAR).
+///
+/// Arguments:
+///
+/// * `vtpm`: An implementation of [`TcgTpmSimulatorInterface`] to send the command to.
+///
+/// Returns:
+///
+/// `true` if the TPM has been started up, `false` if it had already been
+/// started up since it was last reset, or an error.
+pub fn startup<T: TcgTpmSimulatorInterface + ?Sized>(vtpm: &mut T) -> Result<bool, SvsmVTpmError> {
+    let mut cmd = [
+        0x80, 0x01, // TPM_ST_NO_SESSIONS
+        0x00, 0x00, 0x00, 0x00, // Placeholder for command size
+        0x00, 0x00, 0x01, 0x44, // TPM_CC_STARTUP
+        0x00, 0x00, // TPM_SU_CLEAR
+    ];
+
+    match checked_send(vtpm, &mut cmd, /*set_len=*/ true) {
+        Ok(_) => Ok(true),
+        Err(SvsmVTpmError::CommandError(TPM_RC_INITIALIZE)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Uses `vtpm` to run TPM2_PCR_Event, which extends every PCR bank of
+/// `pcr` with the digest of `event_data` computed with the hash algorithm
+/// of the bank.
+///
+/// Arguments:
+///
+/// * `vtpm`: An implementation of [`TcgTpmSimulatorInterface`] to send the command to.
+/// * `pcr`: Index of the PCR to extend.
+/// * `event_data`: Data to measure, at most [`TPM_MAX_EVENT_SIZE`] bytes.
+///
+/// Returns:
+///
+/// The digests of `event_data` the banks have been extended with.
+pub fn pcr_event<T: TcgTpmSimulatorInterface + ?Sized>(
+    vtpm: &mut T,
+    pcr: u32,
+    event_data: &[u8],
+) -> Result<Vec<TpmDigest>, SvsmVTpmError> {
+    if event_data.len() > TPM_MAX_EVENT_SIZE {
+        return Err(SvsmVTpmError::ReqError(SvsmReqError::invalid_parameter()));
+    }
+
+    let mut cmd = Vec::<u8>::with_capacity(TPM_BUFFER_MAX_SIZE);
+
+    // TPM Command header
+    cmd.extend_from_slice(&[
+        0x80, 0x02, // TPM_ST_SESSIONS
+        0x00, 0x00, 0x00, 0x00, // Placeholder for command size
+        0x00, 0x00, 0x01, 0x3C, // TPM_CC_PCR_EVENT
+    ]);
+    // pcrHandle
+    cmd.extend_from_slice(&pcr.to_be_bytes());
+
+    // Authorization block
+    extend_empty_auth(&mut cmd);
+
+    // eventData parameter (TPM2B_EVENT)
+    cmd.extend_from_slice(&(event_data.len() as u16).to_be_bytes());
+    cmd.extend_from_slice(event_data);
+
+    let response = checked_send(vtpm, &mut cmd, /*set_len=*/ true)?;
+    parse_pcr_event_response(&response)
+        .ok_or(SvsmVTpmError::ReqError(SvsmReqError::invalid_request()))
+}
@@ -0,0 +1,374 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! vTPM backend relaying TPM commands to the TPM 2.0 Reference Implementation
+//! running as the user-mode module [`VTPM_MODULE`].
+//!
+//! The kernel keeps handling the vTPM protocol and only passes the platform
+//! commands and TPM command buffers to the module, so a memory-safety bug in
+//! the TPM code is contained in that process. Each platform command is sent
+//! over the command channel of the module together with one end of a new
+//! reply channel, on which the module sends the TPM response or acknowledges
+//! the command.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+use syscall::{ChannelEvents, VtpmCommandHeader};
+use zerocopy::IntoBytes;
+
+use crate::error::SvsmError;
+use crate::fs::{DirEntry, Directory, FsError, RamDirectory, persistent_private_dir};
+use crate::ipc::{ChannelError, ChannelObj, Message};
+use crate::locking::{Mutex, MutexGuard, SpinLock};
+use crate::protocols::{errors::SvsmReqError, vtpm::TpmPlatformCommand};
+use crate::syscall::ObjError;
+use crate::task::{
+    KernelThreadStartInfo, TaskPointer, exec_user, start_kernel_task, wait_for_termination,
+};
+use crate::time::{monotonic, sleep};
+use crate::vtpm::{
+    TPM_BUFFER_MAX_SIZE, TcgTpmSimulatorInterface, VtpmInterface, VtpmProtocolInterface,
+    ek_templates::DEFAULT_PUBLIC_AREA, tss,
+};
+
+/// Path of the user-mode vTPM module in the SVSM filesystem.
+pub const VTPM_MODULE: &str = "/vtpm";
+
+/// File in the root directory of the module holding its NV memory.
+const NV_FILE: &str = "nv";
+
+/// Time the module has to answer a platform command. Creating primary keys
+/// takes the longest, but never more than a few seconds.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time the module has to register after it has been started.
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval in which [`wait_for_registration()`] checks the module.
+const REGISTER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+struct UserVtpmState {
+    /// Kernel end of the command channel, set once the module is started.
+    channel: Option<Arc<ChannelObj>>,
+    /// Module end of the command channel, until the module registers or
+    /// exits.
+    pending: Option<ChannelObj>,
+    /// Main task of the module, until the task watching it picks it up.
+    module: Option<TaskPointer>,
+    /// Cached EK public key.
+    ekpub: Option<Vec<u8>>,
+}
+
+static STATE: SpinLock<UserVtpmState> = SpinLock::new(UserVtpmState {
+    channel: None,
+    pending: None,
+    module: None,
+    ekpub: None,
+});
+
+/// Serializes the platform commands sent to the module, like the lock of the
+/// in-kernel TPM does. Waiting for the module blocks the task, so a spin lock
+/// can not be used.
+static COMMAND_LOCK: Mutex<()> = Mutex::new(());
+
+/// Waits until no other task sends platform commands to the module.
+///
+/// # Returns
+///
+/// A guard which allows the caller to send platform commands until it is
+/// dropped.
+pub fn lock_commands() -> MutexGuard<'static, ()> {
+    COMMAND_LOCK.lock()
+}
+
+/// Waits for the module to exit. If it has not registered by then, its end
+/// of the command channel is dropped, so commands fail instead of waiting
+/// for a reply which never comes.
+fn watch_module(_: usize) {
+    let Some(module) = STATE.lock().module.take() else {
+        return;
+    };
+    wait_for_termination(module);
+
+    let pending = STATE.lock().pending.take();
+    if pending.is_some() {
+        log::error!("vTPM: {VTPM_MODULE} exited before registering");
+    } else {
+        log::error!("vTPM: {VTPM_MODULE} exited");
+    }
+    // Dropping the channel end can wake up tasks, so do it without holding
+    // the lock.
+    drop(pending);
+}
+
+/// Returns the root directory of the module. It is a private directory of the
+/// persistent filesystem, so that no other process can access the NV memory
+/// of the TPM, or an empty directory if there is no persistent storage.
+fn module_root() -> Result<Arc<dyn Directory>, SvsmError> {
+    let Some(dir) = persistent_private_dir("vtpm")? else {
+        return Ok(Arc::new(RamDirectory::new()));
+    };
+
+    // The module does not create the NV file itself, so that it can tell
+    // whether its NV memory is persistent.
+    match dir.lookup_entry(&String::from(NV_FILE)) {
+        Ok(DirEntry::File(_)) => (),
+        Ok(DirEntry::Directory(_)) => return Err(SvsmError::FileSystem(FsError::is_dir())),
+        Err(SvsmError::FileSystem(FsError::FileNotFound)) => {
+            dir.create_file(String::from(NV_FILE))?;
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(dir)
+}
+
+/// Starts the user-mode vTPM module. Commands sent before the module has
+/// registered are queued on the command channel.
+///
+/// # Returns
+///
+/// `Ok(true)` if the module has been started, `Ok(false)` if the filesystem
+/// does not contain the module, or an error if it failed to start.
+pub fn start() -> Result<bool, SvsmReqError> {
+    {
+        let mut state = STATE.lock();
+        if state.channel.is_some() {
+            return Ok(true);
+        }
+        // The module may register before exec_user() returns.
+        let (channel, pending) = ChannelObj::new_pair();
+        state.channel = Some(Arc::new(channel));
+        state.pending = Some(pending);
+    }
+
+    let result = module_root().and_then(|root| exec_user(VTPM_MODULE, root, &[VTPM_MODULE], &[]));
+    let result = result.and_then(|module| {
+        STATE.lock().module = Some(module);
+        start_kernel_task(
+            KernelThreadStartInfo::new(watch_module, 0),
+            String::from("vtpm-watch"),
+        )
+    });
+    if let Err(e) = result {
+        let (channel, pending, module) = {
+            let mut state = STATE.lock();
+            (
+                state.channel.take(),
+                state.pending.take(),
+                state.module.take(),
+            )
+        };
+        // Dropping the channel ends can wake up tasks, so do it without
+        // holding the lock.
+        drop((channel, pending, module));
+
+        if matches!(e, SvsmError::FileSystem(FsError::FileNotFound)) {
+            return Ok(false);
+        }
+        log::error!("vTPM: failed to launch {VTPM_MODULE}: {e:?}");
+        return Err(SvsmReqError::incomplete());
+    }
+
+    log::info!("VTPM: started user-mode TPM 2.0 Reference Implementation");
+    Ok(true)
+}
+
+/// Waits until the started module has registered or exited, so that the
+/// boot does not wait for the reply to a command which the module never
+/// receives.
+///
+/// # Returns
+///
+/// `Ok(())` once the module has registered or exited, or an error if it did
+/// not within [`REGISTER_TIMEOUT`] or the clocks needed to bound the wait
+/// are not available.
+pub fn wait_for_registration() -> Result<(), SvsmReqError> {
+    let deadline = monotonic() + REGISTER_TIMEOUT;
+    while STATE.lock().pending.is_some() {
+        if monotonic() >= deadline {
+            log::error!("vTPM: {VTPM_MODULE} did not register");
+            return Err(SvsmReqError::incomplete());
+        }
+        sleep(REGISTER_POLL_INTERVAL).map_err(|e| {
+            log::error!("vTPM: can not wait for {VTPM_MODULE} to register: {e:?}");
+            SvsmReqError::incomplete()
+        })?;
+    }
+    Ok(())
+}
+
+/// Returns whether the user-mode vTPM module has been started.
+pub fn is_started() -> bool {
+    STATE.lock().channel.is_some()
+}
+
+/// Hands the module end of the command channel to the calling process.
+///
+/// # Returns
+///
+/// The command channel, or an error if the user-mode vTPM is not used or the
+/// module has already registered.
+pub fn register() -> Result<ChannelObj, SvsmError> {
+    let mut state = STATE.lock();
+    if state.channel.is_none() {
+        return Err(SvsmError::NotSupported);
+    }
+    let channel = state.pending.take().ok_or(ObjError::Busy)?;
+    log::info!("VTPM: user-mode TPM registered");
+    Ok(channel)
+}
+
+/// Handle of the user-mode vTPM module.
+#[derive(Debug, Clone, Copy)]
+pub struct UserTpm;
+
+pub(super) const TPM_CMDS_SUPPORTED: &[TpmPlatformCommand] = &[
+    TpmPlatformCommand::SignalPowerOn,
+    TpmPlatformCommand::SignalPowerOff,
+    TpmPlatformCommand::SendCommand,
+    TpmPlatformCommand::SignalCancelOn,
+    TpmPlatformCommand::SignalCancelOff,
+    TpmPlatformCommand::SignalNvOn,
+    TpmPlatformCommand::SignalReset,
+];
+
+/// Relays the platform command `command` to the module. `payload` holds the
+/// TPM command of a [`TpmPlatformCommand::SendCommand`] and is empty for all
+/// other platform commands.
+///
+/// # Returns
+///
+/// The reply of the module, or an error if the module failed to run the
+/// command.
+fn relay_command(
+    command: TpmPlatformCommand,
+    locality: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>, SvsmReqError> {
+    let channel = STATE
+        .lock()
+        .channel
+        .clone()
+        .ok_or_else(SvsmReqError::invalid_request)?;
+
+    let header = VtpmCommandHeader {
+        command: command as u32,
+        locality: locality.into(),
+    };
+    let mut data = Vec::with_capacity(size_of::<VtpmCommandHeader>() + payload.len());
+    data.extend_from_slice(header.as_bytes());
+    data.extend_from_slice(payload);
+
+    let (reply_channel, module_reply_channel) = ChannelObj::new_pair();
+    let msg = Message {
+        data,
+        objs: vec![Arc::new(module_reply_channel)],
+    };
+
+    // All vCPUs share the command channel, so wait for room in its queue.
+    channel.poll_timeout(ChannelEvents::WRITABLE, REPLY_TIMEOUT);
+    match channel.send(msg) {
+        Ok(()) => {}
+        Err(SvsmError::Channel(ChannelError::Full)) => return Err(SvsmReqError::busy()),
+        Err(e) => {
+            log::error!("vTPM: failed to relay TPM command: {e:?}");
+            return Err(SvsmReqError::incomplete());
+        }
+    }
+
+    if reply_channel
+        .poll_timeout(ChannelEvents::READABLE, REPLY_TIMEOUT)
+        .is_empty()
+    {
+        log::error!("vTPM: TPM command timed out");
+        return Err(SvsmReqError::incomplete());
+    }
+    let response = reply_channel
+        .recv(TPM_BUFFER_MAX_SIZE, 0)
+        .map_err(|e| {
+            log::error!("vTPM: no response to TPM command: {e:?}");
+            SvsmReqError::incomplete()
+        })?
+        .data;
+    // An empty response indicates that the module failed to run the
+    // command.
+    if response.is_empty() {
+        return Err(SvsmReqError::invalid_request());
+    }
+
+    Ok(response)
+}
+
+/// Sets or clears the cancel flag of the module. This does not take
+/// [`COMMAND_LOCK`], so that it reaches a command the module is running for
+/// another vCPU. The module handles it without waiting for that command.
+pub fn signal_cancel(cancel: bool) -> Result<(), SvsmReqError> {
+    let signal = if cancel {
+        TpmPlatformCommand::SignalCancelOn
+    } else {
+        TpmPlatformCommand::SignalCancelOff
+    };
+    relay_command(signal, 0, &[]).map(|_| ())
+}
+
+impl VtpmProtocolInterface for UserTpm {
+    fn get_supported_commands(&self) -> &[TpmPlatformCommand] {
+        TPM_CMDS_SUPPORTED
+    }
+}
+
+impl TcgTpmSimulatorInterface for UserTpm {
+    fn send_tpm_command(&mut self, command: &[u8], locality: u8) -> Result<Vec<u8>, SvsmReqError> {
+        if command.len() > TPM_BUFFER_MAX_SIZE - size_of::<VtpmCommandHeader>() {
+            return Err(SvsmReqError::invalid_parameter());
+        }
+        relay_command(TpmPlatformCommand::SendCommand, locality, command)
+    }
+
+    fn signal_poweron(&mut self, only_reset: bool) -> Result<(), SvsmReqError> {
+        let signal = if only_reset {
+            TpmPlatformCommand::SignalReset
+        } else {
+            TpmPlatformCommand::SignalPowerOn
+        };
+        relay_command(signal, 0, &[]).map(|_| ())
+    }
+
+    fn signal_poweroff(&mut self) -> Result<(), SvsmReqError> {
+        relay_command(TpmPlatformCommand::SignalPowerOff, 0, &[]).map(|_| ())
+    }
+
+    fn signal_nvon(&self) -> Result<(), SvsmReqError> {
+        relay_command(TpmPlatformCommand::SignalNvOn, 0, &[]).map(|_| ())
+    }
+}
+
+impl VtpmInterface for UserTpm {
+    fn get_ekpub(&mut self) -> Result<Vec<u8>, SvsmReqError> {
+        if let Some(ekpub) = STATE.lock().ekpub.clone() {
+            return Ok(ekpub);
+        }
+        // Do not hold the lock while waiting for the module.
+        let ekpub = tss::create_ek(self, &DEFAULT_PUBLIC_AREA[..])?;
+        STATE.lock().ekpub = Some(ekpub.clone());
+        Ok(ekpub)
+    }
+
+    fn is_powered_on(&self) -> bool {
+        is_started()
+    }
+
+    fn init(&mut self) -> Result<(), SvsmReqError> {
+        if !is_started() {
+            log::error!("vTPM: {VTPM_MODULE} not found");
+            return Err(SvsmReqError::incomplete());
+        }
+        Ok(())
+    }
+}
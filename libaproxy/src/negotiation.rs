@@ -18,6 +18,10 @@ pub struct NegotiationRequest {
     /// Version of the attestation protocol, represented as semver (MAJOR.MINOR.PATCH).
     pub version: (u32, u32, u32),
     pub tee: kbs_types::Tee,
+    /// Version of the persistent state that SVSM is about to unlock, if it keeps any. Servers
+    /// providing rollback protection request it with [`NegotiationParam::StateVersion`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_version: Option<u64>,
 }
 
 /// A parameter that must be hashed into the negotiation hash.
@@ -27,6 +31,13 @@ pub enum NegotiationParam {
     Challenge,
     /// Hash the EC public key's `Elliptic-Curve-Point-to-Octet-String` encoding.
     EcPublicKeyBytes,
+    /// Hash the state version from the negotiation request, as a little-endian 64-bit integer.
+    ///
+    /// A server requesting this parameter provides rollback protection: it must only release
+    /// the secret if the version is not lower than the one it recorded for the guest, and then
+    /// record this version. SVSM increments the version before it modifies the state, so an
+    /// older state is refused once the guest attested again.
+    StateVersion,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -33,11 +33,15 @@ fn main() {
         .allowlist_function("_plat__LocalitySet")
         .allowlist_function("_plat__SetNvAvail")
         .allowlist_function("_plat__Signal_PowerOn")
+        .allowlist_function("_plat__Signal_PowerOff")
         .allowlist_function("_plat__Signal_Reset")
+        .allowlist_function("_plat__SetCancel")
+        .allowlist_function("_plat__ClearCancel")
         .allowlist_function("_plat__NVDisable")
         .allowlist_function("_plat__NVEnable")
         .allowlist_function("TPM_Manufacture")
         .allowlist_function("TPM_TearDown")
+        .allowlist_var("s_NV")
         .use_core()
         .clang_arg("-Wno-incompatible-library-redeclaration")
         .clang_arg("-nostdinc")
@@ -79,6 +79,9 @@ pub enum SysCallError {
     EEXIST = -9,
     ERDONLY = -10,
     EWRONLY = -11,
+    ENOSPC = -12,
+    EAGAIN = -13,
+    EPIPE = -14,
     UNKNOWN = -128,
 }
 
@@ -96,6 +99,9 @@ impl From<i32> for SysCallError {
             -9 => SysCallError::EEXIST,
             -10 => SysCallError::ERDONLY,
             -11 => SysCallError::EWRONLY,
+            -12 => SysCallError::ENOSPC,
+            -13 => SysCallError::EAGAIN,
+            -14 => SysCallError::EPIPE,
             _ => SysCallError::UNKNOWN,
         }
     }
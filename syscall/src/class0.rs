@@ -4,10 +4,18 @@
 //
 // Author: Chuanxiao Dong <chuanxiao.dong@intel.com>
 
-use super::call::{SysCallError, syscall1, syscall3};
-use super::{SYS_EXEC, SYS_EXIT};
-use core::ffi::CStr;
+use super::call::{SysCallError, syscall1, syscall2, syscall4, syscall5};
+use super::{
+    ClockId, FsObjHandle, MMapFlags, Obj, SYS_CLOCK_GETTIME, SYS_EXEC, SYS_EXIT, SYS_FUTEX_WAIT,
+    SYS_FUTEX_WAKE, SYS_MMAP, SYS_MUNMAP, SYS_SLEEP, SYS_THREAD_CREATE, SYS_WAIT,
+    THREAD_CPU_CURRENT,
+};
+use core::ffi::{CStr, c_char};
+use core::sync::atomic::AtomicU32;
+use core::time::Duration;
 
+/// Terminates the calling thread with the exit code `code`. Other threads of
+/// the process keep running.
 pub fn exit(code: u32) -> ! {
     // SAFETY: SYS_EXIT is supported syscall number by the svsm kernel.
     unsafe {
@@ -16,24 +24,193 @@ pub fn exit(code: u32) -> ! {
     unreachable!("Should never return from SYS_EXIT syscall");
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Tid(u32);
 
-pub fn exec(file: &CStr, root: &CStr, flags: u32) -> Result<Tid, SysCallError> {
+/// Starts a new thread in the current process.
+///
+/// The thread calls `entry` with `arg` as its only argument on the stack
+/// `stack`, and runs on the CPU with the index `cpu`, or on the current CPU if
+/// it is `None`. The thread terminates by calling [`exit`] and can be joined
+/// by the calling thread with [`wait`].
+///
+/// # Safety
+///
+/// `stack` must point to the top of a memory region which is reserved for the
+/// thread for its whole lifetime, with `stack + 8` aligned to 16 bytes as if
+/// `entry` had been called. `entry` must be safe to run concurrently with the
+/// calling thread with the given argument.
+pub unsafe fn thread_create(
+    entry: extern "C" fn(usize) -> !,
+    stack: usize,
+    arg: usize,
+    cpu: Option<usize>,
+) -> Result<Tid, SysCallError> {
+    // SAFETY: SYS_THREAD_CREATE is a supported syscall number by the svsm
+    // kernel. The caller guarantees that the stack and the entry point are
+    // valid for the new thread.
+    unsafe {
+        syscall4(
+            SYS_THREAD_CREATE,
+            entry as usize as u64,
+            stack as u64,
+            arg as u64,
+            cpu.unwrap_or(THREAD_CPU_CURRENT) as u64,
+        )
+        .map(|ret| Tid(ret as u32))
+    }
+}
+
+/// Exit code of processes which terminated without calling [`exit`], for
+/// example because of a fault.
+pub const EXIT_ABNORMAL: u32 = u32::MAX;
+
+/// Waits for a process started with [`exec`] or a thread started with
+/// [`thread_create`] by the calling thread to terminate. Every process or
+/// thread can only be waited for once.
+///
+/// Returns the exit code of the process or thread, or [`EXIT_ABNORMAL`].
+pub fn wait(tid: Tid) -> Result<u32, SysCallError> {
+    // SAFETY: SYS_WAIT is a supported syscall number by the svsm kernel.
+    unsafe { syscall1(SYS_WAIT, u64::from(tid.0)).map(|ret| ret as u32) }
+}
+
+/// Blocks the calling thread until another thread of the process calls
+/// [`futex_wake`] on `futex`, provided that `futex` still holds `expected`.
+///
+/// Returns [`SysCallError::EAGAIN`] without blocking if the value of `futex`
+/// differs from `expected`. Callers must be prepared for spurious wakeups and
+/// re-check the condition they are waiting for.
+pub fn futex_wait(futex: &AtomicU32, expected: u32) -> Result<(), SysCallError> {
+    // SAFETY: SYS_FUTEX_WAIT is a supported syscall number by the svsm
+    // kernel. The kernel only reads from the futex.
+    unsafe { syscall2(SYS_FUTEX_WAIT, futex.as_ptr() as u64, u64::from(expected)).map(|_| ()) }
+}
+
+/// Wakes up to `count` threads blocked in [`futex_wait`] on `futex`.
+///
+/// Returns the number of threads that have been woken.
+pub fn futex_wake(futex: &AtomicU32, count: u32) -> Result<u32, SysCallError> {
+    // SAFETY: SYS_FUTEX_WAKE is a supported syscall number by the svsm
+    // kernel. The kernel does not access the futex.
+    unsafe {
+        syscall2(SYS_FUTEX_WAKE, futex.as_ptr() as u64, u64::from(count)).map(|ret| ret as u32)
+    }
+}
+
+/// Reads the clock `clock`.
+///
+/// Returns [`SysCallError::ENOTSUPP`] if the clock is not available.
+pub fn clock_gettime(clock: ClockId) -> Result<Duration, SysCallError> {
+    // SAFETY: SYS_CLOCK_GETTIME is a supported syscall number by the svsm
+    // kernel.
+    unsafe { syscall1(SYS_CLOCK_GETTIME, clock as u64).map(Duration::from_nanos) }
+}
+
+/// Blocks the calling thread for at least `duration`.
+///
+/// Returns [`SysCallError::ENOTSUPP`] if the kernel has no clock.
+pub fn sleep(duration: Duration) -> Result<(), SysCallError> {
+    let nsecs = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+    // SAFETY: SYS_SLEEP is a supported syscall number by the svsm kernel.
+    unsafe { syscall1(SYS_SLEEP, nsecs).map(|_| ()) }
+}
+
+/// Starts the binary `file` in a new process with the root directory `root`.
+///
+/// `argv` and `envp` are the arguments and the environment of the new
+/// process. Both must be terminated by a NULL pointer, otherwise
+/// [`SysCallError::EINVAL`] is returned. The kernel copies them to the stack
+/// of the new process, where `userlib` makes them available to `main`.
+pub fn exec(
+    file: &CStr,
+    root: &CStr,
+    argv: &[*const c_char],
+    envp: &[*const c_char],
+    flags: u32,
+) -> Result<Tid, SysCallError> {
+    if argv.last().is_none_or(|p| !p.is_null()) || envp.last().is_none_or(|p| !p.is_null()) {
+        return Err(SysCallError::EINVAL);
+    }
     // SAFETY:
     // 1. SYS_EXEC is a supported syscall number by the svsm kernel.
-    // 2. Parameters `file.as_ptr()` and `root.as_ptr()` are passed as raw pointers.
-    // but the function `sys_exec` which this function delegates to, performs the
-    // necessary checks.
+    // 2. Parameters `file.as_ptr()`, `root.as_ptr()`, `argv.as_ptr()` and
+    // `envp.as_ptr()` are passed as raw pointers, but the function `sys_exec`
+    // which this function delegates to, performs the necessary checks.
     // 3. Currently `flags` parameter is unused.
     unsafe {
-        syscall3(
+        syscall5(
             SYS_EXEC,
             file.as_ptr() as u64,
             root.as_ptr() as u64,
             u64::from(flags),
+            argv.as_ptr() as u64,
+            envp.as_ptr() as u64,
         )
         .map(|ret| Tid(ret as u32))
     }
 }
+
+/// Maps zero-filled memory into the address space of the process.
+///
+/// Without [`MMapFlags::FIXED`], `addr` is a hint where to start searching
+/// for free address space and may be 0. The size is rounded up to whole
+/// pages.
+///
+/// Returns the address of the mapping.
+pub fn mmap_anon(addr: usize, size: usize, flags: MMapFlags) -> Result<usize, SysCallError> {
+    let flags = flags | MMapFlags::ANONYMOUS;
+    // SAFETY: SYS_MMAP is a supported syscall number by the svsm kernel. The
+    // new mapping does not overlap existing memory of the process.
+    unsafe {
+        syscall5(
+            SYS_MMAP,
+            addr as u64,
+            size as u64,
+            flags.bits() as u64,
+            0,
+            0,
+        )
+        .map(|ret| ret as usize)
+    }
+}
+
+/// Maps `size` bytes of a file starting at the page-aligned `offset` into
+/// the address space of the process. See [`mmap_anon`] for `addr`.
+///
+/// Returns the address of the mapping.
+pub fn mmap_file(
+    file: &FsObjHandle,
+    offset: usize,
+    addr: usize,
+    size: usize,
+    flags: MMapFlags,
+) -> Result<usize, SysCallError> {
+    let flags = flags - MMapFlags::ANONYMOUS;
+    // SAFETY: SYS_MMAP is a supported syscall number by the svsm kernel. The
+    // new mapping does not overlap existing memory of the process.
+    unsafe {
+        syscall5(
+            SYS_MMAP,
+            addr as u64,
+            size as u64,
+            flags.bits() as u64,
+            file.id().into(),
+            offset as u64,
+        )
+        .map(|ret| ret as usize)
+    }
+}
+
+/// Removes the mapping starting at `addr`, which must have been returned by
+/// [`mmap_anon`] or [`mmap_file`].
+///
+/// # Safety
+///
+/// The caller must ensure that no references into the mapping are used
+/// after it has been removed.
+pub unsafe fn munmap(addr: usize) -> Result<(), SysCallError> {
+    // SAFETY: SYS_MUNMAP is a supported syscall number by the svsm kernel.
+    // The caller guarantees that the memory is no longer in use.
+    unsafe { syscall1(SYS_MUNMAP, addr as u64).map(|_| ()) }
+}
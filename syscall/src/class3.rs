@@ -4,11 +4,138 @@
 //
 // Author: Chuanxiao Dong <chuanxiao.dong@intel.com>
 
-use super::SYS_CAPABILITIES;
-use super::call::{SysCallError, syscall1};
+use super::call::{SysCallError, syscall0, syscall1, syscall4};
+use super::{
+    ChannelEvents, ChannelHandle, Obj, ObjHandle, ProtocolReply, ProtocolRequest, SYS_CAPABILITIES,
+    SYS_GUEST_READ, SYS_GUEST_WRITE, SYS_PROTOCOL_REGISTER, SYS_VTPM_REGISTER, channel_poll,
+    channel_recv, channel_send,
+};
+use zerocopy::IntoBytes;
 
 pub fn capabilities(index: u32) -> Result<u64, SysCallError> {
     // SAFETY: Invokes a system call and does not directly change any memory of
     // the process.
     unsafe { syscall1(SYS_CAPABILITIES, index.into()) }
 }
+
+/// Handle of a user-mode handler of an SVSM protocol, created by
+/// [`protocol_register`]. The handle is one end of the channel on which the
+/// kernel forwards the requests of the protocol.
+#[derive(Debug)]
+pub struct ProtocolHandle(ChannelHandle);
+
+impl ProtocolHandle {
+    /// Returns the channel on which requests arrive, for use with
+    /// [`channel_poll`].
+    pub fn channel(&self) -> &ChannelHandle {
+        &self.0
+    }
+}
+
+impl Obj for ProtocolHandle {
+    fn id(&self) -> u32 {
+        self.0.id()
+    }
+}
+
+/// Registers the calling process as the handler of the SVSM protocol
+/// `protocol`. Requests of the protocol are then forwarded to the process
+/// instead of being handled by the kernel, until the handle is closed.
+///
+/// Requests which are not answered within a kernel-defined timeout fail
+/// with the SVSM `BUSY` result.
+///
+/// Returns [`SysCallError::EPERM`] if the process was not started by the
+/// kernel, [`SysCallError::EBUSY`] if another process handles the protocol
+/// and [`SysCallError::ENOTSUPP`] if the protocol must be handled by the
+/// kernel.
+pub fn protocol_register(protocol: u32) -> Result<ProtocolHandle, SysCallError> {
+    // SAFETY: SYS_PROTOCOL_REGISTER is a supported syscall number by the svsm
+    // kernel. It does not access the memory of the process.
+    unsafe {
+        syscall1(SYS_PROTOCOL_REGISTER, protocol.into())
+            .map(|ret| ProtocolHandle(ChannelHandle::from(ObjHandle::new(ret as u32))))
+    }
+}
+
+/// Waits for the next request forwarded to the protocol handler `handler`.
+///
+/// Returns the request and the channel on which the reply must be sent with
+/// [`protocol_reply`]. [`SysCallError::EPIPE`] is returned if the kernel
+/// stopped forwarding requests.
+pub fn protocol_recv(
+    handler: &ProtocolHandle,
+) -> Result<(ProtocolRequest, ChannelHandle), SysCallError> {
+    let mut req = ProtocolRequest::default();
+    let mut handles = [None];
+    loop {
+        match channel_recv(&handler.0, req.as_mut_bytes(), &mut handles) {
+            Ok((len, 1)) if len == size_of::<ProtocolRequest>() => break,
+            Ok(_) => return Err(SysCallError::EINVAL),
+            Err(SysCallError::EAGAIN) => {
+                channel_poll(&handler.0, ChannelEvents::READABLE)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let reply = handles[0].take().ok_or(SysCallError::EINVAL)?;
+    Ok((req, ChannelHandle::from(reply)))
+}
+
+/// Completes a request received with [`protocol_recv`] by sending `reply` on
+/// the reply channel `ch`.
+pub fn protocol_reply(ch: ChannelHandle, reply: &ProtocolReply) -> Result<(), SysCallError> {
+    channel_send(&ch, reply.as_bytes(), &[])
+}
+
+/// Reads guest memory at the guest physical address `gpa` into `buf`. Only
+/// protocol handlers can access guest memory.
+pub fn guest_read(handler: &ProtocolHandle, gpa: u64, buf: &mut [u8]) -> Result<(), SysCallError> {
+    // SAFETY: SYS_GUEST_READ is a supported syscall number by the svsm
+    // kernel. The kernel only writes to `buf`.
+    unsafe {
+        syscall4(
+            SYS_GUEST_READ,
+            handler.id().into(),
+            gpa,
+            buf.as_mut_ptr() as u64,
+            buf.len() as u64,
+        )
+        .map(|_| ())
+    }
+}
+
+/// Writes `buf` to guest memory at the guest physical address `gpa`. Only
+/// protocol handlers can access guest memory.
+pub fn guest_write(handler: &ProtocolHandle, gpa: u64, buf: &[u8]) -> Result<(), SysCallError> {
+    // SAFETY: SYS_GUEST_WRITE is a supported syscall number by the svsm
+    // kernel. The kernel only reads from `buf`.
+    unsafe {
+        syscall4(
+            SYS_GUEST_WRITE,
+            handler.id().into(),
+            gpa,
+            buf.as_ptr() as u64,
+            buf.len() as u64,
+        )
+        .map(|_| ())
+    }
+}
+
+/// Registers the calling process as the user-mode vTPM started by the
+/// kernel.
+///
+/// Returns the channel on which the kernel relays platform commands. Each
+/// message consists of a [`VtpmCommandHeader`](crate::VtpmCommandHeader),
+/// followed by the TPM command for a TPM_SEND_COMMAND, and carries a channel
+/// handle on which the reply is sent. [`SysCallError::ENOTSUPP`] is returned
+/// if the kernel does not use a user-mode vTPM, [`SysCallError::EPERM`] if
+/// the process was not started by the kernel, and [`SysCallError::EBUSY`]
+/// if the vTPM is already registered.
+pub fn vtpm_register() -> Result<ChannelHandle, SysCallError> {
+    // SAFETY: SYS_VTPM_REGISTER is a supported syscall number by the svsm
+    // kernel. It does not access the memory of the process.
+    unsafe {
+        syscall0(SYS_VTPM_REGISTER).map(|ret| ChannelHandle::from(ObjHandle::new(ret as u32)))
+    }
+}
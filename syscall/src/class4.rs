@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Channels exchange bounded messages between tasks. A channel has two ends,
+//! and each message sent on one end is received on the other one. Messages
+//! can carry object handles, which are duplicated into the receiving process.
+
+use super::call::{SysCallError, syscall1, syscall2};
+use super::def::{
+    CHANNEL_HANDLES_MAX, ChannelEvents, ChannelMsg, SYS_CHANNEL_CREATE, SYS_CHANNEL_POLL,
+    SYS_CHANNEL_RECV, SYS_CHANNEL_SEND,
+};
+use super::{Obj, ObjHandle};
+
+#[derive(Debug)]
+pub struct ChannelHandle(ObjHandle);
+
+impl Obj for ChannelHandle {
+    fn id(&self) -> u32 {
+        u32::from(&self.0)
+    }
+}
+
+impl From<ObjHandle> for ChannelHandle {
+    /// Converts a handle received with [`channel_recv`] into a channel
+    /// handle. System calls fail if the object is not a channel.
+    fn from(obj: ObjHandle) -> Self {
+        Self(obj)
+    }
+}
+
+/// Creates a new channel and returns handles to both of its ends.
+pub fn channel_create() -> Result<(ChannelHandle, ChannelHandle), SysCallError> {
+    let mut ids = [0u32; 2];
+    // SAFETY: SYS_CHANNEL_CREATE is a supported syscall number by the svsm
+    // kernel. The kernel only writes the two handles to `ids`.
+    unsafe {
+        syscall1(SYS_CHANNEL_CREATE, ids.as_mut_ptr() as u64)?;
+    }
+    Ok((
+        ChannelHandle(ObjHandle::new(ids[0])),
+        ChannelHandle(ObjHandle::new(ids[1])),
+    ))
+}
+
+/// Sends `data` together with duplicates of the objects in `handles` to the
+/// other end of the channel `ch`.
+///
+/// Sending never blocks. [`SysCallError::EAGAIN`] is returned if the queue of
+/// the other end is full, and [`SysCallError::EPIPE`] if the other end has
+/// been closed. Use [`channel_poll`] to wait until a message can be sent.
+pub fn channel_send(
+    ch: &ChannelHandle,
+    data: &[u8],
+    handles: &[&dyn Obj],
+) -> Result<(), SysCallError> {
+    if handles.len() > CHANNEL_HANDLES_MAX {
+        return Err(SysCallError::EINVAL);
+    }
+    let mut ids = [0u32; CHANNEL_HANDLES_MAX];
+    for (id, handle) in ids.iter_mut().zip(handles) {
+        *id = handle.id();
+    }
+
+    let msg = ChannelMsg {
+        data: data.as_ptr() as u64,
+        data_len: data.len() as u64,
+        handles: ids.as_ptr() as u64,
+        num_handles: handles.len() as u64,
+    };
+    // SAFETY: SYS_CHANNEL_SEND is a supported syscall number by the svsm
+    // kernel. The kernel only reads from the buffers described by `msg`.
+    unsafe { syscall2(SYS_CHANNEL_SEND, ch.id().into(), &raw const msg as u64).map(|_| ()) }
+}
+
+/// Receives the next message from the channel `ch` into `data`. The handles
+/// sent along with the message are stored in `handles`.
+///
+/// Receiving never blocks. [`SysCallError::EAGAIN`] is returned if no
+/// message is queued, and [`SysCallError::EPIPE`] if no message is queued
+/// and the other end has been closed. If the message does not fit into
+/// `data` and `handles`, [`SysCallError::ENOSPC`] is returned and the message
+/// stays queued. Buffers of [`CHANNEL_MSG_MAX`](crate::CHANNEL_MSG_MAX) bytes
+/// and [`CHANNEL_HANDLES_MAX`] handles can receive any message.
+///
+/// Returns the size of the message and the number of received handles.
+pub fn channel_recv(
+    ch: &ChannelHandle,
+    data: &mut [u8],
+    handles: &mut [Option<ObjHandle>],
+) -> Result<(usize, usize), SysCallError> {
+    let mut ids = [0u32; CHANNEL_HANDLES_MAX];
+    let mut msg = ChannelMsg {
+        data: data.as_mut_ptr() as u64,
+        data_len: data.len() as u64,
+        handles: ids.as_mut_ptr() as u64,
+        num_handles: handles.len().min(CHANNEL_HANDLES_MAX) as u64,
+    };
+    // SAFETY: SYS_CHANNEL_RECV is a supported syscall number by the svsm
+    // kernel. The kernel only writes to the buffers described by `msg` and
+    // to `msg` itself.
+    unsafe {
+        syscall2(SYS_CHANNEL_RECV, ch.id().into(), &raw mut msg as u64)?;
+    }
+
+    let num_handles = msg.num_handles as usize;
+    for (handle, id) in handles.iter_mut().zip(&ids[..num_handles]) {
+        *handle = Some(ObjHandle::new(*id));
+    }
+    Ok((msg.data_len as usize, num_handles))
+}
+
+/// Blocks until at least one of `events` is pending on the channel `ch`.
+/// [`ChannelEvents::CLOSED`] is reported even if it is not requested.
+///
+/// Returns the pending events out of `events`.
+pub fn channel_poll(
+    ch: &ChannelHandle,
+    events: ChannelEvents,
+) -> Result<ChannelEvents, SysCallError> {
+    // SAFETY: SYS_CHANNEL_POLL is a supported syscall number by the svsm
+    // kernel. It does not access the memory of the process.
+    unsafe {
+        syscall2(SYS_CHANNEL_POLL, ch.id().into(), events.bits().into())
+            .map(|ret| ChannelEvents::from_bits_truncate(ret as u32))
+    }
+}
@@ -5,17 +5,26 @@
 // Author: Joerg Roedel <jroedel@suse.de>
 
 use bitflags::bitflags;
-use zerocopy::IntoBytes;
+use zerocopy::{FromBytes, Immutable, IntoBytes};
 
 // Syscall classes
 const CLASS0: u64 = 0;
 const CLASS1: u64 = 1 << 32;
 const CLASS3: u64 = 3 << 32;
+const CLASS4: u64 = 4 << 32;
 
 // Syscall number in class0
 pub const SYS_EXIT: u64 = CLASS0;
 pub const SYS_EXEC: u64 = CLASS0 + 4;
 pub const SYS_CLOSE: u64 = CLASS0 + 10;
+pub const SYS_MMAP: u64 = CLASS0 + 11;
+pub const SYS_MUNMAP: u64 = CLASS0 + 12;
+pub const SYS_WAIT: u64 = CLASS0 + 13;
+pub const SYS_THREAD_CREATE: u64 = CLASS0 + 14;
+pub const SYS_FUTEX_WAIT: u64 = CLASS0 + 15;
+pub const SYS_FUTEX_WAKE: u64 = CLASS0 + 16;
+pub const SYS_CLOCK_GETTIME: u64 = CLASS0 + 17;
+pub const SYS_SLEEP: u64 = CLASS0 + 18;
 
 // Syscall number in class1
 pub const SYS_OPEN: u64 = CLASS1;
@@ -31,6 +40,16 @@ pub const SYS_RMDIR: u64 = CLASS1 + 9;
 
 // Syscall number in class3
 pub const SYS_CAPABILITIES: u64 = CLASS3;
+pub const SYS_PROTOCOL_REGISTER: u64 = CLASS3 + 1;
+pub const SYS_GUEST_READ: u64 = CLASS3 + 2;
+pub const SYS_GUEST_WRITE: u64 = CLASS3 + 3;
+pub const SYS_VTPM_REGISTER: u64 = CLASS3 + 4;
+
+// Syscall number in class4
+pub const SYS_CHANNEL_CREATE: u64 = CLASS4;
+pub const SYS_CHANNEL_SEND: u64 = CLASS4 + 1;
+pub const SYS_CHANNEL_RECV: u64 = CLASS4 + 2;
+pub const SYS_CHANNEL_POLL: u64 = CLASS4 + 3;
 
 ///Maximum length of path name including null character in bytes
 pub const PATH_MAX: usize = 4096;
@@ -73,6 +92,168 @@ bitflags! {
     }
 }
 
+//
+// Flags for MMap system call
+//
+bitflags! {
+    #[derive(Debug, Copy, Clone, Default)]
+    pub struct MMapFlags: usize {
+        /// Pages can be read
+        const READ = 1 << 0;
+        /// Pages can be written
+        const WRITE = 1 << 1;
+        /// Pages can be executed
+        const EXEC = 1 << 2;
+        /// Map private copies of the file pages
+        const PRIVATE = 1 << 3;
+        /// Map at exactly the given address
+        const FIXED = 1 << 4;
+        /// Map zero-filled memory instead of a file
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// Clocks which can be read with the CLOCK_GETTIME system call. Both are
+/// derived from time sources controlled by the host, so they are not trusted.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    /// Time since boot
+    Monotonic = 0,
+    /// Time since the Unix epoch
+    Realtime = 1,
+}
+
+impl TryFrom<u32> for ClockId {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Monotonic),
+            1 => Ok(Self::Realtime),
+            _ => Err(()),
+        }
+    }
+}
+
+/// CPU index passed to the THREAD_CREATE system call to start the thread on
+/// the current CPU.
+pub const THREAD_CPU_CURRENT: usize = usize::MAX;
+
+/// Maximum size of a message sent over a channel in bytes.
+pub const CHANNEL_MSG_MAX: usize = 4096;
+
+/// Maximum number of object handles passed along with a channel message.
+pub const CHANNEL_HANDLES_MAX: usize = 8;
+
+bitflags! {
+    /// Events reported by the CHANNEL_POLL system call
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct ChannelEvents: u32 {
+        /// A message can be received
+        const READABLE = 1 << 0;
+        /// A message can be sent without exceeding the queue limit
+        const WRITABLE = 1 << 1;
+        /// The other end of the channel has been closed. Always reported.
+        const CLOSED = 1 << 2;
+    }
+}
+
+/// Describes the message buffers passed to the CHANNEL_SEND and CHANNEL_RECV
+/// system calls.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, FromBytes, IntoBytes, Immutable)]
+pub struct ChannelMsg {
+    /// Address of the message data
+    pub data: u64,
+    /// Size of the message data in bytes. Set to the size of the received
+    /// message by CHANNEL_RECV.
+    pub data_len: u64,
+    /// Address of an array of `u32` object handles
+    pub handles: u64,
+    /// Number of object handles in the array. Set to the number of received
+    /// handles by CHANNEL_RECV.
+    pub num_handles: u64,
+}
+
+/// SVSM request forwarded to a user-mode protocol handler. The message
+/// carries a channel handle on which the handler sends its [`ProtocolReply`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, FromBytes, IntoBytes, Immutable)]
+pub struct ProtocolRequest {
+    /// Protocol number of the request
+    pub protocol: u32,
+    /// Call number of the request within the protocol
+    pub request: u32,
+    /// SEV features of the calling vCPU
+    pub sev_features: u64,
+    /// Guest RCX at the time of the call
+    pub rcx: u64,
+    /// Guest RDX at the time of the call
+    pub rdx: u64,
+    /// Guest R8 at the time of the call
+    pub r8: u64,
+}
+
+/// Reply of a user-mode protocol handler to a [`ProtocolRequest`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, FromBytes, IntoBytes, Immutable)]
+pub struct ProtocolReply {
+    /// Result code returned to the guest in RAX, one of the `SVSM_*` result
+    /// codes
+    pub result: u64,
+    /// Value returned to the guest in RCX
+    pub rcx: u64,
+    /// Value returned to the guest in RDX
+    pub rdx: u64,
+    /// Value returned to the guest in R8
+    pub r8: u64,
+}
+
+/// Header of a platform command relayed to the user-mode vTPM. For
+/// [`TPM_SEND_COMMAND`], the TPM command buffer follows the header in the
+/// same message. The message carries a channel handle on which the vTPM sends
+/// the TPM response, or the header itself to acknowledge any other platform
+/// command. An empty reply indicates that the command failed.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, FromBytes, IntoBytes, Immutable)]
+pub struct VtpmCommandHeader {
+    /// MSSIM platform command, one of the `TPM_*` platform commands
+    pub command: u32,
+    /// TPM locality the command is executed in
+    pub locality: u32,
+}
+
+/// MSSIM platform command to power on the TPM
+pub const TPM_SIGNAL_POWER_ON: u32 = 1;
+/// MSSIM platform command to power off the TPM
+pub const TPM_SIGNAL_POWER_OFF: u32 = 2;
+/// MSSIM platform command to run a TPM command
+pub const TPM_SEND_COMMAND: u32 = 8;
+/// MSSIM platform command to set the cancel flag of the TPM
+pub const TPM_SIGNAL_CANCEL_ON: u32 = 9;
+/// MSSIM platform command to clear the cancel flag of the TPM
+pub const TPM_SIGNAL_CANCEL_OFF: u32 = 10;
+/// MSSIM platform command to make the NV memory of the TPM available
+pub const TPM_SIGNAL_NV_ON: u32 = 11;
+/// MSSIM platform command to reset the TPM
+pub const TPM_SIGNAL_RESET: u32 = 17;
+
+//
+// Result codes of SVSM requests returned to the guest in RAX
+//
+pub const SVSM_SUCCESS: u64 = 0x0000_0000;
+pub const SVSM_ERR_INCOMPLETE: u64 = 0x8000_0000;
+pub const SVSM_ERR_UNSUPPORTED_PROTOCOL: u64 = 0x8000_0001;
+pub const SVSM_ERR_UNSUPPORTED_CALL: u64 = 0x8000_0002;
+pub const SVSM_ERR_INVALID_ADDRESS: u64 = 0x8000_0003;
+pub const SVSM_ERR_INVALID_FORMAT: u64 = 0x8000_0004;
+pub const SVSM_ERR_INVALID_PARAMETER: u64 = 0x8000_0005;
+pub const SVSM_ERR_INVALID_REQUEST: u64 = 0x8000_0006;
+pub const SVSM_ERR_BUSY: u64 = 0x8000_0007;
+/// Base of the protocol specific result codes
+pub const SVSM_ERR_PROTOCOL_BASE: u64 = 0x8000_1000;
+
 //
 // Modes for Seek system call
 //
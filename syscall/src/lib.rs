@@ -9,6 +9,7 @@ mod call;
 mod class0;
 mod class1;
 mod class3;
+mod class4;
 mod console;
 mod def;
 mod obj;
@@ -17,6 +18,7 @@ pub use call::SysCallError;
 pub use class0::*;
 pub use class1::*;
 pub use class3::*;
+pub use class4::*;
 pub use console::*;
 pub use def::*;
 pub use obj::*;
@@ -14,7 +14,7 @@ use base64::{
 use kbs_types::*;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Value, json};
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct KbsProtocol;
@@ -30,7 +30,8 @@ impl AttestationProtocol for KbsProtocol {
     /// from the server's /auth endpoint. These must be hased in order.
     ///
     /// Make this request to /auth, gather the nonce, and return this in the negotiation
-    /// parameter for SVSM to hash these components in the attestation evidence.
+    /// parameter for SVSM to hash these components in the attestation evidence. If SVSM keeps
+    /// persistent state, its version is forwarded to the KBS and hashed into the evidence as well.
     fn negotiation(
         &mut self,
         http: &mut HttpClient,
@@ -39,10 +40,17 @@ impl AttestationProtocol for KbsProtocol {
         if request.version != (0, 1, 0) {
             return Err(anyhow!("invalid request version"));
         }
+        // A server providing rollback protection needs the version of SVSM's persistent state.
+        // It is sent along with the challenge request, so that the server can check it against
+        // the version it recorded for the guest, and is hashed into the attestation evidence.
+        let extra_params = match request.state_version {
+            Some(version) => json!({ "state_version": version }),
+            None => Value::String("".to_string()), // unused.
+        };
         let req = Request {
             version: "0.4.0".to_string(),
             tee: request.tee,
-            extra_params: Value::String("".to_string()), // unused.
+            extra_params,
         };
 
         // Fetch challenge containing a nonce from the KBS /auth endpoint.
@@ -62,10 +70,13 @@ impl AttestationProtocol for KbsProtocol {
 
         // Challenge nonce is a base64-encoded byte vector. Inform SVSM of this so it could
         // decode the bytes and hash them into the TEE evidence.
-        let params = vec![
+        let mut params = vec![
             NegotiationParam::EcPublicKeyBytes,
             NegotiationParam::Challenge,
         ];
+        if request.state_version.is_some() {
+            params.push(NegotiationParam::StateVersion);
+        }
 
         let resp = NegotiationResponse {
             challenge: BASE64_STANDARD
@@ -79,6 +79,10 @@ pub struct CmdOptions {
     /// Use Alternate Injection if available
     #[arg(long, default_value_t = false)]
     pub alt_injection: bool,
+
+    /// Continue to boot without provisioned keys if attestation fails
+    #[arg(long, default_value_t = false)]
+    pub continue_unprovisioned: bool,
 }
 
 impl CmdOptions {
@@ -279,6 +279,8 @@ impl IgvmBuilder {
             has_qemu_testdev,
             has_fw_cfg_port,
             has_test_iorequests,
+            continue_unprovisioned: u8::from(self.options.continue_unprovisioned),
+            ..Default::default()
         })
     }
 
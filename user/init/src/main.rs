@@ -4,8 +4,11 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 use userlib::*;
 
+use alloc::vec::Vec;
 use core::ptr::{addr_of, addr_of_mut};
 
 static mut SOME_BSS_DATA: [u64; 128] = [0; 128];
@@ -40,5 +43,10 @@ fn main() -> u32 {
         check(&*addr_of!(SOME_RO_DATA), 0xeeu64);
         check(&*addr_of!(SOME_BSS_DATA), 0xaa);
     }
+
+    let heap_data: Vec<u64> = (0..4096).collect();
+    if heap_data.iter().copied().ne(0..4096) {
+        panic!("Unexpected heap value");
+    }
     0
 }
@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Arguments and environment passed to a module by `exec`.
+//!
+//! The kernel places both in an argument block at the top of the user-mode
+//! stack and passes its address to the entry point. The block holds `argc`,
+//! followed by the NULL-terminated `argv` and `envp` pointer arrays.
+
+use core::ffi::{CStr, c_char};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// Start of the argument block of the module.
+static ARG_BLOCK: AtomicPtr<u64> = AtomicPtr::new(ptr::null_mut());
+
+/// Records the argument block passed to the entry point of the module.
+///
+/// # Safety
+///
+/// `block` must be NULL or point to an argument block in the layout created
+/// by the kernel, which stays valid for the lifetime of the module.
+#[doc(hidden)]
+pub unsafe fn init_args(block: *const u64) {
+    ARG_BLOCK.store(block.cast_mut(), Ordering::Relaxed);
+}
+
+/// Iterator over the strings of a NULL-terminated array in the argument
+/// block. The kernel only accepts UTF-8 strings, so they are returned as
+/// `str`.
+#[derive(Debug, Clone)]
+pub struct ArgIter {
+    next: *const *const c_char,
+}
+
+impl Iterator for ArgIter {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        // SAFETY: The pointer points into a NULL-terminated array of the
+        // argument block, which init_args() requires to be valid.
+        let ptr = unsafe { self.next.read() };
+        if ptr.is_null() {
+            return None;
+        }
+        // SAFETY: The array is not terminated yet, so the next entry is valid
+        // as well.
+        self.next = unsafe { self.next.add(1) };
+        // SAFETY: The kernel stores NUL-terminated strings in the block.
+        let s = unsafe { CStr::from_ptr(ptr) };
+        Some(s.to_str().unwrap_or_default())
+    }
+}
+
+/// Returns the `argv` array of an argument block.
+///
+/// # Safety
+///
+/// `block` must be NULL or point to a valid argument block.
+unsafe fn block_args(block: *const u64) -> ArgIter {
+    if block.is_null() {
+        return ArgIter { next: ptr::null() };
+    }
+    ArgIter {
+        // SAFETY: The caller guarantees a valid block, which starts with
+        // argc followed by argv.
+        next: unsafe { block.add(1).cast() },
+    }
+}
+
+/// Returns the `envp` array of an argument block.
+///
+/// # Safety
+///
+/// `block` must be NULL or point to a valid argument block.
+unsafe fn block_env(block: *const u64) -> ArgIter {
+    if block.is_null() {
+        return ArgIter { next: ptr::null() };
+    }
+    // SAFETY: The caller guarantees a valid block, in which envp follows the
+    // argc entries of argv and their NULL terminator.
+    unsafe {
+        let argc = block.read() as usize;
+        ArgIter {
+            next: block.add(argc + 2).cast(),
+        }
+    }
+}
+
+/// Returns the arguments of the module, by convention starting with the
+/// name of the binary.
+pub fn args() -> ArgIter {
+    // SAFETY: init_args() requires the block to be valid.
+    unsafe { block_args(ARG_BLOCK.load(Ordering::Relaxed)) }
+}
+
+/// Returns the environment of the module as `NAME=value` strings.
+pub fn env() -> ArgIter {
+    // SAFETY: init_args() requires the block to be valid.
+    unsafe { block_env(ARG_BLOCK.load(Ordering::Relaxed)) }
+}
+
+/// Returns the value of the environment variable `name`, if it is set.
+pub fn getenv(name: &str) -> Option<&'static str> {
+    env().find_map(|var| var.strip_prefix(name)?.strip_prefix('='))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arg_block() {
+        let strings = [c"init", c"-v", c"LOG=debug"];
+        let [init, verbose, log] = strings.map(|s| s.as_ptr() as u64);
+        let block = [2, init, verbose, 0, log, 0];
+
+        // SAFETY: The block and the strings are valid during the test.
+        let (args, env) = unsafe { (block_args(block.as_ptr()), block_env(block.as_ptr())) };
+        assert!(args.eq(["init", "-v"]));
+        assert!(env.eq(["LOG=debug"]));
+
+        // SAFETY: NULL is a valid block.
+        let (mut args, mut env) = unsafe { (block_args(ptr::null()), block_env(ptr::null())) };
+        assert!(args.next().is_none());
+        assert!(env.next().is_none());
+    }
+}
@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Memory allocator for user-mode modules.
+//!
+//! The heap is kept as a free list of memory blocks ordered by address.
+//! Allocations are served first-fit and freed blocks are merged with their
+//! neighbours, which keeps fragmentation low for the mostly long-lived
+//! allocations of SVSM services. When the free list can not satisfy a
+//! request, the heap grows by mapping more anonymous memory from the kernel.
+
+use crate::SpinLock;
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+
+/// Header stored at the beginning of every free memory block.
+#[derive(Debug)]
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// Granularity of all blocks. Every block is large enough and aligned to
+/// hold a [`FreeBlock`] header once it is freed.
+const BLOCK_SIZE: usize = size_of::<FreeBlock>();
+
+/// A heap managing memory regions handed to it with [`Heap::add_region`].
+#[derive(Debug)]
+pub struct Heap {
+    head: Option<NonNull<FreeBlock>>,
+    free: usize,
+}
+
+// SAFETY: The heap exclusively owns the free memory blocks it points to, so
+// it can be moved to another thread.
+unsafe impl Send for Heap {}
+
+impl Heap {
+    /// Creates an empty heap.
+    pub const fn new() -> Self {
+        Self {
+            head: None,
+            free: 0,
+        }
+    }
+
+    /// Returns the number of free bytes in the heap.
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// Returns the size and alignment of the block used for `layout`.
+    fn block_layout(layout: Layout) -> Option<(usize, usize)> {
+        let size = layout
+            .size()
+            .max(BLOCK_SIZE)
+            .checked_next_multiple_of(BLOCK_SIZE)?;
+        let align = layout.align().max(BLOCK_SIZE);
+        Some((size, align))
+    }
+
+    /// Hands a region of memory to the heap. Parts of the region not aligned
+    /// to the block size are ignored.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the region is valid for reads and writes,
+    /// is not used by anything else and stays valid for the lifetime of the
+    /// heap.
+    pub unsafe fn add_region(&mut self, start: NonNull<u8>, size: usize) {
+        let offset = start.align_offset(BLOCK_SIZE);
+        let size = size.saturating_sub(offset) & !(BLOCK_SIZE - 1);
+        if size == 0 {
+            return;
+        }
+        // SAFETY: offset is within the region since the remaining size is
+        // not zero. The caller guarantees that the region is usable.
+        unsafe { self.insert(start.add(offset), size) };
+    }
+
+    /// Allocates a block of memory for `layout`.
+    ///
+    /// # Returns
+    ///
+    /// A pointer to the allocated memory, or `None` if the heap has no free
+    /// block large enough.
+    pub fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let (size, align) = Self::block_layout(layout)?;
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut cur = self.head;
+
+        while let Some(block) = cur {
+            // SAFETY: All blocks in the free list hold a valid header.
+            let FreeBlock { size: len, next } = unsafe { ptr::read(block.as_ptr()) };
+            let front = block.cast::<u8>().align_offset(align);
+            if front
+                .checked_add(size)
+                .is_none_or(|alloc_end| alloc_end > len)
+            {
+                prev = cur;
+                cur = next;
+                continue;
+            }
+
+            // Replace the block in the list with the parts left free in front
+            // of and behind the allocation. Both are multiples of the block
+            // size, so they can hold a header if they are not empty.
+            let base = block.cast::<u8>();
+            let back = len - front - size;
+            let mut link = next;
+            if back != 0 {
+                // SAFETY: The remainder lies within the free block.
+                let tail = unsafe { base.add(front + size).cast::<FreeBlock>() };
+                // SAFETY: The remainder is free, aligned and large enough to
+                // hold a header.
+                unsafe {
+                    tail.write(FreeBlock {
+                        size: back,
+                        next: link,
+                    })
+                };
+                link = Some(tail);
+            }
+            if front != 0 {
+                // SAFETY: The block is free and large enough for a header.
+                unsafe {
+                    block.write(FreeBlock {
+                        size: front,
+                        next: link,
+                    })
+                };
+                link = Some(block);
+            }
+            self.set_next(prev, link);
+            self.free -= size;
+
+            // SAFETY: The allocation lies within the free block.
+            return Some(unsafe { base.add(front) });
+        }
+
+        None
+    }
+
+    /// Returns a block of memory to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` was returned by [`Heap::alloc`] of
+    /// this heap for the same `layout` and that it is not used anymore.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let (size, _) = Self::block_layout(layout).expect("Invalid layout");
+        // SAFETY: The caller guarantees that the block is no longer in use.
+        unsafe { self.insert(ptr, size) };
+    }
+
+    fn set_next(&mut self, prev: Option<NonNull<FreeBlock>>, next: Option<NonNull<FreeBlock>>) {
+        match prev {
+            // SAFETY: All blocks in the free list hold a valid header.
+            Some(mut prev) => unsafe { prev.as_mut().next = next },
+            None => self.head = next,
+        }
+    }
+
+    /// Inserts a free block into the list, merging it with adjacent blocks.
+    ///
+    /// # Safety
+    ///
+    /// The block must be aligned to and a multiple of [`BLOCK_SIZE`], unused
+    /// and not overlap with any block in the list.
+    unsafe fn insert(&mut self, ptr: NonNull<u8>, size: usize) {
+        let addr = ptr.addr().get();
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut cur = self.head;
+        while let Some(block) = cur {
+            if block.addr().get() > addr {
+                break;
+            }
+            prev = cur;
+            // SAFETY: All blocks in the free list hold a valid header.
+            cur = unsafe { block.as_ref().next };
+        }
+
+        let block = ptr.cast::<FreeBlock>();
+        let mut header = FreeBlock { size, next: cur };
+        if let Some(next) = cur {
+            if addr + size == next.addr().get() {
+                // SAFETY: All blocks in the free list hold a valid header.
+                let next = unsafe { next.as_ref() };
+                header.size += next.size;
+                header.next = next.next;
+            }
+        }
+
+        self.free += size;
+
+        if let Some(mut prev) = prev {
+            // SAFETY: All blocks in the free list hold a valid header.
+            let prev = unsafe { prev.as_mut() };
+            if prev.addr_end() == addr {
+                prev.size += header.size;
+                prev.next = header.next;
+                return;
+            }
+        }
+
+        // SAFETY: The caller guarantees that the block is unused, aligned and
+        // large enough for a header.
+        unsafe { block.write(header) };
+        self.set_next(prev, Some(block));
+    }
+}
+
+impl FreeBlock {
+    fn addr_end(&self) -> usize {
+        ptr::from_ref(self).addr() + self.size
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hook called when an allocation can not be satisfied.
+static OOM_HOOK: SpinLock<Option<fn(Layout)>> = SpinLock::new(None);
+
+/// Installs a hook which is called when the heap runs out of memory, before
+/// the allocation fails. The hook must not allocate memory.
+///
+/// # Arguments
+///
+/// * `hook`: Function called with the layout of the failed allocation.
+pub fn set_oom_hook(hook: fn(Layout)) {
+    *OOM_HOOK.lock() = Some(hook);
+}
+
+#[cfg(all(not(test), target_os = "none"))]
+mod global {
+    use super::*;
+    use core::alloc::GlobalAlloc;
+    use syscall::{MMapFlags, mmap_anon};
+
+    const PAGE_SIZE: usize = 4096;
+
+    /// Minimum amount of memory the heap grows by.
+    const HEAP_GROW_SIZE: usize = 256 * 1024;
+
+    /// Global allocator of user-mode modules, backed by a [`Heap`] which grows
+    /// through the `mmap` system call.
+    #[derive(Debug)]
+    struct UserAllocator {
+        heap: SpinLock<Heap>,
+    }
+
+    impl UserAllocator {
+        const fn new() -> Self {
+            Self {
+                heap: SpinLock::new(Heap::new()),
+            }
+        }
+
+        /// Maps enough memory into the heap to satisfy `layout`.
+        fn grow(heap: &mut Heap, layout: Layout) -> Option<()> {
+            let size = layout
+                .size()
+                .checked_add(layout.align())?
+                .max(HEAP_GROW_SIZE)
+                .checked_next_multiple_of(PAGE_SIZE)?;
+            let addr = mmap_anon(0, size, MMapFlags::READ | MMapFlags::WRITE).ok()?;
+            let start = NonNull::new(ptr::with_exposed_provenance_mut::<u8>(addr))?;
+            // SAFETY: The memory was just mapped and is exclusively used by the
+            // heap. It is never unmapped.
+            unsafe { heap.add_region(start, size) };
+            Some(())
+        }
+    }
+
+    // SAFETY: The heap hands out each block only once and is protected by a lock.
+    unsafe impl GlobalAlloc for UserAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let mut heap = self.heap.lock();
+            if let Some(ptr) = heap.alloc(layout) {
+                return ptr.as_ptr();
+            }
+            if Self::grow(&mut heap, layout).is_some() {
+                if let Some(ptr) = heap.alloc(layout) {
+                    return ptr.as_ptr();
+                }
+            }
+            drop(heap);
+
+            let hook = *OOM_HOOK.lock();
+            if let Some(hook) = hook {
+                hook(layout);
+            }
+            ptr::null_mut()
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // SAFETY: The caller guarantees that the pointer was returned by
+            // alloc() for this layout.
+            unsafe {
+                self.heap
+                    .lock()
+                    .dealloc(NonNull::new_unchecked(ptr), layout)
+            };
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: UserAllocator = UserAllocator::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(align(4096))]
+    struct Arena([u8; 4096]);
+
+    fn heap(arena: &mut Arena) -> Heap {
+        let mut heap = Heap::new();
+        // SAFETY: The arena outlives the heap in all tests.
+        unsafe { heap.add_region(NonNull::from(&mut arena.0).cast(), arena.0.len()) };
+        heap
+    }
+
+    #[test]
+    fn alloc_and_free() {
+        let mut arena = Arena([0; 4096]);
+        let mut heap = heap(&mut arena);
+        assert_eq!(heap.free(), 4096);
+
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let a = heap.alloc(layout).unwrap();
+        let b = heap.alloc(layout).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(heap.free(), 4096 - 2 * 112);
+
+        // SAFETY: Both blocks were allocated from this heap.
+        unsafe {
+            heap.dealloc(a, layout);
+            heap.dealloc(b, layout);
+        }
+        assert_eq!(heap.free(), 4096);
+
+        // Freed blocks are merged, so the whole arena is usable again.
+        let all = Layout::from_size_align(4096, 8).unwrap();
+        assert!(heap.alloc(all).is_some());
+        assert!(heap.alloc(layout).is_none());
+    }
+
+    #[test]
+    fn alignment() {
+        let mut arena = Arena([0; 4096]);
+        let mut heap = heap(&mut arena);
+
+        let small = Layout::from_size_align(16, 16).unwrap();
+        let aligned = Layout::from_size_align(64, 1024).unwrap();
+        let a = heap.alloc(small).unwrap();
+        let b = heap.alloc(aligned).unwrap();
+        assert_eq!(b.addr().get() % 1024, 0);
+
+        // The gap in front of the aligned block is still usable.
+        let c = heap.alloc(small).unwrap();
+        assert!(c < b);
+
+        // SAFETY: All blocks were allocated from this heap.
+        unsafe {
+            heap.dealloc(b, aligned);
+            heap.dealloc(a, small);
+            heap.dealloc(c, small);
+        }
+        assert_eq!(heap.free(), 4096);
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut arena = Arena([0; 4096]);
+        let mut heap = heap(&mut arena);
+
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let blocks: Vec<_> = core::iter::from_fn(|| heap.alloc(layout)).collect();
+        assert_eq!(blocks.len(), 16);
+        assert_eq!(heap.free(), 0);
+
+        // Free every other block; a larger block still does not fit.
+        for block in blocks.iter().step_by(2) {
+            // SAFETY: The block was allocated from this heap.
+            unsafe { heap.dealloc(*block, layout) };
+        }
+        assert!(
+            heap.alloc(Layout::from_size_align(512, 8).unwrap())
+                .is_none()
+        );
+
+        for block in blocks.iter().skip(1).step_by(2) {
+            // SAFETY: The block was allocated from this heap.
+            unsafe { heap.dealloc(*block, layout) };
+        }
+        assert!(
+            heap.alloc(Layout::from_size_align(4096, 8).unwrap())
+                .is_some()
+        );
+    }
+}
@@ -6,19 +6,31 @@
 
 #![cfg_attr(all(not(test), target_os = "none"), no_std)]
 
+pub mod args;
 pub mod console;
+pub mod heap;
 pub mod locking;
+pub mod sync;
+pub mod thread;
+pub mod time;
 
+pub use args::*;
 pub use console::*;
+pub use heap::*;
 pub use locking::*;
+pub use sync::*;
 pub use syscall::*;
+pub use time::*;
 
 #[macro_export]
 macro_rules! declare_main {
     ($path:path) => {
         const _: () = {
             #[unsafe(export_name = "_start")]
-            pub extern "C" fn launch_module() -> ! {
+            pub extern "C" fn launch_module(arg_block: *const u64) -> ! {
+                // SAFETY: The kernel passes the address of the argument block
+                // on the stack, which is never freed.
+                unsafe { init_args(arg_block) };
                 let main_fn: fn() -> u32 = $path;
                 let ret = main_fn();
                 exit(ret);
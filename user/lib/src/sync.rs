@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+//! Blocking synchronization primitives built on the futex system calls.
+//!
+//! Unlike [`SpinLock`](crate::SpinLock), a thread waiting for a [`Mutex`] or
+//! a [`Condvar`] gives up its CPU until it is woken by another thread.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+use syscall::{futex_wait, futex_wake};
+
+/// The mutex is not locked.
+const UNLOCKED: u32 = 0;
+/// The mutex is locked and no other thread is waiting for it.
+const LOCKED: u32 = 1;
+/// The mutex is locked and other threads may be waiting for it.
+const CONTENDED: u32 = 2;
+
+/// A mutual exclusion lock which blocks waiting threads in the kernel.
+///
+/// The lock is taken without a system call as long as it is not contended.
+///
+/// # Examples
+///
+/// ```
+/// use userlib::Mutex;
+///
+/// let mutex = Mutex::new(0);
+/// *mutex.lock() += 1;
+/// assert_eq!(*mutex.lock(), 1);
+/// ```
+#[derive(Debug)]
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: Mutex guarantees mutually exclusive access to wrapped data.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates a new unlocked `Mutex` protecting `data`.
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Acquires the lock, blocking the calling thread until it is available.
+    ///
+    /// # Returns
+    ///
+    /// A [`MutexGuard`] which releases the lock when dropped.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        MutexGuard { mutex: self }
+    }
+
+    /// Tries to acquire the lock without blocking.
+    ///
+    /// # Returns
+    ///
+    /// `Some(MutexGuard)` if the lock has been acquired, `None` if it is held
+    /// by another thread.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    fn lock_contended(&self) {
+        // Mark the lock as contended before sleeping, so that the holder
+        // knows it must wake a waiter on release. Since it is unknown whether
+        // other threads are still waiting, the lock stays marked contended
+        // once acquired here.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            // EAGAIN means that the lock state changed in the meantime.
+            let _ = futex_wait(&self.state, CONTENDED);
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            let _ = futex_wake(&self.state, 1);
+        }
+    }
+
+    /// Returns a mutable reference to the protected data. No locking is
+    /// needed since the mutable borrow guarantees exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Consumes the mutex and returns the protected data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// A lock guard obtained from a [`Mutex`]. The lock is released when the
+/// guard goes out of scope.
+#[derive(Debug)]
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: the guard owns the lock, so there is no other access to
+        // the data.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard owns the lock, so there is no other access to
+        // the data.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+/// A condition variable which lets threads wait for a condition on data
+/// protected by a [`Mutex`] to become true.
+///
+/// Waiting threads can wake up spuriously, so the condition must be checked
+/// again after [`Condvar::wait`] returns.
+///
+/// # Examples
+///
+/// ```no_run
+/// use userlib::{Condvar, Mutex};
+///
+/// static READY: Mutex<bool> = Mutex::new(false);
+/// static COND: Condvar = Condvar::new();
+///
+/// // Waiting thread
+/// let mut ready = READY.lock();
+/// while !*ready {
+///     ready = COND.wait(ready);
+/// }
+///
+/// // Notifying thread
+/// *READY.lock() = true;
+/// COND.notify_all();
+/// ```
+#[derive(Debug, Default)]
+pub struct Condvar {
+    /// Incremented on every notification, so that waiters do not block if
+    /// a notification arrived after they released the mutex.
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    /// Creates a new `Condvar`.
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    /// Releases the lock held by `guard` and blocks the calling thread until
+    /// it is notified, then acquires the lock again.
+    ///
+    /// # Returns
+    ///
+    /// A new guard for the re-acquired lock.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+
+        // EAGAIN means that a notification happened in the meantime.
+        let _ = futex_wait(&self.seq, seq);
+
+        mutex.lock()
+    }
+
+    /// Wakes one thread waiting on this condition variable.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        let _ = futex_wake(&self.seq, 1);
+    }
+
+    /// Wakes all threads waiting on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        let _ = futex_wake(&self.seq, u32::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uncontended_lock() {
+        let mut mutex = Mutex::new(1);
+        {
+            let mut guard = mutex.lock();
+            *guard += 1;
+            assert!(mutex.try_lock().is_none());
+        }
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+        *mutex.get_mut() += 1;
+        assert_eq!(mutex.into_inner(), 3);
+    }
+}
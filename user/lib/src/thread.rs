@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Threads of user-mode modules.
+//!
+//! All threads of a module share its address space and objects. A thread
+//! terminates when its function returns or when it calls [`exit`]. Exiting
+//! the main thread does not terminate the other threads of the module.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use syscall::{MMapFlags, SysCallError, Tid, exit, mmap_anon, munmap, thread_create, wait};
+
+/// Size of the stack of new threads.
+const THREAD_STACK_SIZE: usize = 64 * 1024;
+
+type ThreadMain = Box<dyn FnOnce() -> u32 + Send + 'static>;
+
+extern "C" fn thread_start(arg: usize) -> ! {
+    // SAFETY: spawn_on() passes a pointer obtained from Box::into_raw() which
+    // is only used by this thread.
+    let main = unsafe { Box::from_raw(arg as *mut ThreadMain) };
+    exit(main());
+}
+
+/// Handle of a thread started with [`spawn`] or [`spawn_on`].
+///
+/// Only the thread which started a thread can join it, so the handle can
+/// not be sent to other threads. Dropping the handle detaches the thread,
+/// which then keeps its stack after terminating.
+#[derive(Debug)]
+pub struct JoinHandle {
+    tid: Tid,
+    stack: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl JoinHandle {
+    /// Waits for the thread to terminate and releases its stack.
+    ///
+    /// # Returns
+    ///
+    /// The value returned by the thread function or passed to [`exit`].
+    pub fn join(self) -> Result<u32, SysCallError> {
+        let exit_code = wait(self.tid)?;
+        // SAFETY: The thread has terminated, so its stack is no longer in
+        // use.
+        unsafe { munmap(self.stack)? };
+        Ok(exit_code)
+    }
+}
+
+/// Starts a new thread running `f` on the current CPU.
+pub fn spawn<F>(f: F) -> Result<JoinHandle, SysCallError>
+where
+    F: FnOnce() -> u32 + Send + 'static,
+{
+    spawn_on(None, f)
+}
+
+/// Starts a new thread running `f` on the CPU with the index `cpu`, or on
+/// the current CPU if it is `None`.
+pub fn spawn_on<F>(cpu: Option<usize>, f: F) -> Result<JoinHandle, SysCallError>
+where
+    F: FnOnce() -> u32 + Send + 'static,
+{
+    let stack = mmap_anon(0, THREAD_STACK_SIZE, MMapFlags::READ | MMapFlags::WRITE)?;
+    let main: ThreadMain = Box::new(f);
+    let arg = Box::into_raw(Box::new(main));
+
+    // SAFETY: The stack was just mapped for the exclusive use of the new
+    // thread, and the top of the stack is adjusted as if thread_start() had
+    // been called. The closure is Send and owned by the new thread.
+    let result = unsafe {
+        thread_create(
+            thread_start,
+            stack + THREAD_STACK_SIZE - 8,
+            arg as usize,
+            cpu,
+        )
+    };
+
+    match result {
+        Ok(tid) => Ok(JoinHandle {
+            tid,
+            stack,
+            _not_send: PhantomData,
+        }),
+        Err(e) => {
+            // SAFETY: The thread was not created, so the closure and the
+            // stack are still owned by this function.
+            unsafe {
+                drop(Box::from_raw(arg));
+                let _ = munmap(stack);
+            }
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Measurement of time based on the monotonic clock of the kernel.
+
+use core::ops::Add;
+use core::time::Duration;
+use syscall::{ClockId, clock_gettime};
+
+/// A point in time of the monotonic clock, which never goes backwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// Returns the current time.
+    pub fn now() -> Self {
+        // The monotonic clock is always available.
+        Self(clock_gettime(ClockId::Monotonic).unwrap_or_default())
+    }
+
+    /// Returns the time elapsed from `earlier` to `self`, or zero if
+    /// `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// Returns the time elapsed since `self`.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Self(self.0 + rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instant_arithmetic() {
+        let start = Instant(Duration::from_secs(5));
+        let end = start + Duration::from_millis(1500);
+        assert_eq!(end.duration_since(start), Duration::from_millis(1500));
+        assert_eq!(start.duration_since(end), Duration::ZERO);
+        assert!(start < end);
+    }
+}
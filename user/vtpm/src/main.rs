@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! User-mode vTPM module.
+//!
+//! Runs the TPM 2.0 Reference Implementation outside of the SVSM kernel. The
+//! kernel keeps handling the vTPM protocol and relays the TPM commands of the
+//! guest to this process over the channel returned by [`vtpm_register`].
+//!
+//! The main thread runs the TPM, while a second thread receives the relayed
+//! commands. Like the platform thread of the TPM simulator, the receiving
+//! thread handles the cancel flag itself, so that it reaches the TPM command
+//! which is running.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod nv;
+mod tpm;
+/// Functions required to build the TPM 2.0 Reference Implementation libraries
+#[cfg(target_os = "none")]
+mod wrapper;
+
+use userlib::*;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use tpm::{TPM_BUFFER_MAX_SIZE, Tpm};
+use zerocopy::{FromBytes, IntoBytes};
+
+/// A platform command received from the kernel.
+#[derive(Debug)]
+struct Command {
+    header: VtpmCommandHeader,
+    /// TPM command of a TPM_SEND_COMMAND, empty for the other platform
+    /// commands.
+    payload: Vec<u8>,
+    /// Channel on which the reply is sent.
+    reply: ChannelHandle,
+}
+
+#[derive(Debug)]
+struct Queue {
+    /// Command received but not yet picked up by the main thread.
+    command: Option<Command>,
+    /// Whether the kernel closed the command channel.
+    closed: bool,
+}
+
+/// Hands the received commands over to the main thread.
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    command: None,
+    closed: false,
+});
+/// Notified whenever [`QUEUE`] changes.
+static QUEUE_CHANGED: Condvar = Condvar::new();
+
+/// Sends `response` on the reply channel of a command. An empty response
+/// tells the kernel that the command failed.
+fn send_reply(reply: &ChannelHandle, response: Option<Vec<u8>>) {
+    // The kernel does not wait for the response anymore if the reply channel
+    // is closed, so ignore send errors.
+    let _ = channel_send(reply, &response.unwrap_or_default(), &[]);
+}
+
+/// Runs a platform command relayed by the kernel.
+///
+/// # Returns
+///
+/// The TPM response to a TPM_SEND_COMMAND, the header itself to acknowledge
+/// any other platform command, or `None` if the command failed.
+fn run_platform_command(
+    tpm: &mut Tpm,
+    header: &VtpmCommandHeader,
+    command: &[u8],
+) -> Option<Vec<u8>> {
+    let result = match header.command {
+        TPM_SEND_COMMAND => {
+            let locality = u8::try_from(header.locality).ok()?;
+            return tpm.run_command(command, locality);
+        }
+        TPM_SIGNAL_POWER_ON => tpm.power_on(false),
+        TPM_SIGNAL_RESET => tpm.power_on(true),
+        TPM_SIGNAL_POWER_OFF => {
+            tpm.power_off();
+            Ok(())
+        }
+        TPM_SIGNAL_NV_ON => tpm.nv_on(),
+        _ => return None,
+    };
+
+    match result {
+        Ok(()) => Some(header.as_bytes().to_vec()),
+        Err(e) => {
+            println!("vTPM: platform command {} failed: {e}", header.command);
+            None
+        }
+    }
+}
+
+/// Receives the next command relayed by the kernel. Changes of the cancel
+/// flag are handled right away, all other commands are queued for the main
+/// thread.
+fn receive_command(channel: &ChannelHandle, buf: &mut [u8]) -> Result<(), SysCallError> {
+    let mut handles = [None];
+    let len = loop {
+        match channel_recv(channel, buf, &mut handles) {
+            Ok((len, 1)) => break len,
+            Ok(_) => return Err(SysCallError::EINVAL),
+            Err(SysCallError::EAGAIN) => {
+                channel_poll(channel, ChannelEvents::READABLE)?;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    let reply = ChannelHandle::from(handles[0].take().ok_or(SysCallError::EINVAL)?);
+
+    let Ok((header, payload)) = VtpmCommandHeader::read_from_prefix(&buf[..len]) else {
+        send_reply(&reply, None);
+        return Ok(());
+    };
+    match header.command {
+        TPM_SIGNAL_CANCEL_ON | TPM_SIGNAL_CANCEL_OFF => {
+            tpm::set_cancel(header.command == TPM_SIGNAL_CANCEL_ON);
+            send_reply(&reply, Some(header.as_bytes().to_vec()));
+        }
+        _ => {
+            let command = Command {
+                header,
+                payload: payload.to_vec(),
+                reply,
+            };
+            // The kernel sends the next command only after the reply to the
+            // previous one, unless it gave up waiting for it.
+            let mut queue = QUEUE.lock();
+            while queue.command.is_some() {
+                queue = QUEUE_CHANGED.wait(queue);
+            }
+            queue.command = Some(command);
+            QUEUE_CHANGED.notify_all();
+        }
+    }
+    Ok(())
+}
+
+/// Receives the commands relayed by the kernel until it closes the command
+/// channel.
+fn receive_commands(channel: ChannelHandle) -> u32 {
+    let mut buf: Vec<u8> = vec![0; TPM_BUFFER_MAX_SIZE];
+    loop {
+        match receive_command(&channel, &mut buf) {
+            Ok(()) => {}
+            // The kernel closed the command channel.
+            Err(SysCallError::EPIPE) => break,
+            Err(e) => println!("vTPM: failed to receive TPM command: {e:?}"),
+        }
+    }
+
+    QUEUE.lock().closed = true;
+    QUEUE_CHANGED.notify_all();
+    0
+}
+
+/// Waits for the next command received by the receiving thread.
+///
+/// # Returns
+///
+/// The command, or `None` once the kernel closed the command channel.
+fn next_command() -> Option<Command> {
+    let mut queue = QUEUE.lock();
+    loop {
+        if let Some(command) = queue.command.take() {
+            QUEUE_CHANGED.notify_all();
+            return Some(command);
+        }
+        if queue.closed {
+            return None;
+        }
+        queue = QUEUE_CHANGED.wait(queue);
+    }
+}
+
+declare_main!(main);
+
+fn main() -> u32 {
+    let mut tpm = match Tpm::init() {
+        Ok(tpm) => tpm,
+        Err(e) => {
+            println!("vTPM: failed to initialize the TPM: {e}");
+            return 1;
+        }
+    };
+
+    let channel = match vtpm_register() {
+        Ok(channel) => channel,
+        Err(e) => {
+            println!("vTPM: failed to register with the kernel: {e:?}");
+            return 1;
+        }
+    };
+    let receiver = match thread::spawn(move || receive_commands(channel)) {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            println!("vTPM: failed to start the receiving thread: {e:?}");
+            return 1;
+        }
+    };
+    println!("vTPM: TPM 2.0 Reference Implementation initialized");
+
+    while let Some(command) = next_command() {
+        let response = run_platform_command(&mut tpm, &command.header, &command.payload);
+        send_reply(&command.reply, response);
+    }
+
+    let _ = receiver.join();
+    0
+}
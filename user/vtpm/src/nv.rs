@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Persistence of the TPM 2.0 Reference Implementation NV memory.
+//!
+//! The reference implementation keeps its whole NV state in the `s_NV`
+//! array. This module loads that array from a file on the persistent
+//! filesystem before the TPM is powered on and writes it back whenever a
+//! command modified it. The kernel starts the module with a private
+//! directory of the persistent filesystem as its root directory, which no
+//! other process can access.
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::CStr;
+use libtcgtpm::bindings::s_NV;
+use userlib::{FileFlags, FileModes, FsObjHandle, SeekMode, SysCallError, open, read, seek, write};
+
+/// File holding the NV memory. The kernel only creates it if the SVSM has
+/// persistent storage.
+const NV_FILE: &CStr = c"/nv";
+
+/// Returns the NV memory of the TPM.
+///
+/// # Safety
+///
+/// The caller must ensure that the TPM library is not executing while the
+/// returned slice is alive. This is guaranteed on the main thread, which is
+/// the only one running TPM commands, outside of calls into the library.
+unsafe fn nv_memory() -> &'static mut [u8] {
+    let nv = &raw mut s_NV;
+    // SAFETY: `s_NV` is a plain byte array which is only accessed by the TPM
+    // library, which the caller guarantees to not be running.
+    unsafe { &mut *nv }
+}
+
+#[derive(Debug)]
+pub struct NvFile {
+    file: FsObjHandle,
+    /// Copy of the NV memory as it was last written to the file.
+    shadow: Vec<u8>,
+}
+
+impl NvFile {
+    /// Opens the NV file.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if no persistent filesystem is available, the file on
+    /// success, or an error if the file can not be opened.
+    pub fn open() -> Result<Option<Self>, SysCallError> {
+        match open(
+            NV_FILE,
+            FileModes::READ | FileModes::WRITE,
+            FileFlags::empty(),
+        ) {
+            Ok(file) => Ok(Some(Self {
+                file,
+                shadow: Vec::new(),
+            })),
+            Err(SysCallError::ENOTFOUND) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Restores the NV memory from the file.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(true)` if a previously saved NV image has been restored,
+    /// `Ok(false)` if the file is empty, or an error if the image does not
+    /// match the NV layout of this TPM. The file is left untouched then, so
+    /// the TPM keys it holds are not overwritten.
+    pub fn restore(&mut self) -> Result<bool, SysCallError> {
+        // SAFETY: the TPM is not running while it is being initialized.
+        let nv = unsafe { nv_memory() };
+        // Read one byte more than the NV memory to detect larger images.
+        let mut image = vec![0u8; nv.len() + 1];
+        let mut len = 0;
+        while len < image.len() {
+            match read(&self.file, &mut image[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+
+        match len {
+            0 => Ok(false),
+            len if len == nv.len() => {
+                image.truncate(len);
+                nv.copy_from_slice(&image);
+                self.shadow = image;
+                Ok(true)
+            }
+            // The image does not match the NV layout of this TPM.
+            _ => Err(SysCallError::EINVAL),
+        }
+    }
+
+    /// Writes the NV memory to the file if it changed since the last call.
+    pub fn sync(&mut self) -> Result<(), SysCallError> {
+        // SAFETY: the TPM is not running between commands.
+        let nv = unsafe { nv_memory() };
+        if self.shadow == nv {
+            return Ok(());
+        }
+
+        seek(&self.file, 0, SeekMode::Absolute)?;
+        let mut done = 0;
+        while done < nv.len() {
+            match write(&self.file, &nv[done..])? {
+                0 => return Err(SysCallError::ENOSPC),
+                n => done += n,
+            }
+        }
+        self.shadow.clear();
+        self.shadow.extend_from_slice(nv);
+        Ok(())
+    }
+}
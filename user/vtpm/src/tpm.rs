@@ -0,0 +1,239 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! The TPM 2.0 Reference Implementation, driven through the interface of
+//! the TPM simulator.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::fmt;
+use core::ptr;
+use libtcgtpm::bindings::{
+    _plat__ClearCancel, _plat__LocalitySet, _plat__NVDisable, _plat__NVEnable, _plat__RunCommand,
+    _plat__SetCancel, _plat__SetNvAvail, _plat__Signal_PowerOff, _plat__Signal_PowerOn,
+    _plat__Signal_Reset, TPM_Manufacture, TPM_TearDown,
+};
+use userlib::{CHANNEL_MSG_MAX, SysCallError, console_print, print, println};
+
+use crate::nv::NvFile;
+
+/// Maximum size of TPM commands and responses, bounded by the size of the
+/// messages exchanged with the kernel.
+pub const TPM_BUFFER_MAX_SIZE: usize = CHANNEL_MSG_MAX;
+
+#[derive(Debug)]
+pub enum TpmError {
+    /// A function of the TPM library failed with the given return code.
+    Platform(&'static str, i32),
+    /// The NV memory could not be loaded or saved.
+    Nv(SysCallError),
+    /// The TPM is powered off.
+    PoweredOff,
+}
+
+impl fmt::Display for TpmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Platform(name, rc) => write!(f, "{name} failed rc={rc}"),
+            Self::Nv(err) => write!(f, "NV storage error {err:?}"),
+            Self::PoweredOff => write!(f, "TPM is powered off"),
+        }
+    }
+}
+
+impl From<SysCallError> for TpmError {
+    fn from(err: SysCallError) -> Self {
+        Self::Nv(err)
+    }
+}
+
+fn check(name: &'static str, rc: i32) -> Result<(), TpmError> {
+    match rc {
+        0 => Ok(()),
+        rc => Err(TpmError::Platform(name, rc)),
+    }
+}
+
+/// Sets or clears the cancel flag of the TPM, which makes a long-running
+/// command abort with TPM_RC_CANCELED. Unlike the other functions of the
+/// TPM, this may be called while another thread runs a command.
+pub fn set_cancel(cancel: bool) {
+    // SAFETY: FFI calls. No Parameters or return values. The calls only
+    // store the cancel flag, which the TPM library polls while it runs a
+    // command. The TPM simulator sets it from its platform thread in the
+    // same way.
+    unsafe {
+        if cancel {
+            _plat__SetCancel();
+        } else {
+            _plat__ClearCancel();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Tpm {
+    nv_file: Option<NvFile>,
+    powered_on: bool,
+}
+
+impl Tpm {
+    /// Manufactures the TPM, or restores its previously saved NV state, and
+    /// powers it on. The TPM requires a TPM2_Startup command afterwards.
+    pub fn init() -> Result<Self, TpmError> {
+        // Initialize the TPM TCG following the same steps done in the Simulator:
+        //
+        // 1. Manufacture it for the first time
+        // 2. Make sure it does not fail if it is re-manufactured
+        // 3. Teardown to indicate it needs to be manufactured
+        // 4. Manufacture it for the first time
+        // 5. Power it on indicating it requires startup.
+        //
+        // Steps 1-4 are skipped when a previously manufactured NV state is
+        // restored from the persistent filesystem.
+
+        // SAFETY: FFI call. Parameters and return values are checked.
+        check("_plat__NVEnable", unsafe {
+            _plat__NVEnable(ptr::null_mut::<c_void>(), 0)
+        })?;
+
+        let mut tpm = Self {
+            nv_file: NvFile::open()?,
+            powered_on: false,
+        };
+        let restored = match tpm.nv_file.as_mut() {
+            Some(nv_file) => nv_file.restore()?,
+            None => false,
+        };
+
+        if !restored {
+            // SAFETY: FFI call. Parameter and return values are checked.
+            if let Err(e) = check("TPM_Manufacture", unsafe { TPM_Manufacture(1) }) {
+                // SAFETY: FFI call. Parameter checked, no return value.
+                unsafe { _plat__NVDisable(ptr::without_provenance_mut::<c_void>(1), 0) };
+                return Err(e);
+            }
+            // Manufacturing again must report that the TPM is already
+            // manufactured.
+            // SAFETY: FFI call. Parameter and return values are checked.
+            let rc = unsafe { TPM_Manufacture(0) };
+            if rc != 1 {
+                return Err(TpmError::Platform("TPM_Manufacture", rc));
+            }
+            // SAFETY: FFI call. Return value is checked.
+            check("TPM_TearDown", unsafe { TPM_TearDown() })?;
+            // SAFETY: FFI call. Parameter and return values are checked.
+            check("TPM_Manufacture", unsafe { TPM_Manufacture(1) })?;
+        }
+
+        tpm.power_on(false)?;
+        tpm.nv_on()?;
+
+        tpm.sync_nv();
+        Ok(tpm)
+    }
+
+    /// Powers on the TPM, which also resets it, or only resets it if
+    /// `only_reset` is set. A reset requires the TPM to be powered on.
+    pub fn power_on(&mut self, only_reset: bool) -> Result<(), TpmError> {
+        if self.powered_on && !only_reset {
+            return Ok(());
+        }
+        if only_reset && !self.powered_on {
+            return Err(TpmError::PoweredOff);
+        }
+        if !only_reset {
+            // SAFETY: FFI call. No parameter, return value is checked.
+            check("_plat__Signal_PowerOn", unsafe { _plat__Signal_PowerOn() })?;
+        }
+        // It calls TPM_init() within to indicate that a TPM2_Startup is required.
+        // SAFETY: FFI call. No parameter, return value is checked.
+        check("_plat__Signal_Reset", unsafe { _plat__Signal_Reset() })?;
+        self.powered_on = true;
+        Ok(())
+    }
+
+    /// Powers off the TPM.
+    pub fn power_off(&mut self) {
+        if self.powered_on {
+            // SAFETY: FFI call. No Parameters or return values.
+            unsafe { _plat__Signal_PowerOff() };
+            self.powered_on = false;
+            self.sync_nv();
+        }
+    }
+
+    /// Makes the NV memory of the TPM available.
+    pub fn nv_on(&self) -> Result<(), TpmError> {
+        if !self.powered_on {
+            return Err(TpmError::PoweredOff);
+        }
+        // SAFETY: FFI call. No Parameters or return values.
+        unsafe { _plat__SetNvAvail() };
+        Ok(())
+    }
+
+    /// Writes the NV memory back to the persistent filesystem if it changed.
+    fn sync_nv(&mut self) {
+        if let Some(nv_file) = self.nv_file.as_mut() {
+            if let Err(e) = nv_file.sync() {
+                println!("vTPM: failed to save NV state: {e:?}");
+            }
+        }
+    }
+
+    /// Runs `command` in the given locality.
+    ///
+    /// # Returns
+    ///
+    /// The TPM response, or `None` if the TPM is powered off or did not
+    /// produce a response.
+    pub fn run_command(&mut self, command: &[u8], locality: u8) -> Option<Vec<u8>> {
+        if !self.powered_on || command.len() > TPM_BUFFER_MAX_SIZE {
+            return None;
+        }
+
+        // _plat__RunCommand() should define it `const` because it only uses
+        // it as input, but unfortunately it doesn't. Anyway, this buffer
+        // is only read during the FFI call.
+        let request_ffi_p = command.as_ptr() as *mut u8;
+        let request_ffi_size = command.len() as u32;
+
+        let mut response_ffi = Vec::<u8>::with_capacity(TPM_BUFFER_MAX_SIZE);
+        let mut response_ffi_p = response_ffi.as_mut_ptr();
+        let mut response_ffi_size = TPM_BUFFER_MAX_SIZE as u32;
+
+        // SAFETY: FFI calls. Parameters are checked. Both calls are void,
+        // _plat__RunCommand() returns `response_ffi_size` value by reference
+        // and it is validated.
+        unsafe {
+            _plat__LocalitySet(locality);
+            _plat__RunCommand(
+                request_ffi_size,
+                request_ffi_p,
+                &raw mut response_ffi_size,
+                &raw mut response_ffi_p,
+            );
+            if response_ffi_size == 0 || response_ffi_size as usize > response_ffi.capacity() {
+                return None;
+            }
+            // In TPM failure mode, _plat__RunCommand() redirects the response
+            // pointer to an internal static buffer instead of writing into the
+            // provided one.
+            if response_ffi_p != response_ffi.as_mut_ptr() {
+                ptr::copy(
+                    response_ffi_p,
+                    response_ffi.as_mut_ptr(),
+                    response_ffi_size as usize,
+                );
+            }
+            response_ffi.set_len(response_ffi_size as usize);
+        }
+
+        self.sync_nv();
+
+        Some(response_ffi)
+    }
+}
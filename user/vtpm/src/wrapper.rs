@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+
+//! Functions required by the TPM 2.0 Reference Implementation libraries,
+//! implemented on top of the user-mode heap and console.
+
+use core::alloc::Layout;
+use core::ffi::{c_char, c_int, c_ulong, c_void};
+use core::ptr;
+use core::slice::from_raw_parts;
+use core::str::from_utf8;
+
+extern crate alloc;
+use alloc::alloc::{alloc, alloc_zeroed, dealloc, realloc as _realloc};
+
+use userlib::{console_print, exit, print};
+
+/// Every allocation starts with a header holding its size, which `free()`
+/// and `realloc()` need to reconstruct the layout. The header size keeps the
+/// 16-byte alignment malloc() guarantees.
+const HEADER_SIZE: usize = 16;
+
+fn block_layout(size: usize) -> Option<Layout> {
+    let block_size = size.checked_add(HEADER_SIZE)?;
+    Layout::from_size_align(block_size, HEADER_SIZE).ok()
+}
+
+/// Stores `size` in the header of `block` and returns the pointer handed
+/// to the C code.
+///
+/// # Safety
+///
+/// `block` must be null or point to a block allocated with the layout
+/// returned by `block_layout(size)`.
+unsafe fn finish_block(block: *mut u8, size: usize) -> *mut c_void {
+    if block.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: the block is aligned and large enough for the header.
+    unsafe {
+        block.cast::<usize>().write(size);
+        block.add(HEADER_SIZE).cast()
+    }
+}
+
+/// Returns the block and its layout for a pointer returned by `malloc()`.
+///
+/// # Safety
+///
+/// `p` must have been returned by `malloc()`, `calloc()` or `realloc()` and
+/// not been freed.
+unsafe fn block_of(p: *mut c_void) -> (*mut u8, Layout) {
+    // SAFETY: the caller guarantees that `p` follows a block header.
+    unsafe {
+        let block = p.cast::<u8>().sub(HEADER_SIZE);
+        let size = block.cast::<usize>().read();
+        (block, block_layout(size).unwrap())
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn malloc(size: c_ulong) -> *mut c_void {
+    let size = size as usize;
+    if size == 0 {
+        return ptr::null_mut();
+    }
+    let Some(layout) = block_layout(size) else {
+        return ptr::null_mut();
+    };
+
+    // SAFETY: layout is guaranteed to be non-zero size. Memory may not be
+    // initialized, but that's what the caller expects.
+    unsafe { finish_block(alloc(layout), size) }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn calloc(items: c_ulong, size: c_ulong) -> *mut c_void {
+    let Some(size) = items.checked_mul(size) else {
+        return ptr::null_mut();
+    };
+    let size = size as usize;
+    if size == 0 {
+        return ptr::null_mut();
+    }
+    let Some(layout) = block_layout(size) else {
+        return ptr::null_mut();
+    };
+
+    // SAFETY: layout is guaranteed to be non-zero size.
+    unsafe { finish_block(alloc_zeroed(layout), size) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn realloc(p: *mut c_void, size: c_ulong) -> *mut c_void {
+    if p.is_null() {
+        return malloc(size);
+    }
+
+    let new_size = size as usize;
+    if new_size == 0 {
+        // SAFETY: the caller guarantees that `p` was allocated by malloc().
+        unsafe { free(p) };
+        return ptr::null_mut();
+    }
+    if block_layout(new_size).is_none() {
+        return ptr::null_mut();
+    }
+
+    // SAFETY: the caller guarantees that `p` was allocated by malloc(), so
+    // the block was allocated with `layout`. The new block size does not
+    // overflow when rounded to the alignment and is not 0.
+    unsafe {
+        let (block, layout) = block_of(p);
+        finish_block(_realloc(block, layout, new_size + HEADER_SIZE), new_size)
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free(p: *mut c_void) {
+    if p.is_null() {
+        return;
+    }
+    // SAFETY: the caller guarantees that `p` was allocated by malloc(), so
+    // the block was allocated with `layout`.
+    unsafe {
+        let (block, layout) = block_of(p);
+        dealloc(block, layout);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn serial_out(s: *const c_char, size: c_int) {
+    // SAFETY: caller must provide safety requirements for
+    // [`core::slice::from_raw_parts`]
+    let str_slice: &[u8] = unsafe { from_raw_parts(s as *const u8, size as usize) };
+    if let Ok(rust_str) = from_utf8(str_slice) {
+        print!("[vTPM] {rust_str}");
+    } else {
+        print!("ERR: BUG: serial_out arg1 is not a valid utf8 string\n");
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn abort() -> ! {
+    exit(!0);
+}